@@ -0,0 +1,211 @@
+/*
+System accounts, referenced by the kept files and systemd units.
+
+Accounts, which are not referenced, can be trimmed from /etc/passwd,
+/etc/group and their shadow files. Referenced, but missing accounts are reported.
+ */
+
+use crate::prefix;
+use std::{
+    collections::HashSet,
+    fs,
+    io::Error,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+const PASSWD: &str = "/etc/passwd";
+const GROUP: &str = "/etc/group";
+const SHADOW: &str = "/etc/shadow";
+const GSHADOW: &str = "/etc/gshadow";
+
+/// Overflow user and group, e.g. for NFS and user namespaces
+const NOBODY: u32 = 65534;
+
+#[derive(Default)]
+pub struct Accounts {
+    uids: HashSet<u32>,
+    gids: HashSet<u32>,
+    users: HashSet<String>,
+    groups: HashSet<String>,
+}
+
+impl Accounts {
+    /// Collect accounts, referenced by the kept files
+    pub fn new(kept: &[PathBuf]) -> Self {
+        let mut acc = Accounts::default();
+        acc.uids.extend([0, NOBODY]);
+        acc.gids.extend([0, NOBODY]);
+
+        for p in kept {
+            if let Ok(m) = prefix::symlink_metadata(p) {
+                acc.uids.insert(m.uid());
+                acc.gids.insert(m.gid());
+            }
+
+            if p.components().any(|c| c.as_os_str() == "systemd") && prefix::is_file(p) {
+                acc.scan_unit(p);
+            }
+        }
+
+        acc
+    }
+
+    /// Collect accounts from User=, Group= and SupplementaryGroups= of a systemd unit
+    fn scan_unit(&mut self, p: &Path) {
+        let data = match prefix::read_to_string(p) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+
+        // Dynamic users are allocated at runtime
+        if data.lines().any(|l| matches!(l.trim().replace(' ', "").as_str(), "DynamicUser=yes" | "DynamicUser=true")) {
+            return;
+        }
+
+        for l in data.lines() {
+            let (k, v) = match l.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => continue,
+            };
+
+            let names = v.split_whitespace().map(|n| n.to_string());
+            match k {
+                "User" => {
+                    if let Ok(uid) = v.parse::<u32>() {
+                        self.uids.insert(uid);
+                    } else {
+                        self.users.extend(names);
+                    }
+                }
+                "Group" | "SupplementaryGroups" => {
+                    for n in names {
+                        if let Ok(gid) = n.parse::<u32>() {
+                            self.gids.insert(gid);
+                        } else {
+                            self.groups.insert(n);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Read colon-separated account database as (name, id, primary group id) entries
+    fn read_db(p: &str, gid_field: bool) -> Vec<(String, Option<u32>, Option<u32>, String)> {
+        prefix::read_to_string(p)
+            .unwrap_or_default()
+            .lines()
+            .map(|l| {
+                let f = l.split(':').collect::<Vec<&str>>();
+                (
+                    f[0].to_string(),
+                    f.get(2).and_then(|id| id.parse::<u32>().ok()),
+                    if gid_field { f.get(3).and_then(|id| id.parse::<u32>().ok()) } else { None },
+                    l.to_string(),
+                )
+            })
+            .collect()
+    }
+
+    /// Get ids of the existing users and groups, including root and the overflow ones
+    pub fn get_known_ids() -> (HashSet<u32>, HashSet<u32>) {
+        let mut uids = Self::read_db(PASSWD, true).into_iter().filter_map(|e| e.1).collect::<HashSet<u32>>();
+        let mut gids = Self::read_db(GROUP, false).into_iter().filter_map(|e| e.1).collect::<HashSet<u32>>();
+        uids.extend([0, NOBODY]);
+        gids.extend([0, NOBODY]);
+
+        (uids, gids)
+    }
+
+    /// Get names of the needed users and groups
+    fn get_needed(&self) -> (HashSet<String>, HashSet<String>) {
+        let mut gids = self.gids.clone();
+        let mut users: HashSet<String> = HashSet::default();
+        for (name, uid, gid, _) in Self::read_db(PASSWD, true) {
+            if uid.map(|uid| self.uids.contains(&uid)).unwrap_or(true) || self.users.contains(&name) {
+                users.insert(name);
+                gids.extend(gid);
+            }
+        }
+
+        let mut groups: HashSet<String> = HashSet::default();
+        for (name, gid, _, _) in Self::read_db(GROUP, false) {
+            if gid.map(|gid| gids.contains(&gid)).unwrap_or(true) || self.groups.contains(&name) {
+                groups.insert(name);
+            }
+        }
+
+        (users, groups)
+    }
+
+    /// Verify that all referenced accounts exist. Returns found problems.
+    pub fn verify(&self) -> Vec<String> {
+        let mut out: Vec<String> = vec![];
+        let passwd = Self::read_db(PASSWD, true);
+        let group = Self::read_db(GROUP, false);
+
+        let mut uids =
+            self.uids.iter().filter(|uid| **uid != NOBODY && !passwd.iter().any(|e| e.1 == Some(**uid))).collect::<Vec<_>>();
+        uids.sort();
+        out.extend(uids.iter().map(|uid| format!("User id {} owns kept files, but is not in {}", uid, PASSWD)));
+
+        let mut gids =
+            self.gids.iter().filter(|gid| **gid != NOBODY && !group.iter().any(|e| e.1 == Some(**gid))).collect::<Vec<_>>();
+        gids.sort();
+        out.extend(gids.iter().map(|gid| format!("Group id {} owns kept files, but is not in {}", gid, GROUP)));
+
+        for u in &self.users {
+            if !passwd.iter().any(|e| &e.0 == u) {
+                out.push(format!("User {} is used by a systemd unit, but is not in {}", u, PASSWD));
+            }
+        }
+
+        for g in &self.groups {
+            if !group.iter().any(|e| &e.0 == g) {
+                out.push(format!("Group {} is used by a systemd unit, but is not in {}", g, GROUP));
+            }
+        }
+
+        out
+    }
+
+    /// Get account databases of the image, those are changed by trimming
+    pub fn get_databases() -> Vec<PathBuf> {
+        [PASSWD, SHADOW, GROUP, GSHADOW].iter().filter(|db| prefix::exists(db)).map(PathBuf::from).collect()
+    }
+
+    /// Trim account databases to the needed entries only.
+    /// Returns names of removed users and groups. Nothing is changed in dry run.
+    pub fn trim(&self, dry_run: bool) -> Result<(Vec<String>, Vec<String>), Error> {
+        let (users, groups) = self.get_needed();
+        let mut removed: (Vec<String>, Vec<String>) = (vec![], vec![]);
+
+        for (db, needed, gid_field) in
+            [(PASSWD, &users, true), (SHADOW, &users, false), (GROUP, &groups, false), (GSHADOW, &groups, false)]
+        {
+            if !prefix::exists(db) {
+                continue;
+            }
+
+            let mut out: Vec<String> = vec![];
+            for (name, _, _, line) in Self::read_db(db, gid_field) {
+                if name.is_empty() || name.starts_with('#') || needed.contains(&name) {
+                    out.push(line);
+                } else if db == PASSWD {
+                    removed.0.push(name);
+                } else if db == GROUP {
+                    removed.1.push(name);
+                }
+            }
+
+            if !dry_run {
+                // Overwriting in place keeps permissions of the shadow files
+                fs::write(prefix::host(db), if out.is_empty() { String::new() } else { format!("{}\n", out.join("\n")) })?;
+            }
+        }
+
+        Ok(removed)
+    }
+}
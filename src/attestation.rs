@@ -0,0 +1,69 @@
+/*
+Attestation of the tinted image: a compact final state, designed to be
+consumed by admission policies (e.g. Kubernetes), so clusters can require
+that deployed images were tinted and verified.
+ */
+
+use crate::scanner::binlib::ElfScanner;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::io::Error;
+
+/// Verification status of the targets after the changes are applied
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verification {
+    Passed,
+    Failed,
+    Unknown,
+    Skipped,
+}
+
+impl Verification {
+    /// Verify that all libraries of the targets are resolved
+    pub fn verify(targets: &[String]) -> Self {
+        for t in targets {
+            match ElfScanner::get_unresolved(t) {
+                Some(libs) if !libs.is_empty() => {
+                    log::warn!("Target {} has unresolved libraries: {}", t, libs.join(", "));
+                    return Verification::Failed;
+                }
+                Some(_) => {}
+                None => return Verification::Unknown,
+            }
+        }
+
+        Verification::Passed
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Attestation {
+    tool: String,
+    tinted_at: String,
+    dry_run: bool,
+    profile: String,
+    manifest: String,
+    kept_files: usize,
+    verification: Verification,
+}
+
+impl Attestation {
+    pub fn new(profile: String, manifest: String, kept_files: usize, dry_run: bool, verification: Verification) -> Self {
+        Attestation {
+            tool: format!("mezzotint/{}", env!("CARGO_PKG_VERSION")),
+            tinted_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            dry_run,
+            profile,
+            manifest,
+            kept_files,
+            verification,
+        }
+    }
+
+    /// Get attestation as a compact JSON
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
@@ -0,0 +1,228 @@
+/*
+Backup of the removed files into a tar archive on the host.
+
+Files are streamed with their mode, owner and extended attributes
+right before they are removed, so a mistaken tint can be reversed.
+Archive is compressed in-process, the compression is chosen by the extension.
+It is written in a workspace and moved to its place only when finished.
+ */
+
+use crate::{prefix, workspace::Workspace, xattrs};
+use bytesize::ByteSize;
+use bzip2::{read::BzDecoder, write::BzEncoder};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use std::{
+    fs::File,
+    io::{self, BufReader, Error, ErrorKind, Read, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+use tar::{Builder, EntryType, Header, HeaderMode};
+use xz2::{read::XzDecoder, write::XzEncoder};
+
+/// Largest id, which fits the ustar header. Larger ones are in the PAX extended header.
+const MAX_USTAR_ID: u32 = 0o7777777;
+
+/// Compression of the archive
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Compression {
+    None,
+    Zstd,
+    Gzip,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// Get compression of the archive by its extension
+    fn detect(p: &Path) -> Self {
+        let fname = p.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+        [
+            (".zst", Compression::Zstd),
+            (".tzst", Compression::Zstd),
+            (".gz", Compression::Gzip),
+            (".tgz", Compression::Gzip),
+            (".xz", Compression::Xz),
+            (".bz2", Compression::Bzip2),
+        ]
+        .iter()
+        .find(|(ext, _)| fname.ends_with(ext))
+        .map(|(_, c)| *c)
+        .unwrap_or(Compression::None)
+    }
+}
+
+/// Output of the archive, compressed or not
+enum Sink {
+    Plain(File),
+    Zstd(zstd::Encoder<'static, File>),
+    Gzip(GzEncoder<File>),
+    Xz(XzEncoder<File>),
+    Bzip2(BzEncoder<File>),
+}
+
+impl Sink {
+    fn new(f: File, c: Compression) -> Result<Self, Error> {
+        Ok(match c {
+            Compression::None => Sink::Plain(f),
+            Compression::Zstd => Sink::Zstd(zstd::Encoder::new(f, 0)?),
+            Compression::Gzip => Sink::Gzip(GzEncoder::new(f, flate2::Compression::default())),
+            Compression::Xz => Sink::Xz(XzEncoder::new(f, 6)),
+            Compression::Bzip2 => Sink::Bzip2(BzEncoder::new(f, bzip2::Compression::default())),
+        })
+    }
+
+    /// Finish the compression and sync the archive to the disk
+    fn finish(self) -> Result<File, Error> {
+        let f = match self {
+            Sink::Plain(f) => f,
+            Sink::Zstd(e) => e.finish()?,
+            Sink::Gzip(e) => e.finish()?,
+            Sink::Xz(e) => e.finish()?,
+            Sink::Bzip2(e) => e.finish()?,
+        };
+        f.sync_all()?;
+
+        Ok(f)
+    }
+
+    fn inner(&mut self) -> &mut dyn Write {
+        match self {
+            Sink::Plain(f) => f,
+            Sink::Zstd(e) => e,
+            Sink::Gzip(e) => e,
+            Sink::Xz(e) => e,
+            Sink::Bzip2(e) => e,
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner().flush()
+    }
+}
+
+pub struct Backup {
+    path: PathBuf,
+    out: Builder<Sink>,
+    files: usize,
+    ws: Workspace,
+    // Directory of the archive, opened on the host
+    dir: File,
+}
+
+impl Backup {
+    /// Create a backup archive. It must be called on the host, as the archive is there.
+    pub fn create(p: &Path) -> Result<Self, Error> {
+        let name = p.file_name().and_then(|n| n.to_str()).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("Backup {} is not a file name", p.to_str().unwrap_or_default()))
+        })?;
+        let dir = File::open(match p.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d,
+            _ => Path::new("."),
+        })?;
+        let ws = Workspace::new("backup")?;
+        let out = Sink::new(ws.create(name)?, Compression::detect(p))
+            .map_err(|err| Error::new(err.kind(), format!("Unable to compress the backup {}: {}", p.to_str().unwrap(), err)))?;
+
+        Ok(Backup { path: p.to_owned(), out: Builder::new(out), files: 0, ws, dir })
+    }
+
+    /// Open a backup archive for reading, decompressing it by its extension
+    pub fn open(p: &Path) -> Result<tar::Archive<Box<dyn Read>>, Error> {
+        let f = BufReader::new(File::open(p)?);
+        let r: Box<dyn Read> = match Compression::detect(p) {
+            Compression::None => Box::new(f),
+            Compression::Zstd => Box::new(zstd::Decoder::with_buffer(f)?),
+            Compression::Gzip => Box::new(GzDecoder::new(f)),
+            Compression::Xz => Box::new(XzDecoder::new(f)),
+            Compression::Bzip2 => Box::new(BzDecoder::new(f)),
+        };
+
+        Ok(tar::Archive::new(r))
+    }
+
+    /// Make a PAX record. Its length includes the length field itself.
+    fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+        let body = key.len() + value.len() + 3; // space, equals sign and newline
+        let mut len = body + body.to_string().len();
+        if len.to_string().len() != body.to_string().len() {
+            len += 1;
+        }
+
+        let mut r = format!("{} {}=", len, key).into_bytes();
+        r.extend(value);
+        r.push(b'\n');
+        r
+    }
+
+    /// Add a file, a symlink or an empty directory to the backup
+    pub fn add(&mut self, p: &Path) -> Result<(), Error> {
+        let m = prefix::symlink_metadata(p)?;
+        let ft = m.file_type();
+        if !(ft.is_symlink() || ft.is_file() || ft.is_dir()) {
+            log::debug!("Skipping backup of special file {}", p.to_str().unwrap());
+            return Ok(());
+        }
+
+        // Ids, those do not fit ustar, and extended attributes go to the PAX extended header
+        let mut pax: Vec<u8> = vec![];
+        if m.uid() > MAX_USTAR_ID {
+            pax.extend(Self::pax_record("uid", m.uid().to_string().as_bytes()));
+        }
+        if m.gid() > MAX_USTAR_ID {
+            pax.extend(Self::pax_record("gid", m.gid().to_string().as_bytes()));
+        }
+        for (k, v) in xattrs::get_all(&prefix::host_nofollow(p)) {
+            pax.extend(Self::pax_record(&format!("SCHILY.xattr.{}", k), &v));
+        }
+        if !pax.is_empty() {
+            let mut h = Header::new_ustar();
+            h.set_entry_type(EntryType::XHeader);
+            h.set_size(pax.len() as u64);
+            self.out.append_data(&mut h, "././@PaxHeader", pax.as_slice())?;
+        }
+
+        // Archive paths are relative to the root
+        let name = p.strip_prefix("/").unwrap_or(p);
+        let mut h = Header::new_ustar();
+        h.set_metadata_in_mode(&m, HeaderMode::Complete);
+        if ft.is_symlink() {
+            self.out.append_link(&mut h, name, prefix::read_link(p)?)?;
+        } else if ft.is_dir() {
+            self.out.append_data(&mut h, name, io::empty())?;
+        } else {
+            // Content is limited to the header size, so a growing file does not break the archive
+            let mut f = File::open(prefix::host_nofollow(p))?.take(m.len());
+            self.out.append_data(&mut h, name, &mut f)?;
+            if f.limit() > 0 {
+                return Err(Error::new(ErrorKind::Other, format!("File {} has changed during the backup", p.to_str().unwrap())));
+            }
+        }
+        self.files += 1;
+
+        Ok(())
+    }
+
+    /// Finish the archive and the compression, and move it to its place
+    pub fn finish(self) -> Result<(), Error> {
+        let name = self.path.file_name().unwrap().to_str().unwrap();
+        let size = self
+            .out
+            .into_inner()
+            .and_then(|out| out.finish())
+            .and_then(|f| f.metadata())
+            .and_then(|m| self.ws.persist(name, &self.dir, name).map(|_| m.len()))
+            .map_err(|err| {
+                Error::new(err.kind(), format!("Unable to write the backup {}: {}", self.path.to_str().unwrap(), err))
+            })?;
+
+        log::info!("Backup of {} paths ({}) is written to {}", self.files, ByteSize::b(size), self.path.to_str().unwrap());
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use crate::manifest::REMOVED_MANIFEST_PATH;
 use clap::builder::styling;
 use clap::{Arg, ArgAction, Command};
 use colored::Colorize;
@@ -13,12 +14,13 @@ pub fn cli(version: &'static str) -> Command {
     Command::new("mezzotint")
         .version(version)
         .about(format!("{}{} - {}", "mezzo".bold().underline(), "tint", "is a tool to turn your container into an App Bundle"))
-        .override_usage(format!("{} {} {}", "mezzotint".bright_cyan(), "[OPTIONS]".cyan(), "[FILTERS]".cyan()))
+        .override_usage(format!("{} {} {} {}", "mezzotint".bright_cyan(), "[OPTIONS]".cyan(), "[FILTERS]".cyan(), "[COMMAND]".cyan()))
         // Config
         .arg(
             Arg::new("exe")
                 .short('x')
                 .long("exe")
+                .global(true)
                 .conflicts_with("profile")
                 .help("Specify path to an executable which needs to be preserved")
         )
@@ -26,8 +28,10 @@ pub fn cli(version: &'static str) -> Command {
             Arg::new("profile")
                 .short('p')
                 .long("profile")
+                .global(true)
+                .action(ArgAction::Append)
                 .conflicts_with("exe")
-                .help("Profile, describing whole setup")
+                .help("Profile, describing whole setup. Either a path or an https:// URL, optionally pinned as URL#sha256=<digest>.\nCan be specified multiple times, later profiles extend earlier ones")
         )
         .arg(
             Arg::new("packages")
@@ -50,6 +54,281 @@ pub fn cli(version: &'static str) -> Command {
                 .action(clap::ArgAction::SetTrue)
                 .help("Do not remove anything, only display what will be removed")
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("format")
+                .default_value("text")
+                .value_parser(["text", "json", "ndjson"])
+                .help("Output format of the dry-run result, ndjson prints one decision per line")
+        )
+        .arg(
+            Arg::new("top")
+                .long("top")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Show N largest kept files in the dry-run output, flagging ones those look prunable")
+        )
+        .arg(
+            Arg::new("check")
+                .long("check")
+                .action(clap::ArgAction::SetTrue)
+                .help("Dry-run and exit with non-zero status, if junk files, unresolved libraries or size over the budget are found")
+        )
+        .arg(
+            Arg::new("max-size")
+                .long("max-size")
+                .value_name("size")
+                .value_parser(|s: &str| s.parse::<bytesize::ByteSize>().map(|b| b.as_u64()))
+                .requires("check")
+                .help("Size budget of the kept files for --check, e.g. 50MB")
+        )
+        .arg(
+            Arg::new("plain")
+                .long("plain")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("format")
+                .help("Print kept paths in the dry-run output uncolored, one per line, e.g. for grep or sort")
+        )
+        .arg(
+            Arg::new("sizes")
+                .long("sizes")
+                .action(clap::ArgAction::SetTrue)
+                .requires("plain")
+                .help("Add size column in bytes to the plain output")
+        )
+        .arg(
+            Arg::new("depth")
+                .long("depth")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Limit depth of the tree in the dry-run output, summarising deeper directories")
+        )
+        .arg(
+            Arg::new("collapse")
+                .long("collapse")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Collapse directories with more than N entries into a summary line in the dry-run output")
+        )
+        .arg(
+            Arg::new("dirs-only")
+                .long("dirs-only")
+                .action(clap::ArgAction::SetTrue)
+                .help("Show only directories with amount of files and size in the dry-run output")
+        )
+        .arg(
+            Arg::new("by-package")
+                .long("by-package")
+                .action(clap::ArgAction::SetTrue)
+                .help("Show retained size of the kept files by the owning package in the dry-run output")
+        )
+        .arg(
+            Arg::new("explain")
+                .long("explain")
+                .action(clap::ArgAction::SetTrue)
+                .help("Annotate every kept path in the dry-run output with the rule, which produced it")
+        )
+        .arg(
+            Arg::new("diff-last")
+                .long("diff-last")
+                .action(clap::ArgAction::SetTrue)
+                .help("Show only paths, those are kept or removed since the previous dry run, instead of the whole tree")
+        )
+        .arg(
+            Arg::new("strict")
+                .long("strict")
+                .action(clap::ArgAction::SetTrue)
+                .help("Fail on any unexpected errors, e.g. files those cannot be removed")
+        )
+        .arg(
+            Arg::new("overlay")
+                .long("overlay")
+                .action(clap::ArgAction::SetTrue)
+                .help("Apply the profile on top of an already tinted image, using its manifest as a baseline")
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("overlay")
+                .help("Apply the profile again to an already tinted image, removing only what was added since")
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["dry-run", "overlay", "force"])
+                .help("Finish the removal of an interrupted run from its journal, instead of a new run")
+        )
+        .arg(
+            Arg::new("lockfile")
+                .long("lockfile")
+                .value_name("path")
+                .value_parser(|s: &str| {
+                    if s.starts_with('/') {
+                        Ok(s.to_string())
+                    } else {
+                        Err("path inside the image must be absolute")
+                    }
+                })
+                .help("Path of the lock file with the tint state inside the image (default: /.tinted.lock)")
+        )
+        .arg(
+            Arg::new("no-lockfile")
+                .long("no-lockfile")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("lockfile")
+                .help("Do not write the lock file, e.g. on read-only or immutable root layouts")
+        )
+        .arg(
+            Arg::new("export-profile")
+                .long("export-profile")
+                .value_name("file")
+                .help("Write fully expanded profile with the concrete keep list, so the tint can be reproduced")
+        )
+        .arg(
+            Arg::new("plan")
+                .long("plan")
+                .value_name("file")
+                .help("Write removal plan as JSON, e.g. to preview it with the \"mount\" command")
+        )
+        .arg(
+            Arg::new("metrics-out")
+                .long("metrics-out")
+                .value_name("file")
+                .help("Write metrics of the run in Prometheus textfile format")
+        )
+        .arg(
+            Arg::new("removed-manifest")
+                .long("removed-manifest")
+                .value_name("path")
+                .default_value(REMOVED_MANIFEST_PATH)
+                .help("Location of the manifest of removed files inside the image")
+        )
+        .arg(
+            Arg::new("audit-log")
+                .long("audit-log")
+                .value_name("file")
+                .help("Append every removed file with its SHA-256, taken before the removal, to the audit log as JSON lines")
+        )
+        .arg(
+            Arg::new("backup")
+                .long("backup")
+                .value_name("file")
+                .help("Back up removed files with their mode, owner and xattrs to a tar archive, e.g. backup.tar.zst")
+        )
+        .arg(
+            Arg::new("whiteouts")
+                .long("whiteouts")
+                .value_name("dir")
+                .conflicts_with_all(["quarantine", "backup", "overlay", "force", "resume"])
+                .help("Leave the rootfs untouched and hide removed files by overlayfs whiteouts in this upper directory")
+        )
+        .arg(
+            Arg::new("attestation")
+                .long("attestation")
+                .value_name("file")
+                .help("Write attestation of the final state for admission policies")
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("file")
+                .help("Write standalone HTML report with kept and removed trees, size charts and junk warnings")
+        )
+        .arg(
+            Arg::new("graph")
+                .long("graph")
+                .value_name("file")
+                .help("Write dependency graph of the keep set: targets, libraries, packages and files")
+        )
+        .arg(
+            Arg::new("graph-format")
+                .long("graph-format")
+                .value_name("format")
+                .default_value("dot")
+                .value_parser(["dot", "json"])
+                .help("Format of the dependency graph")
+        )
+        .arg(
+            Arg::new("csv")
+                .long("csv")
+                .value_name("file")
+                .help("Write CSV report of every kept and removed path with the reason")
+        )
+        .arg(
+            Arg::new("sbom")
+                .long("sbom")
+                .value_name("file")
+                .help("Write SBOM of the kept packages and files")
+        )
+        .arg(
+            Arg::new("sbom-format")
+                .long("sbom-format")
+                .value_name("format")
+                .default_value("spdx")
+                .value_parser(["spdx", "cyclonedx"])
+                .help("Format of the SBOM")
+        )
+        .arg(
+            Arg::new("image-config")
+                .long("image-config")
+                .value_name("file")
+                .help("Image config (OCI or Docker) to trace binaries of ENTRYPOINT, CMD, HEALTHCHECK and ONBUILD")
+        )
+        .arg(
+            Arg::new("config-targets")
+                .long("config-targets")
+                .action(clap::ArgAction::SetTrue)
+                .requires("image-config")
+                .help("Add binaries of the image config to the targets instead of suggesting them")
+        )
+        .arg(
+            Arg::new("snapshot")
+                .long("snapshot")
+                .action(clap::ArgAction::SetTrue)
+                .help("Snapshot the rootfs before applying changes (btrfs, ZFS or LVM thin volume)")
+        )
+        .arg(
+            Arg::new("quarantine")
+                .long("quarantine")
+                .action(clap::ArgAction::SetTrue)
+                .help("Move removed files into a quarantine inside the image instead of deleting them")
+        )
+        .arg(
+            Arg::new("no-safety")
+                .long("no-safety")
+                .action(clap::ArgAction::SetTrue)
+                .help("Allow removal of the protected paths, such as the dynamic loader or /etc/passwd")
+        )
+        .arg(
+            Arg::new("yes-i-mean-the-host")
+                .long("yes-i-mean-the-host")
+                .action(clap::ArgAction::SetTrue)
+                .help("Allow tinting the running system, e.g. when the root filesystem is /")
+        )
+        .arg(
+            Arg::new("report-dangling")
+                .long("report-dangling")
+                .action(clap::ArgAction::SetTrue)
+                .help("Only report broken symlinks instead of removing them")
+        )
+        .arg(
+            Arg::new("auto-fix")
+                .long("auto-fix")
+                .action(clap::ArgAction::SetTrue)
+                .requires("quarantine")
+                .help("Restore missing libraries of the targets from the quarantine without asking")
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .help("Number of parallel jobs for scanning and filtering, 0 for the number of CPUs (default)")
+        )
         .arg(
             Arg::new("autodeps")
                 .short('a')
@@ -63,8 +342,116 @@ pub fn cli(version: &'static str) -> Command {
             Arg::new("root")
                 .short('r')
                 .long("root")
-                .required_unless_present_any(["help", "version"])
-                .help("Root filesystem, e.g. mountpoint of an image")
+                .global(true)
+                .action(ArgAction::Append)
+                .help("Root filesystem, e.g. mountpoint of an image. Several ones can be processed with --prefix.")
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+                .help("Access the rootfs by its paths from the host instead of chroot, so no privileges are needed")
+        )
+        .arg(
+            Arg::new("userns")
+                .long("userns")
+                .global(true)
+                .action(clap::ArgAction::SetTrue)
+                .help("Run as root in a new user namespace, so a rootfs owned by the user is tinted without sudo")
+        )
+        .arg(
+            Arg::new("workdir")
+                .long("workdir")
+                .value_name("dir")
+                .global(true)
+                .help("Base directory for temporary data on the host, removed after the run")
+        )
+        .arg(
+            Arg::new("cache-dir")
+                .long("cache-dir")
+                .value_name("dir")
+                .global(true)
+                .help("Directory on the host, where scan results are cached for the next runs on similar images")
+        )
+
+        // Subcommands
+        .subcommand(
+            Command::new("analyze")
+                .about("Analyze the rootfs against the profile, without applying any changes")
+                .arg(
+                    Arg::new("only")
+                        .long("only")
+                        .value_name("stage")
+                        .value_parser(["prune-rules"])
+                        .help("Evaluate only the specified stage, e.g. keep/prune rules of the profile")
+                )
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("Explain why the path is kept or removed by the profile, without applying any changes")
+                .arg(Arg::new("path").required(true).help("Path inside the rootfs, e.g. /usr/lib/x86_64-linux-gnu/libc.so.6"))
+        )
+        .subcommand(
+            Command::new("rollback")
+                .about("Roll the rootfs back to its state before the changes were applied")
+                .arg(
+                    Arg::new("snapshot")
+                        .long("snapshot")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Roll back to the filesystem snapshot, taken with --snapshot")
+                )
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Restore removed files into the rootfs and clear the tint state")
+                .arg(
+                    Arg::new("backup")
+                        .long("backup")
+                        .value_name("file")
+                        .help("Backup archive, written with --backup. Quarantined files are restored without it.")
+                )
+        )
+        .subcommand(
+            Command::new("mount")
+                .about("Mount read-only preview of the rootfs, as it would look after the removal plan is applied")
+                .arg(Arg::new("plan").long("plan").value_name("file").required(true).help("Removal plan, written with --plan"))
+                .arg(Arg::new("mountpoint").help("Directory, where the preview is mounted. Defaults to a temporary directory"))
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare two root filesystems, e.g. results of two profile revisions")
+                .arg(Arg::new("root_a").required(true).help("First root filesystem"))
+                .arg(Arg::new("root_b").required(true).help("Second root filesystem"))
+        )
+        .subcommand(
+            Command::new("profile")
+                .about("Profile tools")
+                .subcommand(
+                    Command::new("lint")
+                        .about("Validate the profile, optionally against the root filesystem, without running the tint")
+                )
+        )
+        .subcommand(
+            Command::new("gen-man")
+                .about("Generate manpage from the current command line definition")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("file")
+                        .help("Write manpage to the file instead of STDOUT")
+                )
+        )
+        .subcommand(
+            Command::new("gen-completions")
+                .about("Generate shell completion script")
+                .arg(
+                    Arg::new("shell")
+                        .required(true)
+                        .value_parser(clap::value_parser!(clap_complete::Shell))
+                        .help("Target shell")
+                )
         )
 
         // Filters
@@ -96,12 +483,44 @@ pub fn cli(version: &'static str) -> Command {
 
         // Other
         .next_help_heading("Other")
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count)
+                .help("Increase verbosity: -v for debug, -vv for trace output."),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["verbose", "debug"])
+                .help("Log only warnings and errors and suppress the final output, e.g. in Dockerfile RUN steps."),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .value_name("file")
+                .global(true)
+                .help("Write log with every decision about kept and removed files also to the file"),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .value_name("format")
+                .default_value("text")
+                .value_parser(["text", "json"])
+                .global(true)
+                .requires("log-file")
+                .help("Format of the log file: text or JSON lines"),
+        )
         .arg(
             Arg::new("debug")
                 .short('d')
                 .long("debug")
                 .action(ArgAction::SetTrue)
-                .help("Set debug mode for more verbose output."),
+                .help("Set debug mode for most verbose output, same as -vv."),
         )
         .arg(
             Arg::new("help")
@@ -112,7 +531,7 @@ pub fn cli(version: &'static str) -> Command {
         )
         .arg(
             Arg::new("version")
-                .short('v')
+                .short('V')
                 .long("version")
                 .action(ArgAction::SetTrue)
                 .help("Get current version."),
@@ -0,0 +1,84 @@
+/*
+CSV report of every path in the final keep/remove decision,
+so the result can be audited in a spreadsheet.
+ */
+
+use crate::{
+    prefix,
+    provenance::Reason,
+    scanner::{debftrace::DebPkgFileTrace, traceitf::PkgFileTrace},
+    xattrs,
+};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Paths with the reasons of the decision
+pub type Decisions = Vec<(PathBuf, Reason)>;
+
+/// Escape a CSV field
+pub fn escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+pub struct CsvReport<'a> {
+    kept: &'a [(PathBuf, Reason)],
+    removed: &'a [(PathBuf, Reason)],
+}
+
+impl<'a> CsvReport<'a> {
+    pub fn new(kept: &'a [(PathBuf, Reason)], removed: &'a [(PathBuf, Reason)]) -> Self {
+        CsvReport { kept, removed }
+    }
+
+    /// Get type of the file
+    fn file_type(p: &Path) -> &'static str {
+        let m = match prefix::symlink_metadata(p) {
+            Ok(m) => m,
+            Err(_) => return "missing",
+        };
+
+        if m.file_type().is_symlink() {
+            "symlink"
+        } else if m.is_dir() {
+            "directory"
+        } else if !m.is_file() {
+            "special"
+        } else {
+            let mut magic = [0u8; 4];
+            match prefix::open(p).and_then(|mut f| f.read_exact(&mut magic)) {
+                Ok(_) if &magic == b"\x7fELF" => "elf",
+                _ => "file",
+            }
+        }
+    }
+
+    /// Get the report as CSV with a header
+    pub fn to_csv(&self) -> String {
+        let mut pt = DebPkgFileTrace::new();
+        let mut out = String::from("path,action,size,package,type,reason,context\n");
+        for (action, paths) in [("keep", self.kept), ("remove", self.removed)] {
+            for (p, r) in paths {
+                out.push_str(&format!(
+                    "{},{},{},{},{},{},{}\n",
+                    escape(p.to_str().unwrap()),
+                    action,
+                    prefix::symlink_metadata(p).map(|m| m.len()).unwrap_or(0),
+                    escape(&pt.trace(p.to_owned()).unwrap_or_default()),
+                    Self::file_type(p),
+                    r.as_str(),
+                    escape(
+                        &xattrs::get_context(&prefix::host_nofollow(p)).map(|c| xattrs::describe_context(&c)).unwrap_or_default()
+                    )
+                ));
+            }
+        }
+
+        out
+    }
+}
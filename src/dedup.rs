@@ -0,0 +1,100 @@
+/*
+Duplicates among the kept files.
+
+Bundled libraries and vendored assets are often shipped several times.
+Kept files with the same content are found by size and then by SHA-256,
+and are replaced by hardlinks to one of them, or only reported. Files are
+linked only if they are on the same filesystem and have the same mode,
+owner and extended attributes, as hardlinks share them.
+ */
+
+use crate::{digest, prefix, xattrs};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Error,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+/// Identity of a file: it can be hardlinked only to the files with the same one
+type FileKey = (u64, u64, u32, u32, u32);
+
+/// Content of a file: its digest and extended attributes
+type ContentKey = (String, Vec<(String, Vec<u8>)>);
+
+pub struct Dedup {
+    link: bool,
+    dry_run: bool,
+}
+
+impl Dedup {
+    /// Constructor. Duplicates are only reported, unless they should be linked.
+    pub fn new(link: bool, dry_run: bool) -> Self {
+        Dedup { link, dry_run }
+    }
+
+    /// Find groups of the identical files. The first file of a group is the one to stay.
+    pub fn find(&self, kept: &[PathBuf]) -> Result<Vec<Vec<PathBuf>>, Error> {
+        let mut inodes: HashSet<(u64, u64)> = HashSet::default();
+        let mut by_key: HashMap<FileKey, Vec<PathBuf>> = HashMap::default();
+        for p in kept {
+            let m = match fs::symlink_metadata(prefix::host_nofollow(p)) {
+                Ok(m) if m.is_file() && m.len() > 0 => m,
+                _ => continue,
+            };
+            // Hardlinks are already the same file
+            if inodes.insert((m.dev(), m.ino())) {
+                by_key.entry((m.len(), m.dev(), m.mode(), m.uid(), m.gid())).or_default().push(p.to_owned());
+            }
+        }
+
+        let mut out: Vec<Vec<PathBuf>> = vec![];
+        for (_, mut files) in by_key.into_iter().filter(|(_, f)| f.len() > 1) {
+            files.sort();
+            let mut by_digest: HashMap<ContentKey, Vec<PathBuf>> = HashMap::default();
+            for p in files {
+                let hp = prefix::host_nofollow(&p);
+                let (_, sha256) = digest::file_digests(&hp)?;
+                by_digest.entry((sha256, xattrs::get_all(&hp))).or_default().push(p);
+            }
+            out.extend(by_digest.into_values().filter(|g| g.len() > 1));
+        }
+        out.sort();
+
+        Ok(out)
+    }
+
+    /// Replace the file with a hardlink to the original one
+    fn link(original: &Path, dup: &Path) -> Result<(), Error> {
+        let (ho, hd) = (prefix::host_nofollow(original), prefix::host_nofollow(dup));
+        let tmp = hd.with_file_name(format!(".{}.mezzotint", hd.file_name().unwrap_or_default().to_str().unwrap_or_default()));
+        fs::hard_link(&ho, &tmp)?;
+        fs::rename(&tmp, &hd).map_err(|err| {
+            let _ = fs::remove_file(&tmp);
+            err
+        })
+    }
+
+    /// Link or report the duplicates. Returns number of the duplicates and the reclaimed bytes.
+    pub fn run(&self, kept: &[PathBuf]) -> Result<(usize, u64), Error> {
+        let (mut files, mut reclaimed) = (0, 0);
+        for group in self.find(kept)? {
+            let size = fs::symlink_metadata(prefix::host_nofollow(&group[0]))?.len();
+            for dup in &group[1..] {
+                if !self.link || self.dry_run {
+                    log::info!("{} is the same as {}", dup.to_str().unwrap(), group[0].to_str().unwrap());
+                } else if let Err(err) = Self::link(&group[0], dup) {
+                    log::warn!("Unable to hardlink {} to {}: {}", dup.to_str().unwrap(), group[0].to_str().unwrap(), err);
+                    continue;
+                } else {
+                    log::debug!("Hardlinked {} to {}", dup.to_str().unwrap(), group[0].to_str().unwrap());
+                }
+                files += 1;
+                reclaimed += size;
+            }
+        }
+
+        Ok((files, reclaimed))
+    }
+}
@@ -0,0 +1,136 @@
+/*
+Difference of two root filesystems: files present in one of them,
+but not in the other, e.g. results of two profile revisions
+or of two base image versions.
+ */
+
+use crate::scanner::{debftrace::DebPkgFileTrace, traceitf::PkgFileTrace};
+use bytesize::ByteSize;
+use colored::Colorize;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Pseudo-filesystems, those are not a part of the image
+const SKIPPED_DIRS: &[&str] = &["/proc", "/sys", "/dev"];
+
+#[derive(Serialize, Debug)]
+struct DiffFile {
+    path: PathBuf,
+    size: u64,
+    package: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RootDiff {
+    root_a: PathBuf,
+    root_b: PathBuf,
+    only_a: Vec<DiffFile>,
+    only_b: Vec<DiffFile>,
+}
+
+impl RootDiff {
+    pub fn new(root_a: &Path, root_b: &Path) -> Result<Self, Error> {
+        let a = Self::walk(root_a)?;
+        let b = Self::walk(root_b)?;
+
+        Ok(RootDiff {
+            root_a: root_a.to_owned(),
+            root_b: root_b.to_owned(),
+            only_a: Self::only(root_a, &a, &b),
+            only_b: Self::only(root_b, &b, &a),
+        })
+    }
+
+    /// Get all files and symlinks of the root as paths inside it with their sizes
+    fn walk(root: &Path) -> Result<BTreeMap<PathBuf, u64>, Error> {
+        if !root.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Root filesystem {} is not a directory", root.to_str().unwrap()),
+            ));
+        }
+
+        let mut out: BTreeMap<PathBuf, u64> = BTreeMap::default();
+        let mut it = walkdir::WalkDir::new(root).follow_links(false).into_iter();
+        while let Some(e) = it.next() {
+            let e = match e {
+                Ok(e) => e,
+                Err(err) => {
+                    log::warn!("Unable to read {}", err);
+                    continue;
+                }
+            };
+
+            let p = Path::new("/").join(e.path().strip_prefix(root).unwrap());
+            if e.file_type().is_dir() {
+                if SKIPPED_DIRS.iter().any(|d| p == Path::new(d)) {
+                    it.skip_current_dir();
+                }
+                continue;
+            }
+            out.insert(p, e.metadata().map(|m| m.len()).unwrap_or(0));
+        }
+
+        Ok(out)
+    }
+
+    /// Get files of the root, those are not in the other root
+    fn only(root: &Path, this: &BTreeMap<PathBuf, u64>, other: &BTreeMap<PathBuf, u64>) -> Vec<DiffFile> {
+        let mut pt = DebPkgFileTrace::at(root);
+        this.iter()
+            .filter(|(p, _)| !other.contains_key(*p))
+            .map(|(p, size)| DiffFile { path: p.to_owned(), size: *size, package: pt.trace(p.to_owned()) })
+            .collect()
+    }
+
+    /// Check if both roots have the same files
+    pub fn is_empty(&self) -> bool {
+        self.only_a.is_empty() && self.only_b.is_empty()
+    }
+
+    /// Get the difference as JSON
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Print files of one side
+    fn format_side(root: &Path, sign: &str, files: &[DiffFile]) {
+        println!("\nOnly in {}", root.to_str().unwrap().bright_blue().bold());
+        println!("{}", "──┬──┄┄╌╌ ╌  ╌".blue());
+        let width = files.iter().map(|f| f.path.to_str().unwrap().chars().count()).max().unwrap_or(0);
+        for (i, f) in files.iter().enumerate() {
+            let leaf = if i == files.len() - 1 { "  ╰─" } else { "  ├─" };
+            let line = format!("{} {:<width$}", sign, f.path.to_str().unwrap());
+            println!(
+                "{} {} {} {}",
+                leaf.blue(),
+                if sign == "+" { line.bright_green() } else { line.bright_red() },
+                format!("{:>10}", ByteSize::b(f.size).to_string()).bright_yellow(),
+                f.package.as_deref().unwrap_or("").dimmed()
+            );
+        }
+        println!(
+            "{}{}{}{}",
+            "Files: ".blue(),
+            files.len().to_string().bright_blue(),
+            ", Size: ".blue(),
+            ByteSize::b(files.iter().map(|f| f.size).sum()).to_string().bright_blue()
+        );
+    }
+
+    /// Print the difference
+    pub fn format(&self) {
+        if self.is_empty() {
+            println!("Both roots have the same files");
+            return;
+        }
+
+        Self::format_side(&self.root_a, "-", &self.only_a);
+        Self::format_side(&self.root_b, "+", &self.only_b);
+        println!();
+    }
+}
@@ -0,0 +1,34 @@
+/*
+Message digests
+ */
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{Error, Read},
+    path::Path,
+};
+
+/// Get SHA-256 hex digest of the data
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Get SHA-1 and SHA-256 hex digests of the file, reading it once.
+/// SHA-1 is not for security, but some formats (e.g. SPDX) require it.
+pub fn file_digests(p: &Path) -> Result<(String, String), Error> {
+    let mut f = File::open(p)?;
+    let (mut sha1, mut sha256) = (Sha1::new(), Sha256::new());
+    let mut buf = vec![0u8; 0x10000];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sha1.update(&buf[..n]);
+        sha256.update(&buf[..n]);
+    }
+
+    Ok((format!("{:x}", sha1.finalize()), format!("{:x}", sha256.finalize())))
+}
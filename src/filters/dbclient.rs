@@ -0,0 +1,75 @@
+/*
+Runtime data of database client libraries.
+
+Client libraries load charsets and authentication plugins at runtime,
+while images with only a client do not need the server-side data.
+ */
+
+use crate::{filters::intf::DataFilter, glob};
+use std::{collections::HashSet, path::PathBuf};
+
+/// Database client preset
+struct DbClient {
+    name: &'static str,
+    libs: &'static [&'static str],        // Client libraries by the name prefix
+    servers: &'static [&'static str],     // Server binaries
+    client_data: &'static [&'static str], // Data, needed by the client libraries
+    server_data: &'static [&'static str], // Data, needed only by the server
+}
+
+const DB_CLIENTS: &[DbClient] = &[
+    DbClient {
+        name: "PostgreSQL",
+        libs: &["libpq.so"],
+        servers: &["postgres"],
+        client_data: &[],
+        server_data: &["/usr/share/postgresql/*", "/usr/lib/postgresql/*/lib"],
+    },
+    DbClient {
+        name: "MySQL/MariaDB",
+        libs: &["libmysqlclient.so", "libmariadb.so"],
+        servers: &["mysqld", "mariadbd"],
+        client_data: &["/usr/share/mysql/charsets", "/usr/share/mariadb/charsets", "/usr/lib/*/libmariadb*/plugin"],
+        server_data: &["/usr/share/mysql", "/usr/share/mariadb", "/usr/lib/mysql/plugin", "/usr/lib/*/mariadb*/plugin"],
+    },
+];
+
+pub struct DbClientDataFilter {}
+
+impl DbClientDataFilter {
+    pub fn new() -> Self {
+        DbClientDataFilter {}
+    }
+}
+
+impl DataFilter for DbClientDataFilter {
+    /// Keep runtime data of the kept client libraries and prune server-side data,
+    /// if the server itself is not kept
+    fn filter(&self, data: &mut HashSet<PathBuf>) {
+        for db in DB_CLIENTS {
+            let fnames = data
+                .iter()
+                .filter_map(|p| p.file_name().and_then(|f| f.to_str()).map(|f| f.to_string()))
+                .collect::<HashSet<String>>();
+
+            if !fnames.iter().any(|f| db.libs.iter().any(|l| f.starts_with(l))) {
+                continue;
+            }
+
+            log::debug!("Keeping {} client data", db.name);
+            for c in db.client_data {
+                data.extend(glob::expand_tree(c));
+            }
+
+            if db.servers.iter().any(|s| fnames.contains(*s)) {
+                continue;
+            }
+
+            log::debug!("Removing {} server data", db.name);
+            data.retain(|p| {
+                db.client_data.iter().any(|c| glob::matches_tree(c, p))
+                    || !db.server_data.iter().any(|s| glob::matches_tree(s, p))
+            });
+        }
+    }
+}
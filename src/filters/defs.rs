@@ -6,6 +6,9 @@ Definitions, constants
 pub const DOC_STUB_FILES: &[&str] =
     &["AUTHORS", "COPYING", "LICENSE", "DEBUG", "DISTRIB", "DOC", "HISTORY", "README", "TERMS", "TODO"];
 
+/// Copyright and license files, matched by the name prefix
+pub const LICENSE_FILES: &[&str] = &["COPYRIGHT", "COPYING", "LICENSE", "LICENCE", "NOTICE"];
+
 /// Docfiles
 pub const DOC_F_EXT: &[&str] = &[".txt", ".doc", ".rtf", ".md", ".rtx", ".tex", ".xml", ".htm", ".html", ".log"];
 
@@ -33,5 +36,41 @@ pub const D_MANPAGES: &str = "/usr/share/man";
 /// Localisation
 pub const D_L10N: &str = "/usr/share/locale";
 
+/// Localisation trees, containing per-language subdirectories
+pub const D_L10N_DIRS: &[&str] = &["/usr/share/locale", "/usr/share/locale-langpack"];
+
 /// Intetrnaetiomns... i18n
 pub const D_I18N: &str = "/usr/share/i18n";
+
+/// Timezone data
+pub const D_ZONEINFO: &str = "/usr/share/zoneinfo";
+
+/// Paths, those are never removed, unless safety is off: dynamic loaders, account and
+/// name resolution databases and mount points of the container
+pub const PROTECTED_PATHS: &[&str] = &[
+    "**/ld-linux*.so*",
+    "**/ld-musl-*.so*",
+    "**/ld64.so*",
+    "/etc/passwd",
+    "/etc/group",
+    "/etc/shadow",
+    "/etc/gshadow",
+    "/etc/nsswitch.conf",
+    "/etc/resolv.conf",
+    "/etc/hosts",
+    "/etc/hostname",
+    "/etc/ld.so.cache",
+    "/etc/ld.so.conf",
+    "/etc/os-release",
+    "/usr/lib/os-release",
+    "/proc",
+    "/sys",
+    "/dev",
+];
+
+/// Mount points of virtual and runtime filesystems, those are never walked
+pub const VIRTUAL_FS: &[&str] = &["/proc", "/sys", "/dev", "/run"];
+
+/// Timezone data files, needed for the TZ parsing
+pub const ZONEINFO_SUPPORT: &[&str] =
+    &["tzdata.zi", "zone.tab", "zone1970.tab", "iso3166.tab", "leapseconds", "leap-seconds.list", "posixrules", "Factory"];
@@ -1,15 +1,14 @@
-use crate::{filters::intf::DataFilter, profile::Profile};
+use crate::{filters::intf::DataFilter, prefix, profile::Profile};
 use std::{collections::HashSet, path::PathBuf};
 
 /// Filter-out paths
 pub struct PathsDataFilter {
-    data: Vec<PathBuf>,
     do_filter: bool,
 }
 
 impl PathsDataFilter {
-    pub fn new(data: Vec<PathBuf>, profile: Profile) -> Self {
-        PathsDataFilter { data, do_filter: profile.filter_dirs() }
+    pub fn new(profile: Profile) -> Self {
+        PathsDataFilter { do_filter: profile.filter_dirs() }
     }
 }
 
@@ -20,10 +19,6 @@ impl DataFilter for PathsDataFilter {
             return;
         }
 
-        let mut out = self.data.clone().into_iter().filter(|p| !p.is_dir()).collect::<Vec<PathBuf>>();
-        out.sort();
-
-        data.clear();
-        data.extend(out);
+        data.retain(|p| !prefix::is_dir(p));
     }
 }
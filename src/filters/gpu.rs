@@ -0,0 +1,197 @@
+/*
+GPU and compute userspace stacks: CUDA, ROCm, OpenCL and Vulkan.
+
+These are multi-hundred-MB trees, which are needed only if targets link them.
+Drivers are not linked directly, but loaded via ICD files, so they are correlated.
+ */
+
+use crate::{
+    filters::intf::DataFilter,
+    prefix,
+    profile::Profile,
+    rootfs::RootFS,
+    scanner::{binlib::ElfScanner, general::Scanner},
+};
+use serde_yaml::Value;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// ICD file and driver libraries it refers to
+type Icd = (PathBuf, Vec<PathBuf>);
+
+/// GPU stack
+struct GpuStack {
+    name: &'static str,
+    libs: &'static [&'static str],    // Libraries, those indicate the stack is used
+    drivers: &'static [&'static str], // Driver libraries by the name prefix
+    dirs: &'static [&'static str],    // Trees of the stack
+    icd: &'static [&'static str],     // Directories of ICD files
+}
+
+const GPU_STACKS: &[GpuStack] = &[
+    GpuStack {
+        name: "CUDA",
+        libs: &["libcuda.so", "libcudart.so", "libnvidia-ml.so", "libcublas", "libcudnn"],
+        drivers: &["libcuda", "libnvidia-", "libnvcuvid", "libnvoptix"],
+        dirs: &["/usr/local/cuda", "/opt/nvidia"],
+        icd: &[],
+    },
+    GpuStack {
+        name: "ROCm",
+        libs: &["libamdhip64.so", "libhsa-runtime64.so", "librocm"],
+        drivers: &["libamdhip64", "libhsa-", "librocm", "libhip"],
+        dirs: &["/opt/rocm"],
+        icd: &[],
+    },
+    GpuStack { name: "OpenCL", libs: &["libOpenCL.so"], drivers: &[], dirs: &["/etc/OpenCL"], icd: &["/etc/OpenCL/vendors"] },
+    GpuStack {
+        name: "Vulkan",
+        libs: &["libvulkan.so"],
+        drivers: &[],
+        dirs: &["/usr/share/vulkan", "/etc/vulkan"],
+        icd: &[
+            "/usr/share/vulkan/icd.d",
+            "/usr/share/vulkan/implicit_layer.d",
+            "/usr/share/vulkan/explicit_layer.d",
+            "/etc/vulkan/icd.d",
+            "/etc/vulkan/implicit_layer.d",
+            "/etc/vulkan/explicit_layer.d",
+        ],
+    },
+];
+
+impl GpuStack {
+    /// Returns true if the path is a library, indicating that the stack is used
+    fn is_linked(&self, p: &Path) -> bool {
+        let fname = p.file_name().unwrap_or_default().to_str().unwrap_or_default();
+        self.libs.iter().any(|l| fname.starts_with(l))
+    }
+
+    /// Get ICD files of the stack and driver libraries they refer to
+    fn get_icds(&self) -> Vec<Icd> {
+        let mut out: Vec<Icd> = vec![];
+        for d in self.icd {
+            let entries = match prefix::read_dir(d) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for e in entries.filter_map(|e| e.ok()) {
+                let icd = Path::new(d).join(e.file_name());
+                let libs = GpuDataFilter::get_icd_libs(&icd).iter().filter_map(|l| GpuDataFilter::find_lib(&icd, l)).collect();
+                out.push((icd, libs));
+            }
+        }
+
+        out
+    }
+
+    /// Returns true if the path belongs to the stack
+    fn contains(&self, p: &Path, icd_libs: &HashSet<PathBuf>) -> bool {
+        let fname = p.file_name().unwrap_or_default().to_str().unwrap_or_default();
+        self.dirs.iter().any(|d| p.to_str().unwrap_or_default().starts_with(d))
+            || self.drivers.iter().any(|d| fname.starts_with(d))
+            || icd_libs.contains(p)
+    }
+}
+
+pub struct GpuDataFilter {
+    keep_all: bool,
+}
+
+impl GpuDataFilter {
+    pub fn new(profile: Profile) -> Self {
+        GpuDataFilter { keep_all: profile.keep_gpu() }
+    }
+
+    /// Get driver libraries from the ICD file. OpenCL ICD is a plain list of libraries,
+    /// Vulkan ICD and layers are JSON.
+    fn get_icd_libs(icd: &Path) -> Vec<String> {
+        let data = match prefix::read_to_string(icd) {
+            Ok(data) => data,
+            Err(_) => return vec![],
+        };
+
+        if icd.extension().unwrap_or_default() != "json" {
+            return data.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty() && !l.starts_with('#')).collect();
+        }
+
+        let v = match serde_yaml::from_str::<Value>(&data) {
+            Ok(v) => v,
+            Err(err) => {
+                log::debug!("Unable to parse ICD file {}: {}", icd.to_str().unwrap(), err);
+                return vec![];
+            }
+        };
+
+        let mut out: Vec<String> = vec![];
+        let mut entries: Vec<&Value> = vec![];
+        entries.extend(v.get("ICD"));
+        entries.extend(v.get("layer"));
+        if let Some(layers) = v.get("layers").and_then(|l| l.as_sequence()) {
+            entries.extend(layers.iter());
+        }
+
+        for e in entries {
+            if let Some(lp) = e.get("library_path").and_then(|l| l.as_str()) {
+                out.push(lp.to_string());
+            }
+        }
+
+        out
+    }
+
+    /// Find a driver library, referred by the ICD file.
+    /// It is either a path, relative to the ICD file, or a name in the standard library directories.
+    fn find_lib(icd: &Path, lib: &str) -> Option<PathBuf> {
+        if lib.contains('/') {
+            let p = RootFS::normalise(&icd.parent().unwrap_or(Path::new("/")).join(lib));
+            return if prefix::exists(&p) { Some(p) } else { None };
+        }
+
+        let mut dirs: Vec<PathBuf> = ["/usr/lib64", "/usr/lib", "/lib64", "/lib"].iter().map(PathBuf::from).collect();
+        if let Ok(entries) = prefix::read_dir("/usr/lib") {
+            // Multiarch, like /usr/lib/x86_64-linux-gnu
+            dirs.extend(entries.filter_map(|e| e.ok()).map(|e| Path::new("/usr/lib").join(e.file_name())).filter(|p| {
+                prefix::is_dir(p) && p.file_name().unwrap_or_default().to_str().unwrap_or_default().contains("-linux-")
+            }));
+        }
+
+        dirs.iter().map(|d| d.join(lib)).find(|p| prefix::exists(p))
+    }
+}
+
+impl DataFilter for GpuDataFilter {
+    /// Keep GPU stacks only if they are linked by targets or explicitly requested,
+    /// together with their ICD files and drivers.
+    fn filter(&self, data: &mut HashSet<PathBuf>) {
+        let mut used: Vec<Icd> = vec![];
+
+        // Drivers might be shared between stacks, so unused ones are removed first
+        for st in GPU_STACKS {
+            let icds = st.get_icds();
+            if self.keep_all || data.iter().any(|p| st.is_linked(p)) {
+                log::debug!("Keeping {} stack", st.name);
+                used.extend(icds);
+                continue;
+            }
+
+            let icd_libs = icds.into_iter().flat_map(|(_, libs)| libs).collect::<HashSet<PathBuf>>();
+            let before = data.len();
+            data.retain(|p| !st.contains(p, &icd_libs));
+            if data.len() < before {
+                log::debug!("Removing unused {} stack ({} files)", st.name, before - data.len());
+            }
+        }
+
+        for (icd, libs) in used {
+            data.insert(icd);
+            for lib in libs {
+                data.extend(ElfScanner::new().scan(lib.to_owned()));
+                data.insert(lib);
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+/*
+ICU data files.
+
+ICU loads its data (icudtXXl.dat or an unpacked icudtXXl tree) at runtime,
+so it is not found as a dependency. The name depends on the ICU version.
+ */
+
+use crate::{filters::intf::DataFilter, prefix};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Directories, where ICU data is looked up
+const ICU_DIRS: &[&str] = &["/usr/share/icu", "/usr/local/share/icu", "/usr/lib/icu", "/usr/lib64/icu", "/usr/local/lib/icu"];
+
+pub struct IcuDataFilter {}
+
+impl IcuDataFilter {
+    pub fn new() -> Self {
+        IcuDataFilter {}
+    }
+
+    /// Get ICU major version from the library name, like "libicuuc.so.72.1"
+    fn get_version(p: &Path) -> Option<String> {
+        let fname = p.file_name()?.to_str()?;
+        if !fname.starts_with("libicu") {
+            return None;
+        }
+
+        let ver = fname.split_once(".so.")?.1.split('.').next()?;
+        if ver.is_empty() || !ver.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(ver.to_string())
+    }
+
+    /// Get directories with ICU data, including multiarch ones, like /usr/lib/x86_64-linux-gnu/icu
+    fn get_dirs() -> Vec<PathBuf> {
+        let mut dirs = ICU_DIRS.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+        if let Ok(entries) = prefix::read_dir("/usr/lib") {
+            dirs.extend(
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| Path::new("/usr/lib").join(e.file_name()))
+                    .filter(|p| p.file_name().unwrap_or_default().to_str().unwrap_or_default().contains("-linux-"))
+                    .map(|p| p.join("icu")),
+            );
+        }
+
+        dirs.into_iter().filter(|d| prefix::is_dir(d)).collect()
+    }
+
+    /// Returns true if the file or directory is ICU data of one of the versions,
+    /// like "icudt72l.dat", "icudt72b.dat" or "icudt72l/"
+    fn is_data(p: &Path, versions: &HashSet<String>) -> bool {
+        let fname = p.file_name().unwrap_or_default().to_str().unwrap_or_default();
+        versions.iter().any(|v| {
+            let name = format!("icudt{}", v);
+            fname.strip_prefix(&name).map(|s| matches!(s, "l" | "b" | "l.dat" | "b.dat")).unwrap_or(false)
+        })
+    }
+}
+
+impl DataFilter for IcuDataFilter {
+    /// Keep ICU data, matching versions of the kept ICU libraries
+    fn filter(&self, data: &mut HashSet<PathBuf>) {
+        let versions = data.iter().filter_map(|p| Self::get_version(p)).collect::<HashSet<String>>();
+        if versions.is_empty() {
+            return;
+        }
+
+        let mut vs = versions.iter().cloned().collect::<Vec<String>>();
+        vs.sort();
+        log::debug!("Keeping ICU data of version {}", vs.join(", "));
+
+        for d in Self::get_dirs() {
+            let hd = prefix::host(&d);
+            for e in walkdir::WalkDir::new(&hd).follow_links(false).into_iter().filter_map(|e| e.ok()) {
+                let p = d.join(e.path().strip_prefix(&hd).unwrap_or(e.path()));
+                if p.ancestors().any(|a| Self::is_data(a, &versions)) && !e.file_type().is_dir() {
+                    data.insert(p);
+                }
+            }
+        }
+    }
+}
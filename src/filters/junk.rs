@@ -0,0 +1,107 @@
+/*
+Junk file extensions.
+
+Built-in lists are taken from the definitions,
+and can be extended or replaced by the profile.
+ */
+
+use super::defs;
+
+/// Kinds of the junk extensions
+pub const JUNK_KINDS: &[&str] = &["doc", "headers", "archives", "images"];
+
+#[derive(Clone, Debug)]
+pub struct JunkExtensions {
+    doc: Vec<String>,
+    headers: Vec<String>,
+    archives: Vec<String>,
+    images: Vec<String>,
+    never: Vec<String>,
+}
+
+impl JunkExtensions {
+    pub fn new() -> Self {
+        let v = |e: &[&str]| e.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+        JunkExtensions {
+            doc: defs::DOC_F_EXT.iter().chain(defs::DOC_FP_EXT).map(|e| e.to_string()).collect(),
+            headers: v(defs::SRC_FH_EXT),
+            archives: v(defs::ARC_F_EXT),
+            images: v(defs::IMG_F_EXT),
+            never: vec![],
+        }
+    }
+
+    /// Extend extensions of a kind, or replace them completely
+    pub fn extend(&mut self, kind: &str, ext: Vec<String>, replace: bool) -> &mut Self {
+        let l = match kind {
+            "doc" => &mut self.doc,
+            "headers" => &mut self.headers,
+            "archives" => &mut self.archives,
+            "images" => &mut self.images,
+            _ => {
+                log::warn!("Unknown kind of junk extensions: {}", kind);
+                return self;
+            }
+        };
+
+        if replace {
+            l.clear();
+        }
+        l.extend(ext.into_iter().map(|e| if e.starts_with('.') { e } else { format!(".{}", e) }));
+
+        self
+    }
+
+    /// Add extensions, those are never considered as junk
+    pub fn add_never(&mut self, ext: Vec<String>) -> &mut Self {
+        self.never.extend(ext);
+        self
+    }
+
+    /// Get extensions of a kind
+    pub fn get(&self, kind: &str) -> &[String] {
+        match kind {
+            "doc" => &self.doc,
+            "headers" => &self.headers,
+            "archives" => &self.archives,
+            "images" => &self.images,
+            _ => &[],
+        }
+    }
+
+    /// Get extensions, those are never considered as junk
+    pub fn get_never(&self) -> &[String] {
+        &self.never
+    }
+
+    /// Returns true if the file name ends with any of the extensions,
+    /// unless it is never a junk.
+    fn matches(&self, ext: &[String], fname: &str) -> bool {
+        !self.never.iter().any(|e| fname.ends_with(e.as_str())) && ext.iter().any(|e| fname.ends_with(e.as_str()))
+    }
+
+    /// Is a documentation file
+    pub fn is_doc(&self, fname: &str) -> bool {
+        self.matches(&self.doc, fname)
+    }
+
+    /// Is a source header
+    pub fn is_header(&self, fname: &str) -> bool {
+        self.matches(&self.headers, fname)
+    }
+
+    /// Is an archive
+    pub fn is_archive(&self, fname: &str) -> bool {
+        self.matches(&self.archives, fname)
+    }
+
+    /// Is an image (picture)
+    pub fn is_image(&self, fname: &str) -> bool {
+        self.matches(&self.images, fname)
+    }
+
+    /// Returns true if the file name is never a junk
+    pub fn is_never(&self, fname: &str) -> bool {
+        self.never.iter().any(|e| fname.ends_with(e.as_str()))
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{filters::intf::DataFilter, profile::Profile};
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+};
+
+use super::defs;
+
+/// Filter out localisation data of all languages,
+/// except those which are explicitly requested to be kept.
+pub struct LocalesDataFilter {
+    locales: Vec<String>,
+}
+
+impl LocalesDataFilter {
+    pub fn new(profile: Profile) -> Self {
+        LocalesDataFilter { locales: profile.get_locales().to_owned() }
+    }
+
+    /// Returns true if the language directory name (e.g. "en_GB", "de@euro", "pt_BR.UTF-8")
+    /// belongs to one of the kept locales.
+    fn is_kept(&self, lang: &str) -> bool {
+        if lang == "C" || lang == "POSIX" {
+            return true;
+        }
+
+        for l in &self.locales {
+            if lang == l
+                || lang.starts_with(&format!("{}_", l))
+                || lang.starts_with(&format!("{}@", l))
+                || lang.starts_with(&format!("{}.", l))
+            {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Get the first path component after the given prefix directory
+    fn lang_after<'a>(p: &'a Path, prefix: &str) -> Option<&'a str> {
+        if let Ok(p) = p.strip_prefix(prefix) {
+            let mut c = p.components();
+            if let (Some(Component::Normal(lang)), Some(_)) = (c.next(), c.next()) {
+                return lang.to_str();
+            }
+        }
+
+        None
+    }
+
+    /// If path is a localisation data of a not kept language
+    fn filter_locale(&self, p: &Path) -> bool {
+        // Locale trees
+        for d in defs::D_L10N_DIRS {
+            if let Some(lang) = Self::lang_after(p, d) {
+                return !self.is_kept(lang);
+            }
+        }
+
+        // Translated manpages, like /usr/share/man/de/man1/...
+        if let Some(lang) = Self::lang_after(p, defs::D_MANPAGES) {
+            return !lang.starts_with("man") && !self.is_kept(lang);
+        }
+
+        // Gettext catalogs elsewhere, like /usr/lib/something/locale/de/LC_MESSAGES/foo.mo
+        if p.to_str().unwrap_or_default().ends_with(".mo") {
+            let c = p.components().filter_map(|c| c.as_os_str().to_str()).collect::<Vec<&str>>();
+            if let Some(i) = c.iter().position(|c| *c == "LC_MESSAGES") {
+                if i > 0 {
+                    return !self.is_kept(c[i - 1]);
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl DataFilter for LocalesDataFilter {
+    /// Filter out localisation data of all languages, except the kept ones
+    fn filter(&self, data: &mut HashSet<PathBuf>) {
+        if self.locales.is_empty() {
+            return;
+        }
+
+        log::debug!("Keeping only locales: {}", self.locales.join(", "));
+
+        data.retain(|p| !self.filter_locale(p));
+    }
+}
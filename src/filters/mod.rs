@@ -1,5 +1,11 @@
+pub mod dbclient;
 pub mod defs;
 pub mod dirs;
+pub mod gpu;
+pub mod icu;
 pub mod intf;
+pub mod junk;
+pub mod locales;
 pub mod resources;
 pub mod texts;
+pub mod zoneinfo;
@@ -1,20 +1,26 @@
-use super::{defs, intf::DataFilter};
+use super::{defs, intf::DataFilter, junk::JunkExtensions};
 use crate::{procdata::Autodeps, profile::Profile};
+use rayon::prelude::*;
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
 };
 
 pub struct ResourcesDataFilter {
-    data: Vec<PathBuf>,
     autodeps: Autodeps,
     remove_archives: bool,
     remove_images: bool, // not blobs (qcow2, raw etc) but images, like JPEG, PNG, XPM...
+    junk: JunkExtensions,
 }
 
 impl ResourcesDataFilter {
-    pub fn new(data: Vec<PathBuf>, profile: Profile, autodeps: Autodeps) -> Self {
-        let mut rdf = ResourcesDataFilter { data, autodeps, remove_archives: false, remove_images: false };
+    pub fn new(profile: Profile, autodeps: Autodeps) -> Self {
+        let mut rdf = ResourcesDataFilter {
+            autodeps,
+            remove_archives: false,
+            remove_images: false,
+            junk: profile.get_junk_extensions().to_owned(),
+        };
         if profile.filter_arc() {
             log::debug!("Removing archives");
             rdf.remove_archives = true;
@@ -34,15 +40,7 @@ impl ResourcesDataFilter {
             return false;
         }
 
-        let p = p.to_str().unwrap();
-
-        for s in defs::ARC_F_EXT {
-            if p.ends_with(s) {
-                return true;
-            }
-        }
-
-        false
+        self.junk.is_archive(p.to_str().unwrap())
     }
 
     /// Is an image (picture)
@@ -51,24 +49,17 @@ impl ResourcesDataFilter {
             return false;
         }
 
-        let p = p.to_str().unwrap();
-        for s in defs::IMG_F_EXT {
-            if p.ends_with(s) {
-                return true;
-            }
-        }
-
-        false
+        self.junk.is_image(p.to_str().unwrap())
     }
 
     /// Detects if a file is still a potential junk (but unsure)
-    pub fn is_potential_junk(fname: &str) -> bool {
-        for ext in
-            defs::DOC_F_EXT.iter().chain(defs::ARC_F_EXT.iter()).chain(defs::SRC_FH_EXT.iter()).chain(defs::DOC_FP_EXT.iter())
-        {
-            if fname.ends_with(ext) {
-                return true;
-            }
+    pub fn is_potential_junk(fname: &str, junk: &JunkExtensions) -> bool {
+        if junk.is_never(fname) {
+            return false;
+        }
+
+        if junk.is_doc(fname) || junk.is_archive(fname) || junk.is_header(fname) {
+            return true;
         }
 
         for sf in defs::DOC_STUB_FILES {
@@ -92,19 +83,19 @@ impl DataFilter for ResourcesDataFilter {
             log::info!("Automatically removing potential junk resources");
         }
 
-        let mut out: Vec<PathBuf> = Vec::default();
-        for p in &self.data {
-            if self.filter_archives(p)
-                || self.filter_images(p)
-                || ((self.autodeps == Autodeps::Clean || self.autodeps == Autodeps::Tight)
-                    && ResourcesDataFilter::is_potential_junk(p.file_name().unwrap().to_str().unwrap()))
-            {
-                continue;
-            }
-            out.push(p.to_owned());
+        // Paths are classified in parallel and removed afterwards
+        let junk = data
+            .par_iter()
+            .filter(|p| {
+                self.filter_archives(p)
+                    || self.filter_images(p)
+                    || ((self.autodeps == Autodeps::Clean || self.autodeps == Autodeps::Tight)
+                        && ResourcesDataFilter::is_potential_junk(p.file_name().unwrap().to_str().unwrap(), &self.junk))
+            })
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        for p in junk {
+            data.remove(&p);
         }
-
-        data.clear();
-        data.extend(out);
     }
 }
@@ -1,4 +1,8 @@
-use crate::{filters::intf::DataFilter, profile::Profile};
+use crate::{
+    filters::{intf::DataFilter, junk::JunkExtensions},
+    profile::Profile,
+};
+use rayon::prelude::*;
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
@@ -7,17 +11,24 @@ use std::{
 use super::defs;
 
 pub struct TextDataFilter {
-    data: HashSet<PathBuf>,
     remove_manpages: bool,
     remove_doc_data: bool,
     remove_l10n: bool,
     remove_i18n: bool,
+    keep_licenses: bool,
+    junk: JunkExtensions,
 }
 
 impl TextDataFilter {
-    pub fn new(data: HashSet<PathBuf>, profile: Profile) -> Self {
-        let mut tdf =
-            TextDataFilter { remove_doc_data: false, remove_manpages: false, remove_l10n: false, remove_i18n: false, data };
+    pub fn new(profile: Profile) -> Self {
+        let mut tdf = TextDataFilter {
+            remove_doc_data: false,
+            remove_manpages: false,
+            remove_l10n: false,
+            remove_i18n: false,
+            keep_licenses: profile.keep_licenses(),
+            junk: profile.get_junk_extensions().to_owned(),
+        };
         if profile.filter_doc() {
             log::debug!("Removing docs");
             tdf.remove_doc_data = true;
@@ -41,6 +52,11 @@ impl TextDataFilter {
         tdf
     }
 
+    /// If path is any of the text data
+    fn is_text(&self, p: &Path) -> bool {
+        self.filter_manpage(p) || self.filter_docs(p) || self.filter_l10n(p) || self.filter_i18n(p)
+    }
+
     /// If path is a manpage
     fn filter_manpage(&self, p: &Path) -> bool {
         let mut _p = p.to_path_buf();
@@ -51,9 +67,15 @@ impl TextDataFilter {
             && _p.file_name().unwrap().to_str().unwrap().starts_with("man")
     }
 
+    /// If path is a copyright or a license file, like "copyright", "LICENSE.txt" or "COPYING.LIB"
+    fn is_license(p: &Path) -> bool {
+        let fname = p.file_name().unwrap_or_default().to_str().unwrap_or_default().to_uppercase();
+        defs::LICENSE_FILES.iter().any(|l| fname.starts_with(l))
+    }
+
     /// If path is a doc
     fn filter_docs(&self, p: &Path) -> bool {
-        if !self.remove_doc_data {
+        if !self.remove_doc_data || self.junk.is_never(p.to_str().unwrap()) || (self.keep_licenses && Self::is_license(p)) {
             return false;
         }
 
@@ -77,13 +99,7 @@ impl TextDataFilter {
             }
         }
 
-        for c in defs::DOC_F_EXT.iter().chain(defs::DOC_FP_EXT).chain(defs::SRC_FH_EXT) {
-            if p.ends_with(c) {
-                return true;
-            }
-        }
-
-        false
+        self.junk.is_doc(p) || self.junk.is_header(p)
     }
 
     /// Is localisation
@@ -100,17 +116,10 @@ impl TextDataFilter {
 impl DataFilter for TextDataFilter {
     /// Filter out text data: manpages, documentation, licensing, localisation etc.
     fn filter(&self, data: &mut HashSet<PathBuf>) {
-        let mut out: Vec<PathBuf> = vec![];
-
-        for p in &self.data {
-            if self.filter_manpage(p) || self.filter_docs(p) || self.filter_l10n(p) || self.filter_i18n(p) {
-                continue;
-            }
-
-            out.push(p.to_owned());
+        // Paths are classified in parallel and removed afterwards
+        let text = data.par_iter().filter(|p| self.is_text(p)).cloned().collect::<Vec<PathBuf>>();
+        for p in text {
+            data.remove(&p);
         }
-
-        data.clear();
-        data.extend(out);
     }
 }
@@ -0,0 +1,56 @@
+use crate::{filters::intf::DataFilter, prefix, profile::Profile};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use super::defs;
+
+/// Filter out timezone data, except explicitly requested zones
+pub struct TimezoneDataFilter {
+    zones: Vec<String>,
+}
+
+impl TimezoneDataFilter {
+    pub fn new(profile: Profile) -> Self {
+        let mut zones = profile.get_timezones().to_owned();
+
+        // Zone of the system itself is always kept
+        if !zones.is_empty() {
+            if let Ok(lt) = prefix::read_link("/etc/localtime") {
+                if let Some(z) = lt.to_str().unwrap_or_default().split_once("zoneinfo/") {
+                    log::debug!("Keeping system timezone {}", z.1);
+                    zones.push(z.1.to_string());
+                }
+            }
+        }
+
+        TimezoneDataFilter { zones }
+    }
+
+    /// If path is a timezone data, not needed
+    fn filter_zone(&self, p: &Path) -> bool {
+        let z = match p.strip_prefix(defs::D_ZONEINFO) {
+            Ok(z) => z.to_str().unwrap_or_default(),
+            Err(_) => return false,
+        };
+
+        // Zone variants with or without leap seconds
+        let z = z.strip_prefix("posix/").or_else(|| z.strip_prefix("right/")).unwrap_or(z);
+
+        !(defs::ZONEINFO_SUPPORT.contains(&z) || self.zones.iter().any(|kz| kz == z))
+    }
+}
+
+impl DataFilter for TimezoneDataFilter {
+    /// Filter out all timezones, except the kept ones
+    fn filter(&self, data: &mut HashSet<PathBuf>) {
+        if self.zones.is_empty() {
+            return;
+        }
+
+        log::debug!("Keeping only timezones: {}", self.zones.join(", "));
+
+        data.retain(|p| !self.filter_zone(p));
+    }
+}
@@ -0,0 +1,532 @@
+/*
+Read-only FUSE view of the rootfs, as it would look after the removal plan is applied.
+
+Removed paths are hidden, everything else is served from the rootfs as is,
+so nothing is changed there. Mounting needs either root or the setuid
+fusermount helper, so the preview works without privileges as well.
+Only the read-only part of the protocol is implemented.
+ */
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    fs::{self, File, Metadata},
+    io::{Error, ErrorKind, Read, Write},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileExt, MetadataExt, OpenOptionsExt},
+        io::{AsRawFd, FromRawFd, OwnedFd},
+    },
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// Operations of the FUSE protocol
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_READLINK: u32 = 5;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+/// Version of the protocol
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+
+/// Content of the opened files stays in the page cache, as the rootfs is not changed meanwhile
+const FOPEN_KEEP_CACHE: u32 = 2;
+
+/// Size of the request header
+const IN_HEADER: usize = 40;
+
+/// Largest data of a request
+const MAX_WRITE: u32 = 128 * 1024;
+
+/// Validity of the entries and attributes in the kernel cache, seconds
+const TTL: u64 = 1;
+
+/// Mount options, those are common for the privileged mount and fusermount
+const MOUNT_OPTIONS: &str = "default_permissions";
+
+/// Append native-endian integers to the reply
+trait Put {
+    fn u32(&mut self, v: u32) -> &mut Self;
+    fn u64(&mut self, v: u64) -> &mut Self;
+}
+
+impl Put for Vec<u8> {
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.extend(v.to_ne_bytes());
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.extend(v.to_ne_bytes());
+        self
+    }
+}
+
+/// Get native-endian integer of the request at the offset
+fn get_u32(b: &[u8], off: usize) -> u32 {
+    b.get(off..off + 4).map(|b| u32::from_ne_bytes(b.try_into().unwrap())).unwrap_or_default()
+}
+
+fn get_u64(b: &[u8], off: usize) -> u64 {
+    b.get(off..off + 8).map(|b| u64::from_ne_bytes(b.try_into().unwrap())).unwrap_or_default()
+}
+
+/// Get errno of the error
+fn errno(err: &Error) -> i32 {
+    err.raw_os_error().unwrap_or(libc::EIO)
+}
+
+pub struct FuseView {
+    dev: File,
+    root: PathBuf,
+    removed: HashSet<PathBuf>,
+    // Paths of the nodes by their ids, starting from 1, which is the root
+    nodes: Vec<PathBuf>,
+    ids: HashMap<PathBuf, u64>,
+    files: HashMap<u64, File>,
+    dirs: HashMap<u64, Vec<(u64, u32, Vec<u8>)>>,
+    next_fh: u64,
+}
+
+impl FuseView {
+    /// Mount the view of the rootfs at the mountpoint, hiding the removed paths.
+    /// It is served by `serve`, until it is unmounted.
+    pub fn mount(root: &Path, removed: &[PathBuf], mountpoint: &Path) -> Result<Self, Error> {
+        let dev = if unsafe { libc::geteuid() } == 0 {
+            match Self::mount_privileged(mountpoint) {
+                // Root of a container might still be not allowed to mount
+                Err(err) if err.raw_os_error() == Some(libc::EPERM) => Self::mount_fusermount(mountpoint)?,
+                r => r?,
+            }
+        } else {
+            Self::mount_fusermount(mountpoint)?
+        };
+
+        Ok(Self::new(dev, root, removed))
+    }
+
+    /// Serve the view over the opened FUSE device
+    fn new(dev: File, root: &Path, removed: &[PathBuf]) -> Self {
+        FuseView {
+            dev,
+            root: root.to_owned(),
+            removed: removed.iter().cloned().collect(),
+            nodes: vec![PathBuf::from("/")],
+            ids: HashMap::from([(PathBuf::from("/"), 1)]),
+            files: HashMap::default(),
+            dirs: HashMap::default(),
+            next_fh: 1,
+        }
+    }
+
+    /// Mount by the kernel directly
+    fn mount_privileged(mountpoint: &Path) -> Result<File, Error> {
+        let dev = fs::OpenOptions::new().read(true).write(true).open("/dev/fuse")?;
+        let opts = format!(
+            "fd={},rootmode=40000,user_id={},group_id={},allow_other,{}",
+            dev.as_raw_fd(),
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() },
+            MOUNT_OPTIONS
+        );
+
+        let to_c = |s: &[u8]| CString::new(s).map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()));
+        let (src, tgt, fstype, opts) =
+            (to_c(b"mezzotint")?, to_c(mountpoint.as_os_str().as_bytes())?, to_c(b"fuse.mezzotint")?, to_c(opts.as_bytes())?);
+        if unsafe {
+            libc::mount(
+                src.as_ptr(),
+                tgt.as_ptr(),
+                fstype.as_ptr(),
+                libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+                opts.as_ptr() as *const libc::c_void,
+            )
+        } != 0
+        {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(dev)
+    }
+
+    /// Mount by the setuid fusermount helper, which passes the opened FUSE device back through a socket
+    fn mount_fusermount(mountpoint: &Path) -> Result<File, Error> {
+        let mut fds = [0; 2];
+        if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        let (ours, theirs) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        unsafe { libc::fcntl(ours.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+
+        let opts = format!("ro,nosuid,nodev,fsname=mezzotint,subtype=mezzotint,{}", MOUNT_OPTIONS);
+        let status = ["fusermount3", "fusermount"]
+            .iter()
+            .find_map(|cmd| {
+                match Command::new(cmd)
+                    .args(["-o", &opts, "--"])
+                    .arg(mountpoint)
+                    .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+                    .status()
+                {
+                    Err(err) if err.kind() == ErrorKind::NotFound => None,
+                    r => Some(r),
+                }
+            })
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    "Mounting the preview without root needs fusermount3 or fusermount, which is not installed",
+                )
+            })??;
+        drop(theirs);
+
+        if !status.success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Unable to mount the preview at {}: fusermount exited with {}", mountpoint.to_str().unwrap(), status),
+            ));
+        }
+
+        Self::recv_fd(&ours)
+    }
+
+    /// Receive a file descriptor from the socket
+    fn recv_fd(sock: &OwnedFd) -> Result<File, Error> {
+        let mut data = [0u8; 1];
+        let mut iov = libc::iovec { iov_base: data.as_mut_ptr() as *mut libc::c_void, iov_len: data.len() };
+        // Control buffer must be aligned for the headers
+        let mut control = [0u64; 8];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        if unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, 0) } < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        if cmsg.is_null() || unsafe { (*cmsg).cmsg_level != libc::SOL_SOCKET || (*cmsg).cmsg_type != libc::SCM_RIGHTS } {
+            return Err(Error::new(ErrorKind::Other, "fusermount has not passed the FUSE device"));
+        }
+
+        Ok(unsafe { File::from_raw_fd(std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int)) })
+    }
+
+    /// Serve the view, until it is unmounted
+    pub fn serve(mut self) -> Result<(), Error> {
+        let mut buf = vec![0u8; MAX_WRITE as usize + 4096];
+        loop {
+            let n = match self.dev.read(&mut buf) {
+                Ok(n) => n,
+                Err(err) => match err.raw_os_error() {
+                    // Unmounted
+                    Some(libc::ENODEV) => return Ok(()),
+                    // Request was interrupted before it was read
+                    Some(libc::EINTR) | Some(libc::EAGAIN) | Some(libc::ENOENT) => continue,
+                    _ => return Err(err),
+                },
+            };
+            if n < IN_HEADER {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Short FUSE request of {} bytes", n)));
+            }
+
+            let (op, unique, node) = (get_u32(&buf, 4), get_u64(&buf, 8), get_u64(&buf, 16));
+            let reply = match self.handle(op, node, &buf[IN_HEADER..n]) {
+                Ok(Some(data)) => (0, data),
+                Ok(None) => continue,
+                Err(e) => (-e, vec![]),
+            };
+
+            let mut out: Vec<u8> = vec![];
+            out.u32(16 + reply.1.len() as u32).u32(reply.0 as u32).u64(unique).extend(reply.1);
+            if let Err(err) = self.dev.write_all(&out) {
+                // Interrupted request is not waited for anymore
+                if err.raw_os_error() != Some(libc::ENOENT) {
+                    return Err(err);
+                }
+            }
+
+            if op == FUSE_DESTROY {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handle a request. Returns the reply data, None if there is no reply at all, or errno.
+    fn handle(&mut self, op: u32, node: u64, arg: &[u8]) -> Result<Option<Vec<u8>>, i32> {
+        let mut out: Vec<u8> = vec![];
+        match op {
+            FUSE_INIT => {
+                let (major, minor, readahead) = (get_u32(arg, 0), get_u32(arg, 4), get_u32(arg, 8));
+                if major < FUSE_KERNEL_VERSION {
+                    return Err(libc::EPROTO);
+                }
+                log::debug!("FUSE protocol of the kernel is {}.{}", major, minor);
+                out.u32(FUSE_KERNEL_VERSION).u32(minor.min(FUSE_KERNEL_MINOR_VERSION)).u32(readahead).u32(0);
+                // Background requests and congestion threshold, max_write, time granularity, max_pages and alignment
+                out.extend(16u16.to_ne_bytes());
+                out.extend(12u16.to_ne_bytes());
+                out.u32(MAX_WRITE).u32(1).u32(0);
+                // flags2 and unused
+                out.extend([0u8; 32]);
+            }
+            FUSE_LOOKUP => {
+                let name = arg.split(|b| *b == 0).next().unwrap_or_default();
+                let p = self.path(node)?.join(std::ffi::OsStr::from_bytes(name));
+                if self.is_hidden(&p) {
+                    return Err(libc::ENOENT);
+                }
+                let m = self.stat(&p)?;
+                let id = self.node(&p);
+                out.u64(id).u64(0).u64(TTL).u64(TTL).u32(0).u32(0);
+                Self::attr(&mut out, id, &m);
+            }
+            FUSE_GETATTR => {
+                let m = self.stat(&self.path(node)?)?;
+                out.u64(TTL).u32(0).u32(0);
+                Self::attr(&mut out, node, &m);
+            }
+            FUSE_READLINK => {
+                out.extend(fs::read_link(self.host(&self.path(node)?)).map_err(|err| errno(&err))?.as_os_str().as_bytes());
+            }
+            FUSE_OPEN => {
+                if get_u32(arg, 0) as i32 & libc::O_ACCMODE != libc::O_RDONLY {
+                    return Err(libc::EROFS);
+                }
+                let f = fs::OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_NOFOLLOW)
+                    .open(self.host(&self.path(node)?))
+                    .map_err(|err| errno(&err))?;
+                let fh = self.next_fh();
+                self.files.insert(fh, f);
+                out.u64(fh).u32(FOPEN_KEEP_CACHE).u32(0);
+            }
+            FUSE_READ => {
+                let (fh, offset, size) = (get_u64(arg, 0), get_u64(arg, 8), get_u32(arg, 16) as usize);
+                let f = self.files.get(&fh).ok_or(libc::EBADF)?;
+                out.resize(size, 0);
+                let mut n = 0;
+                while n < size {
+                    match f.read_at(&mut out[n..], offset + n as u64) {
+                        Ok(0) => break,
+                        Ok(r) => n += r,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                        Err(err) => return Err(errno(&err)),
+                    }
+                }
+                out.truncate(n);
+            }
+            FUSE_RELEASE => {
+                self.files.remove(&get_u64(arg, 0));
+            }
+            FUSE_OPENDIR => {
+                let entries = self.list(node)?;
+                let fh = self.next_fh();
+                self.dirs.insert(fh, entries);
+                out.u64(fh).u32(0).u32(0);
+            }
+            FUSE_READDIR => {
+                let (fh, offset, size) = (get_u64(arg, 0), get_u64(arg, 8) as usize, get_u32(arg, 16) as usize);
+                for (i, (id, kind, name)) in self.dirs.get(&fh).ok_or(libc::EBADF)?.iter().enumerate().skip(offset) {
+                    // Entries are padded to 8 bytes
+                    let len = (24 + name.len() + 7) & !7;
+                    if out.len() + len > size {
+                        break;
+                    }
+                    out.u64(*id).u64(i as u64 + 1).u32(name.len() as u32).u32(*kind).extend(name);
+                    out.resize(out.len() + len - 24 - name.len(), 0);
+                }
+            }
+            FUSE_RELEASEDIR => {
+                self.dirs.remove(&get_u64(arg, 0));
+            }
+            FUSE_STATFS => {
+                let hp = CString::new(self.root.as_os_str().as_bytes()).map_err(|_| libc::EINVAL)?;
+                let mut st: libc::statvfs = unsafe { mem::zeroed() };
+                if unsafe { libc::statvfs(hp.as_ptr(), &mut st) } != 0 {
+                    return Err(errno(&Error::last_os_error()));
+                }
+                out.u64(st.f_blocks).u64(st.f_bfree).u64(st.f_bavail).u64(st.f_files).u64(st.f_ffree);
+                out.u32(st.f_bsize as u32).u32(st.f_namemax as u32).u32(st.f_frsize as u32).u32(0);
+                out.extend([0u8; 24]);
+            }
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return Ok(None),
+            FUSE_DESTROY => {}
+            _ => return Err(libc::ENOSYS),
+        }
+
+        Ok(Some(out))
+    }
+
+    /// Get path of the node
+    fn path(&self, node: u64) -> Result<PathBuf, i32> {
+        self.nodes.get((node as usize).wrapping_sub(1)).cloned().ok_or(libc::ENOENT)
+    }
+
+    /// Get id of the node of the path, adding it if needed. Nodes are never forgotten.
+    fn node(&mut self, p: &Path) -> u64 {
+        if let Some(id) = self.ids.get(p) {
+            return *id;
+        }
+
+        self.nodes.push(p.to_owned());
+        let id = self.nodes.len() as u64;
+        self.ids.insert(p.to_owned(), id);
+        id
+    }
+
+    fn next_fh(&mut self) -> u64 {
+        self.next_fh += 1;
+        self.next_fh
+    }
+
+    /// Get path on the host
+    fn host(&self, p: &Path) -> PathBuf {
+        self.root.join(p.strip_prefix("/").unwrap_or(p))
+    }
+
+    /// Path is removed by the plan itself or as a part of a removed tree
+    fn is_hidden(&self, p: &Path) -> bool {
+        p.ancestors().any(|a| self.removed.contains(a))
+    }
+
+    fn stat(&self, p: &Path) -> Result<Metadata, i32> {
+        fs::symlink_metadata(self.host(p)).map_err(|err| errno(&err))
+    }
+
+    /// Get visible entries of the directory: their node ids, types and names
+    fn list(&mut self, node: u64) -> Result<Vec<(u64, u32, Vec<u8>)>, i32> {
+        let p = self.path(node)?;
+        let parent = p.parent().map(|pp| self.node(pp)).unwrap_or(node);
+        let mut entries = vec![(node, libc::DT_DIR as u32, b".".to_vec()), (parent, libc::DT_DIR as u32, b"..".to_vec())];
+        for e in fs::read_dir(self.host(&p)).map_err(|err| errno(&err))?.filter_map(|e| e.ok()) {
+            let ep = p.join(e.file_name());
+            if self.is_hidden(&ep) {
+                continue;
+            }
+            let kind = e.metadata().map(|m| (m.mode() & libc::S_IFMT) >> 12).unwrap_or(libc::DT_UNKNOWN as u32);
+            entries.push((self.node(&ep), kind, e.file_name().as_bytes().to_vec()));
+        }
+
+        Ok(entries)
+    }
+
+    /// Append attributes of the node
+    fn attr(out: &mut Vec<u8>, id: u64, m: &Metadata) {
+        // Device numbers are in the new encoding of the kernel
+        let (major, minor) = (libc::major(m.rdev()), libc::minor(m.rdev()));
+        let rdev = (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12);
+
+        out.u64(id).u64(m.size()).u64(m.blocks());
+        out.u64(m.atime() as u64).u64(m.mtime() as u64).u64(m.ctime() as u64);
+        out.u32(m.atime_nsec() as u32).u32(m.mtime_nsec() as u32).u32(m.ctime_nsec() as u32);
+        out.u32(m.mode()).u32(m.nlink() as u32).u32(m.uid()).u32(m.gid()).u32(rdev).u32(m.blksize() as u32).u32(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_u32, get_u64, FuseView, Put, FUSE_INIT, FUSE_LOOKUP, FUSE_OPEN, FUSE_OPENDIR, FUSE_READ, FUSE_READDIR};
+    use crate::testutil::scratch;
+    use std::{fs::File, path::PathBuf};
+
+    /// View of a small rootfs, where /etc/gone is removed
+    fn view(name: &str) -> FuseView {
+        let root = scratch(name);
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/kept"), "kept data").unwrap();
+        std::fs::write(root.join("etc/gone"), "gone").unwrap();
+        FuseView::new(File::open("/dev/null").unwrap(), &root, &[PathBuf::from("/etc/gone")])
+    }
+
+    fn lookup(v: &mut FuseView, node: u64, name: &str) -> Result<Vec<u8>, i32> {
+        v.handle(FUSE_LOOKUP, node, format!("{}\0", name).as_bytes()).map(Option::unwrap)
+    }
+
+    #[test]
+    fn init() {
+        let mut v = view("fuse-init");
+        let mut arg: Vec<u8> = vec![];
+        arg.u32(7).u32(38).u32(65536).u32(0);
+        let out = v.handle(FUSE_INIT, 0, &arg).unwrap().unwrap();
+        assert_eq!(out.len(), 64);
+        assert_eq!((get_u32(&out, 0), get_u32(&out, 4), get_u32(&out, 8)), (7, 31, 65536));
+        assert_eq!(get_u32(&out, 20), 128 * 1024);
+
+        arg.clear();
+        arg.u32(6).u32(0).u32(0).u32(0);
+        assert_eq!(v.handle(FUSE_INIT, 0, &arg), Err(libc::EPROTO));
+    }
+
+    #[test]
+    fn lookup_hides_removed() {
+        let mut v = view("fuse-lookup");
+        let etc = lookup(&mut v, 1, "etc").unwrap();
+        let etc_id = get_u64(&etc, 0);
+        assert_eq!(etc_id, 2);
+        // Mode of the attributes is after the entry and the node id, size, blocks and times
+        assert_eq!(get_u32(&etc, 40 + 60) & libc::S_IFMT, libc::S_IFDIR);
+
+        let kept = lookup(&mut v, etc_id, "kept").unwrap();
+        assert_eq!(get_u64(&kept, 0), 3);
+        assert_eq!(get_u64(&kept, 40 + 8), 9);
+        assert_eq!(get_u64(&lookup(&mut v, etc_id, "kept").unwrap(), 0), 3, "node ids are stable");
+
+        assert_eq!(lookup(&mut v, etc_id, "gone"), Err(libc::ENOENT));
+        assert_eq!(lookup(&mut v, etc_id, "missing"), Err(libc::ENOENT));
+        assert_eq!(lookup(&mut v, 42, "kept"), Err(libc::ENOENT));
+
+        let dh = get_u64(&v.handle(FUSE_OPENDIR, etc_id, &[0u8; 8]).unwrap().unwrap(), 0);
+        let mut arg: Vec<u8> = vec![];
+        arg.u64(dh).u64(0).u32(4096);
+        let list = v.handle(FUSE_READDIR, etc_id, &arg).unwrap().unwrap();
+        let mut names = vec![];
+        let mut off = 0;
+        while off < list.len() {
+            let len = get_u32(&list, off + 16) as usize;
+            names.push(String::from_utf8(list[off + 24..off + 24 + len].to_vec()).unwrap());
+            off += (24 + len + 7) & !7;
+        }
+        assert_eq!(names, [".", "..", "kept"]);
+    }
+
+    #[test]
+    fn read() {
+        let mut v = view("fuse-read");
+        let etc = get_u64(&lookup(&mut v, 1, "etc").unwrap(), 0);
+        let kept = get_u64(&lookup(&mut v, etc, "kept").unwrap(), 0);
+
+        let mut arg: Vec<u8> = vec![];
+        arg.u32(libc::O_WRONLY as u32).u32(0);
+        assert_eq!(v.handle(FUSE_OPEN, kept, &arg), Err(libc::EROFS));
+
+        arg.clear();
+        arg.u32(libc::O_RDONLY as u32).u32(0);
+        let fh = get_u64(&v.handle(FUSE_OPEN, kept, &arg).unwrap().unwrap(), 0);
+
+        arg.clear();
+        arg.u64(fh).u64(5).u32(100);
+        assert_eq!(v.handle(FUSE_READ, kept, &arg).unwrap().unwrap(), b"data");
+
+        arg.clear();
+        arg.u64(fh + 1).u64(0).u32(100);
+        assert_eq!(v.handle(FUSE_READ, kept, &arg), Err(libc::EBADF));
+    }
+}
@@ -0,0 +1,26 @@
+/*
+Documentation artifacts, generated from the CLI definition:
+manpages and shell completions.
+ */
+
+use clap::Command;
+use clap_complete::Shell;
+use std::io::Error;
+
+/// Generate a manpage in roff format
+pub fn manpage(cmd: Command, version: &str) -> Result<Vec<u8>, Error> {
+    let name = cmd.get_name().to_string();
+    let mut out: Vec<u8> = vec![];
+    clap_mangen::Man::new(cmd).section("8").source(format!("{} {}", name, version)).render(&mut out)?;
+
+    Ok(out)
+}
+
+/// Generate shell completion script
+pub fn completions(mut cmd: Command, shell: Shell) -> Vec<u8> {
+    let name = cmd.get_name().to_string();
+    let mut out: Vec<u8> = vec![];
+    clap_complete::generate(shell, &mut cmd, name, &mut out);
+
+    out
+}
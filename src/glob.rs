@@ -0,0 +1,215 @@
+/*
+Unix-like path globbing.
+
+Supported patterns:
+  - "*" matches any sequence of characters within one path component
+  - "**" matches any number of path components, including none
+  - "?" matches any single character
+  - "[abc]", "[a-z]" and "[!abc]" match a character class
+
+Keep rules might have exclusions, like "/usr/share/nginx/** !**/
+*.md".
+Relative exclusions are relative to the directory of the pattern.
+ */
+
+use crate::{prefix, rootfs::RootFS};
+use std::path::{Path, PathBuf};
+
+/// Match a character class at the beginning of the pattern.
+/// Returns matching result and the length of the class in the pattern.
+fn match_class(pat: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = i < pat.len() && (pat[i] == '!' || pat[i] == '^');
+    if negate {
+        i += 1;
+    }
+
+    let mut found = false;
+    let mut first = true;
+    while i < pat.len() && (pat[i] != ']' || first) {
+        first = false;
+        if i + 2 < pat.len() && pat[i + 1] == '-' && pat[i + 2] != ']' {
+            if pat[i] <= c && c <= pat[i + 2] {
+                found = true;
+            }
+            i += 3;
+        } else {
+            if pat[i] == c {
+                found = true;
+            }
+            i += 1;
+        }
+    }
+
+    if i >= pat.len() {
+        return None; // Not closed, so not a class
+    }
+
+    Some((found != negate, i + 1))
+}
+
+/// Match one path component against one pattern component
+fn match_component(pat: &[char], s: &[char]) -> bool {
+    if pat.is_empty() {
+        return s.is_empty();
+    }
+
+    match pat[0] {
+        '*' => (0..=s.len()).any(|i| match_component(&pat[1..], &s[i..])),
+        '?' => !s.is_empty() && match_component(&pat[1..], &s[1..]),
+        '[' if !s.is_empty() => match match_class(pat, s[0]) {
+            Some((true, len)) => match_component(&pat[len..], &s[1..]),
+            Some((false, _)) => false,
+            None => s[0] == '[' && match_component(&pat[1..], &s[1..]),
+        },
+        c => !s.is_empty() && s[0] == c && match_component(&pat[1..], &s[1..]),
+    }
+}
+
+/// Match path components
+fn match_components(pat: &[Vec<char>], s: &[Vec<char>]) -> bool {
+    if pat.is_empty() {
+        return s.is_empty();
+    }
+
+    if pat[0].len() == 2 && pat[0][0] == '*' && pat[0][1] == '*' {
+        return (0..=s.len()).any(|i| match_components(&pat[1..], &s[i..]));
+    }
+
+    !s.is_empty() && match_component(&pat[0], &s[0]) && match_components(&pat[1..], &s[1..])
+}
+
+/// Split path into components as char vectors
+fn split(p: &str) -> Vec<Vec<char>> {
+    p.split('/').filter(|c| !c.is_empty()).map(|c| c.chars().collect::<Vec<char>>()).collect::<Vec<Vec<char>>>()
+}
+
+/// Returns true if the path matches the glob pattern
+pub fn matches(pattern: &str, p: &Path) -> bool {
+    match_components(&split(pattern), &split(p.to_str().unwrap_or_default()))
+}
+
+/// Returns true if the path matches any of the patterns
+pub fn matches_any(patterns: &[String], p: &Path) -> bool {
+    patterns.iter().any(|ptn| matches(ptn, p))
+}
+
+/// Returns true if the path or any of its parent directories matches the pattern
+pub fn matches_tree(pattern: &str, p: &Path) -> bool {
+    p.ancestors().any(|a| matches(pattern, a))
+}
+
+/// Returns true if the string contains glob wildcards
+pub fn is_glob(p: &str) -> bool {
+    p.contains(['*', '?', '['])
+}
+
+/// Get the longest path prefix of the pattern without any wildcards
+fn base_dir(pattern: &str) -> PathBuf {
+    let mut base = PathBuf::from("/");
+    for c in pattern.split('/').filter(|c| !c.is_empty()) {
+        if is_glob(c) {
+            break;
+        }
+        base.push(c);
+    }
+
+    base
+}
+
+/// Resolve directory symlinks in the base directory of the pattern, e.g. /bin/* to /usr/bin/*
+/// on usrmerged systems, so it matches the canonical paths
+pub fn resolve_base(pattern: &str) -> String {
+    let base = base_dir(pattern);
+    let rest = match pattern.strip_prefix(base.to_str().unwrap_or_default()) {
+        Some(rest) if base != Path::new("/") => rest,
+        _ => return pattern.to_string(),
+    };
+
+    // Base is the whole pattern, if it has no globs. Then it is a directory or a file.
+    let resolved =
+        if prefix::is_dir(&base) { prefix::canonicalize(&base).unwrap_or(base) } else { RootFS::resolve_dirs(&base).0 };
+
+    format!("{}{}", resolved.to_str().unwrap_or_default(), rest)
+}
+
+/// Expand the pattern against the current filesystem.
+/// Matched directories are expanded to their entire content.
+pub fn expand_tree(pattern: &str) -> Vec<PathBuf> {
+    let mut out: Vec<PathBuf> = vec![];
+    let base = base_dir(pattern);
+    let hbase = prefix::host(&base);
+    let root = RootFS::get_root_mount();
+    for e in walkdir::WalkDir::new(&hbase)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| !e.file_type().is_dir() || !RootFS::is_foreign(&prefix::strip(e.path()), root))
+        .filter_map(|e| e.ok())
+    {
+        // Paths are found under the base, as it is given, even if it is a symlink
+        let p = match e.path().strip_prefix(&hbase) {
+            Ok(r) if !r.as_os_str().is_empty() => base.join(r),
+            _ => base.clone(),
+        };
+        if !e.file_type().is_dir() && matches_tree(pattern, &p) {
+            out.push(p);
+        }
+    }
+    out.sort();
+
+    out
+}
+
+/// Expand the pattern to the topmost real directories, matching it, so their trees can be removed at once
+pub fn expand_dirs(pattern: &str) -> Vec<PathBuf> {
+    let mut out: Vec<PathBuf> = vec![];
+    let base = base_dir(pattern);
+    let hbase = prefix::host(&base);
+    let root = RootFS::get_root_mount();
+    let mut it = walkdir::WalkDir::new(&hbase)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| !e.file_type().is_dir() || !RootFS::is_foreign(&prefix::strip(e.path()), root));
+    while let Some(e) = it.next() {
+        let e = match e {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        let p = match e.path().strip_prefix(&hbase) {
+            Ok(r) if !r.as_os_str().is_empty() => base.join(r),
+            _ => base.clone(),
+        };
+        if e.file_type().is_dir() && !e.path_is_symlink() && matches(pattern, &p) {
+            out.push(p);
+            it.skip_current_dir();
+        }
+    }
+
+    out
+}
+
+/// Split a rule into its pattern and absolute exclusion patterns
+pub fn split_rule(rule: &str) -> (String, Vec<String>) {
+    let mut parts = rule.split(" !").map(|p| p.trim());
+    let pattern = parts.next().unwrap_or_default().to_string();
+    let base = base_dir(&pattern);
+    let exclusions = parts
+        .filter(|x| !x.is_empty())
+        .map(|x| if x.starts_with('/') { x.to_string() } else { base.join(x).to_str().unwrap().to_string() })
+        .collect::<Vec<String>>();
+
+    (pattern, exclusions)
+}
+
+/// Returns true if the path or any of its parent directories matches the rule,
+/// and none of the rule exclusions
+pub fn matches_rule(rule: &str, p: &Path) -> bool {
+    let (pattern, exclusions) = split_rule(rule);
+    matches_tree(&pattern, p) && !exclusions.iter().any(|x| matches_tree(x, p))
+}
+
+/// Expand the rule against the current filesystem, omitting its exclusions
+pub fn expand_rule(rule: &str) -> Vec<PathBuf> {
+    let (pattern, exclusions) = split_rule(rule);
+    expand_tree(&pattern).into_iter().filter(|p| !exclusions.iter().any(|x| matches_tree(x, p))).collect()
+}
@@ -0,0 +1,176 @@
+/*
+Dependency graph of the keep set: targets, libraries they need,
+packages owning them and kept files of those packages.
+It shows why the keep set looks the way it does.
+ */
+
+use crate::scanner::{debftrace::DebPkgFileTrace, elfver::ElfVersions, traceitf::PkgFileTrace};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Target,
+    Library,
+    Package,
+    File,
+}
+
+#[derive(Serialize, Debug)]
+struct Node {
+    id: String,
+    kind: Kind,
+}
+
+#[derive(Serialize, Debug)]
+struct Edge {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct DepGraph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    #[serde(skip)]
+    known: HashSet<String>,
+}
+
+impl DepGraph {
+    /// Build the graph of the targets against the kept paths
+    pub fn new(targets: &[String], kept: &[PathBuf]) -> Self {
+        let mut g = DepGraph::default();
+
+        // Needed libraries are file names, as they are in DT_NEEDED
+        let mut libs: HashMap<String, &PathBuf> = HashMap::default();
+        for p in kept {
+            if let Some(n) = p.file_name().and_then(|n| n.to_str()) {
+                libs.entry(n.to_string()).or_insert(p);
+            }
+        }
+
+        let mut queue: Vec<PathBuf> = vec![];
+        for t in targets {
+            g.add_node(t, Kind::Target);
+            queue.push(PathBuf::from(t));
+        }
+
+        // Walk needed libraries of the binaries
+        let mut binaries: Vec<PathBuf> = vec![];
+        while let Some(bin) = queue.pop() {
+            let from = bin.to_str().unwrap().to_string();
+            binaries.push(bin.to_owned());
+            for lib in ElfVersions::open(&bin).map(|mut e| e.get_needed_libs()).unwrap_or_default() {
+                let to = match libs.get(&lib) {
+                    Some(p) => p.to_str().unwrap().to_string(),
+                    None => lib,
+                };
+                if g.add_node(&to, Kind::Library) {
+                    queue.push(PathBuf::from(&to));
+                }
+                g.add_edge(&from, &to);
+            }
+        }
+
+        // Packages of the binaries and the rest of their kept content
+        let mut pt = DebPkgFileTrace::new();
+        let mut pkgs: HashSet<String> = HashSet::default();
+        for bin in &binaries {
+            if let Some(pkg) = pt.trace(bin.to_owned()) {
+                g.add_node(&pkg, Kind::Package);
+                g.add_edge(bin.to_str().unwrap(), &pkg);
+                pkgs.insert(pkg);
+            }
+        }
+
+        for p in kept.iter().filter(|p| !binaries.contains(p)) {
+            if let Some(pkg) = pt.trace(p.to_owned()).filter(|pkg| pkgs.contains(pkg)) {
+                g.add_node(p.to_str().unwrap(), Kind::File);
+                g.add_edge(&pkg, p.to_str().unwrap());
+            }
+        }
+
+        g
+    }
+
+    /// Add a node, unless it is already there. Returns true if it is added.
+    fn add_node(&mut self, id: &str, kind: Kind) -> bool {
+        if !self.known.insert(id.to_string()) {
+            return false;
+        }
+
+        self.nodes.push(Node { id: id.to_string(), kind });
+        true
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str) {
+        self.edges.push(Edge { from: from.to_string(), to: to.to_string() });
+    }
+
+    /// Get the graph as JSON
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Quote an identifier for DOT. Only the double quote is escaped in DOT strings,
+    /// backslashes start escapes of the labels, so they are doubled to stay as they are.
+    fn quote(s: &str) -> String {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+    }
+
+    /// Get the graph in Graphviz DOT format
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph mezzotint {\n  rankdir=LR;\n  node [fontname=\"monospace\"];\n");
+        for n in &self.nodes {
+            let style = match n.kind {
+                Kind::Target => "shape=box, style=filled, fillcolor=\"#a6e3a1\"",
+                Kind::Library => "shape=box",
+                Kind::Package => "shape=folder, style=filled, fillcolor=\"#f9e2af\"",
+                Kind::File => "shape=note",
+            };
+            let label = match n.kind {
+                Kind::File | Kind::Library => Path::new(&n.id).file_name().and_then(|f| f.to_str()).unwrap_or(&n.id),
+                _ => &n.id,
+            };
+            out.push_str(&format!(
+                "  {} [label={}, tooltip={}, {}];\n",
+                Self::quote(&n.id),
+                Self::quote(label),
+                Self::quote(&n.id),
+                style
+            ));
+        }
+
+        for e in &self.edges {
+            out.push_str(&format!("  {} -> {};\n", Self::quote(&e.from), Self::quote(&e.to)));
+        }
+        out.push_str("}\n");
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DepGraph, Kind};
+
+    #[test]
+    fn dot_quotes_ids() {
+        assert_eq!(DepGraph::quote("/usr/lib/a\"b"), "\"/usr/lib/a\\\"b\"");
+        assert_eq!(DepGraph::quote("C:\\x\ny"), "\"C:\\\\x\\ny\"");
+        assert_eq!(DepGraph::quote("пакет"), "\"пакет\"");
+
+        let mut g = DepGraph::default();
+        g.add_node("/usr/bin/a\"b", Kind::Library);
+        g.add_node("libc6", Kind::Package);
+        g.add_edge("/usr/bin/a\"b", "libc6");
+        let dot = g.to_dot();
+        assert!(dot.contains("  \"/usr/bin/a\\\"b\" -> \"libc6\";\n"), "{}", dot);
+        assert!(dot.contains("label=\"a\\\"b\""), "{}", dot);
+    }
+}
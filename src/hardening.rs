@@ -0,0 +1,244 @@
+/*
+Hardening report of the kept files.
+
+Images often carry artifacts from the build stages: world-writable files,
+setuid/setgid binaries, shell histories, credentials or files owned by users
+and groups, which do not exist.
+ */
+
+use crate::{accounts::Accounts, prefix, xattrs};
+use std::{
+    ffi::CString,
+    fs,
+    io::{Error, Read},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+const S_ISVTX: u32 = 0o1000;
+const S_IWOTH: u32 = 0o0002;
+
+/// Histories of shells and tools, those might carry typed secrets
+const HISTORY_FILES: &[&str] = &[
+    ".bash_history",
+    ".zsh_history",
+    ".sh_history",
+    ".ash_history",
+    ".history",
+    ".python_history",
+    ".node_repl_history",
+    ".mysql_history",
+    ".psql_history",
+    ".sqlite_history",
+    ".lesshst",
+    ".viminfo",
+];
+
+/// Files, those usually carry credentials. Matched by the end of the path.
+const CREDENTIAL_FILES: &[&str] = &[
+    ".netrc",
+    ".git-credentials",
+    ".pgpass",
+    ".my.cnf",
+    ".npmrc",
+    ".pypirc",
+    ".env",
+    ".ssh/id_rsa",
+    ".ssh/id_dsa",
+    ".ssh/id_ecdsa",
+    ".ssh/id_ed25519",
+    ".aws/credentials",
+    ".docker/config.json",
+    ".kube/config",
+];
+
+/// Extensions of the files, those might be private keys
+const KEY_EXT: &[&str] = &[".pem", ".key"];
+
+/// Kind of the hardening issue
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    WorldWritable,
+    SetId,
+    UnknownUser(u32),
+    UnknownGroup(u32),
+    History,
+    Credentials,
+    Capabilities(String),
+}
+
+impl Issue {
+    /// Check if the issue is a security one, rather than a hygiene
+    pub fn is_security(&self) -> bool {
+        matches!(self, Issue::WorldWritable | Issue::SetId | Issue::History | Issue::Credentials | Issue::Capabilities(_))
+    }
+}
+
+pub struct Hardening {
+    issues: Vec<(PathBuf, Issue)>,
+}
+
+impl Hardening {
+    /// Check the kept files
+    pub fn new(kept: &[PathBuf]) -> Self {
+        let (uids, gids) = Accounts::get_known_ids();
+        let mut issues: Vec<(PathBuf, Issue)> = vec![];
+
+        for p in kept {
+            let m = match prefix::symlink_metadata(p) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+
+            // Permissions of symlinks are meaningless
+            if !m.file_type().is_symlink() {
+                let mode = m.permissions().mode();
+                if mode & S_IWOTH != 0 && !(m.is_dir() && mode & S_ISVTX != 0) {
+                    issues.push((p.to_owned(), Issue::WorldWritable));
+                }
+                if m.is_file() && mode & (S_ISUID | S_ISGID) != 0 {
+                    issues.push((p.to_owned(), Issue::SetId));
+                }
+                if m.is_file() {
+                    if let Some(i) = Self::get_secret(p) {
+                        issues.push((p.to_owned(), i));
+                    }
+                    if let Some(caps) = xattrs::get_caps(&prefix::host_nofollow(p)) {
+                        issues.push((p.to_owned(), Issue::Capabilities(xattrs::describe_caps(&caps))));
+                    }
+                }
+            }
+
+            if !uids.contains(&m.uid()) {
+                issues.push((p.to_owned(), Issue::UnknownUser(m.uid())));
+            }
+            if !gids.contains(&m.gid()) {
+                issues.push((p.to_owned(), Issue::UnknownGroup(m.gid())));
+            }
+        }
+
+        Hardening { issues }
+    }
+
+    /// Check if the file looks like a history or credentials
+    fn get_secret(p: &Path) -> Option<Issue> {
+        let fname = p.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+        if HISTORY_FILES.contains(&fname) {
+            return Some(Issue::History);
+        }
+
+        let ps = p.to_str().unwrap_or_default();
+        if CREDENTIAL_FILES.iter().any(|c| ps.ends_with(&format!("/{}", c))) {
+            return Some(Issue::Credentials);
+        }
+
+        // Certificates share the extensions, so the content tells
+        if KEY_EXT.iter().any(|e| fname.ends_with(e)) {
+            let mut head = [0u8; 4096];
+            let n = prefix::open(p).and_then(|mut f| f.read(&mut head)).unwrap_or(0);
+            if String::from_utf8_lossy(&head[..n]).contains("PRIVATE KEY-----") {
+                return Some(Issue::Credentials);
+            }
+        }
+
+        None
+    }
+
+    /// Get found hygiene issues as human-readable messages
+    pub fn report(&self) -> Vec<String> {
+        self.messages(false)
+    }
+
+    /// Get found security issues as human-readable messages
+    pub fn security(&self) -> Vec<String> {
+        self.messages(true)
+    }
+
+    fn messages(&self, security: bool) -> Vec<String> {
+        self.issues
+            .iter()
+            .filter(|(_, i)| i.is_security() == security)
+            .map(|(p, i)| {
+                let p = p.to_str().unwrap();
+                match i {
+                    Issue::WorldWritable => format!("{} is world-writable", p),
+                    Issue::SetId => format!("{} has setuid or setgid bit", p),
+                    Issue::UnknownUser(uid) => format!("{} is owned by unknown user id {}", p, uid),
+                    Issue::UnknownGroup(gid) => format!("{} is owned by unknown group id {}", p, gid),
+                    Issue::History => format!("{} looks like a shell history", p),
+                    Issue::Credentials => format!("{} looks like credentials or a private key", p),
+                    Issue::Capabilities(caps) => format!("{} has file capabilities {}", p, caps),
+                }
+            })
+            .collect()
+    }
+
+    /// Change owner of the path without following symlinks. u32::MAX leaves the id unchanged.
+    /// Kernel drops file capabilities on the owner change, so they are set back.
+    fn chown(p: &Path, uid: u32, gid: u32) -> Result<(), Error> {
+        let caps = xattrs::get_caps(p);
+        let cp = CString::new(p.as_os_str().as_bytes())?;
+        if unsafe { libc::lchown(cp.as_ptr(), uid, gid) } != 0 {
+            return Err(Error::last_os_error());
+        }
+
+        if let Some(caps) = caps {
+            xattrs::set(p, xattrs::CAPABILITY, &caps)?;
+        }
+
+        Ok(())
+    }
+
+    /// Get paths, those are changed by the normalisation
+    pub fn get_fixable(&self) -> Vec<PathBuf> {
+        let mut out: Vec<PathBuf> = vec![];
+        for (p, _) in self.issues.iter().filter(|(_, i)| Self::is_fixable(i)) {
+            if !out.contains(p) {
+                out.push(p.to_owned());
+            }
+        }
+
+        out
+    }
+
+    /// Returns true if the issue is fixed by the normalisation
+    fn is_fixable(i: &Issue) -> bool {
+        matches!(i, Issue::WorldWritable | Issue::UnknownUser(_) | Issue::UnknownGroup(_))
+    }
+
+    /// Normalise the found issues: drop world-writable bit and give files
+    /// of unknown owners to root. Setuid and setgid bits and file capabilities
+    /// are only reported, as some binaries need them. Returns amount of fixed issues.
+    pub fn normalise(&self, dry_run: bool) -> Result<usize, Error> {
+        let mut fixed: usize = 0;
+        for (p, i) in &self.issues {
+            if !Self::is_fixable(i) {
+                continue;
+            }
+
+            fixed += 1;
+            if dry_run {
+                continue;
+            }
+
+            let hp = prefix::host_nofollow(p);
+            match i {
+                Issue::WorldWritable => {
+                    let mut perms = fs::metadata(&hp)?.permissions();
+                    perms.set_mode(perms.mode() & !S_IWOTH);
+                    fs::set_permissions(&hp, perms)?;
+                }
+                Issue::UnknownUser(_) => Self::chown(&hp, 0, u32::MAX)?,
+                Issue::UnknownGroup(_) => Self::chown(&hp, u32::MAX, 0)?,
+                Issue::SetId | Issue::History | Issue::Credentials | Issue::Capabilities(_) => {}
+            }
+        }
+
+        Ok(fixed)
+    }
+}
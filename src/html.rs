@@ -0,0 +1,144 @@
+/*
+Self-contained HTML report of the tint: kept and removed trees with search,
+size charts and junk warnings. It has no external resources, so it can be
+attached to CI artifacts for reviewers.
+ */
+
+use crate::{
+    filters::{junk::JunkExtensions, resources::ResourcesDataFilter},
+    prefix,
+};
+use serde::Serialize;
+use std::{io::Error, path::PathBuf};
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.4em; }
+.cards { display: flex; gap: 1em; margin-bottom: 1.5em; }
+.card { border: 1px solid #ddd; border-radius: 6px; padding: .8em 1.2em; }
+.card b { display: block; font-size: 1.3em; }
+.chart { margin-bottom: 1.5em; }
+.row { display: flex; align-items: center; font-family: monospace; margin: 2px 0; }
+.row .name { width: 12em; }
+.row .bar { height: 1em; }
+.kept { background: #40a02b; }
+.removed { background: #d20f39; }
+.cols { display: flex; gap: 2em; }
+.cols > div { flex: 1; min-width: 0; }
+details { margin-left: 1em; font-family: monospace; }
+summary { cursor: pointer; }
+.file { margin-left: 2.2em; font-family: monospace; white-space: nowrap; }
+.size { color: #888; }
+.junk { color: #d20f39; }
+input { width: 30em; padding: .3em; margin-bottom: 1em; }
+"#;
+
+const SCRIPT: &str = r#"
+const fmt = (n) => { const u = ["B", "KB", "MB", "GB"]; let i = 0; while (n >= 1024 && i < 3) { n /= 1024; i++; } return n.toFixed(i ? 1 : 0) + " " + u[i]; };
+const files = DATA.files;
+const kept = files.filter((f) => f.kept), removed = files.filter((f) => !f.kept);
+const sum = (l) => l.reduce((a, f) => a + f.size, 0);
+
+document.getElementById("cards").innerHTML = [
+  ["Kept files", kept.length], ["Kept size", fmt(sum(kept))],
+  ["Removed files", removed.length], ["Reclaimed", fmt(sum(removed))],
+  ["Potential junk", kept.filter((f) => f.junk).length],
+].map(([t, v]) => `<div class="card">${t}<b>${v}</b></div>`).join("");
+
+const dirs = {};
+for (const f of files) {
+  const d = "/" + (f.path.split("/")[1] || "");
+  dirs[d] = dirs[d] || [0, 0];
+  dirs[d][f.kept ? 0 : 1] += f.size;
+}
+const max = Math.max(1, ...Object.values(dirs).map(([k, r]) => k + r));
+document.getElementById("chart").innerHTML = Object.keys(dirs).sort().map((d) =>
+  `<div class="row"><span class="name">${d}</span>` +
+  `<span class="bar kept" style="width:${dirs[d][0] * 60 / max}%" title="kept ${fmt(dirs[d][0])}"></span>` +
+  `<span class="bar removed" style="width:${dirs[d][1] * 60 / max}%" title="removed ${fmt(dirs[d][1])}"></span>` +
+  `&nbsp;<span class="size">${fmt(dirs[d][0])} / ${fmt(dirs[d][0] + dirs[d][1])}</span></div>`).join("");
+
+document.getElementById("junk").innerHTML = kept.filter((f) => f.junk).map((f) =>
+  `<div class="file junk">⚠ ${esc(f.path)} <span class="size">${fmt(f.size)}</span></div>`).join("") || "<i>None</i>";
+
+function esc(s) { return s.replace(/[&<>"]/g, (c) => ({ "&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;" })[c]); }
+
+function tree(list, open) {
+  const root = {};
+  for (const f of list) {
+    let n = root;
+    const parts = f.path.split("/").filter((p) => p);
+    parts.slice(0, -1).forEach((p) => { n[p] = n[p] || {}; n = n[p]; });
+    (n[""] = n[""] || []).push(f);
+  }
+  const render = (n) => Object.keys(n).filter((k) => k).sort().map((k) =>
+    `<details${open ? " open" : ""}><summary>${esc(k)}/</summary>${render(n[k])}</details>`).join("") +
+    (n[""] || []).map((f) => `<div class="file${f.junk ? " junk" : ""}">${esc(f.path.split("/").pop())} <span class="size">${fmt(f.size)}</span></div>`).join("");
+  return render(root) || "<i>None</i>";
+}
+
+function update() {
+  const q = document.getElementById("search").value.toLowerCase();
+  const m = (l) => l.filter((f) => f.path.toLowerCase().includes(q));
+  document.getElementById("kept").innerHTML = tree(m(kept), q !== "");
+  document.getElementById("removed").innerHTML = tree(m(removed), q !== "");
+}
+document.getElementById("search").addEventListener("input", update);
+update();
+"#;
+
+#[derive(Serialize)]
+struct ReportFile {
+    path: String,
+    size: u64,
+    kept: bool,
+    junk: bool,
+}
+
+#[derive(Serialize)]
+struct ReportData {
+    files: Vec<ReportFile>,
+}
+
+pub struct HtmlReport<'a> {
+    kept: &'a [PathBuf],
+    removed: &'a [PathBuf],
+    junk: &'a JunkExtensions,
+}
+
+impl<'a> HtmlReport<'a> {
+    pub fn new(kept: &'a [PathBuf], removed: &'a [PathBuf], junk: &'a JunkExtensions) -> Self {
+        HtmlReport { kept, removed, junk }
+    }
+
+    /// Get the report as a standalone HTML page
+    pub fn to_html(&self, title: &str) -> Result<String, Error> {
+        let mut files: Vec<ReportFile> = vec![];
+        for (paths, kept) in [(self.kept, true), (self.removed, false)] {
+            for p in paths {
+                let fname = p.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+                files.push(ReportFile {
+                    path: p.to_str().unwrap().to_string(),
+                    size: prefix::symlink_metadata(p).map(|m| m.len()).unwrap_or(0),
+                    kept,
+                    junk: kept && !prefix::is_dir(p) && ResourcesDataFilter::is_potential_junk(fname, self.junk),
+                });
+            }
+        }
+
+        // Data must not close the script element
+        let data = serde_json::to_string(&ReportData { files })?.replace("</", "<\\/");
+        let title = title.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+
+        Ok(format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Mezzotint report: {title}</title>\n\
+             <style>{STYLE}</style>\n</head>\n<body>\n<h1>Mezzotint report: {title}</h1>\n\
+             <div class=\"cards\" id=\"cards\"></div>\n<h2>Size by directory</h2>\n<div class=\"chart\" id=\"chart\"></div>\n\
+             <h2>Potential junk</h2>\n<div id=\"junk\"></div>\n<h2>Files</h2>\n\
+             <input id=\"search\" type=\"search\" placeholder=\"Search paths...\">\n\
+             <div class=\"cols\"><div><h3>Kept</h3><div id=\"kept\"></div></div>\
+             <div><h3>Removed</h3><div id=\"removed\"></div></div></div>\n\
+             <script>\nconst DATA = {data};\n{SCRIPT}</script>\n</body>\n</html>\n"
+        ))
+    }
+}
@@ -0,0 +1,163 @@
+/*
+Image config of a container (OCI or Docker).
+
+Binaries, referenced by ENTRYPOINT, CMD, HEALTHCHECK and ONBUILD, are
+routinely removed by tinting (e.g. "curl" of a healthcheck), which breaks
+orchestration. They are traced from the config, so they can be suggested
+or added to the targets.
+ */
+
+use crate::prefix;
+use serde_yaml::Value;
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Default PATH of the container, if the config has none
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Shell builtins and keywords, those are not binaries
+const BUILTINS: &[&str] = &[
+    "exit", "cd", "export", "set", "unset", "eval", "source", ".", ":", "[", "[[", "test", "echo", "true", "false", "printf",
+    "if", "then", "else", "fi", "for", "do", "done", "while", "case", "esac", "!", "{", "}",
+];
+
+/// Command prefixes, which run the next word
+const WRAPPERS: &[&str] = &["exec", "command", "nohup", "time"];
+
+/// Found binaries and not found commands with the instruction, referencing them
+type Binaries = (Vec<(PathBuf, &'static str)>, Vec<(String, &'static str)>);
+
+#[derive(Clone, Debug)]
+pub struct ImageConfig {
+    // Referenced commands and the instruction, referencing them
+    refs: Vec<(String, &'static str)>,
+    path: String,
+    workdir: PathBuf,
+}
+
+impl ImageConfig {
+    /// Load image config, e.g. output of "skopeo inspect --config" or "docker inspect".
+    /// JSON is read as YAML, which is its superset.
+    pub fn load(p: &Path) -> Result<Self, Error> {
+        let mut v = serde_yaml::from_str::<Value>(&fs::read_to_string(p)?).map_err(|err| {
+            Error::new(ErrorKind::InvalidData, format!("Unable to read image config {}: {}", p.to_str().unwrap(), err))
+        })?;
+
+        // "docker inspect" returns a list of images
+        if let Value::Sequence(s) = v {
+            v = s.into_iter().next().unwrap_or_default();
+        }
+        let cfg = match v.get("config").or_else(|| v.get("Config")) {
+            Some(c) => c.to_owned(),
+            None => v,
+        };
+
+        let strings = |v: Option<&Value>| -> Vec<String> {
+            match v {
+                Some(Value::Sequence(s)) => s.iter().filter_map(|s| s.as_str().map(|s| s.to_string())).collect(),
+                Some(Value::String(s)) => vec![s.to_owned()],
+                _ => vec![],
+            }
+        };
+
+        let mut ic = ImageConfig {
+            refs: vec![],
+            path: strings(cfg.get("Env"))
+                .iter()
+                .find_map(|e| e.strip_prefix("PATH=").map(|p| p.to_string()))
+                .unwrap_or(DEFAULT_PATH.to_string()),
+            workdir: PathBuf::from(cfg.get("WorkingDir").and_then(|w| w.as_str()).unwrap_or("/")),
+        };
+
+        let entrypoint = strings(cfg.get("Entrypoint"));
+        let mut argv = entrypoint.to_owned();
+        argv.extend(strings(cfg.get("Cmd")));
+        ic.add_exec(&argv, if entrypoint.is_empty() { "CMD" } else { "ENTRYPOINT" });
+
+        let test = strings(cfg.get("Healthcheck").and_then(|h| h.get("Test")));
+        match test.first().map(|t| t.as_str()) {
+            Some("CMD") => ic.add_exec(&test[1..], "HEALTHCHECK"),
+            Some("CMD-SHELL") => {
+                ic.refs.push(("/bin/sh".to_string(), "HEALTHCHECK"));
+                ic.add_shell(&test[1..].join(" "), "HEALTHCHECK");
+            }
+            _ => {}
+        }
+
+        // Only RUN of the trigger instructions executes anything
+        for ob in strings(cfg.get("OnBuild")) {
+            let ob = ob.trim();
+            if let Some(cmd) = ob.strip_prefix("RUN ").map(|c| c.trim()) {
+                if cmd.starts_with('[') {
+                    ic.add_exec(&strings(serde_yaml::from_str::<Value>(cmd).ok().as_ref()), "ONBUILD");
+                } else {
+                    ic.refs.push(("/bin/sh".to_string(), "ONBUILD"));
+                    ic.add_shell(cmd, "ONBUILD");
+                }
+            }
+        }
+
+        Ok(ic)
+    }
+
+    /// Add command of the exec form. Shell form is stored as exec form of "sh -c".
+    fn add_exec(&mut self, argv: &[String], src: &'static str) {
+        if let Some(cmd) = argv.first() {
+            self.refs.push((cmd.to_owned(), src));
+            let sh = Path::new(cmd).file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if ["sh", "bash", "dash", "ash"].contains(&sh) && argv.get(1).map(|a| a == "-c").unwrap_or(false) {
+                self.add_shell(&argv[2..].join(" "), src);
+            }
+        }
+    }
+
+    /// Add commands of a shell command line
+    fn add_shell(&mut self, line: &str, src: &'static str) {
+        let line = ["&&", "||", "|", "\n", "$(", "`", "(", ")"].iter().fold(line.to_string(), |l, s| l.replace(s, ";"));
+        for cmd in line.split(';') {
+            let words = cmd.split_whitespace().map(|w| w.trim_matches(|c| c == '"' || c == '\'')).collect::<Vec<&str>>();
+            if let Some(w) = words.into_iter().find(|w| !w.contains('=') && !WRAPPERS.contains(w)) {
+                if !BUILTINS.contains(&w) {
+                    self.refs.push((w.to_string(), src));
+                }
+            }
+        }
+    }
+
+    /// Find the command in the current filesystem, the way the container runtime does
+    fn resolve(&self, cmd: &str) -> Option<PathBuf> {
+        let is_exe = |p: &Path| prefix::metadata(p).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false);
+        if cmd.contains('/') {
+            let p = self.workdir.join(cmd);
+            return if is_exe(&p) { Some(p) } else { None };
+        }
+
+        self.path.split(':').map(|d| Path::new(d).join(cmd)).find(|p| is_exe(p))
+    }
+
+    /// Get referenced binaries with the instruction, referencing them.
+    /// Commands, those are not found in the current filesystem, are returned separately.
+    pub fn get_binaries(&self) -> Binaries {
+        let (mut found, mut missing): Binaries = (vec![], vec![]);
+        for (cmd, src) in &self.refs {
+            match self.resolve(cmd) {
+                Some(p) => {
+                    if !found.iter().any(|(f, _)| *f == p) {
+                        found.push((p, src));
+                    }
+                }
+                None => {
+                    if !missing.iter().any(|(m, _)| m == cmd) {
+                        missing.push((cmd.to_owned(), src));
+                    }
+                }
+            }
+        }
+
+        (found, missing)
+    }
+}
@@ -0,0 +1,121 @@
+/*
+Path interner.
+
+Every path is stored once in an append-only arena and is referred by a small
+integer id. Sets of ids are much cheaper to build, clone and compare than sets
+of PathBuf on images with millions of files.
+
+Only the rootfs dissector holds interned paths: it is the only place, where all
+paths of the image are kept and cloned. Scanners and filters work on the kept
+files, which are a small part of the image, so they stay on PathBuf.
+ */
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    sync::{PoisonError, RwLock},
+};
+
+/// Size of a single arena chunk
+const CHUNK_SIZE: usize = 0x10000;
+
+/// Interned paths are handed out as `&'static Path`, so the arena is never reset
+/// nor freed until the process exits. A process tints a single rootfs, which is
+/// scanned once, so the arena does not grow past the paths of that rootfs.
+static INTERNER: RwLock<Option<Interner>> = RwLock::new(None);
+
+/// Id of an interned path
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct PathId(u32);
+
+impl PathId {
+    /// Get the interned path
+    pub fn path(self) -> &'static Path {
+        resolve(self)
+    }
+}
+
+struct Interner {
+    free: &'static mut [u8],
+    paths: Vec<&'static Path>,
+    ids: HashMap<&'static Path, PathId>,
+}
+
+impl Interner {
+    fn new() -> Self {
+        Interner { free: &mut [], paths: Vec::default(), ids: HashMap::default() }
+    }
+
+    /// Copy path bytes into the arena. Chunks are never freed nor moved,
+    /// so the handed out slices stay valid.
+    fn alloc(&mut self, b: &[u8]) -> &'static [u8] {
+        if b.len() > self.free.len() {
+            let chunk: &'static mut [u8] = Box::leak(vec![0u8; CHUNK_SIZE.max(b.len())].into_boxed_slice());
+            if b.len() > CHUNK_SIZE {
+                chunk.copy_from_slice(b);
+                return chunk;
+            }
+            self.free = chunk;
+        }
+
+        let (used, rest) = std::mem::take(&mut self.free).split_at_mut(b.len());
+        used.copy_from_slice(b);
+        self.free = rest;
+
+        used
+    }
+
+    fn intern(&mut self, p: &Path) -> PathId {
+        if let Some(id) = self.ids.get(p) {
+            return *id;
+        }
+
+        let ip = Path::new(OsStr::from_bytes(self.alloc(p.as_os_str().as_bytes())));
+        let id = PathId(self.paths.len() as u32);
+        self.paths.push(ip);
+        self.ids.insert(ip, id);
+
+        id
+    }
+}
+
+/// Intern a path
+pub fn intern(p: &Path) -> PathId {
+    INTERNER.write().unwrap_or_else(PoisonError::into_inner).get_or_insert_with(Interner::new).intern(p)
+}
+
+/// Get id of a path, if it was interned
+pub fn lookup(p: &Path) -> Option<PathId> {
+    INTERNER.read().unwrap_or_else(PoisonError::into_inner).as_ref().and_then(|itn| itn.ids.get(p).copied())
+}
+
+/// Get interned path by its id
+pub fn resolve(id: PathId) -> &'static Path {
+    INTERNER.read().unwrap_or_else(PoisonError::into_inner).as_ref().expect("Path interner is empty").paths[id.0 as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{intern, lookup, CHUNK_SIZE};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn interns_once() {
+        let a = intern(Path::new("/test/interner/a"));
+        assert_eq!(intern(&PathBuf::from("/test/interner/a")), a);
+        assert_eq!(a.path(), Path::new("/test/interner/a"));
+        assert_eq!(lookup(Path::new("/test/interner/a")), Some(a));
+        assert_eq!(lookup(Path::new("/test/interner/never")), None);
+
+        // Paths longer than a chunk get their own one, and the rest are not moved
+        let long = format!("/test/interner/{}", "x".repeat(CHUNK_SIZE));
+        let b = intern(Path::new(&long));
+        let c = intern(Path::new("/test/interner/c"));
+        assert_ne!(b, c);
+        assert_eq!(b.path().as_os_str().len(), long.len());
+        assert_eq!(c.path(), Path::new("/test/interner/c"));
+        assert_eq!(a.path(), Path::new("/test/interner/a"));
+    }
+}
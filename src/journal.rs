@@ -0,0 +1,173 @@
+/*
+Journal of the removals. It is written inside the image before anything
+is removed and tracks the progress, so an interrupted run can be resumed
+and rolled forward instead of leaving the image half-tinted.
+
+The first line is the pending work as JSON, every further line is the
+number of the paths, those are already processed.
+ */
+
+use crate::manifest::RemovalManifest;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Location of the journal inside the image
+pub const JOURNAL_PATH: &str = "/.tinted.journal";
+
+/// Progress is synced after this many removals
+const SYNC_STEP: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Pending {
+    profile: String,
+    quarantine: bool,
+    // Backup archive of the run, finished only when the run is complete
+    #[serde(default)]
+    backup: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+    kept: Vec<PathBuf>,
+    removing: RemovalManifest,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    pending: Pending,
+    done: usize,
+}
+
+impl Journal {
+    /// Create the journal of the pending removals and sync it to the disk
+    pub fn create(
+        p: &Path, profile: String, quarantine: bool, backup: Option<&Path>, paths: &[PathBuf], kept: &[PathBuf],
+        removing: &RemovalManifest,
+    ) -> Result<Self, Error> {
+        let pending = Pending {
+            profile,
+            quarantine,
+            backup: backup.map(|b| b.to_owned()),
+            paths: paths.to_vec(),
+            kept: kept.to_vec(),
+            removing: removing.clone(),
+        };
+        let mut file = File::create(p)?;
+        serde_json::to_writer(&mut file, &pending)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+
+        Ok(Journal { path: p.to_owned(), file, pending, done: 0 })
+    }
+
+    /// Load the journal of an interrupted run
+    pub fn load(p: &Path) -> Result<Self, Error> {
+        let data = fs::read_to_string(p)?;
+        let mut lines = data.lines();
+        let pending = serde_json::from_str::<Pending>(lines.next().unwrap_or_default()).map_err(|err| {
+            Error::new(ErrorKind::InvalidData, format!("Unable to read journal {}: {}", p.to_str().unwrap(), err))
+        })?;
+
+        // Last line might be torn by the interruption
+        let done = lines.filter_map(|l| l.trim().parse::<usize>().ok()).max().unwrap_or(0).min(pending.paths.len());
+
+        Ok(Journal { path: p.to_owned(), file: OpenOptions::new().append(true).open(p)?, pending, done })
+    }
+
+    /// Record that the paths are processed up to the index
+    pub fn progress(&mut self, done: usize) -> Result<(), Error> {
+        self.done = done;
+        if done % SYNC_STEP == 0 || done == self.pending.paths.len() {
+            writeln!(self.file, "{}", done)?;
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Get number of the processed paths
+    pub fn get_done(&self) -> usize {
+        self.done
+    }
+
+    /// Get all paths to be removed
+    pub fn get_paths(&self) -> &[PathBuf] {
+        &self.pending.paths
+    }
+
+    /// Get paths, those are kept in the image
+    pub fn get_kept(&self) -> &[PathBuf] {
+        &self.pending.kept
+    }
+
+    /// Get description of the files to be removed
+    pub fn get_removing(&self) -> &RemovalManifest {
+        &self.pending.removing
+    }
+
+    /// Get profile identifier of the run
+    pub fn get_profile(&self) -> &str {
+        &self.pending.profile
+    }
+
+    /// Returns true if removed files are moved into the quarantine
+    pub fn is_quarantine(&self) -> bool {
+        self.pending.quarantine
+    }
+
+    /// Get backup archive of the run, if it was taken
+    pub fn get_backup(&self) -> Option<&Path> {
+        self.pending.backup.as_deref()
+    }
+
+    /// Remove the journal, once the run is complete
+    pub fn close(self) -> Result<(), Error> {
+        drop(self.file);
+        fs::remove_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Journal, SYNC_STEP};
+    use crate::{manifest::RemovalManifest, testutil::scratch};
+    use std::{
+        fs::OpenOptions,
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    #[test]
+    fn resumes_progress() {
+        let p = scratch("journal").join("journal");
+        let paths: Vec<PathBuf> = (0..SYNC_STEP * 2 + 1).map(|i| PathBuf::from(format!("/usr/share/doc/{}", i))).collect();
+        let mut j = Journal::create(
+            &p,
+            "test".to_string(),
+            false,
+            Some(Path::new("/tmp/backup.tar")),
+            &paths,
+            &[PathBuf::from("/usr/bin/kept")],
+            &RemovalManifest::new(&paths),
+        )
+        .unwrap();
+        for i in 1..=SYNC_STEP + 3 {
+            j.progress(i).unwrap();
+        }
+        drop(j);
+
+        // Progress is synced by steps, and the last line might be torn by the interruption
+        OpenOptions::new().append(true).open(&p).unwrap().write_all(b"12").unwrap();
+        let j = Journal::load(&p).unwrap();
+        assert_eq!(j.get_done(), SYNC_STEP);
+        assert_eq!(j.get_paths(), paths.as_slice());
+        assert_eq!(j.get_kept(), [PathBuf::from("/usr/bin/kept")]);
+        assert_eq!(j.get_profile(), "test");
+        assert_eq!(j.get_backup(), Some(Path::new("/tmp/backup.tar")));
+
+        j.close().unwrap();
+        assert!(!p.exists());
+    }
+}
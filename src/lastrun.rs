@@ -0,0 +1,129 @@
+/*
+Decisions of the previous dry run.
+
+Every dry run records its kept and removed paths in a state file on the
+host, one per root filesystem. While a profile is iterated, only the paths
+with changed decisions are shown against the previous run, instead of the
+whole tree every time.
+
+The state file is opened before chroot, as it is on the host.
+ */
+
+use crate::{digest, prefix, workspace};
+use bytesize::ByteSize;
+use colored::Colorize;
+use std::{
+    collections::BTreeSet,
+    fs::{File, OpenOptions},
+    io::{Error, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Signs of the kept and removed paths in the state file
+const KEPT: &str = "+";
+const REMOVED: &str = "-";
+
+pub struct LastRun {
+    file: File,
+    path: PathBuf,
+    previous: Option<(BTreeSet<PathBuf>, BTreeSet<PathBuf>)>,
+}
+
+impl LastRun {
+    /// Open the state file of the root filesystem and read the decisions of the previous run
+    pub fn open(root: &Path) -> Result<Self, Error> {
+        let path =
+            workspace::get_base().join(format!("mezzotint.{}.last", &digest::sha256(root.to_str().unwrap().as_bytes())[..16]));
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let mut data = String::new();
+        file.read_to_string(&mut data)?;
+
+        let previous = if data.is_empty() {
+            None
+        } else {
+            let (mut kept, mut removed) = (BTreeSet::default(), BTreeSet::default());
+            for l in data.lines() {
+                match l.split_once(' ') {
+                    Some((KEPT, p)) => kept.insert(PathBuf::from(p)),
+                    Some((REMOVED, p)) => removed.insert(PathBuf::from(p)),
+                    _ => continue,
+                };
+            }
+            Some((kept, removed))
+        };
+
+        Ok(LastRun { file, path, previous })
+    }
+
+    /// Has the root filesystem been analysed before
+    pub fn has_previous(&self) -> bool {
+        self.previous.is_some()
+    }
+
+    /// Write the decisions of this run over the previous ones
+    pub fn save(&mut self, kept: &[PathBuf], removed: &[PathBuf]) -> Result<(), Error> {
+        let mut out = String::new();
+        for (sign, paths) in [(KEPT, kept), (REMOVED, removed)] {
+            for p in paths {
+                out.push_str(&format!("{} {}\n", sign, p.to_str().unwrap()));
+            }
+        }
+
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(out.as_bytes())?;
+        log::debug!("Saved decisions of the dry run to {}", self.path.to_str().unwrap());
+
+        Ok(())
+    }
+
+    /// Print one side of the change
+    fn format_side(title: &str, sign: &str, paths: &[&PathBuf]) {
+        println!("\n{}", title.bright_blue().bold());
+        println!("{}", "──┬──┄┄╌╌ ╌  ╌".blue());
+        let sizes = paths.iter().map(|p| prefix::symlink_metadata(p).map(|m| m.len()).unwrap_or(0)).collect::<Vec<u64>>();
+        let width = paths.iter().map(|p| p.to_str().unwrap().chars().count()).max().unwrap_or(0);
+        for (i, p) in paths.iter().enumerate() {
+            let leaf = if i == paths.len() - 1 { "  ╰─" } else { "  ├─" };
+            let line = format!("{} {:<width$}", sign, p.to_str().unwrap());
+            println!(
+                "{} {} {}",
+                leaf.blue(),
+                if sign == KEPT { line.bright_green() } else { line.bright_red() },
+                format!("{:>10}", ByteSize::b(sizes[i]).to_string()).bright_yellow()
+            );
+        }
+        println!(
+            "{}{}{}{}",
+            "Files: ".blue(),
+            paths.len().to_string().bright_blue(),
+            ", Size: ".blue(),
+            ByteSize::b(sizes.iter().sum()).to_string().bright_blue()
+        );
+    }
+
+    /// Print paths, those decisions are changed since the previous run
+    pub fn format(&self, kept: &[PathBuf], removed: &[PathBuf]) {
+        let (p_kept, p_removed) = match &self.previous {
+            Some(previous) => previous,
+            None => return,
+        };
+
+        let mut new_kept = kept.iter().filter(|p| !p_kept.contains(*p)).collect::<Vec<&PathBuf>>();
+        let mut new_removed = removed.iter().filter(|p| !p_removed.contains(*p)).collect::<Vec<&PathBuf>>();
+        new_kept.sort();
+        new_removed.sort();
+        if new_kept.is_empty() && new_removed.is_empty() {
+            println!("No changes since the previous run");
+            return;
+        }
+
+        if !new_kept.is_empty() {
+            Self::format_side("Kept since the previous run", KEPT, &new_kept);
+        }
+        if !new_removed.is_empty() {
+            Self::format_side("Removed since the previous run", REMOVED, &new_removed);
+        }
+        println!();
+    }
+}
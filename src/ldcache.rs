@@ -0,0 +1,195 @@
+/*
+Cache of the dynamic linker.
+
+After the tint /etc/ld.so.cache still lists the removed libraries, so the
+loader tries them first. Entries of the missing libraries are pruned from
+the cache in place, so no ldconfig is needed in the image.
+
+Only the format of glibc 2.32 and newer ("glibc-ld.so.cache1.1") is
+supported. Its strings are addressed by offsets from the beginning of the
+file, so the entries are compacted, while the string table stays where it
+is.
+ */
+
+use crate::{prefix, xattrs};
+use std::{
+    ffi::CString,
+    fs,
+    io::Error,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::PathBuf,
+};
+
+pub const LD_SO_CACHE: &str = "/etc/ld.so.cache";
+
+const MAGIC: &[u8] = b"glibc-ld.so.cache1.1";
+
+/// Header: magic, number of entries, length of strings, flags, extension offset and unused fields
+const HEADER_LEN: usize = 48;
+
+/// Entry: flags, key, value, OS version and hwcap
+const ENTRY_LEN: usize = 24;
+
+/// Offset of the number of entries in the header
+const NLIBS_OFFSET: usize = 20;
+
+/// Offset of the value (path of the library) in the entry
+const VALUE_OFFSET: usize = 8;
+
+pub struct LdCache {
+    data: Vec<u8>,
+}
+
+impl LdCache {
+    /// Load the cache of the image. Returns None, if there is no cache or its format is not supported.
+    pub fn load() -> Result<Option<Self>, Error> {
+        if !prefix::exists(LD_SO_CACHE) {
+            return Ok(None);
+        }
+
+        let cache = LdCache { data: fs::read(prefix::host(LD_SO_CACHE))? };
+        if !cache.data.starts_with(MAGIC)
+            || cache.data.len() < HEADER_LEN
+            || HEADER_LEN + cache.nlibs() * ENTRY_LEN > cache.data.len()
+        {
+            log::debug!("Format of {} is not supported, it is left as is", LD_SO_CACHE);
+            return Ok(None);
+        }
+
+        Ok(Some(cache))
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_ne_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn nlibs(&self) -> usize {
+        self.u32_at(NLIBS_OFFSET) as usize
+    }
+
+    /// Get zero-terminated string at the offset
+    fn string_at(&self, offset: usize) -> Option<PathBuf> {
+        let s = self.data.get(offset..)?;
+        let end = s.iter().position(|b| *b == 0)?;
+        Some(PathBuf::from(String::from_utf8_lossy(&s[..end]).to_string()))
+    }
+
+    /// Remove entries of the libraries, those are missing. Returns paths of the removed entries.
+    pub fn prune(&mut self) -> Vec<PathBuf> {
+        let mut kept: Vec<u8> = vec![];
+        let mut pruned: Vec<PathBuf> = vec![];
+        let nlibs = self.nlibs();
+        for i in 0..nlibs {
+            let offset = HEADER_LEN + i * ENTRY_LEN;
+            match self.string_at(self.u32_at(offset + VALUE_OFFSET) as usize) {
+                Some(lib) if !prefix::exists(&lib) => pruned.push(lib),
+                _ => kept.extend_from_slice(&self.data[offset..offset + ENTRY_LEN]),
+            }
+        }
+
+        // Entries stay sorted, as the loader looks them up by binary search. The freed space is zeroed.
+        let end = HEADER_LEN + nlibs * ENTRY_LEN;
+        kept.resize(end - HEADER_LEN, 0);
+        self.data[HEADER_LEN..end].copy_from_slice(&kept);
+        self.data[NLIBS_OFFSET..NLIBS_OFFSET + 4].copy_from_slice(&((nlibs - pruned.len()) as u32).to_ne_bytes());
+
+        pruned
+    }
+
+    /// Write the cache back to the image. It is written into a temporary file next to the cache
+    /// and moved over it, so the loader never reads a truncated cache.
+    pub fn save(&self) -> Result<(), Error> {
+        let hp = prefix::host(LD_SO_CACHE);
+        let t = hp.with_file_name(".ld.so.cache.mezzotint");
+        let r = fs::write(&t, &self.data)
+            .and_then(|_| {
+                // Owner is set first, as it drops setuid and setgid bits
+                let m = fs::metadata(&hp)?;
+                let ct = CString::new(t.as_os_str().as_bytes())?;
+                if unsafe { libc::lchown(ct.as_ptr(), m.uid(), m.gid()) } != 0 {
+                    return Err(Error::last_os_error());
+                }
+                fs::set_permissions(&t, m.permissions())?;
+                xattrs::copy_security(&hp, &t)
+            })
+            .and_then(|_| fs::rename(&t, &hp));
+        if r.is_err() {
+            let _ = fs::remove_file(&t);
+        }
+
+        r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LdCache, ENTRY_LEN, HEADER_LEN, LD_SO_CACHE, MAGIC, NLIBS_OFFSET, VALUE_OFFSET};
+    use crate::{prefix, testutil::scratch};
+    use std::{fs, path::PathBuf, sync::PoisonError};
+
+    /// Build a cache of the libraries: header, entries and then the string table of the keys and the paths
+    fn cache(libs: &[&str]) -> Vec<u8> {
+        let strings_at = HEADER_LEN + libs.len() * ENTRY_LEN;
+        let mut strings: Vec<u8> = vec![];
+        let mut entries: Vec<u8> = vec![];
+        for lib in libs {
+            let key = strings_at + strings.len();
+            strings.extend(PathBuf::from(lib).file_name().unwrap().to_str().unwrap().as_bytes());
+            strings.push(0);
+            let value = strings_at + strings.len();
+            strings.extend(lib.as_bytes());
+            strings.push(0);
+
+            entries.extend(0x303i32.to_ne_bytes());
+            entries.extend((key as u32).to_ne_bytes());
+            entries.extend((value as u32).to_ne_bytes());
+            entries.extend(0u32.to_ne_bytes());
+            entries.extend(0u64.to_ne_bytes());
+        }
+
+        let mut out = MAGIC.to_vec();
+        out.extend((libs.len() as u32).to_ne_bytes());
+        out.extend((strings.len() as u32).to_ne_bytes());
+        out.resize(HEADER_LEN, 0);
+        out.extend(entries);
+        out.extend(strings);
+        out
+    }
+
+    #[test]
+    fn prunes_missing() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("ldcache");
+        fs::create_dir_all(d.join("etc")).unwrap();
+        fs::create_dir_all(d.join("lib")).unwrap();
+        fs::write(d.join("lib/liba.so.1"), "").unwrap();
+        fs::write(d.join("lib/libc.so.1"), "").unwrap();
+        let data = cache(&["/lib/liba.so.1", "/lib/libb.so.1", "/lib/libc.so.1"]);
+        fs::write(d.join("etc/ld.so.cache"), &data).unwrap();
+        prefix::set(Some(&d));
+
+        let mut c = LdCache::load().unwrap().unwrap();
+        assert_eq!(c.prune(), [PathBuf::from("/lib/libb.so.1")]);
+        c.save().unwrap();
+        assert!(!d.join("etc/.ld.so.cache.mezzotint").exists());
+
+        let c = LdCache::load().unwrap().unwrap();
+        assert_eq!(c.nlibs(), 2);
+        assert_eq!(c.data.len(), data.len());
+        let values = (0..c.nlibs())
+            .map(|i| c.string_at(c.u32_at(HEADER_LEN + i * ENTRY_LEN + VALUE_OFFSET) as usize).unwrap())
+            .collect::<Vec<PathBuf>>();
+        assert_eq!(values, [PathBuf::from("/lib/liba.so.1"), PathBuf::from("/lib/libc.so.1")]);
+
+        // Header stays but the number of entries, the freed entry is zeroed and the strings are where they were
+        let strings_at = HEADER_LEN + 3 * ENTRY_LEN;
+        assert_eq!(c.data[..NLIBS_OFFSET], data[..NLIBS_OFFSET]);
+        assert_eq!(c.data[NLIBS_OFFSET + 4..HEADER_LEN], data[NLIBS_OFFSET + 4..HEADER_LEN]);
+        assert!(c.data[HEADER_LEN + 2 * ENTRY_LEN..strings_at].iter().all(|b| *b == 0));
+        assert_eq!(c.data[strings_at..], data[strings_at..]);
+        assert_eq!(fs::read(prefix::host(LD_SO_CACHE)).unwrap(), c.data);
+
+        prefix::set(None);
+        fs::remove_dir_all(&d).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+/*
+Profile validation without running the tint
+ */
+
+use crate::{
+    filters::junk::JUNK_KINDS,
+    glob,
+    osinfo::OsInfo,
+    profile::{Profile, AUTODEPS, FILTERS, PIPELINE},
+    scanner::pkgbackend,
+};
+use colored::Colorize;
+use serde_yaml::Value;
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    os::unix,
+    path::{Path, PathBuf},
+};
+
+/// Known keys of the profile structure
+const K_PROFILE: &[&str] = &["version", "targets", "packages", "config", "hooks", "verify", "sections"];
+const K_CONFIG: &[&str] = &[
+    "filters",
+    "pipeline",
+    "prune",
+    "keep",
+    "locales",
+    "expect_undeletable",
+    "timezones",
+    "package_rules",
+    "junk_extensions",
+    "never_junk",
+    "keep_licenses",
+    "gpu",
+    "trim_accounts",
+    "normalise_permissions",
+    "strict",
+    "lockfile",
+    "keep_dirs",
+    "allow_dangling",
+    "drop_pkgdb",
+    "strip",
+    "dedup",
+];
+const K_PACKAGE_RULES: &[&str] = &["keep", "prune"];
+const K_HOOKS: &[&str] = &["before", "after", "pre", "post"];
+const K_SECTION: &[&str] = &["when", "targets", "packages", "config"];
+const K_CONDITION: &[&str] = &["os", "arch"];
+const K_TARGET: &[&str] = &["path", "autodeps"];
+
+/// Profile linter
+pub struct ProfileLinter {
+    sources: Vec<String>,
+    root: Option<PathBuf>,
+    issues: Vec<String>,
+}
+
+impl ProfileLinter {
+    pub fn new(sources: Vec<String>) -> Self {
+        ProfileLinter { sources, root: None, issues: vec![] }
+    }
+
+    /// Set root filesystem, against which targets and rules are checked
+    pub fn set_root(&mut self, root: Option<PathBuf>) -> &mut Self {
+        self.root = root;
+        self
+    }
+
+    /// Register an issue
+    fn issue(&mut self, msg: String) {
+        log::warn!("{}", msg);
+        self.issues.push(msg);
+    }
+
+    /// Check keys of a mapping against the known ones
+    fn check_keys(&mut self, src: &str, path: &str, v: &Value, known: &[&str]) {
+        let m = match v.as_mapping() {
+            Some(m) => m,
+            None => {
+                if !v.is_null() {
+                    self.issue(format!("{}: \"{}\" should be a mapping", src, path));
+                }
+                return;
+            }
+        };
+
+        for k in m.keys() {
+            let k = k.as_str().unwrap_or_default();
+            if !known.contains(&k) {
+                self.issue(format!(
+                    "{}: unknown key \"{}{}\"",
+                    src,
+                    if path.is_empty() { "".to_string() } else { format!("{}.", path) },
+                    k
+                ));
+            }
+        }
+    }
+
+    /// Check configuration section
+    fn check_config(&mut self, src: &str, path: &str, cfg: &Value) {
+        self.check_keys(src, path, cfg, K_CONFIG);
+
+        if let Some(flt) = cfg.get("filters").and_then(|f| f.as_sequence()) {
+            for f in flt.iter().filter_map(|f| f.as_str()) {
+                if !FILTERS.contains(&f) {
+                    self.issue(format!("{}: unknown filter \"{}\" in \"{}.filters\"", src, f, path));
+                }
+            }
+        }
+
+        if let Some(pl) = cfg.get("pipeline").and_then(|f| f.as_sequence()) {
+            for s in pl.iter().filter_map(|s| s.as_str()) {
+                if !PIPELINE.contains(&s.trim_start_matches('-')) {
+                    self.issue(format!("{}: unknown stage \"{}\" in \"{}.pipeline\"", src, s, path));
+                } else if s == "-rules" {
+                    self.issue(format!("{}: keep and prune rules cannot be disabled in \"{}.pipeline\"", src, path));
+                }
+            }
+        }
+
+        if let Some(je) = cfg.get("junk_extensions") {
+            let known = [&["replace"], JUNK_KINDS].concat();
+            self.check_keys(src, &format!("{}.junk_extensions", path), je, &known);
+        }
+
+        if let Some(lf) = cfg.get("lockfile") {
+            let lf = lf.as_str().unwrap_or_default();
+            if lf != "none" && !lf.starts_with('/') {
+                self.issue(format!("{}: \"{}.lockfile\" should be an absolute path or \"none\", not \"{}\"", src, path, lf));
+            }
+        }
+
+        if let Some(rules) = cfg.get("package_rules").and_then(|r| r.as_mapping()) {
+            for (pkg, r) in rules {
+                self.check_keys(src, &format!("{}.package_rules.{}", path, pkg.as_str().unwrap_or_default()), r, K_PACKAGE_RULES);
+            }
+        }
+    }
+
+    /// Check targets, those might be given with their own settings
+    fn check_targets(&mut self, src: &str, path: &str, targets: &Value) {
+        for (i, t) in targets.as_sequence().into_iter().flatten().enumerate() {
+            if t.is_string() {
+                continue;
+            }
+
+            let tpath = format!("{}[{}]", path, i);
+            self.check_keys(src, &tpath, t, K_TARGET);
+            if t.get("path").and_then(|p| p.as_str()).is_none() {
+                self.issue(format!("{}: \"{}.path\" is missing", src, tpath));
+            }
+            if let Some(ad) = t.get("autodeps") {
+                let ad = ad.as_str().unwrap_or_default();
+                if !AUTODEPS.contains(&ad) {
+                    self.issue(format!("{}: unknown autodeps mode \"{}\" in \"{}.autodeps\"", src, ad, tpath));
+                }
+            }
+        }
+    }
+
+    /// Check structure of one profile source. Returns false if it cannot be parsed at all.
+    fn check_structure(&mut self, src: &str) -> Result<bool, Error> {
+        let v = match Profile::read_value(src).and_then(|v| Profile::migrate(src, v)) {
+            Ok(v) => v,
+            Err(err) if err.kind() == ErrorKind::InvalidData => {
+                self.issue(err.to_string());
+                return Ok(false);
+            }
+            Err(err) => return Err(err),
+        };
+
+        self.check_keys(src, "", &v, K_PROFILE);
+        match v.get("targets") {
+            Some(targets) => self.check_targets(src, "targets", targets),
+            None => self.issue(format!("{}: \"targets\" is missing", src)),
+        }
+        if let Some(cfg) = v.get("config") {
+            self.check_config(src, "config", cfg);
+        }
+        if let Some(hooks) = v.get("hooks") {
+            self.check_keys(src, "hooks", hooks, K_HOOKS);
+        }
+        if let Some(sections) = v.get("sections").and_then(|s| s.as_sequence()) {
+            for (i, s) in sections.iter().enumerate() {
+                let path = format!("sections[{}]", i);
+                self.check_keys(src, &path, s, K_SECTION);
+                match s.get("when") {
+                    Some(w) => self.check_keys(src, &format!("{}.when", path), w, K_CONDITION),
+                    None => self.issue(format!("{}: \"{}.when\" is missing", src, path)),
+                }
+                if let Some(targets) = s.get("targets") {
+                    self.check_targets(src, &format!("{}.targets", path), targets);
+                }
+                if let Some(cfg) = s.get("config") {
+                    self.check_config(src, &format!("{}.config", path), cfg);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Check the profile against the root filesystem
+    fn check_root(&mut self, root: &Path, mut profile: Profile) -> Result<(), Error> {
+        unix::fs::chroot(root)?;
+        std::env::set_current_dir("/")?;
+        profile.apply_conditions(&OsInfo::detect());
+        for t in profile.expand_targets() {
+            self.issue(format!("Target {} does not match any executables", t));
+        }
+
+        for t in profile.get_targets().to_owned() {
+            if !Path::new(&t).exists() {
+                self.issue(format!("Target {} does not exist", t));
+            }
+        }
+
+        let pkb = pkgbackend::detect();
+        if pkb.capabilities().owners {
+            for p in profile.get_packages().iter().chain(profile.get_dropped_packages().iter()).cloned().collect::<Vec<String>>()
+            {
+                if pkb.get_package_contents(p.to_owned()).map(|c| c.is_empty()).unwrap_or(true) {
+                    self.issue(format!("Package {} is not installed", p));
+                }
+            }
+        }
+
+        let mut kept: Vec<(String, Vec<PathBuf>)> = vec![];
+        for p in profile.get_keep_paths() {
+            let p = p.to_str().unwrap().to_string();
+            let m = glob::expand_rule(&p);
+            if m.is_empty() {
+                self.issue(format!("Keep rule {} matches nothing", p));
+            }
+            kept.push((p, m));
+        }
+
+        for p in profile.get_prune_paths() {
+            let p = p.to_str().unwrap().to_string();
+            if glob::expand_tree(&p).is_empty() {
+                self.issue(format!("Prune rule {} matches nothing", p));
+            }
+
+            let mut conflicts: HashMap<String, usize> = HashMap::default();
+            for (k, m) in &kept {
+                let n = m.iter().filter(|x| glob::matches_tree(&p, x)).count();
+                if n > 0 {
+                    conflicts.insert(k.to_owned(), n);
+                }
+            }
+            let mut conflicts = conflicts.into_iter().collect::<Vec<(String, usize)>>();
+            conflicts.sort();
+            for (k, n) in conflicts {
+                self.issue(format!("Prune rule {} conflicts with keep rule {} on {} paths", p, k, n));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lint the profile. Returns amount of found issues.
+    pub fn lint(&mut self) -> Result<usize, Error> {
+        self.issues.clear();
+
+        let mut profile = Profile::default();
+        for src in self.sources.clone() {
+            log::info!("Checking profile {}", src);
+            if self.check_structure(&src)? {
+                if let Err(err) = profile.add_profile(&src) {
+                    self.issue(err.to_string());
+                }
+            }
+        }
+
+        if let Some(root) = self.root.to_owned() {
+            log::info!("Checking profile against {}", root.to_str().unwrap().bright_yellow());
+            self.check_root(&root, profile)?;
+        }
+
+        if self.issues.is_empty() {
+            log::info!("No issues found");
+        } else {
+            log::error!("Found {} issues", self.issues.len());
+        }
+
+        Ok(self.issues.len())
+    }
+}
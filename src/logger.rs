@@ -1,6 +1,120 @@
-use chrono::Local;
+use chrono::{Local, SecondsFormat};
 use colored::{self, Colorize};
 use log::{Level, Metadata, Record};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{Error, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+/// Log to STDERR, e.g. when STDOUT carries a machine-readable output
+static STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Set logging to STDERR
+pub(crate) fn set_stderr(stderr: bool) {
+    STDERR.store(stderr, Ordering::Relaxed);
+}
+
+/// Log file on the host and whether it is written as JSON lines
+static LOG_FILE: Mutex<Option<(File, bool)>> = Mutex::new(None);
+
+/// Structured log event
+#[derive(Serialize)]
+struct Event<'a> {
+    time: String,
+    level: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<&'a str>,
+}
+
+impl<'a> Event<'a> {
+    fn new(level: &'a str) -> Self {
+        Event {
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            level,
+            module: None,
+            message: None,
+            event: None,
+            path: None,
+            action: None,
+            reason: None,
+            origin: None,
+        }
+    }
+}
+
+/// Set log file, where all messages are written in addition to the console, as text or JSON lines
+pub(crate) fn set_log_file(p: &Path, json: bool) -> Result<(), Error> {
+    *LOG_FILE.lock().unwrap() = Some((File::create(p)?, json));
+    Ok(())
+}
+
+/// Check if the log file is set
+pub(crate) fn has_log_file() -> bool {
+    LOG_FILE.lock().unwrap().is_some()
+}
+
+/// Write the event to the log file, if any. Text line is used, if the file is not JSON.
+fn write_file(e: &Event, text: &str) {
+    if let Some((f, is_json)) = LOG_FILE.lock().unwrap().as_mut() {
+        let line = if *is_json { serde_json::to_string(e).map(|l| l + "\n").unwrap_or_default() } else { format!("{}\n", text) };
+        if let Err(err) = f.write_all(line.as_bytes()) {
+            eprintln!("Unable to write the log file: {}", err);
+        }
+    }
+}
+
+/// Record the decision about the path with its reason into the log file
+pub(crate) fn decision(p: &Path, action: &str, reason: &str, origin: &str) {
+    let path = p.to_str().unwrap_or_default();
+    let mut e = Event::new("info");
+    e.event = Some("decision");
+    e.path = Some(path);
+    e.action = Some(action);
+    e.reason = Some(reason);
+    e.origin = if origin.is_empty() { None } else { Some(origin) };
+
+    let o = if origin.is_empty() { String::new() } else { format!(": {}", origin) };
+    write_file(&e, &format!("[{}] - DECISION: {} {} ({}{})", Local::now().format("%d/%m/%Y %H:%M:%S"), action, path, reason, o));
+}
+
+/// Amount of logged warnings and errors as (level, module, count)
+static COUNTERS: Mutex<Vec<(String, String, u64)>> = Mutex::new(Vec::new());
+
+/// Get amount of logged warnings and errors by the level and the module
+pub(crate) fn get_counters() -> Vec<(String, String, u64)> {
+    let mut c = COUNTERS.lock().unwrap().to_owned();
+    c.sort();
+    c
+}
+
+/// Count the warning or the error
+fn count(msg: &Record) {
+    let level = msg.level().as_str().to_lowercase();
+    let module = msg.target().rsplit("::").next().unwrap_or_default().to_string();
+    let mut c = COUNTERS.lock().unwrap();
+    match c.iter_mut().find(|(l, m, _)| *l == level && *m == module) {
+        Some(e) => e.2 += 1,
+        None => c.push((level, module, 1)),
+    }
+}
 
 pub(crate) struct STDOUTLogger;
 
@@ -11,6 +125,10 @@ impl log::Log for STDOUTLogger {
 
     fn log(&self, msg: &Record) {
         if self.enabled(msg.metadata()) {
+            if msg.level() <= Level::Warn {
+                count(msg);
+            }
+
             let s_level: String = match msg.level() {
                 log::Level::Info => format!("{}", msg.level().as_str().bright_green()),
                 log::Level::Warn => format!("{}", msg.level().as_str().yellow()),
@@ -19,7 +137,18 @@ impl log::Log for STDOUTLogger {
                 log::Level::Trace => format!("{}", msg.level().as_str().cyan()),
             };
 
-            println!("[{}] - {}: {}", Local::now().format("%d/%m/%Y %H:%M:%S"), s_level, msg.args());
+            let level = msg.level().as_str().to_lowercase();
+            let mut e = Event::new(&level);
+            e.module = Some(msg.target());
+            e.message = Some(msg.args().to_string());
+            write_file(&e, &format!("[{}] - {}: {}", Local::now().format("%d/%m/%Y %H:%M:%S"), msg.level().as_str(), msg.args()));
+
+            let line = format!("[{}] - {}: {}", Local::now().format("%d/%m/%Y %H:%M:%S"), s_level, msg.args());
+            if STDERR.load(Ordering::Relaxed) {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
         }
     }
 
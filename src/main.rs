@@ -1,16 +1,56 @@
+mod accounts;
+mod attestation;
+mod backup;
 mod clidef;
+mod csv;
+mod dedup;
+mod diff;
+mod digest;
 mod filters;
+mod fuse;
+mod gendoc;
+mod glob;
+mod graph;
+mod hardening;
+mod html;
+mod imgconfig;
+mod interner;
+mod journal;
+mod lastrun;
+mod ldcache;
+mod lint;
 mod logger;
+mod manifest;
+mod metrics;
+mod osinfo;
+mod plan;
+mod prefix;
 mod procdata;
 mod profile;
+mod provenance;
+mod quarantine;
+mod restore;
 mod rootfs;
+mod sbom;
+mod scancache;
 mod scanner;
+mod secpolicy;
 mod shcall;
+mod snapshot;
+mod strip;
+mod syscaches;
+#[cfg(test)]
+mod testutil;
+mod userns;
+mod whiteouts;
+mod workspace;
+mod xattrs;
 use crate::profile::Profile;
 use clap::{ArgMatches, Command};
 use colored::Colorize;
 use std::{
     env,
+    io::Write,
     path::{Path, PathBuf},
     process,
 };
@@ -18,6 +58,21 @@ use std::{
 static VERSION: &str = "0.1";
 static LOGGER: logger::STDOUTLogger = logger::STDOUTLogger;
 
+/// Options, those write a single file on the host
+const SINGLE_FILE_OPTIONS: &[&str] = &[
+    "plan",
+    "metrics-out",
+    "sbom",
+    "attestation",
+    "audit-log",
+    "report",
+    "graph",
+    "csv",
+    "export-profile",
+    "backup",
+    "whiteouts",
+];
+
 /// Get flag from the params and return inverted if requested
 fn f(params: &ArgMatches, name: &str) -> bool {
     let p = *params.get_one::<bool>(name).unwrap();
@@ -32,13 +87,29 @@ fn is_f(params: &ArgMatches, name: &str) -> bool {
     *params.get_one::<bool>(name).unwrap()
 }
 
+/// Get log level from the verbosity flags
+fn get_log_level(params: &ArgMatches) -> log::LevelFilter {
+    if params.get_flag("debug") {
+        return log::LevelFilter::Trace;
+    }
+    if params.get_flag("quiet") {
+        return log::LevelFilter::Warn;
+    }
+
+    match params.get_count("verbose") {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
+    }
+}
+
 /// Construct profile either from the CLI
 /// or get it from the YAML and configure it.
 fn get_profile(mut cli: Command, params: &ArgMatches) -> Profile {
     let exe = params.get_one::<String>("exe");
-    let profile_path = params.get_one::<String>("profile");
+    let profile_paths = params.get_many::<String>("profile").map(|p| p.collect::<Vec<&String>>());
 
-    if exe.is_none() && profile_path.is_none() {
+    if exe.is_none() && profile_paths.is_none() {
         cli.print_help().unwrap();
         process::exit(exitcode::OK);
     }
@@ -56,48 +127,58 @@ fn get_profile(mut cli: Command, params: &ArgMatches) -> Profile {
             .set_log(f(params, "f_log"))
             .set_arch(f(params, "f_arc"))
             .set_img(f(params, "f_pic"));
-    } else if let Some(profile_path) = profile_path {
-        log::info!("Getting profile at {profile_path}");
-        match Profile::new(Path::new(profile_path)) {
-            Ok(p) => {
-                profile = p;
-
-                // Override profile
-                if is_f(params, "f_man") {
-                    profile.set_manpages(f(params, "f_man"));
-                }
-                if is_f(params, "f_dir") {
-                    profile.set_manpages(f(params, "f_dir"));
-                }
-                if is_f(params, "f_doc") {
-                    profile.set_manpages(f(params, "f_doc"));
-                }
-                if is_f(params, "f_i18n") {
-                    profile.set_manpages(f(params, "f_i18n"));
-                }
-                if is_f(params, "f_l10n") {
-                    profile.set_manpages(f(params, "f_l10n"));
-                }
-                if is_f(params, "f_log") {
-                    profile.set_manpages(f(params, "f_log"));
-                }
-                if is_f(params, "f_pic") {
-                    profile.set_img(f(params, "f_pic"));
-                }
-                if is_f(params, "f_arc") {
-                    profile.set_arch(f(params, "f_arc"));
-                }
-            }
-            Err(err) => {
+    } else if let Some(profile_paths) = profile_paths {
+        for profile_path in profile_paths {
+            log::info!("Getting profile at {profile_path}");
+            if let Err(err) = profile.add_profile(profile_path) {
                 log::error!("{}", err);
                 process::exit(exitcode::OSERR);
             }
         }
+
+        // Override profile
+        if is_f(params, "f_man") {
+            profile.set_manpages(f(params, "f_man"));
+        }
+        if is_f(params, "f_dir") {
+            profile.set_manpages(f(params, "f_dir"));
+        }
+        if is_f(params, "f_doc") {
+            profile.set_manpages(f(params, "f_doc"));
+        }
+        if is_f(params, "f_i18n") {
+            profile.set_manpages(f(params, "f_i18n"));
+        }
+        if is_f(params, "f_l10n") {
+            profile.set_manpages(f(params, "f_l10n"));
+        }
+        if is_f(params, "f_log") {
+            profile.set_manpages(f(params, "f_log"));
+        }
+        if is_f(params, "f_pic") {
+            profile.set_img(f(params, "f_pic"));
+        }
+        if is_f(params, "f_arc") {
+            profile.set_arch(f(params, "f_arc"));
+        }
     }
 
     profile
 }
 
+/// Get path of the lock file inside the image. The option overrides the profile, which disables it by "none".
+fn get_lockfile(params: &ArgMatches, profile: Option<&Profile>) -> Option<PathBuf> {
+    if params.get_flag("no-lockfile") {
+        return None;
+    }
+
+    match params.get_one::<String>("lockfile").map(|l| l.as_str()).or_else(|| profile.and_then(|p| p.get_lockfile())) {
+        Some("none") => None,
+        Some(l) => Some(PathBuf::from(l)),
+        None => Some(PathBuf::from(manifest::LOCKFILE_PATH)),
+    }
+}
+
 /// Main
 fn main() -> Result<(), std::io::Error> {
     let args: Vec<String> = env::args().collect();
@@ -123,33 +204,305 @@ fn main() -> Result<(), std::io::Error> {
         return Ok(());
     }
 
+    // Documentation artifacts do not need any root
+    match params.subcommand() {
+        Some(("gen-man", sub)) => {
+            colored::control::set_override(false);
+            let man = gendoc::manpage(clidef::cli(VERSION), VERSION)?;
+            return match sub.get_one::<String>("output") {
+                Some(out) => std::fs::write(out, man),
+                None => std::io::stdout().write_all(&man),
+            };
+        }
+        Some(("gen-completions", sub)) => {
+            colored::control::set_override(false);
+            let shell = *sub.get_one::<clap_complete::Shell>("shell").unwrap();
+            return std::io::stdout().write_all(&gendoc::completions(clidef::cli(VERSION), shell));
+        }
+        _ => {}
+    }
+
     // Setup logger
-    if let Err(err) = log::set_logger(&LOGGER)
-        .map(|()| log::set_max_level(if params.get_flag("debug") { log::LevelFilter::Trace } else { log::LevelFilter::Info }))
-    {
+    if let Err(err) = log::set_logger(&LOGGER).map(|()| log::set_max_level(get_log_level(&params))) {
         return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()));
     }
 
+    // Plain output is for scripts, so it has no colors at all. NO_COLOR disables them everywhere.
+    let plain = params.get_flag("plain");
+    if plain || std::env::var_os("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false) {
+        colored::control::set_override(false);
+    }
+
+    // Machine-readable output goes to STDOUT, so logs must not mix with it
+    let json = params.get_one::<String>("format").map(|f| f == "json").unwrap_or(false);
+    let ndjson = params.get_one::<String>("format").map(|f| f == "ndjson").unwrap_or(false);
+    logger::set_stderr(json || ndjson || plain);
+
+    // Log file is on the host, so it is opened before anything else
+    if let Some(lf) = params.get_one::<String>("log-file") {
+        if let Err(err) = logger::set_log_file(Path::new(lf), params.get_one::<String>("log-format").unwrap() == "json") {
+            log::error!("Unable to open log file {}: {}", lf, err);
+            process::exit(exitcode::IOERR);
+        }
+    }
+
+    if let Some(wd) = params.get_one::<String>("workdir") {
+        workspace::set_base(Path::new(wd));
+    }
+
+    // Namespace is entered before anything is spawned or touched in the rootfs
+    if params.get_flag("userns") {
+        if let Err(err) = userns::enter() {
+            log::error!("{}", err);
+            process::exit(exitcode::NOPERM);
+        }
+    }
+
+    // Cache directory is on the host, so it is opened before chroot
+    if let Some(cd) = params.get_one::<String>("cache-dir") {
+        if let Err(err) = scancache::open(Path::new(cd)) {
+            log::error!("Unable to open scan cache {}: {}", cd, err);
+            process::exit(exitcode::IOERR);
+        }
+    }
+
+    // Validate profile without running anything
+    if let Some(("profile", sub)) = params.subcommand() {
+        if let Some(("lint", _)) = sub.subcommand() {
+            let sources = match params.get_many::<String>("profile") {
+                Some(p) => p.cloned().collect::<Vec<String>>(),
+                None => {
+                    log::error!("Profile is not specified. Use {} option.", "--profile".bright_yellow());
+                    process::exit(exitcode::USAGE);
+                }
+            };
+
+            match lint::ProfileLinter::new(sources).set_root(params.get_one::<String>("root").map(PathBuf::from)).lint() {
+                Ok(0) => return Ok(()),
+                Ok(_) => process::exit(exitcode::DATAERR),
+                Err(err) => {
+                    log::error!("{}", err);
+                    process::exit(exitcode::IOERR);
+                }
+            }
+        }
+
+        cli.find_subcommand_mut("profile").unwrap().print_help().unwrap();
+        return Ok(());
+    }
+
+    // Preview the rootfs according to the removal plan
+    if let Some(("mount", sub)) = params.subcommand() {
+        let ret = plan::Plan::load(Path::new(sub.get_one::<String>("plan").unwrap())).and_then(|plan| {
+            let root = params.get_one::<String>("root").map(PathBuf::from).unwrap_or_else(|| plan.get_root().to_owned());
+            plan.preview(&root, sub.get_one::<String>("mountpoint").map(Path::new))
+        });
+
+        if let Err(err) = ret {
+            log::error!("{}", err);
+            process::exit(exitcode::IOERR);
+        }
+        return Ok(());
+    }
+
+    // Compare two root filesystems on the host
+    if let Some(("diff", sub)) = params.subcommand() {
+        let ret = diff::RootDiff::new(
+            Path::new(sub.get_one::<String>("root_a").unwrap()),
+            Path::new(sub.get_one::<String>("root_b").unwrap()),
+        )
+        .and_then(|d| {
+            if json {
+                print!("{}", d.to_json()?);
+            } else {
+                d.format();
+            }
+            Ok(())
+        });
+
+        if let Err(err) = ret {
+            log::error!("{}", err);
+            process::exit(exitcode::IOERR);
+        }
+        return Ok(());
+    }
+
     // Start data processor
-    let rpth = PathBuf::from(params.get_one::<String>("root").unwrap());
-    if !rpth.exists() {
-        log::error!("Mountpoint \"{}\" does not exist or is not accessible", rpth.to_str().unwrap().bright_yellow());
-        process::exit(exitcode::IOERR);
+    let roots = params.get_many::<String>("root").map(|r| r.map(PathBuf::from).collect::<Vec<PathBuf>>()).unwrap_or_default();
+    if roots.is_empty() {
+        log::error!("Root filesystem is not specified. Use {} option.", "--root".bright_yellow());
+        process::exit(exitcode::USAGE);
+    }
+
+    let prefix = params.get_flag("prefix");
+    if roots.len() > 1 {
+        if !prefix || matches!(params.subcommand_name(), Some("rollback") | Some("restore")) {
+            log::error!("Several root filesystems can be processed only by a tint with {} option.", "--prefix".bright_yellow());
+            process::exit(exitcode::USAGE);
+        }
+
+        // Every root would overwrite the file of the previous one
+        if let Some(opt) = SINGLE_FILE_OPTIONS.iter().find(|o| params.get_one::<String>(o).is_some()) {
+            log::error!("Option {} cannot be used with several root filesystems.", format!("--{}", opt).bright_yellow());
+            process::exit(exitcode::USAGE);
+        }
+    }
+
+    // Remote profiles are fetched once for all root filesystems. Restore and rollback need no profile.
+    let profile = match params.subcommand_name() {
+        Some("rollback") | Some("restore") => None,
+        _ => Some(get_profile(cli.to_owned(), &params)),
+    };
+    let lockfile = get_lockfile(&params, profile.as_ref());
+
+    let mut rpths: Vec<PathBuf> = vec![];
+    for rpth in roots {
+        if !rpth.exists() {
+            log::error!("Mountpoint \"{}\" does not exist or is not accessible", rpth.to_str().unwrap().bright_yellow());
+            process::exit(exitcode::IOERR);
+        }
+        match rootfs::RootFS::resolve_root(&rpth, lockfile.as_deref()) {
+            Ok(rpth) => rpths.push(rpth),
+            Err(err) => {
+                log::error!("{}", err);
+                process::exit(if err.kind() == std::io::ErrorKind::InvalidInput { exitcode::USAGE } else { exitcode::IOERR });
+            }
+        }
+    }
+    let rpth = rpths[0].to_owned();
+
+    if let Some(("rollback", sub)) = params.subcommand() {
+        if !sub.get_flag("snapshot") {
+            log::error!("Rollback source is not specified. Use {} option.", "--snapshot".bright_yellow());
+            process::exit(exitcode::USAGE);
+        }
+
+        if let Err(err) =
+            snapshot::Snapshot::load(&rpth.join(snapshot::SNAPSHOT_PATH.trim_start_matches('/'))).and_then(|s| s.rollback())
+        {
+            log::error!("{}", err);
+            process::exit(exitcode::IOERR);
+        }
+
+        log::info!("Rolled back");
+        return Ok(());
+    }
+
+    if let Some(("restore", sub)) = params.subcommand() {
+        let mut r = restore::Restore::new(&rpth);
+        r.set_backup(sub.get_one::<String>("backup").map(PathBuf::from))
+            .set_removed_manifest(params.get_one::<String>("removed-manifest").map(PathBuf::from))
+            .set_lockfile(lockfile);
+
+        if let Err(err) = r.run() {
+            log::error!("{}", err);
+            process::exit(exitcode::IOERR);
+        }
+
+        return Ok(());
     }
 
     log::info!("Launching scanner and data processor");
 
-    if let Err(err) = procdata::TintProcessor::new(rpth)
-        .set_profile(get_profile(cli, &params))
-        .set_dry_run(params.get_flag("dry-run"))
-        .set_autodeps(params.get_one::<String>("autodeps").unwrap().to_string())
-        .start()
+    // Scanning and filtering share one pool of the workers. Zero is the number of CPUs.
+    if let Err(err) =
+        rayon::ThreadPoolBuilder::new().num_threads(params.get_one::<usize>("jobs").copied().unwrap_or(0)).build_global()
     {
-        log::error!("{}", err);
-        process::exit(exitcode::IOERR);
+        log::error!("Unable to start the workers: {}", err);
+        process::exit(exitcode::OSERR);
+    }
+
+    let mut dry_run = params.get_flag("dry-run") || params.get_flag("check");
+    let several = rpths.len() > 1;
+    let profile = profile.unwrap_or_else(Profile::default);
+    for rpth in rpths {
+        if several {
+            log::info!("Processing root filesystem {}", rpth.to_str().unwrap().bright_yellow());
+        }
+
+        // A stray invocation must not tint the machine of the operator
+        if !dry_run
+            && !params.get_flag("yes-i-mean-the-host")
+            && !matches!(params.subcommand_name(), Some("analyze") | Some("explain"))
+        {
+            if let Some(reason) = rootfs::RootFS::is_host(&rpth) {
+                log::error!(
+                    "Root filesystem {} {}. Use {} to tint the running system anyway.",
+                    rpth.to_str().unwrap(),
+                    reason,
+                    "--yes-i-mean-the-host".bright_yellow()
+                );
+                process::exit(exitcode::USAGE);
+            }
+        }
+
+        let mut proc = procdata::TintProcessor::new(rpth);
+        proc.set_profile(profile.to_owned())
+            .set_strict(params.get_flag("strict"))
+            .set_json(json)
+            .set_ndjson(ndjson)
+            .set_by_package(params.get_flag("by-package"))
+            .set_top(params.get_one::<usize>("top").copied().unwrap_or(0))
+            .set_quiet(params.get_flag("quiet"))
+            .set_plain(plain, params.get_flag("sizes"))
+            .set_check(params.get_flag("check"), params.get_one::<u64>("max-size").copied())
+            .set_tree_layout(
+                params.get_one::<usize>("depth").copied().unwrap_or(0),
+                params.get_one::<usize>("collapse").copied().unwrap_or(0),
+                params.get_flag("dirs-only"),
+            )
+            .set_explain(params.get_flag("explain"))
+            .set_diff_last(params.get_flag("diff-last"))
+            .set_overlay(params.get_flag("overlay"))
+            .set_force(params.get_flag("force"))
+            .set_resume(params.get_flag("resume"))
+            .set_snapshot(params.get_flag("snapshot"))
+            .set_prefix(prefix)
+            .set_safety(!params.get_flag("no-safety"))
+            .set_report_dangling(params.get_flag("report-dangling"))
+            .set_quarantine(params.get_flag("quarantine"))
+            .set_auto_fix(params.get_flag("auto-fix"))
+            .set_plan(params.get_one::<String>("plan").map(PathBuf::from))
+            .set_metrics_out(params.get_one::<String>("metrics-out").map(PathBuf::from))
+            .set_sbom(params.get_one::<String>("sbom").map(PathBuf::from))
+            .set_removed_manifest(params.get_one::<String>("removed-manifest").map(PathBuf::from))
+            .set_attestation(params.get_one::<String>("attestation").map(PathBuf::from))
+            .set_audit_log(params.get_one::<String>("audit-log").map(PathBuf::from))
+            .set_backup(params.get_one::<String>("backup").map(PathBuf::from))
+            .set_whiteouts(params.get_one::<String>("whiteouts").map(PathBuf::from))
+            .set_report(params.get_one::<String>("report").map(PathBuf::from))
+            .set_graph(params.get_one::<String>("graph").map(PathBuf::from))
+            .set_graph_json(params.get_one::<String>("graph-format").map(|f| f == "json").unwrap_or(false))
+            .set_csv(params.get_one::<String>("csv").map(PathBuf::from))
+            .set_image_config(params.get_one::<String>("image-config").map(PathBuf::from))
+            .set_config_targets(params.get_flag("config-targets"))
+            .set_cyclonedx(params.get_one::<String>("sbom-format").map(|f| f == "cyclonedx").unwrap_or(false))
+            .set_export_profile(params.get_one::<String>("export-profile").map(PathBuf::from))
+            .set_autodeps(params.get_one::<String>("autodeps").unwrap().to_string())
+            .set_lockfile(lockfile.to_owned());
+
+        let ret = match params.subcommand() {
+            Some(("analyze", sub)) => {
+                dry_run = true;
+                match sub.get_one::<String>("only").map(|s| s.as_str()) {
+                    Some("prune-rules") => proc.analyze_rules(),
+                    _ => proc.set_dry_run(dry_run).start(),
+                }
+            }
+            Some(("explain", sub)) => {
+                dry_run = true;
+                proc.set_explain_path(sub.get_one::<String>("path").map(PathBuf::from)).set_dry_run(dry_run).start()
+            }
+            _ => proc.set_dry_run(dry_run).start(),
+        };
+
+        if let Err(err) = ret {
+            log::error!("{}", err);
+            process::exit(if err.kind() == std::io::ErrorKind::InvalidData { exitcode::DATAERR } else { exitcode::IOERR });
+        }
     }
 
-    if params.get_flag("dry-run") {
+    if dry_run {
         log::warn!("This was a dry-run. Changes were not applied.");
     } else {
         log::info!("Finished. Hopefully it even works :-)");
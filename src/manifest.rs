@@ -0,0 +1,367 @@
+/*
+Manifest of a tinted image. It is stored inside the image
+and describes what was kept, so the image can be tinted further
+by another profile without re-deriving anything from packages.
+
+Removal manifest describes what was removed, so later audits can
+tell exactly what the tint did. Kept files, those are changed by the tint,
+are described by their original mode and owner, so they can be restored.
+
+State of the tint is stored in the lock file: who tinted the image, when,
+with which profile and what was removed, so later runs can report it.
+ */
+
+use crate::{digest, prefix};
+use bytesize::ByteSize;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::CString,
+    fs,
+    io::{Error, ErrorKind},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+};
+
+/// Location of the manifest inside the image
+pub const MANIFEST_PATH: &str = "/.tinted.manifest";
+
+/// Default location of the lock file with the state of the tint inside the image
+pub const LOCKFILE_PATH: &str = "/.tinted.lock";
+
+/// Default location of the removal manifest inside the image
+pub const REMOVED_MANIFEST_PATH: &str = "/var/lib/mezzotint/removed.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Manifest {
+    kept: Vec<PathBuf>,
+}
+
+impl Manifest {
+    pub fn new(kept: &[PathBuf]) -> Self {
+        let mut kept = kept.iter().filter(|p| prefix::exists(p) || prefix::is_symlink(p)).cloned().collect::<Vec<PathBuf>>();
+        kept.sort();
+        kept.dedup();
+
+        Manifest { kept }
+    }
+
+    /// Load manifest from the file
+    pub fn load(p: &Path) -> Result<Self, Error> {
+        serde_yaml::from_str::<Manifest>(&fs::read_to_string(p)?).map_err(|err| {
+            Error::new(ErrorKind::InvalidData, format!("Unable to read manifest {}: {}", p.to_str().unwrap(), err))
+        })
+    }
+
+    /// Get manifest as it is stored in the image
+    pub fn to_yaml(&self) -> Result<String, Error> {
+        serde_yaml::to_string(self).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+    }
+
+    /// Save manifest to the file
+    pub fn save(&self, p: &Path) -> Result<(), Error> {
+        fs::write(p, self.to_yaml()?)
+    }
+
+    /// Get SHA-256 digest of the stored manifest
+    pub fn digest(&self) -> Result<String, Error> {
+        Ok(format!("sha256:{}", digest::sha256(self.to_yaml()?.as_bytes())))
+    }
+
+    /// Get paths, those were kept in the image
+    pub fn get_kept(&self) -> &Vec<PathBuf> {
+        &self.kept
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemovedFile {
+    path: PathBuf,
+    size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+/// Original state of a kept file, which is changed by the tint
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChangedFile {
+    path: PathBuf,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    content: bool, // Content is changed, so the original is in the backup or the quarantine
+}
+
+impl ChangedFile {
+    /// Get path of the changed file
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true if the content of the file is changed, not only its mode or owner
+    pub fn is_content(&self) -> bool {
+        self.content
+    }
+
+    /// Set the original mode and owner of the file back. Symlinks have no mode of their own.
+    pub fn restore(&self, p: &Path) -> Result<(), Error> {
+        let cp = CString::new(p.as_os_str().as_bytes())?;
+        if unsafe { libc::lchown(cp.as_ptr(), self.uid, self.gid) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        if !fs::symlink_metadata(p)?.is_symlink() {
+            fs::set_permissions(p, fs::Permissions::from_mode(self.mode))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Record of the audit log about a removed file
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    time: &'a str,
+    action: &'a str,
+    #[serde(flatten)]
+    file: &'a RemovedFile,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RemovalManifest {
+    removed: Vec<RemovedFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    changed: Vec<ChangedFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    created: Vec<PathBuf>, // Directories, created by the tint, parents first
+}
+
+impl RemovalManifest {
+    /// Describe files, those are about to be removed. Only regular files have a digest.
+    pub fn new(paths: &[PathBuf]) -> Self {
+        RemovalManifest {
+            removed: paths
+                .iter()
+                .filter_map(|p| {
+                    let m = prefix::symlink_metadata(p).ok()?;
+                    let sha256 = if m.is_file() {
+                        digest::file_digests(&prefix::host_nofollow(p)).ok().map(|(_, sha256)| sha256)
+                    } else {
+                        None
+                    };
+                    Some(RemovedFile { path: p.to_owned(), size: m.len(), sha256 })
+                })
+                .collect(),
+            changed: vec![],
+            created: vec![],
+        }
+    }
+
+    /// Describe kept files, those are about to be changed. Must be called before they are changed.
+    pub fn add_changed(&mut self, paths: &[PathBuf], content: bool) -> &mut Self {
+        for p in paths {
+            if let Ok(m) = prefix::symlink_metadata(p) {
+                let mode = m.permissions().mode() & 0o7777;
+                self.changed.push(ChangedFile { path: p.to_owned(), mode, uid: m.uid(), gid: m.gid(), content });
+            }
+        }
+        self
+    }
+
+    /// Describe missing directories, those are about to be created by the tint with their parents.
+    /// Must be called before they are created.
+    pub fn add_created(&mut self, dirs: &[PathBuf]) -> &mut Self {
+        for d in dirs {
+            let missing: Vec<&Path> = d.ancestors().take_while(|a| !prefix::exists(a) && !prefix::is_symlink(a)).collect();
+            for m in missing.into_iter().rev() {
+                if !self.created.iter().any(|c| c == m) {
+                    self.created.push(m.to_owned());
+                }
+            }
+        }
+        self
+    }
+
+    /// Load removal manifest from the file. Missing file is an empty manifest.
+    pub fn load(p: &Path) -> Result<Self, Error> {
+        if !p.exists() {
+            return Ok(RemovalManifest::default());
+        }
+
+        serde_yaml::from_str::<RemovalManifest>(&fs::read_to_string(p)?).map_err(|err| {
+            Error::new(ErrorKind::InvalidData, format!("Unable to read removal manifest {}: {}", p.to_str().unwrap(), err))
+        })
+    }
+
+    /// Add files of another run, e.g. of an overlay
+    pub fn extend(&mut self, other: RemovalManifest) -> &mut Self {
+        self.removed.extend(other.removed);
+        self.changed.extend(other.changed);
+        for d in other.created {
+            if !self.created.contains(&d) {
+                self.created.push(d);
+            }
+        }
+        self
+    }
+
+    /// Forget files, those are restored
+    pub fn forget(&mut self, paths: &[PathBuf]) -> &mut Self {
+        self.removed.retain(|f| !paths.contains(&f.path));
+        self
+    }
+
+    /// Get audit log records of the removed files as JSON lines, except the skipped ones
+    pub fn to_audit(&self, action: &str, skip: &[PathBuf]) -> Result<String, Error> {
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let mut out = String::new();
+        for file in self.removed.iter().filter(|f| !skip.contains(&f.path)) {
+            out.push_str(&serde_json::to_string(&AuditRecord { time: &time, action, file })?);
+            out.push('\n');
+        }
+
+        Ok(out)
+    }
+
+    /// Get removal manifest as it is stored in the image
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Save removal manifest to the file as JSON
+    pub fn save(&self, p: &Path) -> Result<(), Error> {
+        if let Some(d) = p.parent() {
+            fs::create_dir_all(d)?;
+        }
+
+        fs::write(p, self.to_json()?)
+    }
+
+    /// Get paths of the removed files
+    pub fn get_paths(&self) -> Vec<&Path> {
+        self.removed.iter().map(|f| f.path.as_path()).collect()
+    }
+
+    /// Get kept files, those are changed, in the order of the changes
+    pub fn get_changed(&self) -> &[ChangedFile] {
+        &self.changed
+    }
+
+    /// Get directories, created by the tint, parents first
+    pub fn get_created(&self) -> &[PathBuf] {
+        &self.created
+    }
+
+    /// Get number and total size of the removed files
+    pub fn get_total(&self) -> (usize, u64) {
+        (self.removed.len(), self.removed.iter().map(|f| f.size).sum())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TintState {
+    version: String,
+    tinted_at: String,
+    profile: String,
+    #[serde(flatten)]
+    removed: RemovalManifest,
+}
+
+impl TintState {
+    pub fn new(profile: String, removed: RemovalManifest) -> Self {
+        TintState {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            tinted_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            profile,
+            removed,
+        }
+    }
+
+    /// Load state from the lock file. Lock files of older versions are empty, so there is no state.
+    pub fn load(p: &Path) -> Result<Option<Self>, Error> {
+        let data = fs::read_to_string(p)?;
+        if data.trim().is_empty() {
+            return Ok(None);
+        }
+
+        serde_yaml::from_str::<TintState>(&data).map(Some).map_err(|err| {
+            Error::new(ErrorKind::InvalidData, format!("Unable to read tint state {}: {}", p.to_str().unwrap(), err))
+        })
+    }
+
+    /// Get state as it is stored in the lock file
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Save state to the lock file as JSON
+    pub fn save(&self, p: &Path) -> Result<(), Error> {
+        if let Some(d) = p.parent() {
+            fs::create_dir_all(d)?;
+        }
+
+        fs::write(p, self.to_json()?)
+    }
+
+    /// Describe the run in a human-readable way
+    pub fn describe(&self) -> String {
+        let (files, size) = self.removed.get_total();
+        format!(
+            "tinted at {} by mezzotint {} with profile {}, {} files ({}) were removed",
+            self.tinted_at,
+            self.version,
+            self.profile,
+            files,
+            ByteSize::b(size)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RemovalManifest, TintState};
+    use crate::{prefix, testutil::scratch};
+    use std::{fs, path::PathBuf, sync::PoisonError};
+
+    #[test]
+    fn json_round_trip() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("manifest");
+        let names = ["plain", "quo\"te", "back\\slash", "new\nline", "ünï\u{1}code"];
+        for n in names {
+            fs::write(d.join(n), n).unwrap();
+        }
+        prefix::set(Some(&d));
+
+        let paths = names.iter().map(|n| PathBuf::from("/").join(n)).collect::<Vec<PathBuf>>();
+        let mut rm = RemovalManifest::new(&paths);
+        rm.add_changed(&paths[..1], true).add_created(&[PathBuf::from("/var/lib/mezzotint")]);
+        rm.save(&d.join("removed.json")).unwrap();
+        let loaded = RemovalManifest::load(&d.join("removed.json")).unwrap();
+        assert_eq!(loaded.get_paths(), paths.iter().map(|p| p.as_path()).collect::<Vec<_>>());
+        assert_eq!(loaded.get_total(), rm.get_total());
+        assert_eq!(loaded.get_changed()[0].get_path(), paths[0]);
+        assert_eq!(loaded.get_created(), [PathBuf::from("/var"), PathBuf::from("/var/lib"), PathBuf::from("/var/lib/mezzotint")]);
+        assert_eq!(loaded.to_json().unwrap(), rm.to_json().unwrap());
+
+        // Every audit record is a single line
+        let audit = rm.to_audit("removed", &paths[1..2]).unwrap();
+        assert_eq!(audit.lines().count(), names.len() - 1);
+        assert!(audit.lines().all(|l| serde_json::from_str::<serde_json::Value>(l).is_ok()));
+
+        // Lock file of the state is JSON, but is read as YAML, and the empty one of older versions has no state
+        let state = TintState::new("sha256:0".to_string(), rm);
+        state.save(&d.join("lock/tinted.lock")).unwrap();
+        let loaded = TintState::load(&d.join("lock/tinted.lock")).unwrap().unwrap();
+        assert_eq!(loaded.to_json().unwrap(), state.to_json().unwrap());
+        fs::write(d.join("lock/tinted.lock"), "").unwrap();
+        assert!(TintState::load(&d.join("lock/tinted.lock")).unwrap().is_none());
+
+        prefix::set(None);
+        fs::remove_dir_all(&d).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+/*
+Metrics of the run in Prometheus textfile format,
+so minimisation can be tracked across the build pipelines.
+ */
+
+use crate::{logger, prefix};
+use filesize::PathExt;
+use std::{path::PathBuf, time::Duration};
+
+pub struct Metrics {
+    phases: Vec<(&'static str, Duration)>,
+    removed: (u64, u64),
+    kept: (u64, u64),
+    dry_run: bool,
+}
+
+impl Metrics {
+    pub fn new(dry_run: bool) -> Self {
+        Metrics { phases: vec![], removed: (0, 0), kept: (0, 0), dry_run }
+    }
+
+    /// Get amount of the files and their size on disk
+    fn count(paths: &[PathBuf]) -> (u64, u64) {
+        let size = paths
+            .iter()
+            .filter_map(|p| prefix::symlink_metadata(p).ok().and_then(|m| prefix::host_nofollow(p).size_on_disk_fast(&m).ok()))
+            .sum::<u64>();
+
+        (paths.len() as u64, size)
+    }
+
+    /// Record duration of a phase
+    pub fn add_phase(&mut self, name: &'static str, d: Duration) -> &mut Self {
+        self.phases.push((name, d));
+        self
+    }
+
+    /// Record removed files. Must be called before they are actually removed.
+    pub fn set_removed(&mut self, paths: &[PathBuf]) -> &mut Self {
+        self.removed = Self::count(paths);
+        self
+    }
+
+    /// Record kept files
+    pub fn set_kept(&mut self, paths: &[PathBuf]) -> &mut Self {
+        self.kept = Self::count(paths);
+        self
+    }
+
+    /// Add a metric with its help and type
+    fn metric(out: &mut Vec<String>, name: &str, kind: &str, help: &str, samples: &[(String, String)]) {
+        out.push(format!("# HELP mezzotint_{} {}", name, help));
+        out.push(format!("# TYPE mezzotint_{} {}", name, kind));
+        for (labels, value) in samples {
+            out.push(format!("mezzotint_{}{} {}", name, labels, value));
+        }
+    }
+
+    /// Render metrics in Prometheus textfile format
+    pub fn to_prom(&self) -> String {
+        let mut out: Vec<String> = vec![];
+        let dr = if self.dry_run { "1" } else { "0" };
+
+        Self::metric(&mut out, "dry_run", "gauge", "Whether the changes were not applied", &[("".to_string(), dr.to_string())]);
+        Self::metric(
+            &mut out,
+            "removed_files_total",
+            "counter",
+            "Files removed from the rootfs",
+            &[("".to_string(), self.removed.0.to_string())],
+        );
+        Self::metric(
+            &mut out,
+            "reclaimed_bytes_total",
+            "counter",
+            "Disk space released by the removed files",
+            &[("".to_string(), self.removed.1.to_string())],
+        );
+        Self::metric(&mut out, "kept_files", "gauge", "Files kept in the rootfs", &[("".to_string(), self.kept.0.to_string())]);
+        Self::metric(
+            &mut out,
+            "kept_bytes",
+            "gauge",
+            "Disk space taken by the kept files",
+            &[("".to_string(), self.kept.1.to_string())],
+        );
+        Self::metric(
+            &mut out,
+            "phase_duration_seconds",
+            "gauge",
+            "Duration of the processing phase",
+            &self.phases.iter().map(|(p, d)| (format!("{{phase=\"{}\"}}", p), format!("{:.3}", d.as_secs_f64()))).collect::<Vec<(
+                String,
+                String,
+            )>>(
+            ),
+        );
+        Self::metric(
+            &mut out,
+            "messages_total",
+            "counter",
+            "Logged warnings and errors by the level and the module",
+            &logger::get_counters()
+                .iter()
+                .map(|(level, module, n)| (format!("{{level=\"{}\",module=\"{}\"}}", level, module), n.to_string()))
+                .collect::<Vec<(String, String)>>(),
+        );
+
+        format!("{}\n", out.join("\n"))
+    }
+}
@@ -0,0 +1,120 @@
+/*
+Operating system detection of the rootfs
+ */
+
+use crate::prefix;
+use std::io::Read;
+
+/// Locations of os-release file
+const OS_RELEASE: &[&str] = &["/etc/os-release", "/usr/lib/os-release"];
+
+/// Binaries, used to detect the architecture of the rootfs
+const ARCH_PROBES: &[&str] = &["/bin/sh", "/usr/bin/sh", "/usr/bin/env", "/bin/ls", "/usr/bin/ls"];
+
+/// Architecture aliases. First one is the canonical (Debian) name.
+const ARCH_ALIASES: &[&[&str]] = &[
+    &["amd64", "x86_64", "x64"],
+    &["arm64", "aarch64"],
+    &["i386", "i686", "x86"],
+    &["armhf", "armv7l", "arm"],
+    &["ppc64el", "ppc64le"],
+    &["riscv64"],
+    &["s390x"],
+];
+
+/// Information about the operating system inside the rootfs
+#[derive(Debug, Clone)]
+pub struct OsInfo {
+    id: String,
+    id_like: Vec<String>,
+    arch: String,
+}
+
+impl OsInfo {
+    /// Detect OS and architecture of the current root
+    pub fn detect() -> Self {
+        let mut osi = OsInfo { id: "linux".to_string(), id_like: vec![], arch: OsInfo::detect_arch() };
+
+        for p in OS_RELEASE {
+            if let Ok(data) = prefix::read_to_string(p) {
+                for l in data.lines() {
+                    if let Some((k, v)) = l.split_once('=') {
+                        let v = v.trim().trim_matches('"').trim_matches('\'').to_lowercase();
+                        match k.trim() {
+                            "ID" => osi.id = v,
+                            "ID_LIKE" => osi.id_like = v.split_whitespace().map(|s| s.to_string()).collect::<Vec<String>>(),
+                            _ => {}
+                        }
+                    }
+                }
+                break;
+            }
+        }
+
+        osi
+    }
+
+    /// Detect architecture by looking at ELF header of a typical binary
+    fn detect_arch() -> String {
+        for p in ARCH_PROBES {
+            let mut hdr = [0u8; 20];
+            if let Ok(mut f) = prefix::open(p) {
+                if f.read_exact(&mut hdr).is_err() || &hdr[..4] != b"\x7fELF" {
+                    continue;
+                }
+
+                let le = hdr[5] == 1;
+                let machine = if le { u16::from_le_bytes([hdr[18], hdr[19]]) } else { u16::from_be_bytes([hdr[18], hdr[19]]) };
+                let arch = match machine {
+                    0x03 => "i386",
+                    0x28 => "armhf",
+                    0x3e => "amd64",
+                    0xb7 => "arm64",
+                    0x15 if le => "ppc64el",
+                    0x16 => "s390x",
+                    0xf3 => "riscv64",
+                    _ => continue,
+                };
+
+                return arch.to_string();
+            }
+        }
+
+        log::debug!("Unable to detect architecture of the rootfs, assuming host architecture");
+        OsInfo::canonical_arch(std::env::consts::ARCH)
+    }
+
+    /// Get canonical (Debian) architecture name
+    fn canonical_arch(arch: &str) -> String {
+        let arch = arch.to_lowercase();
+        for aliases in ARCH_ALIASES {
+            if aliases.contains(&arch.as_str()) {
+                return aliases[0].to_string();
+            }
+        }
+
+        arch
+    }
+
+    /// Get OS ID
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    /// Get canonical architecture name
+    pub fn get_arch(&self) -> &str {
+        &self.arch
+    }
+
+    /// Returns true if the OS is the specified one or is derived from it
+    /// (e.g. Ubuntu is also "debian")
+    pub fn is_os(&self, os: &str) -> bool {
+        let os = os.trim().to_lowercase();
+        self.id == os || self.id_like.contains(&os)
+    }
+
+    /// Returns true if the architecture matches, taking aliases to the account
+    pub fn is_arch(&self, arch: &str) -> bool {
+        self.arch == OsInfo::canonical_arch(arch.trim())
+    }
+}
@@ -0,0 +1,61 @@
+/*
+Removal plan: what would be removed from the rootfs and what is kept.
+It is written after a dry run and can be previewed without applying.
+ */
+
+use crate::{fuse::FuseView, workspace::Workspace};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Plan {
+    root: PathBuf,
+    removed: Vec<PathBuf>,
+    kept: Vec<PathBuf>,
+}
+
+impl Plan {
+    pub fn new(root: &Path, removed: &[PathBuf], kept: &[PathBuf]) -> Self {
+        Plan { root: root.to_owned(), removed: removed.to_vec(), kept: kept.to_vec() }
+    }
+
+    /// Load plan from the file
+    pub fn load(p: &Path) -> Result<Self, Error> {
+        serde_yaml::from_str::<Plan>(&fs::read_to_string(p)?)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Unable to read plan {}: {}", p.to_str().unwrap(), err)))
+    }
+
+    /// Get plan as JSON
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(format!("{}\n", serde_json::to_string_pretty(self)?))
+    }
+
+    /// Get rootfs, for which the plan was made
+    pub fn get_root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Mount read-only preview of the rootfs, as it would look after the plan is applied, and serve it
+    /// until it is unmounted. Without a mountpoint the preview is mounted in a workspace.
+    pub fn preview(&self, root: &Path, mountpoint: Option<&Path>) -> Result<(), Error> {
+        let ws = match mountpoint {
+            Some(_) => None,
+            None => Some(Workspace::new("preview")?),
+        };
+        let mp = mountpoint.or(ws.as_ref().map(|ws| ws.get_path())).unwrap();
+
+        let view = FuseView::mount(&fs::canonicalize(root)?, &self.removed, mp)?;
+        log::info!("Preview is mounted at {}", mp.to_str().unwrap().bright_yellow());
+        log::info!(
+            "When done, unmount it with \"fusermount -u {}\" or \"umount {}\"",
+            mp.to_str().unwrap(),
+            mp.to_str().unwrap()
+        );
+        view.serve()
+    }
+}
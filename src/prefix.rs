@@ -0,0 +1,186 @@
+/*
+Access to the image without chroot.
+
+By default the process chroots into the image, so paths inside the image
+are paths of the process. In prefix mode the image is accessed from the
+host instead: paths inside the image are joined against its root and
+symlinks are resolved within it, as chroot would do. This needs no
+privileges and allows processing several images in one run.
+
+Without the prefix all functions are the plain filesystem calls.
+ */
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, Metadata, ReadDir},
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{PoisonError, RwLock},
+};
+
+/// Limit of symlinks to follow, same as of Linux
+pub const MAX_LINKS: usize = 40;
+
+static ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Serialises tests, those depend on the root
+#[cfg(test)]
+pub static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Set root of the image, accessed from the host, or None for chroot
+pub fn set(root: Option<&Path>) {
+    *ROOT.write().unwrap_or_else(PoisonError::into_inner) = root.map(|r| r.to_owned());
+}
+
+/// Get root of the image, if it is accessed from the host
+pub fn get() -> Option<PathBuf> {
+    ROOT.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// Returns true if the image is accessed from the host
+pub fn is_set() -> bool {
+    ROOT.read().unwrap_or_else(PoisonError::into_inner).is_some()
+}
+
+/// Get location of the path inside the image on the host, as is
+pub fn at<P: AsRef<Path>>(p: P) -> PathBuf {
+    let p = p.as_ref();
+    match get() {
+        Some(root) => root.join(p.strip_prefix("/").unwrap_or(p)),
+        None => p.to_path_buf(),
+    }
+}
+
+/// Get path inside the image from its location on the host
+pub fn strip<P: AsRef<Path>>(p: P) -> PathBuf {
+    let p = p.as_ref();
+    match get() {
+        Some(root) => Path::new("/").join(p.strip_prefix(root).unwrap_or(p)),
+        None => p.to_path_buf(),
+    }
+}
+
+/// Resolve all symlinks of the path inside the image, like canonicalize does
+pub fn canonicalize<P: AsRef<Path>>(p: P) -> Result<PathBuf, Error> {
+    let p = p.as_ref();
+    if !is_set() {
+        return fs::canonicalize(p);
+    }
+
+    let mut out = PathBuf::from("/");
+    let mut rest: Vec<OsString> = p.components().rev().map(|c| c.as_os_str().to_owned()).collect();
+    let mut links = 0;
+    while let Some(c) = rest.pop() {
+        if c == ".." {
+            out.pop();
+            continue;
+        } else if c == "." || c == "/" {
+            continue;
+        }
+
+        out.push(c);
+        let loc = at(&out);
+        if !loc.symlink_metadata()?.file_type().is_symlink() {
+            continue;
+        }
+
+        links += 1;
+        if links > MAX_LINKS {
+            return Err(Error::new(ErrorKind::Other, format!("Too many levels of symbolic links: {}", p.to_str().unwrap())));
+        }
+
+        let tgt = fs::read_link(&loc)?;
+        out.pop();
+        if tgt.is_absolute() {
+            out = PathBuf::from("/");
+        }
+        rest.extend(tgt.components().rev().map(|c| c.as_os_str().to_owned()));
+    }
+
+    Ok(out)
+}
+
+/// Get location of the path on the host with its symlinks resolved inside the image.
+/// Unresolvable path is located as is, so accessing it fails as usual.
+pub fn host<P: AsRef<Path>>(p: P) -> PathBuf {
+    let p = p.as_ref();
+    if !is_set() {
+        return p.to_path_buf();
+    }
+
+    at(canonicalize(p).unwrap_or_else(|_| p.to_path_buf()))
+}
+
+/// Get location of the path on the host, resolving only its parent, e.g. to access a symlink itself
+pub fn host_nofollow<P: AsRef<Path>>(p: P) -> PathBuf {
+    let p = p.as_ref();
+    match (is_set(), p.parent(), p.file_name()) {
+        (true, Some(d), Some(n)) => host(d).join(n),
+        _ => at(p),
+    }
+}
+
+/// Returns true if the path exists inside the image, following symlinks
+pub fn exists<P: AsRef<Path>>(p: P) -> bool {
+    let p = p.as_ref();
+    if !is_set() {
+        return p.exists();
+    }
+
+    canonicalize(p).map(|t| at(t).exists()).unwrap_or(false)
+}
+
+/// Returns true if the path is a regular file inside the image, following symlinks
+pub fn is_file<P: AsRef<Path>>(p: P) -> bool {
+    metadata(p).map(|m| m.is_file()).unwrap_or(false)
+}
+
+/// Returns true if the path is a directory inside the image, following symlinks
+pub fn is_dir<P: AsRef<Path>>(p: P) -> bool {
+    metadata(p).map(|m| m.is_dir()).unwrap_or(false)
+}
+
+/// Returns true if the path is a symlink inside the image
+pub fn is_symlink<P: AsRef<Path>>(p: P) -> bool {
+    symlink_metadata(p).map(|m| m.file_type().is_symlink()).unwrap_or(false)
+}
+
+/// Get metadata of the path inside the image, following symlinks
+pub fn metadata<P: AsRef<Path>>(p: P) -> Result<Metadata, Error> {
+    let p = p.as_ref();
+    if !is_set() {
+        return fs::metadata(p);
+    }
+
+    fs::metadata(at(canonicalize(p)?))
+}
+
+/// Get metadata of the path inside the image, without following symlinks
+pub fn symlink_metadata<P: AsRef<Path>>(p: P) -> Result<Metadata, Error> {
+    fs::symlink_metadata(host_nofollow(p))
+}
+
+/// Read target of the symlink inside the image
+pub fn read_link<P: AsRef<Path>>(p: P) -> Result<PathBuf, Error> {
+    fs::read_link(host_nofollow(p))
+}
+
+/// Read the directory inside the image. Paths of the entries are on the host, see strip().
+pub fn read_dir<P: AsRef<Path>>(p: P) -> Result<ReadDir, Error> {
+    fs::read_dir(host(p))
+}
+
+/// Read the file inside the image
+pub fn read<P: AsRef<Path>>(p: P) -> Result<Vec<u8>, Error> {
+    fs::read(host(p))
+}
+
+/// Read the file inside the image into a string
+pub fn read_to_string<P: AsRef<Path>>(p: P) -> Result<String, Error> {
+    fs::read_to_string(host(p))
+}
+
+/// Open the file inside the image for reading
+pub fn open<P: AsRef<Path>>(p: P) -> Result<File, Error> {
+    File::open(host(p))
+}
@@ -1,18 +1,66 @@
 use crate::{
-    filters::{dirs::PathsDataFilter, intf::DataFilter, resources::ResourcesDataFilter, texts::TextDataFilter},
+    accounts::Accounts,
+    attestation::{Attestation, Verification},
+    backup::Backup,
+    csv::{CsvReport, Decisions},
+    dedup::Dedup,
+    filters::{
+        dbclient::DbClientDataFilter, defs, dirs::PathsDataFilter, gpu::GpuDataFilter, icu::IcuDataFilter, intf::DataFilter,
+        locales::LocalesDataFilter, resources::ResourcesDataFilter, texts::TextDataFilter, zoneinfo::TimezoneDataFilter,
+    },
+    glob,
+    graph::DepGraph,
+    hardening::Hardening,
+    html::HtmlReport,
+    imgconfig::ImageConfig,
+    journal::{Journal, JOURNAL_PATH},
+    lastrun::LastRun,
+    ldcache::{LdCache, LD_SO_CACHE},
+    logger,
+    manifest::{Manifest, RemovalManifest, TintState, LOCKFILE_PATH, MANIFEST_PATH, REMOVED_MANIFEST_PATH},
+    metrics::Metrics,
+    osinfo::OsInfo,
+    plan::Plan,
+    prefix,
     profile::Profile,
-    rootfs::{self, RootFS},
-    scanner::{binlib::ElfScanner, debpkg::DebPackageScanner, dlst::ContentFormatter, general::Scanner},
+    provenance::{Provenance, Reason},
+    quarantine::Quarantine,
+    restore::Restore,
+    rootfs::{MountId, RootFS},
+    sbom::Sbom,
+    scanner::{
+        binlib::ElfScanner,
+        dlst::{ContentFormatter, JsonFormatter, NdjsonFormatter, RulesFormatter, Summary},
+        elfver,
+        general::Scanner,
+        pkgbackend::{self, PackageBackend, PackageScanner},
+        runtimes::RuntimeScanner,
+    },
+    secpolicy,
     shcall::ShellScript,
+    snapshot::{Snapshot, SNAPSHOT_PATH},
+    strip::Stripper,
+    syscaches::SysCaches,
+    whiteouts::Whiteouts,
+    xattrs,
 };
+use bytesize::ByteSize;
+use colored::Colorize;
+use rayon::prelude::*;
 use std::fs::{self, canonicalize, remove_file, DirEntry, File};
 use std::{
-    collections::HashSet,
-    io::Error,
-    os::unix,
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{Error, Write},
+    os::unix::{self, fs::PermissionsExt, io::AsRawFd},
     path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+    time::Instant,
 };
 
+/// Amount of files, listed in a summary of the files, those cannot be removed
+const MAX_LISTED: usize = 10;
+
 /// Autodependency mode
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Autodeps {
@@ -21,14 +69,112 @@ pub enum Autodeps {
     Clean,
     Tight,
 }
+
+impl Autodeps {
+    /// Get mode by its name. Unknown names mean no automatic dependencies.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "free" => Autodeps::Free,
+            "clean" => Autodeps::Clean,
+            "tight" => Autodeps::Tight,
+            _ => Autodeps::Undef,
+        }
+    }
+}
+
+/// Paths, found by a scanning task, with their reasons and origins
+type ScanResult = Vec<(Reason, String, Vec<PathBuf>)>;
+
+/// Unit of work of the scanning workers
+enum ScanTask {
+    Target(String),
+    Package(String),
+}
+
+/// Amount of scan results, queued for the filtering stage
+const SCAN_QUEUE_SIZE: usize = 64;
+
+/// State of the filtering stage
+#[derive(Default)]
+struct Filtered {
+    prov: Provenance,
+    paths: HashSet<PathBuf>,
+    unfiltered: HashSet<PathBuf>,
+    skeleton: HashSet<PathBuf>,
+    merged: usize, // Scan results, added to the provenance
+}
+
+/// Result of the dissection stage
+struct Dissected {
+    rfs: RootFS,
+    paths: HashSet<PathBuf>,
+    links: Vec<(PathBuf, PathBuf)>,
+    removed: Vec<PathBuf>,
+}
+
+/// Result of all processing stages
+struct Stages {
+    prov: Provenance,
+    paths: HashSet<PathBuf>,
+    unfiltered: HashSet<PathBuf>,
+    skeleton: HashSet<PathBuf>,
+    rfs: RootFS,
+    removed: Vec<PathBuf>,
+    t_dissect: Instant,
+}
+
 /// Main processing of profiles or other data
 #[derive(Clone)]
 pub struct TintProcessor {
     profile: Profile,
     root: PathBuf,
     dry_run: bool,
+    json: bool,
+    ndjson: bool,
+    by_package: bool,
+    top: usize,
+    depth: usize,
+    collapse: usize,
+    dirs_only: bool,
+    plain: bool,
+    sizes: bool,
+    check: bool,
+    max_size: Option<u64>,
+    quiet: bool,
+    explain: bool,
+    explain_path: Option<PathBuf>,
+    diff_last: bool,
     autodeps: Autodeps,
-    lockfile: PathBuf,
+    lockfile: Option<PathBuf>,
+    removed_manifest: PathBuf,
+    strict: bool,
+    overlay: bool,
+    force: bool,
+    resume: bool,
+    snapshot: bool,
+    snap: Option<Snapshot>,
+    export_profile: Option<PathBuf>,
+    plan: Option<PathBuf>,
+    metrics_out: Option<PathBuf>,
+    sbom: Option<PathBuf>,
+    cyclonedx: bool,
+    image_config: Option<PathBuf>,
+    csv: Option<PathBuf>,
+    graph: Option<PathBuf>,
+    report: Option<PathBuf>,
+    graph_json: bool,
+    attestation: Option<PathBuf>,
+    audit_log: Option<PathBuf>,
+    backup: Option<PathBuf>,
+    whiteouts: Option<PathBuf>,
+    config_targets: bool,
+    quarantine: bool,
+    prefix: bool,
+    safety: bool,
+    report_dangling: bool,
+    auto_fix: bool,
+    skeleton: HashSet<PathBuf>,
+    dangling: HashSet<PathBuf>,
 }
 
 impl TintProcessor {
@@ -37,13 +183,60 @@ impl TintProcessor {
             profile: Profile::default(),
             root,
             dry_run: true,
+            json: false,
+            ndjson: false,
+            by_package: false,
+            top: 0,
+            depth: 0,
+            collapse: 0,
+            dirs_only: false,
+            plain: false,
+            sizes: false,
+            check: false,
+            max_size: None,
+            quiet: false,
+            explain: false,
+            explain_path: None,
+            diff_last: false,
             autodeps: Autodeps::Free,
-            lockfile: PathBuf::from("/.tinted.lock"),
+            lockfile: Some(PathBuf::from(LOCKFILE_PATH)),
+            removed_manifest: PathBuf::from(REMOVED_MANIFEST_PATH),
+            strict: false,
+            overlay: false,
+            force: false,
+            resume: false,
+            snapshot: false,
+            snap: None,
+            export_profile: None,
+            plan: None,
+            metrics_out: None,
+            sbom: None,
+            cyclonedx: false,
+            image_config: None,
+            csv: None,
+            graph: None,
+            report: None,
+            graph_json: false,
+            attestation: None,
+            audit_log: None,
+            backup: None,
+            whiteouts: None,
+            config_targets: false,
+            quarantine: false,
+            prefix: false,
+            safety: true,
+            report_dangling: false,
+            skeleton: HashSet::default(),
+            dangling: HashSet::default(),
+            auto_fix: false,
         }
     }
 
     /// Set configuration from a profile
     pub fn set_profile(&mut self, profile: Profile) -> &mut Self {
+        if let Some(lockfile) = profile.get_lockfile() {
+            self.lockfile = if lockfile == "none" { None } else { Some(PathBuf::from(lockfile)) };
+        }
         self.profile = profile;
         self
     }
@@ -54,214 +247,2259 @@ impl TintProcessor {
         self
     }
 
-    /// Set flag for automatic dependency tracing
-    pub fn set_autodeps(&mut self, ad: String) -> &mut Self {
-        match ad.as_str() {
-            "free" => self.autodeps = Autodeps::Free,
-            "clean" => self.autodeps = Autodeps::Clean,
-            "tight" => self.autodeps = Autodeps::Tight,
-            _ => self.autodeps = Autodeps::Undef,
+    /// Set JSON output of the dry-run result instead of the tree
+    pub fn set_json(&mut self, json: bool) -> &mut Self {
+        self.json = json;
+        self
+    }
+
+    /// Set streaming output of the dry-run result: one JSON object per decision and line
+    pub fn set_ndjson(&mut self, ndjson: bool) -> &mut Self {
+        self.ndjson = ndjson;
+        self
+    }
+
+    /// Set amount of the largest kept files to show in the dry-run output
+    pub fn set_top(&mut self, top: usize) -> &mut Self {
+        self.top = top;
+        self
+    }
+
+    /// Set check mode: the dry-run fails, if the keep set has junk files,
+    /// exceeds the size budget (if any) or has unresolved libraries
+    pub fn set_check(&mut self, check: bool, max_size: Option<u64>) -> &mut Self {
+        self.check = check;
+        self.max_size = max_size;
+        self
+    }
+
+    /// Set plain dry-run output: kept paths one per line, optionally with their sizes
+    pub fn set_plain(&mut self, plain: bool, sizes: bool) -> &mut Self {
+        self.plain = plain;
+        self.sizes = sizes;
+        self
+    }
+
+    /// Set depth of the tree in the dry-run output, collapsing of the directories
+    /// above the amount of entries and listing only directories
+    pub fn set_tree_layout(&mut self, depth: usize, collapse: usize, dirs_only: bool) -> &mut Self {
+        self.depth = depth;
+        self.collapse = collapse;
+        self.dirs_only = dirs_only;
+        self
+    }
+
+    /// Set size breakdown of the kept files by the owning package in the dry-run output
+    pub fn set_by_package(&mut self, by_package: bool) -> &mut Self {
+        self.by_package = by_package;
+        self
+    }
+
+    /// Suppress the final output: the dry-run tree and the summary of the changes
+    pub fn set_quiet(&mut self, quiet: bool) -> &mut Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Annotate every kept path in the dry-run output with the rule, which produced it
+    pub fn set_explain(&mut self, explain: bool) -> &mut Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Set path to explain instead of the dry-run output
+    pub fn set_explain_path(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.explain_path = p;
+        self
+    }
+
+    /// Show only changes since the previous dry run instead of the whole tree
+    pub fn set_diff_last(&mut self, diff_last: bool) -> &mut Self {
+        self.diff_last = diff_last;
+        self
+    }
+
+    /// Set strict mode, failing on any unexpected errors
+    pub fn set_strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Set overlay mode: the profile is applied on top of an already tinted image,
+    /// using its manifest as the baseline of what is kept.
+    pub fn set_overlay(&mut self, overlay: bool) -> &mut Self {
+        self.overlay = overlay;
+        self
+    }
+
+    /// Set force mode: the profile is applied again to an already tinted image,
+    /// keeping everything from its manifest, so only the delta since the previous run is removed.
+    pub fn set_force(&mut self, force: bool) -> &mut Self {
+        self.force = force;
+        self
+    }
+
+    /// Set resume mode: an interrupted run is rolled forward from its journal, instead of a new run
+    pub fn set_resume(&mut self, resume: bool) -> &mut Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Set snapshot mode: the rootfs is snapshotted before the changes are applied
+    pub fn set_snapshot(&mut self, snapshot: bool) -> &mut Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    /// Set prefix mode: the rootfs is accessed from the host by its paths, instead of chroot to it.
+    pub fn set_prefix(&mut self, prefix: bool) -> &mut Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Set safety: paths of the built-in safety list are never removed
+    pub fn set_safety(&mut self, safety: bool) -> &mut Self {
+        self.safety = safety;
+        self
+    }
+
+    /// Set report-only mode of the broken symlinks: they are reported, but not removed
+    pub fn set_report_dangling(&mut self, report: bool) -> &mut Self {
+        self.report_dangling = report;
+        self
+    }
+
+    /// Set quarantine mode: removed files are moved into the quarantine inside the image
+    pub fn set_quarantine(&mut self, quarantine: bool) -> &mut Self {
+        self.quarantine = quarantine;
+        self
+    }
+
+    /// Restore missing libraries from the quarantine without asking
+    pub fn set_auto_fix(&mut self, auto_fix: bool) -> &mut Self {
+        self.auto_fix = auto_fix;
+        self
+    }
+
+    /// Set path on the host, where the expanded profile is exported
+    pub fn set_export_profile(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.export_profile = p;
+        self
+    }
+
+    /// Set path on the host, where the removal plan is written
+    pub fn set_plan(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.plan = p;
+        self
+    }
+
+    /// Set path on the host, where metrics in Prometheus textfile format are written
+    pub fn set_metrics_out(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.metrics_out = p;
+        self
+    }
+
+    /// Set path on the host, where SBOM of the tinted image is written
+    pub fn set_sbom(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.sbom = p;
+        self
+    }
+
+    /// Set CycloneDX format of the SBOM instead of SPDX
+    pub fn set_cyclonedx(&mut self, cdx: bool) -> &mut Self {
+        self.cyclonedx = cdx;
+        self
+    }
+
+    /// Set path on the host, where CSV report of the decision about every path is written
+    pub fn set_csv(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.csv = p;
+        self
+    }
+
+    /// Set path inside the image, where the removal manifest is written
+    pub fn set_removed_manifest(&mut self, p: Option<PathBuf>) -> &mut Self {
+        if let Some(p) = p {
+            self.removed_manifest = p;
         }
+        self
+    }
+
+    /// Set path inside the image, where the lock file with the state of the tint is written.
+    /// No lock file is written if the path is None, so re-runs cannot be detected.
+    pub fn set_lockfile(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.lockfile = p;
+        self
+    }
+
+    /// Save manifest of the kept files. Removal manifest is kept as well, so further overlays preserve it.
+    fn save_manifest(&self, kept: &[PathBuf]) -> Result<(), Error> {
+        let mut kept = kept.to_vec();
+        kept.push(self.removed_manifest.to_owned());
+        Manifest::new(&kept).save(&prefix::at(MANIFEST_PATH))
+    }
+
+    /// Set path on the host, where attestation of the final state is written
+    pub fn set_attestation(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.attestation = p;
+        self
+    }
+
+    /// Set path on the host, where HTML report is written
+    pub fn set_report(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.report = p;
+        self
+    }
+
+    /// Set path on the host, where the dependency graph is written
+    pub fn set_graph(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.graph = p;
+        self
+    }
+
+    /// Set JSON format of the dependency graph instead of Graphviz DOT
+    pub fn set_graph_json(&mut self, json: bool) -> &mut Self {
+        self.graph_json = json;
+        self
+    }
+
+    /// Set path on the host to the tar archive, where removed files are backed up before the removal
+    pub fn set_backup(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.backup = p;
+        self
+    }
+
+    /// Set path on the host to the overlayfs upper directory. Removed files are hidden there by whiteouts,
+    /// instead of being removed from the image.
+    pub fn set_whiteouts(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.whiteouts = p;
+        self
+    }
+
+    /// Set path on the host to the audit log, where every removed file is recorded with its digest
+    pub fn set_audit_log(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.audit_log = p;
+        self
+    }
 
+    /// Set path on the host to the image config, which binaries are traced
+    pub fn set_image_config(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.image_config = p;
         self
     }
 
-    // Chroot to the mount point
+    /// Set whether binaries of the image config are added to the targets, instead of suggested
+    pub fn set_config_targets(&mut self, ct: bool) -> &mut Self {
+        self.config_targets = ct;
+        self
+    }
+
+    /// Set flag for automatic dependency tracing
+    pub fn set_autodeps(&mut self, ad: String) -> &mut Self {
+        self.autodeps = Autodeps::from_name(&ad);
+        self
+    }
+
+    // Chroot to the mount point. In prefix mode the rootfs is accessed from the host instead.
     fn switch_root(&self) -> Result<(), Error> {
-        unix::fs::chroot(self.root.to_str().unwrap())?;
+        prefix::set(if self.prefix { Some(&self.root) } else { None });
+        if self.prefix {
+            return Ok(());
+        }
+
+        if let Err(err) = unix::fs::chroot(self.root.to_str().unwrap()) {
+            if err.kind() != std::io::ErrorKind::PermissionDenied {
+                return Err(err);
+            }
+            return Err(Error::new(
+                err.kind(),
+                format!(
+                    "Unable to chroot to {}: {}. Run as root, or use --userns or --prefix to run without privileges.",
+                    self.root.to_str().unwrap(),
+                    err
+                ),
+            ));
+        }
         std::env::set_current_dir("/")?;
 
         Ok(())
     }
 
-    /// Swipe for any broken symlinks.
-    fn remove_broken_symlinks(p: &PathBuf) {
-        fs::read_dir(p).unwrap().filter_map(|fe| fe.ok()).collect::<Vec<DirEntry>>().into_iter().for_each(|e| {
-            if e.path().is_symlink() && canonicalize(e.path()).is_err() {
-                log::debug!("Removing broken symlink: {:?}", e.path());
-                let _ = remove_file(e.path());
+    /// Leave the chroot back to the root of the host, opened before the chroot
+    fn leave_root(host_root: &File) -> Result<(), Error> {
+        if unsafe { libc::fchdir(host_root.as_raw_fd()) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        unix::fs::chroot(".")?;
+        std::env::set_current_dir("/")
+    }
+
+    /// Run commands, those verify the image after the changes are applied.
+    /// Failed verification rolls the changes back from the backup, if it was taken.
+    fn verify(&self, host_root: Option<File>) -> Result<(), Error> {
+        let err = match self.call_commands("Verify", self.profile.get_verify_commands()) {
+            Ok(_) => return Ok(()),
+            Err(err) => err,
+        };
+        log::error!("{}", err);
+
+        let backup = match (&self.backup, host_root) {
+            (Some(backup), Some(host_root)) if !self.dry_run => {
+                Self::leave_root(&host_root)?;
+                backup
+            }
+            _ => {
+                return Err(Error::new(
+                    err.kind(),
+                    format!(
+                        "Verification has failed, the image in {} is {}. Use --backup to roll it back automatically.",
+                        self.root.to_str().unwrap(),
+                        "broken".bright_red().bold()
+                    ),
+                ))
             }
+        };
+
+        log::warn!("Verification has failed, rolling back the changes from {}", backup.to_str().unwrap());
+        Restore::new(&self.root)
+            .set_backup(Some(backup.to_owned()))
+            .set_removed_manifest(Some(self.removed_manifest.to_owned()))
+            .set_lockfile(self.lockfile.to_owned())
+            .run()?;
+
+        Err(Error::new(err.kind(), "Verification has failed, the changes are rolled back"))
+    }
 
-            if e.path().is_dir() {
-                TintProcessor::remove_broken_symlinks(&e.path());
+    /// Back up a path, which is about to be swept. Returns false, if it should stay.
+    fn backup_swept(backup: &mut Option<Backup>, p: &Path) -> bool {
+        match backup.as_mut().map(|b| b.add(p)) {
+            Some(Err(err)) => {
+                log::error!("Unable to back up {}, it is not removed: {}", p.to_str().unwrap(), err);
+                false
             }
-        });
+            _ => true,
+        }
+    }
+
+    /// Returns true if the path is protected from the removal
+    fn is_protected(&self, p: &Path) -> bool {
+        self.safety && RootFS::is_protected(p)
     }
 
-    /// After changes are applied, remove all empty directories
-    fn remove_empty_dirs(p: &PathBuf) -> Result<bool, Error> {
-        let mut empty = true;
+    /// Returns true if the broken symlink stays: in the report-only mode, or if the profile allows it
+    fn keeps_dangling(&self, p: &Path) -> bool {
+        self.report_dangling || glob::matches_any(self.profile.get_allow_dangling(), p)
+    }
 
-        for e in fs::read_dir(p).unwrap() {
-            let e = e.unwrap();
-            let meta = e.metadata().unwrap();
+    /// Report the broken symlink, which stays
+    fn report_dangling(&self, p: &Path) {
+        if glob::matches_any(self.profile.get_allow_dangling(), p) {
+            log::debug!("Keeping broken symlink {}, allowed by the profile", p.to_str().unwrap());
+        } else {
+            let tgt = prefix::read_link(p).unwrap_or_default();
+            log::warn!("Broken symlink {} to {} is not removed", p.to_str().unwrap(), tgt.to_str().unwrap());
+        }
+    }
 
-            if meta.is_dir() {
-                let sub_p = e.path();
+    /// Returns true if the path stays, even if it is empty or broken: it is protected,
+    /// an empty directory of a kept package or a kept directory of the profile.
+    fn stays(&self, p: &Path) -> bool {
+        self.is_protected(p) || self.skeleton.contains(p) || self.profile.get_keep_dirs().iter().any(|(d, _)| Path::new(d) == p)
+    }
 
-                if TintProcessor::remove_empty_dirs(&sub_p)? {
-                    let _ = fs::remove_dir(&sub_p);
-                } else {
-                    empty = false;
+    /// Get directories, matching prune rules, which are removed as a whole tree.
+    /// A directory stays, if anything kept is inside it.
+    fn get_pruned_trees(&self, kept: &HashSet<PathBuf>) -> Vec<PathBuf> {
+        let mut trees: Vec<PathBuf> = vec![];
+        let root = RootFS::get_root_mount();
+        for rule in self.profile.get_prune_paths() {
+            for d in glob::expand_dirs(rule.to_str().unwrap()) {
+                let d = prefix::canonicalize(&d).unwrap_or(d);
+                if d == Path::new("/")
+                    || defs::VIRTUAL_FS.iter().any(|v| d.starts_with(v))
+                    || RootFS::is_foreign(&d, root)
+                    || self.stays(&d)
+                    || trees.iter().any(|t| d.starts_with(t))
+                    || kept.iter().any(|k| k.starts_with(&d))
+                    || self.skeleton.iter().any(|s| s.starts_with(&d))
+                    || self.profile.get_keep_dirs().iter().any(|(kd, _)| Path::new(kd).starts_with(&d))
+                {
+                    continue;
+                }
+                log::debug!("Removing pruned directory tree {}", d.to_str().unwrap());
+                trees.push(d);
+            }
+        }
+
+        trees
+    }
+
+    /// Prune entries of the removed libraries from the dynamic linker cache
+    fn prune_ld_cache() -> Result<(), Error> {
+        if let Some(mut cache) = LdCache::load()? {
+            let pruned = cache.prune();
+            if !pruned.is_empty() {
+                cache.save()?;
+                log::info!("Pruned {} stale entries from {}", pruned.len(), LD_SO_CACHE);
+                for p in pruned {
+                    log::debug!("  - {}", p.to_str().unwrap());
                 }
             }
         }
 
-        TintProcessor::remove_broken_symlinks(p);
+        Ok(())
+    }
+
+    /// Strip debug sections and, if requested, symbol tables of the kept ELF files
+    fn strip_elfs(&self, kept: &[PathBuf]) -> Result<(), Error> {
+        if let Some(mode) = self.profile.get_strip() {
+            let (files, saved) = Stripper::new(mode == "all", self.dry_run).run(kept)?;
+            if files > 0 {
+                log::info!(
+                    "{} {} ELF files, saving {}",
+                    if self.dry_run { "Would strip" } else { "Stripped" },
+                    files,
+                    ByteSize::b(saved)
+                );
+            }
+        }
 
-        Ok(empty)
+        Ok(())
     }
 
-    /// Remove files from the image
-    fn apply_changes(&self, paths: Vec<PathBuf>) -> Result<(), Error> {
-        for p in paths {
-            if let Err(err) = fs::remove_file(&p) {
-                log::error!("Unable to remove file {}: {}", p.to_str().unwrap(), err);
+    /// Replace identical kept files with hardlinks, or only report them
+    fn dedup_files(&self, kept: &[PathBuf]) -> Result<(), Error> {
+        if let Some(mode) = self.profile.get_dedup() {
+            let (files, reclaimed) = Dedup::new(mode == "link", self.dry_run).run(kept)?;
+            if files > 0 {
+                log::info!(
+                    "{} {} duplicate files, reclaiming {}",
+                    if mode == "link" && !self.dry_run { "Hardlinked" } else { "Found" },
+                    files,
+                    ByteSize::b(reclaimed)
+                );
             }
         }
 
-        TintProcessor::remove_empty_dirs(&PathBuf::from("/"))?;
-        File::create(&self.lockfile)?; // Create an empty lock file, indicated mission complete.
+        Ok(())
+    }
+
+    /// Create kept directories of the profile, those are missing, and set their modes
+    fn make_keep_dirs(&self) -> Result<(), Error> {
+        for (d, mode) in self.profile.get_keep_dirs() {
+            let hp = prefix::at(d);
+            if !prefix::exists(d) {
+                log::debug!("Creating kept directory {}", d);
+                fs::create_dir_all(&hp)?;
+                if mode.is_none() {
+                    fs::set_permissions(&hp, fs::Permissions::from_mode(0o755))?;
+                }
+            }
+            if let Some(mode) = mode {
+                fs::set_permissions(&hp, fs::Permissions::from_mode(*mode))?;
+            }
+        }
 
         Ok(())
     }
 
-    fn ext_path(p: HashSet<PathBuf>, mut np: HashSet<PathBuf>) -> HashSet<PathBuf> {
-        for tgt in p.iter() {
-            if tgt.is_symlink() {
-                let mut n_tgt = fs::read_link(tgt).unwrap();
-                n_tgt = tgt.parent().unwrap().join(&n_tgt);
+    /// Swipe for broken symlinks in the directory.
+    fn remove_broken_symlinks(&self, p: &Path, backup: &mut Option<Backup>) -> Result<(), Error> {
+        for e in prefix::read_dir(p)?.filter_map(|fe| fe.ok()).collect::<Vec<DirEntry>>() {
+            let ep = prefix::strip(e.path());
+            if !prefix::is_symlink(&ep) || prefix::canonicalize(&ep).is_ok() || self.is_protected(&ep) {
+                continue;
+            }
 
-                if !np.contains(&n_tgt) {
-                    np.insert(n_tgt);
-                    np.extend(TintProcessor::ext_path(p.clone(), np.clone()));
+            // Broken symlinks of the image are already reported
+            if self.keeps_dangling(&ep) {
+                if !self.dangling.contains(&ep) {
+                    self.report_dangling(&ep);
                 }
+            } else if TintProcessor::backup_swept(backup, &ep) {
+                log::debug!("Removing broken symlink: {:?}", ep);
+                let _ = remove_file(e.path());
             }
         }
 
-        np
+        Ok(())
     }
 
-    /// Call a script hook
-    fn call_script(s: String) -> Result<(), Error> {
-        // XXX: It can run args, but from where pass them? Profile? CLI? Both? None at all?..
-        let (stdout, stderr) = ShellScript::new(s, None).run()?;
+    /// After changes are applied, remove all empty directories and broken symlinks.
+    /// Only the filesystem of the root is walked: virtual filesystems and other mounts are skipped.
+    /// Returns true if the directory is left empty.
+    fn remove_empty_dirs(&self, p: &Path, root: MountId, backup: &mut Option<Backup>) -> Result<bool, Error> {
+        for e in prefix::read_dir(p)? {
+            let e = e?;
+            let sub_p = prefix::strip(e.path());
+            let meta = e.metadata()?;
+            if !meta.is_dir() {
+                continue;
+            }
 
-        if !stdout.is_empty() {
-            log::debug!("Post-hook stdout:");
-            log::debug!("{}", stdout);
+            if RootFS::is_foreign(&sub_p, root) {
+                continue;
+            }
+
+            if self.remove_empty_dirs(&sub_p, root, backup)? && !self.stays(&sub_p) && TintProcessor::backup_swept(backup, &sub_p)
+            {
+                let _ = fs::remove_dir(e.path());
+            }
         }
 
-        if !stderr.is_empty() {
-            log::error!("Post-hook error:");
-            log::error!("{}", stderr);
+        // Symlinks to the removed directories are broken now as well
+        self.remove_broken_symlinks(p, backup)?;
+
+        Ok(prefix::read_dir(p)?.next().is_none())
+    }
+
+    /// Is the path a state file of the previous run
+    fn is_state_file(&self, p: &Path) -> bool {
+        self.lockfile.as_deref() == Some(p) || [Path::new(MANIFEST_PATH), Path::new(SNAPSHOT_PATH)].contains(&p)
+    }
+
+    /// Trim unused users and groups from the account databases. Nothing is changed, if the image stays untouched.
+    fn trim_accounts(&self, acc: &Accounts, untouched: bool) -> Result<(), Error> {
+        if !self.profile.trim_accounts() {
+            return Ok(());
+        }
+
+        let (users, groups) = acc.trim(untouched)?;
+        let verb = if untouched { "Would remove" } else { "Removed" };
+        if !users.is_empty() {
+            log::info!("{} unused users: {}", verb, users.join(", "));
+        }
+        if !groups.is_empty() {
+            log::info!("{} unused groups: {}", verb, groups.join(", "));
         }
 
         Ok(())
     }
 
-    // Start tint processor
-    pub fn start(&self) -> Result<(), Error> {
-        self.switch_root()?;
+    /// Fix permissions and ownership of the kept files. Nothing is changed, if the image stays untouched.
+    fn normalise_permissions(&self, hrd: &Hardening, untouched: bool) -> Result<(), Error> {
+        if !self.profile.normalise_permissions() {
+            return Ok(());
+        }
 
-        // Bail-out if the image is already processed
-        if self.lockfile.exists() {
-            return Err(Error::new(std::io::ErrorKind::AlreadyExists, "This container seems already tinted."));
+        let fixed = hrd.normalise(untouched)?;
+        if fixed > 0 {
+            log::info!("{} {} permission and ownership issues", if untouched { "Would fix" } else { "Fixed" }, fixed);
         }
 
-        // Run pre-hook, if any
-        if self.profile.has_pre_hook() {
-            if self.dry_run {
-                log::debug!("Pre-hook:\n{}", self.profile.get_pre_hook());
-            } else {
-                Self::call_script(self.profile.get_pre_hook())?;
+        Ok(())
+    }
+
+    /// Remove files from the image and change the kept ones.
+    /// Returns files, which could not be removed.
+    fn apply_changes(
+        &self, paths: Vec<PathBuf>, kept: &[PathBuf], audit: Option<&mut File>, mut backup: Option<Backup>, acc: &Accounts,
+        hrd: &Hardening,
+    ) -> Result<Vec<PathBuf>, Error> {
+        // Files can be described only before they are removed or changed. Overlays add to the previous runs.
+        // State files of the previous run are re-created, so they are not removals.
+        let mut removing =
+            RemovalManifest::new(&paths.iter().filter(|p| !self.is_state_file(p)).cloned().collect::<Vec<PathBuf>>());
+        let accounts = if self.profile.trim_accounts() { Accounts::get_databases() } else { vec![] };
+        let fixed = if self.profile.normalise_permissions() { hrd.get_fixable() } else { vec![] };
+        removing.add_changed(&accounts, true).add_changed(&fixed, false);
+
+        // Nothing is removed or changed, unless the files are backed up
+        if let Some(backup) = backup.as_mut() {
+            for p in paths.iter().filter(|p| !self.is_state_file(p)).chain(&accounts).chain(&fixed) {
+                backup.add(p).map_err(|err| {
+                    Error::new(err.kind(), format!("Unable to back up {}, nothing is removed: {}", p.to_str().unwrap(), err))
+                })?;
+            }
+        }
+        if self.quarantine {
+            let q = Quarantine::new();
+            for p in &accounts {
+                q.keep(p).map_err(|err| {
+                    Error::new(
+                        err.kind(),
+                        format!("Unable to keep the original {}, nothing is removed: {}", p.to_str().unwrap(), err),
+                    )
+                })?;
             }
         }
 
-        // Paths to keep
-        let mut paths: HashSet<PathBuf> = HashSet::default();
+        // Journal has the original state of the changed files, so an interrupted run can be restored
+        let mut journal = Journal::create(
+            &prefix::at(JOURNAL_PATH),
+            self.profile.get_id(),
+            self.quarantine,
+            backup.as_ref().map(|_| self.backup.as_deref().unwrap()),
+            &paths,
+            kept,
+            &removing,
+        )?;
+        self.trim_accounts(acc, false)?;
+        self.normalise_permissions(hrd, false)?;
+        let failed = self.remove_journaled(&mut journal)?;
 
-        for target_path in self.profile.get_targets() {
-            log::debug!("Find binary dependencies for {target_path}");
-            paths.extend(ElfScanner::new().scan(Path::new(target_path).to_owned()));
+        if let Some(audit) = audit {
+            audit.write_all(removing.to_audit(if self.quarantine { "quarantined" } else { "removed" }, &failed)?.as_bytes())?;
+            log::info!("Audit log is written to {}", self.audit_log.as_ref().unwrap().to_str().unwrap());
+        }
 
-            log::debug!("Find package dependencies for {target_path}");
-            // XXX: This will re-scan again and again, if target_path belongs to the same package
-            paths.extend(DebPackageScanner::new(self.autodeps).scan(Path::new(target_path).to_owned()));
+        self.finish_changes(journal, &failed, backup)?;
+        Ok(failed)
+    }
 
-            // Add the target itself
-            paths.insert(Path::new(target_path).to_owned());
-        }
+    /// Hide files by the whiteouts in the upper directory, leaving the image untouched.
+    /// State of the tint is written into the upper directory as well.
+    fn apply_whiteouts(
+        &self, paths: Vec<PathBuf>, kept: &[PathBuf], audit: Option<&mut File>, mut whiteouts: Whiteouts,
+    ) -> Result<(), Error> {
+        let removing = RemovalManifest::new(&paths);
+        whiteouts.apply(&paths, &|p| self.stays(p) || (prefix::is_symlink(p) && self.keeps_dangling(p)))?;
 
-        // Scan content of all profile packages (if any)
-        // and then let TextDataFilter removes what still should be removed.
-        // The idea is to keep parts only relevant to the runtime.
-        log::debug!("Adding requested packages");
-        let pscan = DebPackageScanner::new(Autodeps::Undef);
-        for p in self.profile.get_packages() {
-            log::debug!("Getting content of package \"{}\"", p);
-            paths.extend(pscan.get_package_contents(p.to_string())?);
+        if let Some(audit) = audit {
+            audit.write_all(removing.to_audit("hidden", &[])?.as_bytes())?;
+            log::info!("Audit log is written to {}", self.audit_log.as_ref().unwrap().to_str().unwrap());
         }
 
-        log::debug!("Filtering text data");
-        TextDataFilter::new(paths.to_owned(), self.profile.to_owned()).filter(&mut paths);
-
-        log::debug!("Filtering directories");
-        PathsDataFilter::new(paths.clone().into_iter().collect::<Vec<PathBuf>>(), self.profile.to_owned()).filter(&mut paths);
+        if let Some(lockfile) = &self.lockfile {
+            whiteouts.write(lockfile, TintState::new(self.profile.get_id(), removing.clone()).to_json()?.as_bytes())?;
+        }
+        whiteouts.write(&self.removed_manifest, removing.to_json()?.as_bytes())?;
+        whiteouts.write(Path::new(MANIFEST_PATH), Manifest::new(kept).to_yaml()?.as_bytes())
+    }
 
-        // Explicitly keep paths
-        // XXX: Support globbing
-        paths.extend(self.profile.get_keep_paths());
+    /// Remove a file of any type. Directories are removed with their content,
+    /// staying on the root filesystem, so mounts inside them are left alone.
+    fn remove_path(hp: &Path) -> Result<(), Error> {
+        if !fs::symlink_metadata(hp)?.is_dir() {
+            return fs::remove_file(hp);
+        }
 
-        // Explicitly knock-out paths
-        // XXX: Support globbing
-        for p in self.profile.get_prune_paths() {
-            paths.remove(&p);
+        // Mounts are filtered out before they are descended into, and the content is removed bottom-up
+        let root = RootFS::get_root_mount();
+        let entries = walkdir::WalkDir::new(hp)
+            .into_iter()
+            .filter_entry(|e| !e.file_type().is_dir() || !RootFS::is_foreign(&prefix::strip(e.path()), root))
+            .collect::<Result<Vec<_>, _>>()?;
+        for e in entries.into_iter().rev() {
+            if e.file_type().is_dir() {
+                if let Err(err) = fs::remove_dir(e.path()) {
+                    // A mount point still has its filesystem inside
+                    log::warn!("Unable to remove directory {}: {}", e.path().to_str().unwrap(), err);
+                }
+            } else {
+                fs::remove_file(e.path())?;
+            }
         }
 
-        paths.extend(TintProcessor::ext_path(paths.clone(), HashSet::default()));
+        if hp.exists() {
+            return Err(Error::new(std::io::ErrorKind::Other, "directory is not empty"));
+        }
+        Ok(())
+    }
 
-        // Remove resources
-        log::debug!("Filtering resources");
-        ResourcesDataFilter::new(paths.clone().into_iter().collect::<Vec<PathBuf>>(), self.profile.to_owned(), self.autodeps)
-            .filter(&mut paths);
+    /// Remove pending files of the journal. Files, those are already gone, are done by an interrupted run.
+    /// Returns files, which could not be removed.
+    fn remove_journaled(&self, journal: &mut Journal) -> Result<Vec<PathBuf>, Error> {
+        let mut failed: Vec<PathBuf> = vec![];
+        let mut immutable: Vec<PathBuf> = vec![];
+        let mut readonly: Vec<PathBuf> = vec![];
+        let q = Quarantine::new();
+        let paths = journal.get_paths().to_vec();
+        for (i, p) in paths.into_iter().enumerate().skip(journal.get_done()) {
+            if prefix::exists(&p) || prefix::is_symlink(&p) {
+                if let Err(err) = if journal.is_quarantine() { q.put(&p) } else { Self::remove_path(&prefix::host_nofollow(&p)) }
+                {
+                    if glob::matches_any(self.profile.get_expect_undeletable(), &p) {
+                        log::debug!("Expectedly unable to remove file {}: {}", p.to_str().unwrap(), err);
+                        continue;
+                    }
 
-        // Remove package content before dissection
-        // XXX: Exlude .so binaries also from the Elf reader?
-        for p in self.profile.get_dropped_packages() {
-            log::debug!("Removing dropped package contents from \"{}\"", p);
-            for p in pscan.get_package_contents(p.to_string())? {
-                for p in RootFS::expand_target(p, true) {
-                    paths.remove(&p);
+                    // Immutable files and read-only mounts fail all the same, so they are summarised
+                    match err.raw_os_error() {
+                        Some(libc::EPERM) => immutable.push(p.to_owned()),
+                        Some(libc::EROFS) => readonly.push(p.to_owned()),
+                        _ => log::error!("Unable to remove file {}: {}", p.to_str().unwrap(), err),
+                    }
+                    failed.push(p);
                 }
             }
+            journal.progress(i + 1)?;
         }
 
-        // Scan rootfs
-        log::debug!("Scanning existing rootfs");
-        let mut p = rootfs::RootFS::new()
-            .keep_pds(true)
-            .keep_tmp(false)
-            .keep_tree(vec![])
-            .dissect(paths.clone().into_iter().collect::<Vec<PathBuf>>());
-        p.sort();
+        Self::report_unremovable(
+            &immutable,
+            "are immutable or append-only. Clear the attributes with \"chattr -i -a\" and run again",
+        );
+        Self::report_unremovable(
+            &readonly,
+            "are on read-only filesystems. Remount them read-write or drop the read-only bind mounts and run again",
+        );
 
-        let mut paths = paths.into_iter().collect::<Vec<PathBuf>>();
-        paths.sort();
+        Ok(failed)
+    }
 
-        if self.dry_run {
-            if self.profile.has_post_hook() {
-                log::debug!("Post-hook:\n{}", self.profile.get_post_hook());
-            }
-            ContentFormatter::new(&paths).set_removed(&p).format();
-        } else {
-            // Run post-hook (doesn't affect changes apply)
-            if self.profile.has_post_hook() {
-                Self::call_script(self.profile.get_post_hook())?;
-            }
-            self.apply_changes(p)?;
+    /// Summarise files, which cannot be removed for the same reason
+    fn report_unremovable(paths: &[PathBuf], reason: &str) {
+        if paths.is_empty() {
+            return;
+        }
+
+        log::error!("{} files {}:", paths.len(), reason);
+        for p in paths.iter().take(MAX_LISTED) {
+            log::error!("  - {}", p.to_str().unwrap());
+        }
+        if paths.len() > MAX_LISTED {
+            log::error!("  ...and {} more", paths.len() - MAX_LISTED);
+        }
+    }
+
+    /// Fail the strict run, if some files could not be removed. The rest of the run is already done.
+    fn check_failed(&self, failed: &[PathBuf]) -> Result<(), Error> {
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        if self.strict {
+            return Err(Error::new(
+                std::io::ErrorKind::Other,
+                format!("Unable to remove {} files, those are not expected to be undeletable", failed.len()),
+            ));
         }
+        log::warn!("{} files could not be removed, the image is tinted only partially", failed.len());
 
         Ok(())
     }
+
+    /// Sweep the leftovers of the removal and write the state of the tint. The journal is closed afterwards.
+    fn finish_changes(&self, journal: Journal, failed: &[PathBuf], mut backup: Option<Backup>) -> Result<(), Error> {
+        // Empty directories and symlinks, broken by the removal, are swept and backed up as well
+        self.remove_empty_dirs(Path::new("/"), RootFS::get_root_mount(), &mut backup)?;
+
+        // Directories, created for the state and the kept directories, are removed by the restore
+        let mut removing = journal.get_removing().clone();
+        removing.forget(failed);
+        let mut dirs: Vec<PathBuf> = self.profile.get_keep_dirs().iter().map(|(d, _)| PathBuf::from(d)).collect();
+        dirs.extend(
+            [self.lockfile.as_deref(), Some(self.removed_manifest.as_path())]
+                .into_iter()
+                .flatten()
+                .filter_map(|p| p.parent().map(|p| p.to_owned())),
+        );
+        removing.add_created(&dirs);
+
+        self.make_keep_dirs()?;
+        if let Some(backup) = backup {
+            backup.finish()?;
+        }
+        if let Some(lockfile) = &self.lockfile {
+            TintState::new(journal.get_profile().to_string(), removing.clone()).save(&prefix::at(lockfile))?;
+            // Lock file indicates mission complete
+        }
+        let mut rm = RemovalManifest::load(&prefix::at(&self.removed_manifest))?;
+        rm.extend(removing).save(&prefix::at(&self.removed_manifest))?;
+        log::info!("Removal manifest is written to {}", self.removed_manifest.to_str().unwrap());
+        self.save_manifest(journal.get_kept())?;
+        if let Some(snap) = &self.snap {
+            snap.save(&prefix::at(SNAPSHOT_PATH))?;
+        }
+        journal.close()?;
+
+        Ok(())
+    }
+
+    /// Verify that kept files still have their capabilities, setting lost ones back
+    fn verify_caps(caps: &[(PathBuf, Vec<u8>)]) -> Result<(), Error> {
+        for (p, c) in caps {
+            if xattrs::get_caps(&prefix::host_nofollow(p)).as_ref() != Some(c) {
+                log::warn!(
+                    "{} has lost its file capabilities {}, setting them back",
+                    p.to_str().unwrap(),
+                    xattrs::describe_caps(c)
+                );
+                xattrs::set(&prefix::host_nofollow(p), xattrs::CAPABILITY, c).map_err(|err| {
+                    Error::new(err.kind(), format!("Unable to set file capabilities of {}: {}", p.to_str().unwrap(), err))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verify that kept files still have their SELinux contexts, setting lost ones back
+    fn verify_contexts(contexts: &[(PathBuf, Vec<u8>)]) -> Result<(), Error> {
+        for (p, c) in contexts {
+            if xattrs::get_context(&prefix::host_nofollow(p)).as_ref() != Some(c) {
+                log::warn!(
+                    "{} has lost its SELinux context {}, setting it back",
+                    p.to_str().unwrap(),
+                    xattrs::describe_context(c)
+                );
+                xattrs::set(&prefix::host_nofollow(p), xattrs::SELINUX, c).map_err(|err| {
+                    Error::new(err.kind(), format!("Unable to set SELinux context of {}: {}", p.to_str().unwrap(), err))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Roll an interrupted run forward from its journal
+    fn resume_changes(&self) -> Result<(), Error> {
+        let mut journal = Journal::load(&prefix::at(JOURNAL_PATH))?;
+        // Backup is moved to its place only when finished, so the archive of the interrupted run is lost
+        if let Some(backup) = journal.get_backup() {
+            return Err(Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!(
+                    "Interrupted run has not finished its backup {}, so resuming it would remove the rest of the files \
+                     without a backup. Use the \"restore\" command to undo it.",
+                    backup.to_str().unwrap()
+                ),
+            ));
+        }
+        log::warn!(
+            "Resuming interrupted run: {} of {} files were already processed",
+            journal.get_done(),
+            journal.get_paths().len()
+        );
+        let failed = self.remove_journaled(&mut journal)?;
+        self.finish_changes(journal, &failed, None)?;
+        self.check_failed(&failed)?;
+        log::info!("Interrupted run is complete");
+
+        Ok(())
+    }
+
+    /// Check that targets have all their libraries after the changes are applied.
+    /// Missing ones are restored from the quarantine, if confirmed, and the manifest is updated.
+    /// Returns the kept paths, including the restored ones.
+    fn resolve_unresolved(&self, kept: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+        let q = Quarantine::new();
+        let mut kept = kept.to_vec();
+        let interactive = unsafe { libc::isatty(0) } == 1;
+
+        loop {
+            let mut unresolved: Vec<(String, String)> = vec![];
+            for t in self.profile.get_targets() {
+                match ElfScanner::get_unresolved(t) {
+                    Some(libs) => unresolved.extend(libs.into_iter().map(|l| (t.to_owned(), l))),
+                    None => {
+                        log::warn!("Unable to verify libraries of the targets: ldd is not available");
+                        return Ok(kept);
+                    }
+                }
+            }
+
+            if unresolved.is_empty() {
+                return Ok(kept);
+            }
+
+            let mut restored: Vec<PathBuf> = vec![];
+            for (t, lib) in &unresolved {
+                for p in q.find(lib) {
+                    let restore = self.auto_fix
+                        || (interactive && Self::confirm(&format!("{} needs {}. Restore {}?", t, lib, p.to_str().unwrap())));
+                    if restore && !restored.contains(&p) {
+                        q.restore(&p)?;
+                        log::info!("Restored {} from quarantine", p.to_str().unwrap());
+                        restored.push(p);
+                    }
+                }
+            }
+
+            if restored.is_empty() {
+                for (t, lib) in &unresolved {
+                    log::error!("Target {} has unresolved library {}", t, lib);
+                }
+                return Err(Error::new(std::io::ErrorKind::NotFound, "Targets are broken after the changes are applied"));
+            }
+
+            RemovalManifest::load(&prefix::at(&self.removed_manifest))?
+                .forget(&restored)
+                .save(&prefix::at(&self.removed_manifest))?;
+            kept.extend(restored);
+            self.save_manifest(&kept)?;
+        }
+    }
+
+    /// Find the profile or package keep rule, which matches the path
+    fn get_keep_rule(&self, p: &Path) -> Option<String> {
+        if let Some(r) = self.profile.get_keep_paths().iter().find(|r| glob::matches_rule(r.to_str().unwrap(), p)) {
+            return Some(r.to_str().unwrap().to_string());
+        }
+
+        for (pkg, rules) in self.profile.get_package_rules() {
+            if let Some(r) = rules.get_keep().iter().find(|k| glob::matches_rule(k, p)) {
+                return Some(format!("{} of package \"{}\"", r, pkg));
+            }
+        }
+
+        None
+    }
+
+    /// Add provenance of the kept paths, those were added after scanning:
+    /// by the keep rules, as targets of the kept symlinks or by the filters.
+    fn resolve_provenance(&self, prov: &mut Provenance, kept: &[PathBuf]) {
+        let mut links: HashMap<PathBuf, PathBuf> = HashMap::default();
+        for lnk in kept.iter().filter(|p| prefix::is_symlink(p)) {
+            if let Some(tgt) = RootFS::link_target(lnk) {
+                links.entry(tgt).or_insert_with(|| lnk.to_owned());
+            }
+        }
+
+        for p in kept {
+            if prov.contains(p) {
+                continue;
+            }
+
+            let (r, origin) = if let Some(rule) = self.get_keep_rule(p) {
+                (Reason::ProfileKeep, rule)
+            } else if let Some(lnk) = links.get(p) {
+                (Reason::Symlink, lnk.to_str().unwrap().to_string())
+            } else {
+                (Reason::Filter, String::new())
+            };
+            prov.add(vec![p.to_owned()], r, &origin);
+        }
+    }
+
+    /// Get reasons of the decision about every kept and removed path.
+    /// Provenance of the kept paths must be already resolved.
+    fn get_reasons(prov: &Provenance, kept: &[PathBuf], removed: &[PathBuf]) -> (Decisions, Decisions) {
+        let kept = kept.iter().map(|p| (p.to_owned(), prov.get(p).map(|(r, _)| *r).unwrap_or(Reason::Filter))).collect();
+        let removed = removed
+            .iter()
+            .map(|p| (p.to_owned(), if prov.contains(p) { Reason::Filter } else { Reason::Unreferenced }))
+            .collect();
+
+        (kept, removed)
+    }
+
+    /// Call the function with the decision about every kept and removed path:
+    /// path, action, reason and its origin
+    fn for_each_decision(prov: &Provenance, kept: &[PathBuf], removed: &[PathBuf], mut f: impl FnMut(&Path, &str, Reason, &str)) {
+        for p in kept {
+            let (r, o) = prov.get(p).map(|(r, o)| (*r, o.as_str())).unwrap_or((Reason::Filter, ""));
+            f(p, "keep", r, o);
+        }
+
+        for p in removed {
+            match prov.get(p) {
+                Some((r, o)) => f(p, "remove", Reason::Filter, &r.describe(o)),
+                None => f(p, "remove", Reason::Unreferenced, ""),
+            }
+        }
+    }
+
+    /// Explain why the path is kept or removed
+    fn print_explanation(&self, p: &Path, prov: &Provenance, kept: &[PathBuf], removed: &[PathBuf]) {
+        println!("{}", p.to_str().unwrap().bright_yellow());
+
+        // The path might be known by an alias through a symlinked directory, e.g. /lib on merged /usr
+        let mut p = p.to_path_buf();
+        if kept.binary_search(&p).is_err() && removed.binary_search(&p).is_err() {
+            if let Ok(cp) = prefix::canonicalize(&p) {
+                if let Some(alias) =
+                    kept.iter().chain(removed).find(|a| prefix::canonicalize(a).map(|a| a == cp).unwrap_or(false))
+                {
+                    println!("  {}: {}", "Known as".bold(), alias.to_str().unwrap());
+                    p = alias.to_owned();
+                }
+            }
+        }
+        let p = p.as_path();
+
+        let pkg = pkgbackend::detect().get_package_for(p.to_str().unwrap().to_string()).ok().flatten();
+        if kept.binary_search(&p.to_path_buf()).is_ok() {
+            println!("  {}: kept", "Decision".bold());
+            println!("  {}: {}", "Reason".bold(), prov.describe(p).unwrap_or_default());
+        } else if removed.binary_search(&p.to_path_buf()).is_ok() {
+            println!("  {}: removed", "Decision".bold());
+            match prov.describe(p) {
+                Some(d) => println!("  {}: removed by the filters, although it is {}", "Reason".bold(), d),
+                None => println!("  {}: {}", "Reason".bold(), Reason::Unreferenced.describe("")),
+            }
+        } else if prefix::is_dir(p) {
+            println!("  {}: directory, kept while any path under it is kept", "Decision".bold());
+        } else {
+            println!("  {}: not found in the rootfs", "Decision".bold());
+        }
+        if let Some(pkg) = pkg {
+            println!("  {}: {}", "Package".bold(), pkg);
+        }
+    }
+
+    /// Suggest binaries of the image config, those are not targets, or add them to the targets
+    fn trace_image_config(&mut self, ic: &ImageConfig) {
+        let (found, missing) = ic.get_binaries();
+        for (cmd, src) in missing {
+            log::warn!("{} of the image config runs \"{}\", which is not found in the rootfs", src, cmd);
+        }
+
+        for (p, src) in found {
+            let p = p.to_str().unwrap().to_string();
+            if self.profile.get_targets().contains(&p) {
+                continue;
+            }
+
+            if self.config_targets {
+                log::info!("Adding target {} from {} of the image config", p, src);
+                self.profile.add_target(p);
+            } else {
+                log::info!("{} of the image config runs {}, which is not a target. Use --config-targets to keep it.", src, p);
+            }
+        }
+    }
+
+    /// Report targets and profile packages, those are not present in the rootfs.
+    /// In strict mode this fails before anything is removed, otherwise missing targets are dropped.
+    fn check_missing(&mut self, unmatched: &[String]) -> Result<(), Error> {
+        let mut missing =
+            unmatched.iter().map(|t| format!("Target {} does not match any executables", t)).collect::<Vec<String>>();
+        let absent = self
+            .profile
+            .get_targets()
+            .iter()
+            .filter(|t| !prefix::exists(t) && !prefix::is_symlink(t))
+            .cloned()
+            .collect::<Vec<String>>();
+        missing.extend(absent.iter().map(|t| format!("Target {} does not exist", t)));
+
+        // Querying the package manager is expensive, so packages are checked only when it matters
+        if self.strict {
+            let pkb = pkgbackend::detect();
+            for p in self.profile.get_packages() {
+                if pkb.get_package_contents(p.to_owned()).map(|c| c.is_empty()).unwrap_or(true) {
+                    missing.push(format!("Package {} is not installed", p));
+                }
+            }
+        }
+
+        // Missing targets are not kept, so they do not get to the manifest
+        if !self.strict {
+            missing.iter().for_each(|m| log::warn!("{}", m));
+            self.profile.remove_targets(&absent);
+            return Ok(());
+        }
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        missing.iter().for_each(|m| log::error!("{}", m));
+        Err(Error::new(std::io::ErrorKind::NotFound, format!("{} targets or packages are missing in the rootfs", missing.len())))
+    }
+
+    /// Ask user for the confirmation
+    fn confirm(msg: &str) -> bool {
+        print!("{} [y/N] ", msg);
+        let _ = std::io::stdout().flush();
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+    }
+
+    /// Get targets of the symlinks among the paths, following chains of symlinks.
+    /// Every symlink is resolved only once, so cycles end there.
+    fn ext_path(p: &HashSet<PathBuf>) -> HashSet<PathBuf> {
+        let mut np: HashSet<PathBuf> = HashSet::default();
+        let mut queue = p.iter().filter(|x| prefix::is_symlink(x)).cloned().collect::<Vec<PathBuf>>();
+        while let Some(l) = queue.pop() {
+            let tgt = match RootFS::link_target(&l) {
+                Some(tgt) => tgt,
+                None => continue,
+            };
+
+            if !p.contains(&tgt) && np.insert(tgt.to_owned()) && prefix::is_symlink(&tgt) {
+                queue.push(tgt);
+            }
+        }
+
+        np
+    }
+
+    /// Normalise kept paths through directory symlinks, keeping the symlinks themselves,
+    /// e.g. /bin/bash is kept as /usr/bin/bash and /bin on usrmerged systems
+    fn resolve_dir_links(paths: &mut HashSet<PathBuf>, prov: &mut Provenance) {
+        for p in paths.iter().cloned().collect::<Vec<PathBuf>>() {
+            let (canonical, links) = RootFS::resolve_dirs(&p);
+            if canonical == p || !(prefix::exists(&canonical) || prefix::is_symlink(&canonical)) {
+                continue;
+            }
+
+            if let Some((r, origin)) = prov.get(&p).cloned() {
+                prov.add(vec![canonical.to_owned()], r, &origin);
+            }
+            for l in links.iter().filter(|l| !paths.contains(*l)) {
+                prov.add(vec![l.to_owned()], Reason::DirSymlink, canonical.to_str().unwrap());
+            }
+            paths.remove(&p);
+            paths.insert(canonical);
+            paths.extend(links);
+        }
+    }
+
+    /// Get the filter of a pipeline stage, which decides on every path alone,
+    /// so it can run on the paths as they are found
+    fn get_path_filter(&self, stage: &str) -> Option<Box<dyn DataFilter>> {
+        match stage {
+            "texts" => {
+                log::debug!("Filtering text data");
+                Some(Box::new(TextDataFilter::new(self.profile.to_owned())))
+            }
+            "locales" => {
+                log::debug!("Filtering locales");
+                Some(Box::new(LocalesDataFilter::new(self.profile.to_owned())))
+            }
+            "timezones" => {
+                log::debug!("Filtering timezones");
+                Some(Box::new(TimezoneDataFilter::new(self.profile.to_owned())))
+            }
+            "dirs" => {
+                log::debug!("Filtering directories");
+                Some(Box::new(PathsDataFilter::new(self.profile.to_owned())))
+            }
+            _ => None,
+        }
+    }
+
+    /// Run filter pipeline stages in the order, given by the profile
+    fn run_pipeline(&self, paths: &mut HashSet<PathBuf>, stages: &[String]) {
+        for stage in stages {
+            if let Some(f) = self.get_path_filter(stage) {
+                f.filter(paths);
+                continue;
+            }
+
+            match stage.as_str() {
+                "gpu" => {
+                    log::debug!("Filtering GPU stacks");
+                    GpuDataFilter::new(self.profile.to_owned()).filter(paths);
+                }
+                "icu" => {
+                    log::debug!("Looking for ICU data");
+                    IcuDataFilter::new().filter(paths);
+                }
+                "dbclient" => {
+                    log::debug!("Filtering database client data");
+                    DbClientDataFilter::new().filter(paths);
+                }
+                "rules" => {
+                    // Explicitly keep paths
+                    for p in self.profile.get_keep_paths() {
+                        paths.extend(glob::expand_rule(p.to_str().unwrap()));
+                    }
+
+                    // Explicitly knock-out paths, by any spelling through directory symlinks
+                    let resolved = if self.profile.get_prune_paths().is_empty() {
+                        HashMap::default()
+                    } else {
+                        paths.iter().map(|x| (x.to_owned(), RootFS::resolve_dirs(x).0)).collect::<HashMap<PathBuf, PathBuf>>()
+                    };
+                    for p in self.profile.get_prune_paths() {
+                        let p = p.to_str().unwrap();
+                        let rp = glob::resolve_base(p);
+                        paths.retain(|x| !glob::matches_tree(p, x) && !glob::matches_tree(&rp, &resolved[x]));
+                    }
+
+                    paths.extend(TintProcessor::ext_path(paths));
+                }
+                "resources" => {
+                    log::debug!("Filtering resources");
+                    ResourcesDataFilter::new(self.profile.to_owned(), self.autodeps).filter(paths);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Keep symlinks consistent with their targets after all filters were applied.
+    /// A target, removed by a filter, is added back, unless it is explicitly pruned.
+    /// In that case the symlink itself is dropped.
+    fn repair_symlinks(&self, paths: &mut HashSet<PathBuf>) {
+        loop {
+            let mut repaired = false;
+            for lnk in paths.iter().filter(|p| prefix::is_symlink(p)).cloned().collect::<Vec<PathBuf>>() {
+                let tgt = match RootFS::link_target(&lnk) {
+                    Some(tgt) => tgt,
+                    None => continue,
+                };
+
+                if paths.contains(&tgt) || !(prefix::exists(&tgt) || prefix::is_symlink(&tgt)) || prefix::is_dir(&tgt) {
+                    continue;
+                }
+
+                if self.profile.get_prune_paths().iter().any(|p| glob::matches_tree(p.to_str().unwrap(), &tgt)) {
+                    log::info!("Dropping symlink {}, as its target {} is pruned", lnk.to_str().unwrap(), tgt.to_str().unwrap());
+                    paths.remove(&lnk);
+                } else {
+                    log::info!(
+                        "Keeping {}, as it is a target of the kept symlink {}",
+                        tgt.to_str().unwrap(),
+                        lnk.to_str().unwrap()
+                    );
+                    paths.insert(tgt);
+                }
+                repaired = true;
+            }
+
+            if !repaired {
+                break;
+            }
+        }
+    }
+
+    /// Check hygiene of the keep set. Returns amount of found issues.
+    fn check_hygiene(&self, paths: &[PathBuf]) -> usize {
+        let mut issues = 0;
+        for p in paths.iter().filter(|p| !prefix::is_dir(p)) {
+            let fname = p.file_name().and_then(|f| f.to_str()).unwrap_or_default();
+            if ResourcesDataFilter::is_potential_junk(fname, self.profile.get_junk_extensions()) {
+                log::error!("Check: {} looks like junk", p.to_str().unwrap());
+                issues += 1;
+            }
+        }
+
+        for (p, lib) in elfver::get_unresolved(paths) {
+            log::error!("Check: {} needs {}, which is not kept", p.to_str().unwrap(), lib);
+            issues += 1;
+        }
+
+        if let Some(max_size) = self.max_size {
+            let mut seen: HashSet<(u64, u64)> = HashSet::default();
+            let size = paths
+                .iter()
+                .filter_map(|p| prefix::symlink_metadata(p).ok())
+                .filter(|m| RootFS::first_link(m, &mut seen))
+                .map(|m| m.len())
+                .sum::<u64>();
+            if size > max_size {
+                log::error!(
+                    "Check: kept files take {}, which exceeds the budget of {}",
+                    ByteSize::b(size),
+                    ByteSize::b(max_size)
+                );
+                issues += 1;
+            }
+        }
+
+        issues
+    }
+
+    /// Check that interpreters of the script targets are kept.
+    /// Such image would be broken only at runtime, so this is a hard error.
+    fn check_interpreters(&self, kept: &dyn Fn(&Path) -> bool, pkb: &dyn PackageBackend) -> Result<(), Error> {
+        let mut errors: Vec<String> = vec![];
+        for t in self.profile.get_targets() {
+            let mut intp = match RootFS::interpreter(Path::new(t)) {
+                Some(intp) => intp,
+                None => continue,
+            };
+
+            // Whole symlink chain to the interpreter must stay
+            loop {
+                if !kept(&intp) {
+                    let mut reason = String::from("it is not kept");
+                    if let Some(rule) =
+                        self.profile.get_prune_paths().iter().find(|p| glob::matches_tree(p.to_str().unwrap(), &intp))
+                    {
+                        reason = format!("it is pruned by the rule {}", rule.to_str().unwrap());
+                    } else if let Ok(Some(pkg)) = pkb.get_package_for(intp.to_str().unwrap().to_string()) {
+                        if self.profile.get_dropped_packages().contains(&pkg) {
+                            reason = format!("its package {} is dropped", pkg);
+                        }
+                    }
+                    errors.push(format!(
+                        "Target {} is a script, but its interpreter {} will be removed: {}",
+                        t,
+                        intp.to_str().unwrap(),
+                        reason
+                    ));
+                    break;
+                }
+
+                match RootFS::link_target(&intp) {
+                    Some(tgt) => intp = tgt,
+                    None => break,
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        for e in &errors {
+            log::error!("{}", e);
+        }
+
+        Err(Error::new(std::io::ErrorKind::InvalidInput, "Profile contradicts to its script targets"))
+    }
+
+    /// Warn about kept binaries, those need newer symbol versions than the kept libraries provide,
+    /// e.g. glibc after copying a binary between images. Such binaries fail at startup.
+    fn check_versions(&self, paths: &HashSet<PathBuf>) {
+        for (p, lib, versions) in elfver::get_incompatible(&paths.iter().cloned().collect::<Vec<PathBuf>>()) {
+            log::warn!("{} requires {} from {}, which is not provided", p.to_str().unwrap(), versions.join(", "), lib);
+        }
+    }
+
+    /// Verify that the removal does not break any kept binary: its interpreter and
+    /// needed libraries must stay. Each broken binary is reported as an error.
+    fn verify_deps(&self, kept: &[PathBuf], removed: &[PathBuf]) -> Result<(), Error> {
+        let broken = elfver::get_broken(kept, removed);
+        if broken.is_empty() {
+            return Ok(());
+        }
+
+        for (p, deps) in &broken {
+            log::error!("{} {} {}", p.to_str().unwrap(), if self.dry_run { "would lose" } else { "has lost" }, deps.join(", "));
+        }
+
+        Err(Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "{} kept binaries {} broken. Keep their dependencies in the profile.",
+                broken.len(),
+                if self.dry_run { "would be" } else { "are" }
+            ),
+        ))
+    }
+
+    /// Scan a target or a profile package
+    fn scan_task(&self, task: &ScanTask, with_packages: bool) -> Result<ScanResult, Error> {
+        let target_path = match task {
+            ScanTask::Target(t) => t,
+            ScanTask::Package(p) => {
+                log::debug!("Getting content of package \"{}\"", p);
+                let c = pkgbackend::detect().get_package_contents(p.to_owned())?;
+                return Ok(vec![(Reason::PackageContent, format!("package \"{}\"", p), c)]);
+            }
+        };
+
+        log::debug!("Find binary dependencies for {target_path}");
+        let mut out = vec![(Reason::ElfDep, target_path.to_owned(), ElfScanner::new().scan(PathBuf::from(target_path)))];
+
+        // Contents of the packages are memoized by the backend, so a package of several targets is listed once
+        if with_packages {
+            log::debug!("Find package dependencies for {target_path}");
+            let autodeps = self.profile.get_target_autodeps(target_path).map(Autodeps::from_name).unwrap_or(self.autodeps);
+            out.push((
+                Reason::PackageContent,
+                format!("the package of {}", target_path),
+                PackageScanner::new(autodeps).scan(PathBuf::from(target_path)),
+            ));
+        }
+
+        // Add the target itself
+        out.push((Reason::Target, String::new(), vec![PathBuf::from(target_path)]));
+        Ok(out)
+    }
+
+    /// Scanning stage: scan the tasks in parallel, sending what is found to the filtering stage.
+    /// Stops on the first error, which is sent as well.
+    fn scan_stage(&self, tasks: &[(usize, ScanTask)], with_packages: bool, tx: SyncSender<Result<(usize, ScanResult), Error>>) {
+        let _ = tasks.par_iter().try_for_each_with(tx, |tx, (i, t)| {
+            let found = self.scan_task(t, with_packages);
+            let failed = found.is_err();
+            tx.send(found.map(|f| (*i, f))).map_err(|_| ())?;
+            if failed {
+                Err(())
+            } else {
+                Ok(())
+            }
+        });
+    }
+
+    /// Filtering stage: run per-path filters on the paths as they are found. Filters, those need
+    /// the whole keep set, run afterwards. Results are added to the provenance in the order of the tasks,
+    /// so it does not depend on the timing.
+    fn filter_stage(
+        &self, rx: Receiver<Result<(usize, ScanResult), Error>>, filters: &[Box<dyn DataFilter>],
+    ) -> Result<Filtered, Error> {
+        let mut out = Filtered::default();
+        let mut pending: BTreeMap<usize, ScanResult> = BTreeMap::new();
+        for found in rx {
+            let (i, found) = found?;
+            for (_, _, p) in &found {
+                Self::filter_found(p, filters, &mut out);
+            }
+
+            pending.insert(i, found);
+            while let Some(found) = pending.remove(&out.merged) {
+                out.merged += 1;
+                for (reason, origin, p) in found {
+                    out.prov.add(p, reason, &origin);
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Run per-path filters on the paths, those are found for the first time
+    fn filter_found(found: &[PathBuf], filters: &[Box<dyn DataFilter>], out: &mut Filtered) {
+        let mut batch = found.iter().filter(|p| !out.unfiltered.contains(*p)).cloned().collect::<HashSet<PathBuf>>();
+
+        // Packages ship empty directories for the runtime, e.g. /var/log, those are kept
+        out.skeleton.extend(batch.iter().filter(|p| RootFS::is_skeleton(p)).cloned());
+        out.unfiltered.extend(batch.iter().cloned());
+        for f in filters {
+            f.filter(&mut batch);
+        }
+        out.paths.extend(batch);
+    }
+
+    /// Dissection stage: walk the whole rootfs, as it does not depend on what is kept,
+    /// then wait for the keep set and get what needs to be removed.
+    fn dissect_stage(rx: Receiver<HashSet<PathBuf>>) -> Option<Dissected> {
+        log::debug!("Scanning existing rootfs");
+        let rfs = RootFS::new();
+        let mut paths = rx.recv().ok()?;
+
+        // All names of a kept file are kept, otherwise it is not removed anyway
+        let links = rfs.get_hardlinks(&paths);
+        paths.extend(links.iter().map(|(lnk, _)| lnk.to_owned()));
+
+        let mut removed = rfs.dissect(&paths);
+        removed.sort();
+        Some(Dissected { rfs, paths, links, removed })
+    }
+
+    /// Run scanning of targets and packages, filtering and dissection as concurrent stages,
+    /// connected by channels. Returns paths to keep and to remove.
+    fn run_stages(
+        &self, baseline: Option<&Manifest>, previous: Option<&Manifest>, metrics: &mut Metrics,
+    ) -> Result<Stages, Error> {
+        // Content of all profile packages (if any) is scanned along with the targets,
+        // and then TextDataFilter removes what still should be removed.
+        // The idea is to keep parts only relevant to the runtime.
+        let with_packages = baseline.is_none();
+        let targets = self.profile.get_targets().iter().map(|t| ScanTask::Target(t.to_owned())).collect::<Vec<ScanTask>>();
+        let mut packages: Vec<ScanTask> = vec![];
+        if with_packages {
+            packages.extend(self.profile.get_packages().iter().map(|p| ScanTask::Package(p.to_owned())));
+
+            // Packages of all targets are found at once, instead of one query per target
+            if let Err(err) = pkgbackend::detect().find_owners(self.profile.get_targets()) {
+                log::debug!("Unable to find packages of the targets: {}", err);
+            }
+        }
+        let targets = targets.into_iter().enumerate().collect::<Vec<(usize, ScanTask)>>();
+        let packages = packages.into_iter().enumerate().map(|(i, t)| (i + targets.len(), t)).collect::<Vec<(usize, ScanTask)>>();
+        log::debug!(
+            "Scanning {} targets and {} packages with {} jobs",
+            targets.len(),
+            packages.len(),
+            rayon::current_num_threads()
+        );
+
+        // Per-path filters at the head of the pipeline run while scanning, the rest needs the whole keep set
+        let pipeline = self.profile.get_pipeline();
+        let mut filters: Vec<Box<dyn DataFilter>> = vec![];
+        for stage in pipeline {
+            match self.get_path_filter(stage) {
+                Some(f) => filters.push(f),
+                None => break,
+            }
+        }
+        let rest = &pipeline[filters.len()..];
+
+        let mut rules = self.profile.get_package_rules().iter().collect::<Vec<_>>();
+        rules.sort_by_key(|(pkg, _)| *pkg);
+        let rule_pkgs = rules.iter().map(|(pkg, _)| pkg.to_string()).collect::<Vec<String>>();
+        let dropped = self.profile.get_dropped_packages();
+
+        let t_scan = Instant::now();
+        thread::scope(|s| {
+            let (keep_tx, keep_rx) = mpsc::channel::<HashSet<PathBuf>>();
+            let dissection = s.spawn(move || Self::dissect_stage(keep_rx));
+
+            // Contents of the packages with rules and of the dropped ones are listed concurrently,
+            // but are applied in order
+            let contents =
+                s.spawn(|| -> Result<_, Error> { Ok((Self::get_contents(&rule_pkgs)?, Self::get_contents(dropped)?)) });
+
+            let (tx, rx) = mpsc::sync_channel::<Result<(usize, ScanResult), Error>>(SCAN_QUEUE_SIZE);
+            let ptx = tx.clone();
+            s.spawn(move || self.scan_stage(&targets, with_packages, tx));
+            s.spawn(move || self.scan_stage(&packages, with_packages, ptx));
+
+            let mut f = self.filter_stage(rx, &filters)?;
+            metrics.add_phase("scan", t_scan.elapsed());
+
+            let t_filter = Instant::now();
+            if let Some(baseline) = baseline {
+                // Packages might be already partially removed, so the manifest is the only source of truth
+                log::info!("Using manifest of the tinted image as a baseline ({} paths)", baseline.get_kept().len());
+                Self::filter_found(baseline.get_kept(), &filters, &mut f);
+                f.prov.add(baseline.get_kept().to_vec(), Reason::Manifest, "");
+            }
+            if let Some(previous) = previous {
+                log::info!("Re-running against the manifest of the previous run ({} paths)", previous.get_kept().len());
+                Self::filter_found(previous.get_kept(), &filters, &mut f);
+                f.prov.add(previous.get_kept().to_vec(), Reason::Manifest, "");
+            }
+            let Filtered { mut prov, mut paths, unfiltered, mut skeleton, .. } = f;
+            self.run_pipeline(&mut paths, rest);
+
+            // Filters are heuristics, so they never drop files with capabilities
+            for fp in unfiltered.difference(&paths).cloned().collect::<Vec<PathBuf>>() {
+                if let Some(caps) = xattrs::get_caps(&prefix::host_nofollow(&fp)) {
+                    log::info!("Keeping {}, as it has file capabilities {}", fp.to_str().unwrap(), xattrs::describe_caps(&caps));
+                    paths.insert(fp);
+                }
+            }
+
+            // Apply per-package rules
+            let (contents, dropped_contents) =
+                contents.join().map_err(|_| Error::new(std::io::ErrorKind::Other, "Package contents scanner has crashed"))??;
+            for ((pkg, rules), contents) in rules.into_iter().zip(contents) {
+                log::debug!("Applying rules for package \"{}\"", pkg);
+                for p in contents {
+                    if rules.get_keep().iter().any(|r| glob::matches_rule(r, &p)) {
+                        paths.insert(p);
+                    } else if rules.get_prune().iter().any(|r| glob::matches_tree(r, &p)) {
+                        for p in RootFS::expand_target(p, true) {
+                            paths.remove(&p);
+                        }
+                    }
+                }
+            }
+
+            // Re-check symlinks, as filters might remove their targets
+            self.repair_symlinks(&mut paths);
+
+            // Remove package content before dissection
+            // XXX: Exlude .so binaries also from the Elf reader?
+            for (p, contents) in dropped.iter().zip(dropped_contents) {
+                log::debug!("Removing dropped package contents from \"{}\"", p);
+                for p in contents {
+                    let canonical = RootFS::resolve_dirs(&p).0;
+                    for p in RootFS::expand_target(p, true).into_iter().chain([canonical]) {
+                        skeleton.remove(&p);
+                        paths.remove(&p);
+                    }
+                }
+            }
+
+            Self::resolve_dir_links(&mut paths, &mut prov);
+            self.check_interpreters(&|p| paths.contains(p), pkgbackend::detect().as_ref())?;
+            self.check_versions(&paths);
+            metrics.add_phase("filter", t_filter.elapsed());
+
+            let t_dissect = Instant::now();
+            let _ = keep_tx.send(paths);
+            let Dissected { rfs, paths, links, removed } = dissection
+                .join()
+                .ok()
+                .flatten()
+                .ok_or_else(|| Error::new(std::io::ErrorKind::Other, "Rootfs scanner has crashed"))?;
+            for (lnk, k) in links {
+                log::debug!("Keeping {}, which is a hard link of {}", lnk.to_str().unwrap(), k.to_str().unwrap());
+                prov.add(vec![lnk], Reason::Hardlink, k.to_str().unwrap());
+            }
+
+            Ok(Stages { prov, paths, unfiltered, skeleton, rfs, removed, t_dissect })
+        })
+    }
+
+    /// Get contents of the packages in parallel. Contents are in the order of the packages.
+    fn get_contents(pkgs: &[String]) -> Result<Vec<Vec<PathBuf>>, Error> {
+        pkgs.par_iter().map(|p| pkgbackend::detect().get_package_contents(p.to_owned())).collect()
+    }
+
+    /// Call a script hook
+    fn call_script(s: String) -> Result<(), Error> {
+        // XXX: It can run args, but from where pass them? Profile? CLI? Both? None at all?..
+        let (stdout, stderr) = ShellScript::new(s, None).run()?;
+
+        if !stdout.is_empty() {
+            log::debug!("Post-hook stdout:");
+            log::debug!("{}", stdout);
+        }
+
+        if !stderr.is_empty() {
+            log::error!("Post-hook error:");
+            log::error!("{}", stderr);
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate only keep/prune rules of the profile against the rootfs,
+    /// without running any scanners.
+    pub fn analyze_rules(&mut self) -> Result<(), Error> {
+        self.switch_root()?;
+        self.profile.apply_conditions(&OsInfo::detect());
+
+        let keep = self
+            .profile
+            .get_keep_paths()
+            .iter()
+            .map(|p| (p.to_str().unwrap().to_string(), glob::expand_rule(p.to_str().unwrap())))
+            .collect::<Vec<(String, Vec<PathBuf>)>>();
+        let prune = self
+            .profile
+            .get_prune_paths()
+            .iter()
+            .map(|p| (p.to_str().unwrap().to_string(), glob::expand_tree(p.to_str().unwrap())))
+            .collect::<Vec<(String, Vec<PathBuf>)>>();
+
+        RulesFormatter::new(&keep, &prune).format();
+
+        // Rules alone might remove interpreters of the script targets, as the full run would find out
+        let pruned = prune
+            .iter()
+            .flat_map(|(_, p)| p.iter())
+            .filter(|p| !keep.iter().any(|(_, k)| k.contains(p)))
+            .collect::<HashSet<&PathBuf>>();
+        self.check_interpreters(&|p| !pruned.contains(&p.to_path_buf()), pkgbackend::detect().as_ref())
+    }
+
+    /// Call hook commands. Any failed command stops the processing.
+    fn call_commands(&self, title: &str, cmds: &Vec<String>) -> Result<(), Error> {
+        for c in cmds {
+            if self.dry_run {
+                log::debug!("{} command: {}", title, c);
+                continue;
+            }
+
+            log::info!("Running {} command: {}", title.to_lowercase(), c);
+            let (stdout, _) = ShellScript::new(c.to_string(), None).run_checked()?;
+            if !stdout.is_empty() {
+                log::debug!("{}", stdout);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Start tint processor
+    pub fn start(&mut self) -> Result<(), Error> {
+        // Interrupted run must be finished first
+        if self.root.join(JOURNAL_PATH.trim_start_matches('/')).exists() {
+            if !self.resume || self.dry_run {
+                return Err(Error::new(
+                    std::io::ErrorKind::Interrupted,
+                    format!(
+                        "Previous run on {} was interrupted while removing files. Use --resume to finish it, \
+                         or the \"restore\" command to undo it.",
+                        self.root.to_str().unwrap()
+                    ),
+                ));
+            }
+
+            self.switch_root()?;
+            return self.resume_changes();
+        } else if self.resume {
+            return Err(Error::new(std::io::ErrorKind::NotFound, "There is no interrupted run to resume."));
+        }
+
+        // Snapshot is taken on the host, before anything is changed
+        let lockfile = self.lockfile.as_ref().map(|l| self.root.join(l.strip_prefix("/").unwrap_or(l)));
+        let tinted = lockfile.as_ref().map(|l| l.exists()).unwrap_or(false);
+        if let Some(lockfile) = lockfile.filter(|_| tinted) {
+            match TintState::load(&lockfile)? {
+                Some(state) => log::info!("Root filesystem was {}", state.describe()),
+                None => log::info!("Root filesystem was tinted by an older version, which does not record its state"),
+            }
+        }
+
+        if tinted && !self.overlay && !self.force {
+            return Err(Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!(
+                    "Root filesystem {} is already tinted ({} exists). Use --force to apply the profile again, \
+                     --overlay to tint it further with another profile, or the \"restore\" command to undo it.",
+                    self.root.to_str().unwrap(),
+                    self.lockfile.as_ref().unwrap().to_str().unwrap()
+                ),
+            ));
+        }
+
+        if self.snapshot && !self.dry_run && (self.overlay || self.force || !tinted) {
+            self.snap = Some(Snapshot::create(&self.root)?);
+        }
+
+        // Export file is on the host, so it is opened before chroot
+        let mut export = match &self.export_profile {
+            Some(p) => Some(File::create(p)?),
+            None => None,
+        };
+        let mut plan = match &self.plan {
+            Some(p) => Some((File::create(p)?, canonicalize(&self.root)?)),
+            None => None,
+        };
+        let mut metrics_out = match &self.metrics_out {
+            Some(p) => Some(File::create(p)?),
+            None => None,
+        };
+        let mut sbom = match &self.sbom {
+            Some(p) => Some((File::create(p)?, canonicalize(&self.root)?)),
+            None => None,
+        };
+        let mut csv_out = match &self.csv {
+            Some(p) => Some(File::create(p)?),
+            None => None,
+        };
+        let mut report = match &self.report {
+            Some(p) => Some((File::create(p)?, canonicalize(&self.root)?)),
+            None => None,
+        };
+        let mut graph = match &self.graph {
+            Some(p) => Some(File::create(p)?),
+            None => None,
+        };
+        let mut audit = match &self.audit_log {
+            Some(p) if !self.dry_run => Some(fs::OpenOptions::new().create(true).append(true).open(p)?),
+            _ => None,
+        };
+        let backup = match &self.backup {
+            Some(p) if !self.dry_run => Some(Backup::create(p)?),
+            _ => None,
+        };
+        let whiteouts = match &self.whiteouts {
+            Some(p) if !self.dry_run => Some(Whiteouts::create(p)?),
+            _ => None,
+        };
+        let mut attestation = match &self.attestation {
+            Some(p) => Some(File::create(p)?),
+            None => None,
+        };
+        let image_config = match &self.image_config {
+            Some(p) => Some(ImageConfig::load(p)?),
+            None => None,
+        };
+        let mut metrics = Metrics::new(self.dry_run);
+        // Files, those could not be removed
+        let mut failed: Vec<PathBuf> = vec![];
+        // Decisions of the dry runs are compared to the previous one, while the profile is iterated
+        let mut last = if self.dry_run {
+            match canonicalize(&self.root).and_then(|r| LastRun::open(&r)) {
+                Ok(last) => Some(last),
+                Err(err) => {
+                    log::warn!("Unable to open decisions of the previous dry run: {}", err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Rollback extracts the backup on the host, so the way back from the chroot is kept
+        let host_root = match &self.backup {
+            Some(_) if !self.dry_run && !self.prefix && !self.profile.get_verify_commands().is_empty() => Some(File::open("/")?),
+            _ => None,
+        };
+
+        self.switch_root()?;
+
+        // Bail-out if the image is already processed, unless it is tinted further
+        let mut baseline: Option<Manifest> = None;
+        let mut previous: Option<Manifest> = None;
+        if self.overlay {
+            if !self.lockfile.as_ref().map(prefix::exists).unwrap_or(true) || !prefix::exists(MANIFEST_PATH) {
+                return Err(Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "This container is not tinted yet or has no manifest, nothing to overlay.",
+                ));
+            }
+            baseline = Some(Manifest::load(&prefix::at(MANIFEST_PATH))?);
+        } else if self.force {
+            if prefix::exists(MANIFEST_PATH) {
+                previous = Some(Manifest::load(&prefix::at(MANIFEST_PATH))?);
+            } else if tinted {
+                log::warn!("Tinted image has no manifest, processing it from scratch");
+            }
+        }
+
+        // Apply conditional profile sections
+        let osi = OsInfo::detect();
+        log::info!("Detected OS: {}, architecture: {}", osi.get_id(), osi.get_arch());
+        self.profile.apply_conditions(&osi);
+        self.strict |= self.profile.is_strict();
+        if let Some(ic) = &image_config {
+            self.trace_image_config(ic);
+        }
+        let unmatched = self.profile.expand_targets();
+        self.check_missing(&unmatched)?;
+
+        // Hooks run inside the image, which is possible only after chroot
+        if self.prefix
+            && !self.dry_run
+            && (self.profile.has_pre_hook()
+                || self.profile.has_post_hook()
+                || !self.profile.get_pre_commands().is_empty()
+                || !self.profile.get_post_commands().is_empty()
+                || !self.profile.get_verify_commands().is_empty())
+        {
+            return Err(Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Profile has hooks or verify commands, those need to run inside the image. They cannot run with --prefix.",
+            ));
+        }
+
+        // Run pre-hook, if any
+        if self.profile.has_pre_hook() {
+            if self.dry_run {
+                log::debug!("Pre-hook:\n{}", self.profile.get_pre_hook());
+            } else {
+                Self::call_script(self.profile.get_pre_hook())?;
+            }
+        }
+
+        // Run pre-hook commands. Failure aborts everything before any deletion.
+        self.call_commands("Pre-hook", self.profile.get_pre_commands())?;
+
+        // Report features, those are not available with the package backend
+        let pkb = pkgbackend::detect();
+        let unavailable = pkb.capabilities().get_unavailable();
+        if !unavailable.is_empty() {
+            log::warn!("Package backend \"{}\" does not support: {}", pkb.name(), unavailable.join(", "));
+        }
+        if !pkb.capabilities().autodeps && self.autodeps != Autodeps::Undef {
+            log::warn!("Automatic dependencies are requested, but are not available. Ignoring.");
+        }
+
+        // Scanning, filtering and dissection run concurrently
+        let Stages { mut prov, mut paths, unfiltered, skeleton, rfs, removed: mut p, t_dissect } =
+            self.run_stages(baseline.as_ref(), previous.as_ref(), &mut metrics)?;
+        self.skeleton = skeleton;
+        let pscan = pkgbackend::detect();
+
+        // One bad rule must not break the image, so protected paths stay
+        if self.safety {
+            let (protected, removed): (Vec<PathBuf>, Vec<PathBuf>) = p.into_iter().partition(|p| RootFS::is_protected(p));
+
+            // Scanners might find the path by another name, e.g. /lib64 instead of /usr/lib64
+            let canonical = |set: &HashSet<PathBuf>| {
+                if protected.is_empty() {
+                    return HashSet::default();
+                }
+                set.iter().filter_map(|u| prefix::canonicalize(u).ok()).collect::<HashSet<PathBuf>>()
+            };
+            let (selected, kept) = (canonical(&unfiltered), canonical(&paths));
+            for pp in protected {
+                let cp = prefix::canonicalize(&pp).ok();
+                let dropped = unfiltered.contains(&pp) || cp.as_ref().map(|c| selected.contains(c)).unwrap_or(false);
+                if dropped && !cp.as_ref().map(|c| kept.contains(c)).unwrap_or(false) {
+                    log::warn!(
+                        "Keeping protected {}, which filters or prune rules remove. Use --no-safety to remove it.",
+                        pp.to_str().unwrap()
+                    );
+                } else {
+                    log::debug!("Keeping protected {}", pp.to_str().unwrap());
+                }
+                prov.add(vec![pp.to_owned()], Reason::Protected, "");
+                paths.insert(pp);
+            }
+            p = removed;
+        }
+
+        // Broken symlinks of the image might be intended, e.g. pointing into volumes
+        let (dangling, removed): (Vec<PathBuf>, Vec<PathBuf>) =
+            p.into_iter().partition(|lp| prefix::is_symlink(lp) && prefix::canonicalize(lp).is_err() && self.keeps_dangling(lp));
+        p = removed;
+        for lp in dangling {
+            self.report_dangling(&lp);
+            if let Some(ptn) = self.profile.get_allow_dangling().iter().find(|ptn| glob::matches(ptn, &lp)) {
+                prov.add(vec![lp.to_owned()], Reason::ProfileKeep, ptn);
+            }
+            self.dangling.insert(lp.to_owned());
+            paths.insert(lp);
+        }
+        p.extend(self.get_pruned_trees(&paths));
+
+        // Nothing queries the package database after the scans, so it can go with the rest
+        if self.profile.drop_pkgdb() {
+            let db = pscan.get_database().iter().flat_map(|ptn| glob::expand_tree(ptn)).collect::<Vec<PathBuf>>();
+            if !db.is_empty() {
+                log::warn!(
+                    "Dropping the {} package database, so SBOM can no longer be made from the image.{}",
+                    pscan.name(),
+                    if self.sbom.is_none() { " Use --sbom to make it now." } else { "" }
+                );
+            }
+            let removed = p.iter().cloned().collect::<HashSet<PathBuf>>();
+            for dp in db {
+                paths.remove(&dp);
+                if !removed.contains(&dp) {
+                    p.push(dp);
+                }
+            }
+        }
+        for (lp, caps) in p.iter().filter_map(|lp| rfs.get_caps(lp).map(|c| (lp, c))) {
+            log::warn!("Removing {}, which has file capabilities {}", lp.to_str().unwrap(), caps);
+        }
+        if previous.is_some() {
+            log::info!("Found {} paths, added since the previous run", p.iter().filter(|p| !self.is_state_file(p)).count());
+        }
+
+        let mut paths = paths.into_iter().collect::<Vec<PathBuf>>();
+        paths.sort();
+        metrics.add_phase("dissect", t_dissect.elapsed());
+        self.resolve_provenance(&mut prov, &paths);
+        if logger::has_log_file() {
+            Self::for_each_decision(&prov, &paths, &p, |p, action, r, origin| logger::decision(p, action, r.as_str(), origin));
+        }
+
+        if let Some(ep) = &self.explain_path {
+            self.print_explanation(ep, &prov, &paths, &p);
+            return Ok(());
+        }
+        if metrics_out.is_some() {
+            metrics.set_removed(&p).set_kept(&paths);
+        }
+
+        if let Some((plan, root)) = plan.as_mut() {
+            plan.write_all(Plan::new(root, &p, &paths).to_json()?.as_bytes())?;
+            log::info!("Removal plan is written to {}", self.plan.as_ref().unwrap().to_str().unwrap());
+        }
+
+        if let Some((report, root)) = report.as_mut() {
+            let html = HtmlReport::new(&paths, &p, self.profile.get_junk_extensions()).to_html(root.to_str().unwrap())?;
+            report.write_all(html.as_bytes())?;
+            log::info!("HTML report is written to {}", self.report.as_ref().unwrap().to_str().unwrap());
+        }
+
+        if let Some(graph) = graph.as_mut() {
+            let g = DepGraph::new(self.profile.get_targets(), &paths);
+            graph.write_all(if self.graph_json { g.to_json()? } else { g.to_dot() }.as_bytes())?;
+            log::info!("Dependency graph is written to {}", self.graph.as_ref().unwrap().to_str().unwrap());
+        }
+
+        if let Some(csv_out) = csv_out.as_mut() {
+            let (kept, removed) = Self::get_reasons(&prov, &paths, &p);
+            csv_out.write_all(CsvReport::new(&kept, &removed).to_csv().as_bytes())?;
+            log::info!("CSV report is written to {}", self.csv.as_ref().unwrap().to_str().unwrap());
+        }
+
+        // Package database might be removed by tinting, so SBOM is made before applying changes
+        if let Some((sbom, root)) = sbom.as_mut() {
+            let name = root.file_name().and_then(|n| n.to_str()).unwrap_or("rootfs");
+            let s = Sbom::new(&paths, pscan.as_ref());
+            sbom.write_all(if self.cyclonedx { s.to_cyclonedx(name)? } else { s.to_spdx(name)? }.as_bytes())?;
+            log::info!("SBOM is written to {}", self.sbom.as_ref().unwrap().to_str().unwrap());
+        }
+
+        if let Some(export) = export.as_mut() {
+            let mut pkgs = self.profile.get_packages().to_owned();
+            for t in self.profile.get_targets() {
+                if let Ok(Some(p)) = pscan.get_package_for(t.to_string()) {
+                    pkgs.push(p);
+                }
+            }
+            pkgs.sort();
+            pkgs.dedup();
+
+            export.write_all(self.profile.export(&pkgs, &paths)?.as_bytes())?;
+            log::info!("Exported expanded profile to {}", self.export_profile.as_ref().unwrap().to_str().unwrap());
+        }
+
+        // System accounts of the kept files
+        let acc = Accounts::new(&paths);
+        for e in acc.verify() {
+            log::warn!("{}", e);
+        }
+        // Image stays untouched with the whiteouts, otherwise accounts are trimmed after the backup
+        let untouched = self.dry_run || whiteouts.is_some();
+        if untouched {
+            self.trim_accounts(&acc, true)?;
+        }
+
+        // Kept binaries are left unconfined without their policies
+        for (bin, policy) in secpolicy::get_removed_policies(&paths, &p) {
+            log::warn!(
+                "Removing {}, which is the security policy of the kept {}",
+                policy.to_str().unwrap(),
+                bin.to_str().unwrap()
+            );
+        }
+
+        // Capabilities and SELinux contexts are verified after the changes are applied
+        let caps = paths
+            .iter()
+            .filter_map(|p| xattrs::get_caps(&prefix::host_nofollow(p)).map(|c| (p.to_owned(), c)))
+            .collect::<Vec<_>>();
+        let contexts = paths
+            .iter()
+            .filter_map(|p| xattrs::get_context(&prefix::host_nofollow(p)).map(|c| (p.to_owned(), c)))
+            .collect::<Vec<_>>();
+
+        // Permissions and ownership of the kept files
+        let hrd = Hardening::new(&paths);
+        let hardening = hrd.report();
+        let security = hrd.security();
+        if !self.dry_run {
+            security.iter().chain(hardening.iter()).for_each(|h| log::warn!("{}", h));
+        }
+        if untouched {
+            self.normalise_permissions(&hrd, true)?;
+        }
+
+        if self.dry_run {
+            if self.profile.has_post_hook() {
+                log::debug!("Post-hook:\n{}", self.profile.get_post_hook());
+            }
+            let mut rts = RuntimeScanner::new();
+            if self.ndjson {
+                let mut nf = NdjsonFormatter::new();
+                Self::for_each_decision(&prov, &paths, &p, |p, action, r, origin| nf.emit(p, action, r.as_str(), origin));
+            } else if self.json {
+                JsonFormatter::new(&paths, &p, rts.scan(), &hardening, &security).format()?;
+            } else if self.diff_last && !self.quiet && last.as_ref().map(|l| l.has_previous()).unwrap_or(false) {
+                last.as_ref().unwrap().format(&paths, &p);
+            } else if !self.quiet {
+                if self.diff_last {
+                    log::info!("No previous dry run of this root filesystem, showing all paths");
+                }
+                ContentFormatter::new(&paths)
+                    .set_removed(&p)
+                    .set_runtimes(rts.scan())
+                    .set_hardening(&hardening)
+                    .set_security(&security)
+                    .set_junk_extensions(self.profile.get_junk_extensions())
+                    .set_by_package(self.by_package)
+                    .set_top(self.top)
+                    .set_depth(self.depth)
+                    .set_collapse(self.collapse)
+                    .set_dirs_only(self.dirs_only)
+                    .set_plain(self.plain, self.sizes)
+                    .set_provenance(if self.explain { Some(&prov) } else { None })
+                    .format();
+            }
+            if let Some(last) = last.as_mut() {
+                last.save(&paths, &p)?;
+            }
+            SysCaches::new(&p, true).refresh()?;
+            self.strip_elfs(&paths)?;
+            self.dedup_files(&paths)?;
+            self.call_commands("Post-hook", self.profile.get_post_commands())?;
+            self.call_commands("Verify", self.profile.get_verify_commands())?;
+            self.verify_deps(&paths, &p)?;
+        } else {
+            // Run post-hook (doesn't affect changes apply)
+            if self.profile.has_post_hook() {
+                Self::call_script(self.profile.get_post_hook())?;
+            }
+            // Sizes of the removed files are known only before they are removed
+            let summary = Summary::new(&paths, &p);
+            let removed = p.clone();
+            let t_apply = Instant::now();
+            if let Some(whiteouts) = whiteouts {
+                self.apply_whiteouts(p, &paths, audit.as_mut(), whiteouts)?;
+            } else {
+                failed = self.apply_changes(p, &paths, audit.as_mut(), backup, &acc, &hrd)?;
+                Self::verify_caps(&caps)?;
+            }
+            let kept = if self.quarantine { self.resolve_unresolved(&paths)? } else { paths.clone() };
+            // Libraries might be restored from the quarantine, so the cache is pruned afterwards
+            if self.whiteouts.is_none() {
+                Self::prune_ld_cache()?;
+                SysCaches::new(&removed, false).refresh()?;
+                self.strip_elfs(&kept)?;
+                self.dedup_files(&kept)?;
+                Self::verify_contexts(&contexts)?;
+            }
+            metrics.add_phase("apply", t_apply.elapsed());
+            if !self.quiet {
+                summary.format();
+            }
+            self.call_commands("Post-hook", self.profile.get_post_commands())?;
+            self.verify_deps(&kept, &removed)?;
+            // Untouched image has nothing to verify
+            if self.whiteouts.is_none() {
+                self.verify(host_root)?;
+            }
+        }
+
+        if let Some(att) = attestation.as_mut() {
+            // Manifest might be updated by restoring files from the quarantine
+            let (manifest, verification) = if self.dry_run || self.whiteouts.is_some() {
+                (Manifest::new(&paths), Verification::Skipped)
+            } else {
+                (Manifest::load(&prefix::at(MANIFEST_PATH))?, Verification::verify(self.profile.get_targets()))
+            };
+            att.write_all(
+                Attestation::new(
+                    self.profile.get_id(),
+                    manifest.digest()?,
+                    manifest.get_kept().len(),
+                    self.dry_run,
+                    verification,
+                )
+                .to_json()?
+                .as_bytes(),
+            )?;
+            log::info!("Attestation is written to {}", self.attestation.as_ref().unwrap().to_str().unwrap());
+        }
+
+        if let Some(mo) = metrics_out.as_mut() {
+            mo.write_all(metrics.to_prom().as_bytes())?;
+            log::info!("Metrics are written to {}", self.metrics_out.as_ref().unwrap().to_str().unwrap());
+        }
+
+        if self.check {
+            let issues = self.check_hygiene(&paths);
+            if issues > 0 {
+                return Err(Error::new(std::io::ErrorKind::InvalidData, format!("Check has found {} issues", issues)));
+            }
+            log::info!("Check has passed");
+        }
+
+        // Unremovable files do not stop the steps after the removal
+        self.check_failed(&failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TintProcessor;
+    use crate::{
+        journal::{Journal, JOURNAL_PATH},
+        manifest::{Manifest, RemovalManifest, MANIFEST_PATH, REMOVED_MANIFEST_PATH},
+        prefix,
+        profile::Profile,
+        testutil::scratch,
+    };
+    use std::{
+        collections::HashSet,
+        fs,
+        os::unix::fs::symlink,
+        path::{Path, PathBuf},
+        sync::PoisonError,
+    };
+
+    fn set(paths: &[&Path]) -> HashSet<PathBuf> {
+        paths.iter().map(|p| p.to_path_buf()).collect()
+    }
+
+    #[test]
+    fn ext_path_follows_chains() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("chains");
+        fs::write(d.join("lib.so.1.2"), "").unwrap();
+        symlink("lib.so.1.2", d.join("lib.so.1")).unwrap();
+        symlink(d.join("lib.so.1"), d.join("lib.so")).unwrap();
+
+        let np = TintProcessor::ext_path(&set(&[&d.join("lib.so")]));
+        assert_eq!(np, set(&[&d.join("lib.so.1"), &d.join("lib.so.1.2")]));
+        fs::remove_dir_all(&d).unwrap();
+    }
+
+    #[test]
+    fn ext_path_resolves_relative_links() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("relative");
+        fs::create_dir_all(d.join("usr/lib")).unwrap();
+        fs::create_dir_all(d.join("usr/bin")).unwrap();
+        fs::write(d.join("usr/lib/tool"), "").unwrap();
+        symlink("../lib/tool", d.join("usr/bin/tool")).unwrap();
+        symlink("./tool", d.join("usr/bin/alias")).unwrap();
+
+        let np = TintProcessor::ext_path(&set(&[&d.join("usr/bin/alias")]));
+        assert_eq!(np, set(&[&d.join("usr/bin/tool"), &d.join("usr/lib/tool")]));
+        fs::remove_dir_all(&d).unwrap();
+    }
+
+    #[test]
+    fn ext_path_ends_on_cycles() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("cycles");
+        symlink("b", d.join("a")).unwrap();
+        symlink("c", d.join("b")).unwrap();
+        symlink("b", d.join("c")).unwrap();
+        symlink("self", d.join("self")).unwrap();
+
+        let np = TintProcessor::ext_path(&set(&[&d.join("a"), &d.join("self")]));
+        assert_eq!(np, set(&[&d.join("b"), &d.join("c")]));
+        fs::remove_dir_all(&d).unwrap();
+    }
+
+    #[test]
+    fn ext_path_skips_known_paths() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("known");
+        fs::write(d.join("file"), "").unwrap();
+        symlink("file", d.join("link")).unwrap();
+
+        assert!(TintProcessor::ext_path(&set(&[&d.join("link"), &d.join("file")])).is_empty());
+        assert!(TintProcessor::ext_path(&set(&[&d.join("file")])).is_empty());
+        fs::remove_dir_all(&d).unwrap();
+    }
+
+    #[test]
+    fn check_missing_drops_targets() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("missing");
+        fs::create_dir_all(d.join("usr/bin")).unwrap();
+        fs::write(d.join("usr/bin/tool"), "").unwrap();
+        prefix::set(Some(&d));
+
+        let mut p = Profile::default();
+        p.add_target("/usr/bin/tool".to_string()).add_target("/usr/bin/gone".to_string());
+        let mut tp = TintProcessor::new(d.to_owned());
+        tp.set_profile(p.clone()).set_strict(true);
+        assert!(tp.check_missing(&[]).is_err());
+        assert_eq!(tp.profile.get_targets().len(), 2);
+
+        tp.set_profile(p).set_strict(false);
+        tp.check_missing(&[]).unwrap();
+        assert_eq!(tp.profile.get_targets(), &["/usr/bin/tool".to_string()]);
+
+        prefix::set(None);
+        fs::remove_dir_all(&d).unwrap();
+    }
+
+    #[test]
+    fn resumes_journal() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("resume");
+        fs::create_dir_all(d.join("usr/share/doc/tool")).unwrap();
+        fs::create_dir_all(d.join("usr/bin")).unwrap();
+        fs::write(d.join("usr/bin/tool"), "").unwrap();
+        let docs = ["a", "b", "c"].iter().map(|n| PathBuf::from(format!("/usr/share/doc/tool/{}", n))).collect::<Vec<PathBuf>>();
+        for p in &docs {
+            fs::write(d.join(p.strip_prefix("/").unwrap()), "doc").unwrap();
+        }
+        prefix::set(Some(&d));
+
+        // Run was interrupted after the first file
+        let kept = [PathBuf::from("/usr/bin/tool")];
+        let mut j = Journal::create(
+            &prefix::at(JOURNAL_PATH),
+            "test".to_string(),
+            false,
+            None,
+            &docs,
+            &kept,
+            &RemovalManifest::new(&docs),
+        )
+        .unwrap();
+        j.progress(1).unwrap();
+        fs::remove_file(d.join("usr/share/doc/tool/a")).unwrap();
+        drop(j);
+
+        let mut tp = TintProcessor::new(d.to_owned());
+        tp.set_prefix(true).set_dry_run(false);
+        tp.resume_changes().unwrap();
+        assert!(!d.join(JOURNAL_PATH.trim_start_matches('/')).exists());
+        assert!(!d.join("usr/share/doc").exists(), "emptied directories are swept");
+        assert!(d.join("usr/bin/tool").exists());
+        assert_eq!(
+            Manifest::load(&prefix::at(MANIFEST_PATH)).unwrap().get_kept(),
+            &[kept[0].to_owned(), PathBuf::from(REMOVED_MANIFEST_PATH)]
+        );
+        let rm = RemovalManifest::load(&prefix::at(REMOVED_MANIFEST_PATH)).unwrap();
+        assert_eq!(rm.get_paths(), docs.iter().map(|p| p.as_path()).collect::<Vec<&Path>>());
+
+        // Backup of an interrupted run is never finished, so it is not resumed
+        Journal::create(&prefix::at(JOURNAL_PATH), "test".to_string(), false, Some(Path::new("/tmp/b.tar")), &[], &[], &rm)
+            .unwrap();
+        assert!(tp.resume_changes().is_err());
+
+        prefix::set(None);
+        fs::remove_dir_all(&d).unwrap();
+    }
 }
@@ -1,26 +1,141 @@
+use crate::{
+    digest,
+    filters::junk::{JunkExtensions, JUNK_KINDS},
+    glob,
+    osinfo::OsInfo,
+    prefix,
+};
 use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, path::PathBuf};
-use std::{fs, io::Error, path::Path};
+use std::{collections::HashMap, fmt::Debug, path::PathBuf};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    process::Command,
+};
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// Current version of the profile layout
+pub const PROFILE_VERSION: u64 = 2;
+
+/// Known data filters of the profile configuration
+pub const FILTERS: &[&str] = &["l10n", "i18n", "doc", "man", "log", "dir", "images", "archives", "all"];
+
+/// Default order of the filter pipeline stages
+pub const PIPELINE: &[&str] = &["texts", "locales", "timezones", "dirs", "gpu", "icu", "dbclient", "rules", "resources"];
+
+/// Modes of automatic dependencies
+pub const AUTODEPS: &[&str] = &["free", "clean", "tight", "none"];
+
+/// Strip modes of the kept ELF files
+const STRIP_MODES: &[&str] = &["debug", "all"];
+
+/// Modes of the kept duplicates
+const DEDUP_MODES: &[&str] = &["link", "report"];
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct PConfig {
     filters: Option<Vec<String>>,
+    pipeline: Option<Vec<String>>,
+    prune: Option<Vec<String>>,
+    keep: Option<Vec<String>>,
+    locales: Option<Vec<String>>,
+    expect_undeletable: Option<Vec<String>>,
+    timezones: Option<Vec<String>>,
+    package_rules: Option<HashMap<String, PPackageRules>>,
+    junk_extensions: Option<PJunkExtensions>,
+    never_junk: Option<Vec<String>>,
+    keep_licenses: Option<bool>,
+    gpu: Option<bool>,
+    trim_accounts: Option<bool>,
+    normalise_permissions: Option<bool>,
+    strict: Option<bool>,
+    lockfile: Option<String>,
+    keep_dirs: Option<Vec<PKeepDir>>,
+    allow_dangling: Option<Vec<String>>,
+    drop_pkgdb: Option<bool>,
+    strip: Option<String>,
+    dedup: Option<String>,
+}
+
+/// Directory, which is kept even if empty, optionally with its mode
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum PKeepDir {
+    Path(String),
+    Entry { path: String, mode: Option<String> },
+}
+
+/// Extensions of junk files, those are appended to the built-in ones or replace them
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PJunkExtensions {
+    replace: Option<bool>,
+    doc: Option<Vec<String>>,
+    headers: Option<Vec<String>>,
+    archives: Option<Vec<String>>,
+    images: Option<Vec<String>>,
+}
+
+/// Keep/prune rules, scoped to the content of a package
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct PPackageRules {
     prune: Option<Vec<String>>,
     keep: Option<Vec<String>>,
 }
 
+impl PPackageRules {
+    /// Get glob patterns of package content to be kept
+    pub fn get_keep(&self) -> Vec<String> {
+        self.keep.to_owned().unwrap_or_default()
+    }
+
+    /// Get glob patterns of package content to be pruned
+    pub fn get_prune(&self) -> Vec<String> {
+        self.prune.to_owned().unwrap_or_default()
+    }
+}
+
+/// Target is either a path, or a path with its own settings
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum PTarget {
+    Path(String),
+    Entry { path: String, autodeps: Option<String> },
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct PTargets {
-    targets: Vec<String>,
+    version: Option<u64>,
+    targets: Vec<PTarget>,
     packages: Option<Vec<String>>,
     config: Option<PConfig>,
     hooks: Option<PHooks>,
+    verify: Option<Vec<String>>,
+    sections: Option<Vec<PSection>>,
+}
+
+/// Condition of a profile section
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PCondition {
+    os: Option<String>,
+    arch: Option<String>,
+}
+
+/// Profile section, which is applied only if its condition matches the rootfs
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct PSection {
+    when: PCondition,
+    targets: Option<Vec<PTarget>>,
+    packages: Option<Vec<String>>,
+    config: Option<PConfig>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct PHooks {
     before: Option<String>,
     after: Option<String>,
+    pre: Option<Vec<String>>,
+    post: Option<Vec<String>>,
 }
 
 /// Profile
@@ -34,16 +149,45 @@ pub struct Profile {
     f_log: bool,
     f_img: bool,
     f_arc: bool,
+    pipeline: Vec<String>,
     f_expl_prune: Vec<PathBuf>,
     f_expl_keep: Vec<PathBuf>,
+    locales: Vec<String>,
+    expect_undeletable: Vec<String>,
+    timezones: Vec<String>,
+    package_rules: HashMap<String, PPackageRules>,
+    junk: JunkExtensions,
+    keep_licenses: bool,
+    gpu: bool,
+    trim_accounts: bool,
+    normalise_permissions: bool,
+    strict: bool,
+    lockfile: Option<String>,
+    keep_dirs: Vec<(String, Option<u32>)>,
+    allow_dangling: Vec<String>,
+    drop_pkgdb: bool,
+    strip: Option<String>,
+    dedup: Option<String>,
 
     packages: Vec<String>,
     dropped_packages: Vec<String>,
     targets: Vec<String>,
+    target_autodeps: HashMap<String, String>,
+
+    // Conditional sections, pending for the rootfs detection
+    sections: Vec<PSection>,
+
+    // Digests of the profile sources
+    digests: Vec<String>,
 
     // hooks
     s_pre: String,
     s_post: String,
+    c_pre: Vec<String>,
+    c_post: Vec<String>,
+
+    // smoke tests of the tinted image
+    verify: Vec<String>,
 }
 
 impl Profile {
@@ -59,89 +203,245 @@ impl Profile {
             f_log: true,
             f_img: true,
             f_arc: true,
+            pipeline: PIPELINE.iter().map(|s| s.to_string()).collect(),
 
             packages: vec![],
             dropped_packages: vec![],
             targets: vec![],
+            target_autodeps: HashMap::default(),
+            digests: vec![],
             f_expl_prune: vec![],
             f_expl_keep: vec![],
+            locales: vec![],
+            expect_undeletable: vec![],
+            timezones: vec![],
+            package_rules: HashMap::default(),
+            junk: JunkExtensions::new(),
+            keep_licenses: false,
+            gpu: false,
+            trim_accounts: false,
+            normalise_permissions: false,
+            strict: false,
+            lockfile: None,
+            keep_dirs: vec![],
+            allow_dangling: vec![],
+            drop_pkgdb: false,
+            strip: None,
+            dedup: None,
+
+            sections: vec![],
 
             s_post: String::from(""),
             s_pre: String::from(""),
+            c_pre: vec![],
+            c_post: vec![],
+            verify: vec![],
         }
     }
 
-    /// Constructor for the Profile. By default all filters are set to OFF
-    pub fn new(pfl_path: &Path) -> Result<Self, Error> {
-        let mut p = Profile::default();
-        match p.parse_profile(pfl_path) {
-            Ok(_) => Ok(p),
-            Err(err) => Err(err),
-        }
+    /// Merge another profile on top of the current one.
+    /// Profile is either a path or an https:// URL.
+    /// Lists are extended, single values (e.g. hooks) are overridden.
+    pub fn add_profile(&mut self, src: &str) -> Result<&mut Self, Error> {
+        self.parse_profile(src)?;
+        Ok(self)
     }
 
-    fn parse_profile(&mut self, pfl_path: &Path) -> Result<(), Error> {
-        let p: PTargets = serde_yaml::from_str::<PTargets>(&fs::read_to_string(pfl_path)?).unwrap();
+    /// Read profile source, which is either a local file or an https:// URL.
+    /// URL can be pinned to the content digest as "https://...#sha256=<digest>".
+    pub fn read_source(src: &str) -> Result<String, Error> {
+        if src.starts_with("http://") {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Only https:// profile URLs are supported: {}", src)));
+        } else if !src.starts_with("https://") {
+            return fs::read_to_string(src);
+        }
 
-        log::trace!("{:?}", p);
+        let (url, pin) = match src.split_once("#sha256=") {
+            Some((url, pin)) => (url, Some(pin.trim().to_lowercase())),
+            None => (src, None),
+        };
 
-        if let Some(cfg) = p.config {
-            if let Some(af) = cfg.filters {
-                for flt in af {
-                    match flt.as_str() {
-                        "l10n" => self.f_l10n = false,
-                        "i18n" => self.f_i18n = false,
-                        "doc" => self.f_doc = false,
-                        "man" => self.f_man = false,
-                        "log" => self.f_log = false,
-                        "dir" => self.f_dir = false,
-                        "images" => self.f_img = false,
-                        "archives" => self.f_arc = false,
-
-                        // Filter out everything
-                        "all" => {
-                            self.f_l10n = false;
-                            self.f_i18n = false;
-                            self.f_doc = false;
-                            self.f_man = false;
-                            self.f_log = false;
-                            self.f_dir = false;
-                            self.f_img = false;
-                            self.f_arc = false;
-                        }
-                        unknown => {
-                            log::warn!("Unknown filter: {}", unknown);
-                        }
-                    }
-                }
+        log::debug!("Fetching profile from {}", url);
+        // Redirects are restricted as well, so the profile is never fetched over plain http
+        let out = match Command::new("curl").args(["-fsSL", "--proto", "=https", "--proto-redir", "=https", url]).output() {
+            Ok(out) => out,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("Unable to fetch profile from {}: curl is not installed", url),
+                ));
             }
+            Err(err) => return Err(err),
+        };
 
-            if let Some(prn) = cfg.prune {
-                self.f_expl_prune.extend(prn.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
-            }
+        if !out.status.success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("Unable to fetch profile from {}: {}", url, String::from_utf8_lossy(&out.stderr).trim()),
+            ));
+        }
 
-            if let Some(keep) = cfg.keep {
-                self.f_expl_keep.extend(keep.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+        if let Some(pin) = pin {
+            let digest = digest::sha256(&out.stdout);
+            if digest != pin {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Profile from {} does not match pinned SHA-256 digest: expected {}, got {}", url, pin, digest),
+                ));
             }
         }
 
-        self.targets.extend(p.targets);
+        String::from_utf8(out.stdout).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+    }
 
-        if let Some(pkgs) = p.packages {
-            for p in &pkgs {
-                let mut p = p.replace(' ', "");
-                if let Some(p) = p.strip_prefix('-') {
-                    log::debug!("Dropping contents from package \"{}\"", p);
-                    self.dropped_packages.push(p.to_string());
-                    continue;
+    /// Read profile source and parse it as a generic value.
+    /// Format is YAML, JSON or TOML, detected by the extension. Sources without a known extension,
+    /// e.g. URLs, are read as YAML (JSON is its subset), and as TOML if that is not a mapping.
+    pub fn read_value(src: &str) -> Result<serde_yaml::Value, Error> {
+        Profile::parse_value(src, &Profile::read_source(src)?)
+    }
+
+    /// Parse profile data, read from the source, as a generic value
+    fn parse_value(src: &str, data: &str) -> Result<serde_yaml::Value, Error> {
+        let ext = src.split(['#', '?']).next().unwrap_or_default().rsplit_once('.').map(|(_, e)| e.to_lowercase());
+        match ext.as_deref() {
+            Some("toml") => toml::from_str::<serde_yaml::Value>(data).map_err(|err| err.to_string()),
+            Some("json") => serde_json::from_str::<serde_yaml::Value>(data).map_err(|err| err.to_string()),
+            Some("yaml") | Some("yml") => serde_yaml::from_str::<serde_yaml::Value>(data).map_err(|err| err.to_string()),
+            _ => match serde_yaml::from_str::<serde_yaml::Value>(data) {
+                Ok(v) if v.is_mapping() => Ok(v),
+                yaml => toml::from_str::<serde_yaml::Value>(data)
+                    .map_err(|_| yaml.err().map(|err| err.to_string()).unwrap_or_else(|| "profile is not a mapping".to_string())),
+            },
+        }
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+        .and_then(Profile::interpolate)
+        .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Unable to parse profile {}: {}", src, err)))
+    }
+
+    /// Resolve "${env:NAME}" and "${env:NAME:-default}" references in all string values
+    fn interpolate(v: serde_yaml::Value) -> Result<serde_yaml::Value, Error> {
+        Ok(match v {
+            serde_yaml::Value::String(s) => serde_yaml::Value::String(Profile::interpolate_str(&s)?),
+            serde_yaml::Value::Sequence(s) => {
+                serde_yaml::Value::Sequence(s.into_iter().map(Profile::interpolate).collect::<Result<Vec<_>, Error>>()?)
+            }
+            serde_yaml::Value::Mapping(m) => {
+                let mut out = serde_yaml::Mapping::new();
+                for (k, v) in m {
+                    out.insert(k, Profile::interpolate(v)?);
+                }
+                serde_yaml::Value::Mapping(out)
+            }
+            v => v,
+        })
+    }
+
+    fn interpolate_str(s: &str) -> Result<String, Error> {
+        let mut out = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find("${env:") {
+            out.push_str(&rest[..i]);
+            let end = rest[i..]
+                .find('}')
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unterminated environment reference in \"{}\"", s)))?;
+            let (name, default) = match rest[i + 6..i + end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&rest[i + 6..i + end], None),
+            };
+
+            match (std::env::var(name), default) {
+                (Ok(val), _) => out.push_str(&val),
+                (Err(_), Some(default)) => out.push_str(default),
+                (Err(_), None) => {
+                    return Err(Error::new(ErrorKind::NotFound, format!("Environment variable {} is not set", name)));
                 }
+            }
+            rest = &rest[i + end + 1..];
+        }
+        out.push_str(rest);
 
-                if let Some(s) = p.strip_prefix('+') {
-                    p = s.to_string();
+        Ok(out)
+    }
+
+    /// Lists of the layout 1 could be given as a single value
+    fn listify(v: &mut serde_yaml::Value) {
+        for (section, key) in [
+            (None, "targets"),
+            (None, "packages"),
+            (Some("config"), "filters"),
+            (Some("config"), "keep"),
+            (Some("config"), "prune"),
+        ] {
+            let m = match section {
+                Some(s) => v.get_mut(s),
+                None => Some(&mut *v),
+            };
+            if let Some(e) = m.and_then(|m| m.get_mut(key)) {
+                if e.is_string() {
+                    *e = serde_yaml::Value::Sequence(vec![e.to_owned()]);
                 }
+            }
+        }
+    }
 
-                self.packages.push(p);
+    /// Upgrade profile of an older layout to the current one in memory.
+    /// Profiles without a version are of the current layout, still accepting single values for lists.
+    pub fn migrate(src: &str, mut v: serde_yaml::Value) -> Result<serde_yaml::Value, Error> {
+        let ver = match v.get("version") {
+            Some(ver) => ver
+                .as_u64()
+                .filter(|ver| *ver > 0)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Profile {} has invalid version: {:?}", src, ver)))?,
+            None => {
+                Self::listify(&mut v);
+                return Ok(v);
             }
+        };
+
+        if ver > PROFILE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Profile {} has version {}, but only up to {} is supported", src, ver, PROFILE_VERSION),
+            ));
+        }
+
+        if ver == PROFILE_VERSION {
+            return Ok(v);
+        }
+
+        log::warn!("Profile {} has layout version {}, upgrading to {}. Please update the profile.", src, ver, PROFILE_VERSION);
+
+        // 1 -> 2: lists could be given as a single value
+        if ver < 2 {
+            Self::listify(&mut v);
+        }
+
+        if let serde_yaml::Value::Mapping(m) = &mut v {
+            m.insert("version".into(), PROFILE_VERSION.into());
+        }
+
+        Ok(v)
+    }
+
+    fn parse_profile(&mut self, src: &str) -> Result<(), Error> {
+        // Digest is of the source as it is, so it is the same as the one it is pinned to
+        let data = Profile::read_source(src)?;
+        self.digests.push(digest::sha256(data.as_bytes()));
+        let v = Profile::parse_value(src, &data)?;
+        let p: PTargets = serde_yaml::from_value::<PTargets>(Profile::migrate(src, v)?)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Unable to parse profile {}: {}", src, err)))?;
+
+        log::trace!("{:?}", p);
+
+        if let Some(cfg) = p.config {
+            self.apply_config(cfg);
+        }
+
+        self.apply_targets(p.targets);
+
+        if let Some(pkgs) = p.packages {
+            self.apply_packages(pkgs);
         }
 
         // Get hooks
@@ -152,17 +452,305 @@ impl Profile {
             if let Some(post) = hooks.after {
                 self.s_post = post;
             }
+            if let Some(pre) = hooks.pre {
+                self.c_pre.extend(pre);
+            }
+            if let Some(post) = hooks.post {
+                self.c_post.extend(post);
+            }
+        }
+
+        if let Some(verify) = p.verify {
+            self.verify.extend(verify);
+        }
+
+        if let Some(sections) = p.sections {
+            self.sections.extend(sections);
         }
 
         Ok(())
     }
 
+    /// Apply profile configuration
+    fn apply_config(&mut self, cfg: PConfig) {
+        if let Some(af) = cfg.filters {
+            for flt in af {
+                match flt.as_str() {
+                    "l10n" => self.f_l10n = false,
+                    "i18n" => self.f_i18n = false,
+                    "doc" => self.f_doc = false,
+                    "man" => self.f_man = false,
+                    "log" => self.f_log = false,
+                    "dir" => self.f_dir = false,
+                    "images" => self.f_img = false,
+                    "archives" => self.f_arc = false,
+
+                    // Filter out everything
+                    "all" => {
+                        self.f_l10n = false;
+                        self.f_i18n = false;
+                        self.f_doc = false;
+                        self.f_man = false;
+                        self.f_log = false;
+                        self.f_dir = false;
+                        self.f_img = false;
+                        self.f_arc = false;
+                    }
+                    unknown => {
+                        log::warn!("Unknown filter: {}", unknown);
+                    }
+                }
+            }
+        }
+
+        if let Some(prn) = cfg.prune {
+            self.f_expl_prune.extend(prn.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+        }
+
+        if let Some(keep) = cfg.keep {
+            self.f_expl_keep.extend(keep.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+        }
+
+        if let Some(locales) = cfg.locales {
+            self.locales.extend(locales.iter().map(|l| l.trim().to_string()));
+        }
+
+        if let Some(undel) = cfg.expect_undeletable {
+            self.expect_undeletable.extend(undel);
+        }
+
+        if let Some(tz) = cfg.timezones {
+            self.timezones.extend(tz.iter().map(|z| z.trim().to_string()));
+        }
+
+        if let Some(pl) = cfg.pipeline {
+            self.set_pipeline(pl);
+        }
+
+        if let Some(je) = cfg.junk_extensions {
+            let replace = je.replace.unwrap_or(false);
+            for (kind, ext) in [("doc", je.doc), ("headers", je.headers), ("archives", je.archives), ("images", je.images)] {
+                if let Some(ext) = ext {
+                    self.junk.extend(kind, ext, replace);
+                }
+            }
+        }
+
+        if let Some(nj) = cfg.never_junk {
+            self.junk.add_never(nj);
+        }
+
+        if let Some(kl) = cfg.keep_licenses {
+            self.keep_licenses = kl;
+        }
+
+        if let Some(gpu) = cfg.gpu {
+            self.gpu = gpu;
+        }
+
+        if let Some(ta) = cfg.trim_accounts {
+            self.trim_accounts = ta;
+        }
+
+        if let Some(np) = cfg.normalise_permissions {
+            self.normalise_permissions = np;
+        }
+
+        if let Some(strict) = cfg.strict {
+            self.strict = strict;
+        }
+
+        if let Some(lockfile) = cfg.lockfile {
+            if lockfile == "none" || lockfile.starts_with('/') {
+                self.lockfile = Some(lockfile);
+            } else {
+                log::warn!("Lock file path should be absolute or \"none\", ignoring: {}", lockfile);
+            }
+        }
+
+        if let Some(kd) = cfg.keep_dirs {
+            for d in kd {
+                let (path, mode) = match d {
+                    PKeepDir::Path(path) => (path, None),
+                    PKeepDir::Entry { path, mode } => (path, mode),
+                };
+                if !path.starts_with('/') {
+                    log::warn!("Kept directory path should be absolute, ignoring: {}", path);
+                    continue;
+                }
+
+                let mode = mode.and_then(|m| match u32::from_str_radix(m.trim_start_matches("0o"), 8) {
+                    Ok(m) if m <= 0o7777 => Some(m),
+                    _ => {
+                        log::warn!("Invalid mode \"{}\" of the kept directory {}, ignoring", m, path);
+                        None
+                    }
+                });
+                self.keep_dirs.retain(|(p, _)| *p != path);
+                self.keep_dirs.push((path, mode));
+            }
+        }
+
+        if let Some(ad) = cfg.allow_dangling {
+            self.allow_dangling.extend(ad);
+        }
+
+        if let Some(dp) = cfg.drop_pkgdb {
+            self.drop_pkgdb = dp;
+        }
+
+        if let Some(strip) = cfg.strip {
+            if STRIP_MODES.contains(&strip.as_str()) {
+                self.strip = Some(strip);
+            } else {
+                log::warn!("Unknown strip mode \"{}\", expected \"debug\" or \"all\", ignoring", strip);
+            }
+        }
+
+        if let Some(dedup) = cfg.dedup {
+            if DEDUP_MODES.contains(&dedup.as_str()) {
+                self.dedup = Some(dedup);
+            } else {
+                log::warn!("Unknown dedup mode \"{}\", expected \"link\" or \"report\", ignoring", dedup);
+            }
+        }
+
+        if let Some(prules) = cfg.package_rules {
+            for (pkg, r) in prules {
+                let pr = self.package_rules.entry(pkg).or_default();
+                pr.keep.get_or_insert_with(Vec::default).extend(r.get_keep());
+                pr.prune.get_or_insert_with(Vec::default).extend(r.get_prune());
+            }
+        }
+    }
+
+    /// Add targets with their own settings, if any
+    fn apply_targets(&mut self, targets: Vec<PTarget>) {
+        for t in targets {
+            match t {
+                PTarget::Path(path) => self.targets.push(path),
+                PTarget::Entry { path, autodeps } => {
+                    if let Some(ad) = autodeps {
+                        if AUTODEPS.contains(&ad.as_str()) {
+                            self.target_autodeps.insert(path.to_owned(), ad);
+                        } else {
+                            log::warn!("Unknown autodeps mode \"{}\" of target {}, using the global one", ad, path);
+                        }
+                    }
+                    self.targets.push(path);
+                }
+            }
+        }
+    }
+
+    /// Apply packages list, where "-" prefix drops the package contents
+    fn apply_packages(&mut self, pkgs: Vec<String>) {
+        for p in &pkgs {
+            let mut p = p.replace(' ', "");
+            if let Some(p) = p.strip_prefix('-') {
+                log::debug!("Dropping contents from package \"{}\"", p);
+                self.dropped_packages.push(p.to_string());
+                continue;
+            }
+
+            if let Some(s) = p.strip_prefix('+') {
+                p = s.to_string();
+            }
+
+            self.packages.push(p);
+        }
+    }
+
+    /// Check if the path is an executable file, following symlinks
+    fn is_executable(p: &Path) -> bool {
+        prefix::metadata(p).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+    }
+
+    /// Expand glob and directory targets against the current filesystem
+    /// into concrete executables. Returns targets, those did not match anything.
+    pub fn expand_targets(&mut self) -> Vec<String> {
+        let mut unmatched: Vec<String> = vec![];
+        let mut targets: Vec<String> = vec![];
+        for t in std::mem::take(&mut self.targets) {
+            if !glob::is_glob(&t) && !prefix::is_dir(&t) {
+                targets.push(t);
+                continue;
+            }
+
+            // Only executables are targets, the rest of the directory is e.g. data of the plugins
+            let found = glob::expand_tree(&t).into_iter().filter(|p| Self::is_executable(p)).collect::<Vec<PathBuf>>();
+            if found.is_empty() {
+                unmatched.push(t);
+                continue;
+            }
+
+            log::debug!("Target {} is expanded to {} executables", t, found.len());
+            let found = found.into_iter().map(|p| p.to_str().unwrap().to_string()).collect::<Vec<String>>();
+            if let Some(ad) = self.target_autodeps.remove(&t) {
+                for f in &found {
+                    self.target_autodeps.entry(f.to_owned()).or_insert_with(|| ad.to_owned());
+                }
+            }
+            targets.extend(found);
+        }
+
+        targets.sort();
+        targets.dedup();
+        self.targets = targets;
+
+        unmatched
+    }
+
+    /// Merge conditional sections those are matching the detected OS and architecture.
+    /// Non-matching sections are discarded.
+    pub fn apply_conditions(&mut self, osi: &OsInfo) -> &mut Self {
+        for s in std::mem::take(&mut self.sections) {
+            if let Some(os) = &s.when.os {
+                if !osi.is_os(os) {
+                    log::debug!("Skipping section for OS \"{}\"", os);
+                    continue;
+                }
+            }
+
+            if let Some(arch) = &s.when.arch {
+                if !osi.is_arch(arch) {
+                    log::debug!("Skipping section for architecture \"{}\"", arch);
+                    continue;
+                }
+            }
+
+            log::debug!("Applying section {:?}", s.when);
+            if let Some(cfg) = s.config {
+                self.apply_config(cfg);
+            }
+
+            if let Some(targets) = s.targets {
+                self.apply_targets(targets);
+            }
+
+            if let Some(pkgs) = s.packages {
+                self.apply_packages(pkgs);
+            }
+        }
+
+        self
+    }
+
     /// Add target
     pub fn add_target(&mut self, target: String) -> &mut Self {
         self.targets.push(target);
         self
     }
 
+    /// Remove targets, e.g. those missing in the rootfs
+    pub fn remove_targets(&mut self, targets: &[String]) -> &mut Self {
+        self.targets.retain(|t| !targets.contains(t));
+        for t in targets {
+            self.target_autodeps.remove(t);
+        }
+        self
+    }
+
     /// Set localisation filter
     pub fn set_l10n(&mut self, remove: bool) -> &mut Self {
         self.f_l10n = remove;
@@ -229,11 +817,29 @@ impl Profile {
         self
     }
 
+    /// Get identifier of the profile: digest of its sources, or of the targets,
+    /// if the profile is made from the command line
+    pub fn get_id(&self) -> String {
+        format!(
+            "sha256:{}",
+            match self.digests.len() {
+                0 => digest::sha256(self.targets.join("\n").as_bytes()),
+                1 => self.digests[0].to_owned(),
+                _ => digest::sha256(self.digests.join("\n").as_bytes()),
+            }
+        )
+    }
+
     /// Get targets
     pub fn get_targets(&self) -> &Vec<String> {
         &self.targets
     }
 
+    /// Get autodeps mode of the target, if it overrides the global one
+    pub fn get_target_autodeps(&self, target: &str) -> Option<&str> {
+        self.target_autodeps.get(target).map(|ad| ad.as_str())
+    }
+
     /// Get paths to be explicitly pruned
     pub fn get_prune_paths(&self) -> Vec<PathBuf> {
         self.f_expl_prune.clone()
@@ -244,6 +850,124 @@ impl Profile {
         self.f_expl_keep.clone()
     }
 
+    /// Get languages, those localisation data should be kept
+    pub fn get_locales(&self) -> &Vec<String> {
+        &self.locales
+    }
+
+    /// Get keep/prune rules, scoped per package
+    pub fn get_package_rules(&self) -> &HashMap<String, PPackageRules> {
+        &self.package_rules
+    }
+
+    /// Get timezones, those data should be kept
+    pub fn get_timezones(&self) -> &Vec<String> {
+        &self.timezones
+    }
+
+    /// Get glob patterns of paths, those are expected to fail on removal
+    /// (e.g. immutable files)
+    pub fn get_expect_undeletable(&self) -> &Vec<String> {
+        &self.expect_undeletable
+    }
+
+    /// Set order of the filter pipeline stages. Stages, prefixed with "-" are disabled.
+    /// If only disabled stages are listed, the current order is kept.
+    fn set_pipeline(&mut self, pl: Vec<String>) {
+        let (off, order): (Vec<String>, Vec<String>) = pl.into_iter().partition(|s| s.starts_with('-'));
+        if !order.is_empty() {
+            self.pipeline = order;
+        }
+
+        for s in off {
+            let s = s.trim_start_matches('-');
+            if s == "rules" {
+                log::warn!("Keep and prune rules cannot be disabled");
+                continue;
+            }
+            self.pipeline.retain(|x| x != s);
+        }
+
+        self.pipeline.retain(|s| {
+            if !PIPELINE.contains(&s.as_str()) {
+                log::warn!("Unknown pipeline stage: {}", s);
+                return false;
+            }
+            true
+        });
+        self.pipeline.dedup();
+
+        if !self.pipeline.iter().any(|s| s == "rules") {
+            self.pipeline.push("rules".to_string());
+        }
+    }
+
+    /// Get ordered stages of the filter pipeline
+    pub fn get_pipeline(&self) -> &Vec<String> {
+        &self.pipeline
+    }
+
+    /// Get extensions of junk files
+    pub fn get_junk_extensions(&self) -> &JunkExtensions {
+        &self.junk
+    }
+
+    /// Returns true if copyright and license files should be kept, even if docs are removed
+    pub fn keep_licenses(&self) -> bool {
+        self.keep_licenses
+    }
+
+    /// Returns true if GPU stacks should be kept, even if not linked by targets
+    pub fn keep_gpu(&self) -> bool {
+        self.gpu
+    }
+
+    /// Returns true if users and groups, not referenced by kept files, should be removed
+    pub fn trim_accounts(&self) -> bool {
+        self.trim_accounts
+    }
+
+    /// Returns true if world-writable kept files and files of unknown owners should be fixed
+    pub fn normalise_permissions(&self) -> bool {
+        self.normalise_permissions
+    }
+
+    /// Returns true if missing targets and packages should fail the run
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Get path of the lock file inside the image, if the profile changes it.
+    /// Lock file is disabled if the path is "none".
+    pub fn get_lockfile(&self) -> Option<&str> {
+        self.lockfile.as_deref()
+    }
+
+    /// Get directories, those are kept even if empty, with their modes, if set
+    pub fn get_keep_dirs(&self) -> &Vec<(String, Option<u32>)> {
+        &self.keep_dirs
+    }
+
+    /// Get patterns of the broken symlinks, those are kept, e.g. pointing into volumes
+    pub fn get_allow_dangling(&self) -> &Vec<String> {
+        &self.allow_dangling
+    }
+
+    /// Returns true if the package database and the package manager should be removed
+    pub fn drop_pkgdb(&self) -> bool {
+        self.drop_pkgdb
+    }
+
+    /// Get strip mode of the kept ELF files: "debug" or "all"
+    pub fn get_strip(&self) -> Option<&str> {
+        self.strip.as_deref()
+    }
+
+    /// Get mode of the identical kept files: "link" or "report"
+    pub fn get_dedup(&self) -> Option<&str> {
+        self.dedup.as_deref()
+    }
+
     /// Returns true if localisation data needs to be removed
     pub fn filter_l10n(&self) -> bool {
         !self.f_l10n
@@ -315,4 +1039,220 @@ impl Profile {
     pub fn get_post_hook(&self) -> String {
         self.s_post.to_owned()
     }
+
+    /// Get commands, those are called inside the rootfs before scanning
+    pub fn get_pre_commands(&self) -> &Vec<String> {
+        &self.c_pre
+    }
+
+    /// Get commands, those are called inside the rootfs after changes are applied
+    pub fn get_post_commands(&self) -> &Vec<String> {
+        &self.c_post
+    }
+
+    /// Get commands, those verify the rootfs after changes are applied
+    pub fn get_verify_commands(&self) -> &Vec<String> {
+        &self.verify
+    }
+
+    /// Get names of active filters
+    fn get_filters(&self) -> Vec<String> {
+        [
+            ("l10n", self.f_l10n),
+            ("i18n", self.f_i18n),
+            ("doc", self.f_doc),
+            ("man", self.f_man),
+            ("log", self.f_log),
+            ("dir", self.f_dir),
+            ("images", self.f_img),
+            ("archives", self.f_arc),
+        ]
+        .iter()
+        .filter(|(_, keep)| !keep)
+        .map(|(f, _)| f.to_string())
+        .collect::<Vec<String>>()
+    }
+
+    /// Get junk extensions, those differ from the built-in ones, as the profile sets them.
+    /// Extended lists keep the built-in extensions in front, otherwise they are replaced.
+    fn export_junk(&self) -> Option<PJunkExtensions> {
+        let builtin = JunkExtensions::new();
+        let changed = JUNK_KINDS.iter().filter(|k| self.junk.get(k) != builtin.get(k)).collect::<Vec<_>>();
+        if changed.is_empty() {
+            return None;
+        }
+
+        let replace = changed.iter().any(|k| !self.junk.get(k).starts_with(builtin.get(k)));
+        let ext = |kind: &str| {
+            let (cur, bi) = (self.junk.get(kind), builtin.get(kind));
+            if cur == bi {
+                None
+            } else if replace {
+                Some(cur.to_vec())
+            } else {
+                Some(cur[bi.len()..].to_vec())
+            }
+        };
+
+        Some(PJunkExtensions {
+            replace: if replace { Some(true) } else { None },
+            doc: ext("doc"),
+            headers: ext("headers"),
+            archives: ext("archives"),
+            images: ext("images"),
+        })
+    }
+
+    /// Export fully expanded profile as YAML: resolved targets and packages,
+    /// and concrete list of kept paths instead of rules. Conditional sections
+    /// are expected to be already applied.
+    pub fn export(&self, packages: &[String], keep: &[PathBuf]) -> Result<String, Error> {
+        let mut packages = packages.to_vec();
+        packages.extend(self.dropped_packages.iter().map(|p| format!("-{}", p)));
+        let opt = |v: &Vec<String>| if v.is_empty() { None } else { Some(v.to_owned()) };
+
+        let p = PTargets {
+            version: Some(PROFILE_VERSION),
+            targets: self
+                .targets
+                .iter()
+                .map(|t| match self.target_autodeps.get(t) {
+                    Some(ad) => PTarget::Entry { path: t.to_owned(), autodeps: Some(ad.to_owned()) },
+                    None => PTarget::Path(t.to_owned()),
+                })
+                .collect(),
+            packages: opt(&packages),
+            config: Some(PConfig {
+                filters: opt(&self.get_filters()),
+                pipeline: if self.pipeline.iter().eq(PIPELINE.iter()) { None } else { Some(self.pipeline.to_owned()) },
+                prune: opt(&self.f_expl_prune.iter().map(|p| p.to_str().unwrap().to_string()).collect::<Vec<String>>()),
+                keep: Some(keep.iter().map(|p| p.to_str().unwrap().to_string()).collect::<Vec<String>>()),
+                locales: opt(&self.locales),
+                expect_undeletable: opt(&self.expect_undeletable),
+                timezones: opt(&self.timezones),
+                package_rules: if self.package_rules.is_empty() { None } else { Some(self.package_rules.to_owned()) },
+                junk_extensions: self.export_junk(),
+                never_junk: opt(&self.junk.get_never().to_vec()),
+                keep_licenses: if self.keep_licenses { Some(true) } else { None },
+                gpu: if self.gpu { Some(true) } else { None },
+                trim_accounts: if self.trim_accounts { Some(true) } else { None },
+                normalise_permissions: if self.normalise_permissions { Some(true) } else { None },
+                strict: if self.strict { Some(true) } else { None },
+                lockfile: self.lockfile.to_owned(),
+                keep_dirs: if self.keep_dirs.is_empty() {
+                    None
+                } else {
+                    Some(
+                        self.keep_dirs
+                            .iter()
+                            .map(|(path, mode)| match mode {
+                                Some(m) => PKeepDir::Entry { path: path.to_owned(), mode: Some(format!("{:04o}", m)) },
+                                None => PKeepDir::Path(path.to_owned()),
+                            })
+                            .collect(),
+                    )
+                },
+                allow_dangling: opt(&self.allow_dangling),
+                drop_pkgdb: if self.drop_pkgdb { Some(true) } else { None },
+                strip: self.strip.to_owned(),
+                dedup: self.dedup.to_owned(),
+            }),
+            hooks: Some(PHooks {
+                before: if self.s_pre.is_empty() { None } else { Some(self.s_pre.to_owned()) },
+                after: if self.s_post.is_empty() { None } else { Some(self.s_post.to_owned()) },
+                pre: opt(&self.c_pre),
+                post: opt(&self.c_post),
+            }),
+            verify: opt(&self.verify),
+            sections: None,
+        };
+
+        // Omit everything what is not set
+        let mut v = serde_yaml::to_value(&p).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?;
+        if let Some(serde_yaml::Value::Mapping(m)) = v.get_mut("config").and_then(|c| c.get_mut("junk_extensions")) {
+            m.retain(|_, v| !v.is_null());
+        }
+        for k in ["config", "hooks"] {
+            if let Some(serde_yaml::Value::Mapping(m)) = v.get_mut(k) {
+                m.retain(|_, v| !v.is_null());
+            }
+        }
+        if let serde_yaml::Value::Mapping(m) = &mut v {
+            m.retain(|_, v| !v.is_null() && v.as_mapping().map(|m| !m.is_empty()).unwrap_or(true));
+        }
+
+        serde_yaml::to_string(&v).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profile;
+    use crate::{digest, filters::junk::JUNK_KINDS, testutil::scratch_file};
+    use std::fs;
+
+    /// Load the profile from the source, written into a scratch file
+    fn load(name: &str, data: &str) -> Profile {
+        let p = scratch_file(name, data);
+        let mut profile = Profile::default();
+        profile.add_profile(p.to_str().unwrap()).unwrap();
+        fs::remove_file(&p).unwrap();
+        profile
+    }
+
+    /// Export the profile, load the export back and compare both
+    fn round_trip(name: &str, data: &str) {
+        let orig = load(&format!("{}.yaml", name), data);
+        let exported = orig.export(&[], &[]).unwrap();
+        assert!(exported.contains("junk_extensions"), "{}", exported);
+        assert!(exported.contains("never_junk"), "{}", exported);
+
+        let loaded = load(&format!("{}-exported.yaml", name), &exported);
+        for kind in JUNK_KINDS {
+            assert_eq!(orig.get_junk_extensions().get(kind), loaded.get_junk_extensions().get(kind), "{}", kind);
+        }
+        assert_eq!(orig.get_junk_extensions().get_never(), loaded.get_junk_extensions().get_never());
+        assert_eq!(exported, loaded.export(&[], &[]).unwrap());
+    }
+
+    #[test]
+    fn export_keeps_extended_junk() {
+        round_trip(
+            "junk-extended",
+            "version: 2\ntargets:\n  - /usr/bin/bash\nconfig:\n  junk_extensions:\n    doc: [adoc]\n  never_junk: [.license.md]\n",
+        );
+    }
+
+    #[test]
+    fn export_keeps_replaced_junk() {
+        round_trip(
+            "junk-replaced",
+            "version: 2\ntargets:\n  - /usr/bin/bash\nconfig:\n  junk_extensions:\n    replace: true\n    images: [.xcf]\n    \
+             doc: [.adoc]\n  never_junk: [.svg]\n",
+        );
+    }
+
+    #[test]
+    fn reads_toml_and_json() {
+        let yaml = load("format.yaml", "targets:\n  - /usr/bin/bash\nconfig:\n  filters: [doc, man]\n  keep: [/etc/passwd]\n");
+        for (ext, data) in [
+            ("toml", "targets = [\"/usr/bin/bash\"]\n\n[config]\nfilters = [\"doc\", \"man\"]\nkeep = [\"/etc/passwd\"]\n"),
+            (
+                "json",
+                "{\"targets\": [\"/usr/bin/bash\"], \"config\": {\"filters\": [\"doc\", \"man\"], \"keep\": [\"/etc/passwd\"]}}",
+            ),
+        ] {
+            let profile = load(&format!("format.{}", ext), data);
+            assert_eq!(profile.export(&[], &[]).unwrap(), yaml.export(&[], &[]).unwrap(), "{}", ext);
+        }
+    }
+
+    #[test]
+    fn id_is_digest_of_source() {
+        // Digest is the same as the pin of the source, whatever the interpolation gives
+        let data = "targets:\n  - ${env:MEZZOTINT_TEST_TARGET:-/usr/bin/bash}\n";
+        let profile = load("digest.yaml", data);
+        assert_eq!(profile.get_targets(), &["/usr/bin/bash".to_string()]);
+        assert_eq!(profile.get_id(), format!("sha256:{}", digest::sha256(data.as_bytes())));
+    }
 }
@@ -0,0 +1,96 @@
+/*
+Provenance of the keep set: which rule produced every kept path,
+e.g. ELF dependency of a target, content of a package or a profile keep entry.
+ */
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Reason of the decision about a path. Earlier reasons take precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    Protected,
+    Target,
+    ElfDep,
+    PackageContent,
+    Manifest,
+    ProfileKeep,
+    Symlink,
+    DirSymlink,
+    Hardlink,
+    Filter,
+    Unreferenced,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Reason::Protected => "protected",
+            Reason::Target => "target",
+            Reason::ElfDep => "elf-dep",
+            Reason::PackageContent => "package-content",
+            Reason::Manifest => "manifest",
+            Reason::ProfileKeep => "profile-keep",
+            Reason::Symlink => "symlink",
+            Reason::DirSymlink => "dir-symlink",
+            Reason::Hardlink => "hardlink",
+            Reason::Filter => "filter",
+            Reason::Unreferenced => "unreferenced",
+        }
+    }
+
+    /// Describe the reason with its origin in a human-readable way
+    pub fn describe(&self, origin: &str) -> String {
+        match self {
+            Reason::Protected => "protected by the built-in safety list".to_string(),
+            Reason::Target => "target of the profile".to_string(),
+            Reason::ElfDep => format!("ELF dependency of {}", origin),
+            Reason::PackageContent => format!("content of {}", origin),
+            Reason::Manifest => "kept by the manifest of the tinted image".to_string(),
+            Reason::ProfileKeep => format!("profile keep entry {}", origin),
+            Reason::Symlink => format!("target of the kept symlink {}", origin),
+            Reason::DirSymlink => format!("directory symlink on the way to {}", origin),
+            Reason::Hardlink => format!("hard link of the kept file {}", origin),
+            Reason::Filter => "left by the filters".to_string(),
+            Reason::Unreferenced => "not referenced by any target, package or rule".to_string(),
+        }
+    }
+}
+
+/// Reason with its origin: a target, a package, a rule or a symlink
+pub type Origin = (Reason, String);
+
+#[derive(Debug, Default)]
+pub struct Provenance {
+    paths: HashMap<PathBuf, Origin>,
+}
+
+impl Provenance {
+    /// Add paths with the reason, keeping the stronger one, if a path is already there
+    pub fn add(&mut self, paths: Vec<PathBuf>, r: Reason, origin: &str) {
+        for p in paths {
+            match self.paths.get(&p) {
+                Some((pr, _)) if *pr <= r => {}
+                _ => {
+                    self.paths.insert(p, (r, origin.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Get the reason with its origin of the path, if known
+    pub fn get(&self, p: &Path) -> Option<&Origin> {
+        self.paths.get(p)
+    }
+
+    pub fn contains(&self, p: &Path) -> bool {
+        self.paths.contains_key(p)
+    }
+
+    /// Describe why the path is there
+    pub fn describe(&self, p: &Path) -> Option<String> {
+        self.paths.get(p).map(|(r, o)| r.describe(o))
+    }
+}
@@ -0,0 +1,159 @@
+/*
+Quarantine of removed files.
+
+Instead of unlinking, removed files are moved into a directory inside the image,
+so particular files can be restored if the tinted image turns out to be broken.
+ */
+
+use crate::{prefix, rootfs::RootFS, xattrs};
+use std::{
+    ffi::CString,
+    fs,
+    io::{Error, ErrorKind},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+/// Location of the quarantine inside the image
+pub const QUARANTINE_PATH: &str = "/.tinted.quarantine";
+
+pub struct Quarantine {
+    root: PathBuf,
+    prefix: PathBuf,
+}
+
+impl Quarantine {
+    pub fn new() -> Self {
+        Quarantine::at(&prefix::at("/"))
+    }
+
+    /// Quarantine of the image, mounted at the root, e.g. when it is accessed from the host
+    pub fn at(root: &Path) -> Self {
+        Quarantine { root: root.join(QUARANTINE_PATH.trim_start_matches('/')), prefix: root.to_owned() }
+    }
+
+    /// Get location of the path in the quarantine
+    fn location(&self, p: &Path) -> PathBuf {
+        self.root.join(p.strip_prefix("/").unwrap_or(p))
+    }
+
+    /// Move file between locations, copying it if they are on different filesystems
+    fn relocate(src: &Path, dst: &Path) -> Result<(), Error> {
+        if let Some(d) = dst.parent() {
+            fs::create_dir_all(d)?;
+        }
+
+        if fs::rename(src, dst).is_ok() {
+            return Ok(());
+        }
+
+        if src.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(src)?, dst)?;
+        } else {
+            fs::copy(src, dst)?;
+            xattrs::copy_security(src, dst)?;
+        }
+        fs::remove_file(src)
+    }
+
+    /// Move file into the quarantine. Directories are moved with their content, staying on
+    /// the root filesystem, so mounts inside them are left alone. Their files might be in the quarantine already.
+    pub fn put(&self, p: &Path) -> Result<(), Error> {
+        let src = self.prefix.join(p.strip_prefix("/").unwrap_or(p));
+        if src.is_symlink() || !src.is_dir() {
+            return Self::relocate(&src, &self.location(p));
+        }
+
+        let root = RootFS::get_root_mount();
+        let entries = walkdir::WalkDir::new(&src)
+            .into_iter()
+            .filter_entry(|e| !e.file_type().is_dir() || !RootFS::is_foreign(&prefix::strip(e.path()), root))
+            .collect::<Result<Vec<_>, _>>()?;
+        for e in entries.into_iter().rev() {
+            let q = self.location(&prefix::strip(e.path()));
+            if !e.file_type().is_dir() {
+                Self::relocate(e.path(), &q)?;
+                continue;
+            }
+
+            // Directories are mirrored in the quarantine, so restore finds them
+            fs::create_dir_all(&q)?;
+            if let Err(err) = fs::remove_dir(e.path()) {
+                // A mount point still has its filesystem inside
+                log::warn!("Unable to move directory {} into the quarantine: {}", e.path().to_str().unwrap(), err);
+            }
+        }
+
+        if src.exists() {
+            return Err(Error::new(ErrorKind::Other, "directory is not empty"));
+        }
+        Ok(())
+    }
+
+    /// Keep a copy of the file, which is about to be changed, with its mode and owner.
+    /// The first copy is the original one, so it is never overwritten by later runs.
+    pub fn keep(&self, p: &Path) -> Result<(), Error> {
+        let (src, dst) = (self.prefix.join(p.strip_prefix("/").unwrap_or(p)), self.location(p));
+        if dst.exists() {
+            return Ok(());
+        }
+        if let Some(d) = dst.parent() {
+            fs::create_dir_all(d)?;
+        }
+
+        let m = fs::metadata(&src)?;
+        fs::copy(&src, &dst)?;
+        let cp = CString::new(dst.as_os_str().as_bytes())?;
+        if unsafe { libc::chown(cp.as_ptr(), m.uid(), m.gid()) } != 0 {
+            return Err(Error::last_os_error());
+        }
+        xattrs::copy_security(&src, &dst)
+    }
+
+    /// Restore file from the quarantine to its original location.
+    /// Directories are only re-created, their files are restored on their own.
+    pub fn restore(&self, p: &Path) -> Result<(), Error> {
+        let q = self.location(p);
+        if !q.exists() && !q.is_symlink() {
+            return Err(Error::new(ErrorKind::NotFound, format!("{} is not in quarantine", p.to_str().unwrap())));
+        }
+
+        let dst = self.prefix.join(p.strip_prefix("/").unwrap_or(p));
+        if !q.is_symlink() && q.is_dir() {
+            fs::create_dir_all(dst)?;
+            // Files of the directory might still be in the quarantine
+            let _ = fs::remove_dir(q);
+            return Ok(());
+        }
+
+        Self::relocate(&q, &dst)
+    }
+
+    /// Returns true if there are no files in the quarantine
+    pub fn is_empty(&self) -> bool {
+        !walkdir::WalkDir::new(&self.root).into_iter().filter_map(|e| e.ok()).any(|e| !e.file_type().is_dir())
+    }
+
+    /// Remove the quarantine, if there is nothing left in it
+    pub fn clear(&self) -> Result<(), Error> {
+        if self.root.exists() && self.is_empty() {
+            fs::remove_dir_all(&self.root)?;
+        }
+
+        Ok(())
+    }
+
+    /// Find original paths of the quarantined files by the file name
+    pub fn find(&self, fname: &str) -> Vec<PathBuf> {
+        let mut out = walkdir::WalkDir::new(&self.root)
+            .follow_links(false)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| !e.file_type().is_dir() && e.file_name().to_str() == Some(fname))
+            .filter_map(|e| e.path().strip_prefix(&self.root).ok().map(|p| Path::new("/").join(p)))
+            .collect::<Vec<PathBuf>>();
+        out.sort();
+
+        out
+    }
+}
@@ -0,0 +1,283 @@
+/*
+Restore of a tinted image: removed files are brought back from the backup
+archive and from the quarantine, changed files get their original content,
+mode and owner, and the tint state is cleared, so the image can be tinted again,
+e.g. by the next revision of the profile.
+ */
+
+use crate::{
+    backup::Backup,
+    journal::{Journal, JOURNAL_PATH},
+    manifest::{RemovalManifest, LOCKFILE_PATH, MANIFEST_PATH, REMOVED_MANIFEST_PATH},
+    prefix,
+    quarantine::Quarantine,
+};
+use std::{
+    ffi::OsString,
+    fs,
+    io::{Error, ErrorKind},
+    path::{Component, Path, PathBuf},
+};
+
+pub struct Restore {
+    root: PathBuf,
+    backup: Option<PathBuf>,
+    removed_manifest: PathBuf,
+    lockfile: Option<PathBuf>,
+}
+
+impl Restore {
+    pub fn new(root: &Path) -> Self {
+        Restore {
+            root: root.to_owned(),
+            backup: None,
+            removed_manifest: PathBuf::from(REMOVED_MANIFEST_PATH),
+            lockfile: Some(PathBuf::from(LOCKFILE_PATH)),
+        }
+    }
+
+    /// Set path on the host to the backup archive, written with --backup
+    pub fn set_backup(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.backup = p;
+        self
+    }
+
+    /// Set path inside the image to the removal manifest
+    pub fn set_removed_manifest(&mut self, p: Option<PathBuf>) -> &mut Self {
+        if let Some(p) = p {
+            self.removed_manifest = p;
+        }
+        self
+    }
+
+    /// Set path inside the image to the lock file, if there is any
+    pub fn set_lockfile(&mut self, p: Option<PathBuf>) -> &mut Self {
+        self.lockfile = p;
+        self
+    }
+
+    /// Get location of the path inside the image on the host
+    fn at_root(&self, p: &Path) -> PathBuf {
+        self.root.join(p.strip_prefix("/").unwrap_or(p))
+    }
+
+    /// Get location of the path inside the image on the host. Symlinks of its existing directories
+    /// are resolved inside the image, so absolute ones do not lead to the host.
+    fn at_image(&self, p: &Path) -> Result<PathBuf, Error> {
+        let (d, name) = match (p.parent(), p.file_name()) {
+            (Some(d), Some(name)) => (d, name),
+            _ => return Ok(self.at_root(p)),
+        };
+
+        let mut out = PathBuf::from("/");
+        let mut rest: Vec<OsString> = d.components().rev().map(|c| c.as_os_str().to_owned()).collect();
+        let mut links = 0;
+        while let Some(c) = rest.pop() {
+            if c == ".." {
+                out.pop();
+                continue;
+            } else if c == "." || c == "/" {
+                continue;
+            }
+
+            out.push(c);
+            let loc = self.at_root(&out);
+            if !loc.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+                continue;
+            }
+
+            links += 1;
+            if links > prefix::MAX_LINKS {
+                return Err(Error::new(ErrorKind::Other, format!("Too many levels of symbolic links: {}", p.to_str().unwrap())));
+            }
+
+            let tgt = fs::read_link(&loc)?;
+            out.pop();
+            if tgt.is_absolute() {
+                out = PathBuf::from("/");
+            }
+            rest.extend(tgt.components().rev().map(|c| c.as_os_str().to_owned()));
+        }
+
+        Ok(self.at_root(&out.join(name)))
+    }
+
+    /// Extract the backup archive into the image with mode, owner and extended attributes.
+    /// Returns the extracted paths.
+    fn extract(&self, backup: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut out: Vec<PathBuf> = vec![];
+        let mut ar = Backup::open(backup)?;
+        ar.set_preserve_permissions(true);
+        ar.set_preserve_ownerships(true);
+        ar.set_unpack_xattrs(true);
+        ar.set_overwrite(true);
+
+        for e in ar.entries()? {
+            let mut e = e?;
+            let p = Path::new("/").join(e.path()?);
+            if p.components().any(|c| c == Component::ParentDir) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("{} is outside of the image", p.to_str().unwrap())));
+            }
+
+            let dst = self.at_image(&p)?;
+            if let Some(d) = dst.parent() {
+                fs::create_dir_all(d)?;
+            }
+            e.unpack(&dst)?;
+            out.push(p);
+        }
+
+        Ok(out)
+    }
+
+    /// Restore removed files and clear the tint state.
+    /// State is kept, if some removed files could not be restored.
+    pub fn run(&self) -> Result<(), Error> {
+        let lockfile = self.lockfile.as_ref().map(|l| self.at_root(l));
+        let mut rm = RemovalManifest::load(&self.at_root(&self.removed_manifest))?;
+
+        // Interrupted run has not written its removal manifest yet
+        let journal = self.at_root(Path::new(JOURNAL_PATH));
+        if journal.exists() {
+            log::warn!("Previous run was interrupted, restoring files from its journal as well");
+            rm.extend(Journal::load(&journal)?.get_removing().clone());
+        }
+        if self.backup.is_none() && !lockfile.as_ref().map(|l| l.exists()).unwrap_or(false) && rm.get_paths().is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "This container is not tinted, nothing to restore."));
+        }
+
+        let mut extracted: Vec<PathBuf> = vec![];
+        if let Some(backup) = &self.backup {
+            extracted = self.extract(backup).map_err(|err| {
+                Error::new(err.kind(), format!("Unable to extract backup {}: {}", backup.to_str().unwrap(), err))
+            })?;
+            log::info!("Extracted backup {}", backup.to_str().unwrap());
+        }
+
+        let q = Quarantine::at(&self.root);
+        let mut missing: Vec<&Path> = vec![];
+        for p in rm.get_paths() {
+            let rp = self.at_root(p);
+            if rp.exists() || rp.is_symlink() {
+                continue;
+            }
+
+            match q.restore(p) {
+                Ok(_) => log::debug!("Restored {} from quarantine", p.to_str().unwrap()),
+                Err(_) => missing.push(p),
+            }
+        }
+
+        // Changed files get back their original content, mode and owner. Files, changed by several runs,
+        // are restored in the reverse order, so the state before the first run stays.
+        for c in rm.get_changed().iter().rev() {
+            let p = c.get_path();
+            if c.is_content() && q.restore(p).is_err() && self.backup.is_none() {
+                missing.push(p);
+                continue;
+            }
+            c.restore(&self.at_root(p)).map_err(|err| {
+                Error::new(err.kind(), format!("Unable to restore mode and owner of {}: {}", p.to_str().unwrap(), err))
+            })?;
+            log::debug!("Restored changed {}", p.to_str().unwrap());
+        }
+        q.clear()?;
+
+        if !missing.is_empty() {
+            for p in &missing {
+                log::error!("Unable to restore {}: it is neither in the backup, nor in the quarantine", p.to_str().unwrap());
+            }
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "Unable to restore {} files, the tint state is kept. Use --backup with the archive of the tint.",
+                    missing.len()
+                ),
+            ));
+        }
+
+        for p in
+            [lockfile, Some(self.at_root(Path::new(MANIFEST_PATH))), Some(self.at_root(&self.removed_manifest)), Some(journal)]
+                .into_iter()
+                .flatten()
+                .filter(|p| p.exists())
+        {
+            fs::remove_file(p)?;
+        }
+
+        // Directories, created by the tint, are removed children first, unless they were in the image before.
+        // Directory of the removal manifest is created by the tint, even if older ones have not recorded it.
+        for d in rm.get_created().iter().rev().filter(|d| !extracted.contains(d)) {
+            let _ = fs::remove_dir(self.at_root(d));
+        }
+        if let Some(d) = self.at_root(&self.removed_manifest).parent() {
+            let _ = fs::remove_dir(d);
+        }
+        log::info!("Restored {} files, the tint state is cleared", rm.get_paths().len());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Restore;
+    use crate::testutil::scratch;
+    use std::{fs, os::unix::fs::symlink, path::Path};
+
+    /// Removal manifest of a tint, which has removed /etc/gone and created the directories of the state
+    const MANIFEST: &str =
+        r#"{"removed": [{"path": "/etc/gone", "size": 4}], "created": ["/var", "/var/lib", "/var/lib/mezzotint"]}"#;
+
+    #[test]
+    fn restores_quarantine() {
+        let root = scratch("restore-quarantine");
+        fs::create_dir_all(root.join(".tinted.quarantine/etc")).unwrap();
+        fs::write(root.join(".tinted.quarantine/etc/gone"), "gone").unwrap();
+        fs::create_dir_all(root.join("var/lib/mezzotint")).unwrap();
+        fs::write(root.join("var/lib/mezzotint/removed.json"), MANIFEST).unwrap();
+        fs::write(root.join(".tinted.lock"), "").unwrap();
+        // Created directory has got data of the image since
+        fs::create_dir_all(root.join("var/lib/other")).unwrap();
+
+        Restore::new(&root).run().unwrap();
+        assert_eq!(fs::read_to_string(root.join("etc/gone")).unwrap(), "gone");
+        assert!(!root.join(".tinted.quarantine").exists());
+        assert!(!root.join(".tinted.lock").exists());
+        assert!(!root.join("var/lib/mezzotint").exists());
+        assert!(root.join("var/lib/other").exists());
+
+        assert!(Restore::new(&root).run().is_err(), "restored image is not tinted");
+    }
+
+    #[test]
+    fn restores_backup() {
+        let root = scratch("restore-backup");
+        fs::create_dir_all(root.join("var/lib/mezzotint")).unwrap();
+        fs::write(root.join("var/lib/mezzotint/removed.json"), MANIFEST).unwrap();
+        fs::create_dir_all(root.join("usr/lib")).unwrap();
+        symlink("/usr/lib", root.join("lib")).unwrap();
+
+        // Backup has /var, which was in the image before, and a file behind an absolute symlink
+        let ar = scratch("restore-backup-archive").join("backup.tar");
+        let mut b = tar::Builder::new(fs::File::create(&ar).unwrap());
+        for (name, data) in [("var/", None), ("etc/gone", Some("gone")), ("lib/libgone.so", Some("elf"))] {
+            let mut h = tar::Header::new_ustar();
+            h.set_entry_type(if data.is_some() { tar::EntryType::Regular } else { tar::EntryType::Directory });
+            h.set_mode(0o755);
+            h.set_uid(unsafe { libc::getuid() } as u64);
+            h.set_gid(unsafe { libc::getgid() } as u64);
+            h.set_mtime(0);
+            h.set_size(data.unwrap_or_default().len() as u64);
+            b.append_data(&mut h, name, data.unwrap_or_default().as_bytes()).unwrap();
+        }
+        b.into_inner().unwrap();
+
+        Restore::new(&root).set_backup(Some(ar)).set_lockfile(None).run().unwrap();
+        assert_eq!(fs::read_to_string(root.join("etc/gone")).unwrap(), "gone");
+        assert_eq!(fs::read_to_string(root.join("usr/lib/libgone.so")).unwrap(), "elf");
+        assert!(!Path::new("/usr/lib/libgone.so").exists());
+        assert!(!root.join("var/lib").exists());
+        assert!(root.join("var").exists());
+    }
+}
@@ -1,62 +1,117 @@
+use crate::{
+    filters::defs,
+    glob,
+    interner::{self, PathId},
+    prefix,
+    quarantine::QUARANTINE_PATH,
+    workspace, xattrs,
+};
 use std::{
     collections::{HashMap, HashSet},
-    fs,
-    path::{Path, PathBuf},
+    ffi::CString,
+    fs::{self, FileType, Metadata},
+    io::{Error, ErrorKind},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Component, Path, PathBuf},
     vec,
 };
 
+/// Device and mount id of a path. Mount id tells apart bind mounts within the same filesystem,
+/// but it is known only on Linux 5.8 and newer.
+pub type MountId = (u64, Option<u64>);
+
 pub struct RootFS {
-    pds: bool, // Keep /proc, /sys and /dev
-    tmp: bool, // keep /tmp
-    rootfs: HashSet<PathBuf>,
-    ptree: Vec<PathBuf>,
-    broken_links: HashSet<PathBuf>,
+    rootfs: HashSet<PathId>,
+    broken_links: HashSet<PathId>,
+    inodes: HashMap<(u64, u64), Vec<PathId>>, // Names of the files with multiple hard links
+    caps: HashMap<PathId, String>,            // Files with capabilities
 }
 
 impl RootFS {
     pub fn new() -> Self {
-        let mut rf =
-            RootFS { pds: true, tmp: true, rootfs: HashSet::default(), broken_links: HashSet::default(), ptree: Vec::default() };
+        let mut rf = RootFS {
+            rootfs: HashSet::default(),
+            broken_links: HashSet::default(),
+            inodes: HashMap::default(),
+            caps: HashMap::default(),
+        };
         rf.scan();
 
         rf
     }
 
-    /// Set the flag to keep /proc, /sys and /dev directories
-    pub fn keep_pds(&mut self, keep: bool) -> &mut Self {
-        self.pds = keep;
-        self
+    /// Get a list what needs to be deleted from the image
+    pub fn dissect(&self, src: &HashSet<PathBuf>) -> Vec<PathBuf> {
+        let mut rfs: HashSet<PathId> = self.rootfs.clone();
+
+        // Paths, which are not in the rootfs were never interned
+        for x in src {
+            for y in Self::expand_target(x.to_owned(), false) {
+                if let Some(id) = interner::lookup(&y) {
+                    rfs.remove(&id);
+                }
+            }
+        }
+
+        // Removed files of the previous runs
+        rfs.retain(|id| !id.path().starts_with(QUARANTINE_PATH));
+
+        rfs.extend(&self.broken_links);
+        rfs.into_iter().map(|id| id.path().to_path_buf()).collect::<Vec<PathBuf>>()
     }
 
-    /// Set the flag to keep the /tmp directory
-    pub fn keep_tmp(&mut self, keep: bool) -> &mut Self {
-        self.tmp = keep;
-        self
+    /// Returns true if the path is on the built-in safety list, so it is never removed.
+    /// Broken symlinks are protected as well, e.g. /etc/resolv.conf to the stub of the resolver.
+    pub fn is_protected(p: &Path) -> bool {
+        defs::PROTECTED_PATHS.iter().any(|ptn| glob::matches(ptn, p))
     }
 
-    pub fn keep_tree(&mut self, paths: Vec<PathBuf>) -> &mut Self {
-        self.ptree.extend(paths);
-        self
+    /// Returns true if the path is a directory, which holds no files, only empty directories,
+    /// e.g. /var/log, shipped empty by a package for the runtime.
+    pub fn is_skeleton(p: &Path) -> bool {
+        prefix::is_dir(p)
+            && !prefix::is_symlink(p)
+            && walkdir::WalkDir::new(prefix::host(p)).into_iter().all(|e| e.map(|e| e.file_type().is_dir()).unwrap_or(false))
     }
 
-    /// Get a list what needs to be deleted from the image
-    pub fn dissect(&mut self, src: Vec<PathBuf>) -> Vec<PathBuf> {
-        let mut rfs: HashSet<PathBuf> = HashSet::default();
-        for p in &self.rootfs {
-            // Don't throw away ld-linux :)
-            if !p.is_file() || !p.file_name().unwrap().to_str().unwrap().starts_with("ld-linux-") {
-                rfs.insert(p.to_owned());
-            }
+    /// Get other names of the kept files with multiple hard links, those are not kept yet,
+    /// as removing them does not free any space, but breaks their users.
+    /// Returns pairs of the name and the kept file.
+    pub fn get_hardlinks(&self, kept: &HashSet<PathBuf>) -> Vec<(PathBuf, PathBuf)> {
+        let mut out: Vec<(PathBuf, PathBuf)> = vec![];
+        if self.inodes.is_empty() {
+            return out;
         }
 
-        for x in src {
-            for y in Self::expand_target(x, false) {
-                rfs.remove(&y);
+        for k in kept {
+            let m = match prefix::metadata(k) {
+                Ok(m) if m.is_file() && m.nlink() > 1 => m,
+                _ => continue,
+            };
+
+            for id in self.inodes.get(&(m.dev(), m.ino())).into_iter().flatten() {
+                let p = id.path();
+                if !kept.contains(p) && !out.iter().any(|(o, _)| o == p) {
+                    out.push((p.to_path_buf(), k.to_owned()));
+                }
             }
         }
 
-        rfs.extend(self.broken_links.to_owned());
-        rfs.into_iter().collect::<Vec<PathBuf>>()
+        out
+    }
+
+    /// Get file capabilities of the path, if it has any
+    pub fn get_caps(&self, p: &Path) -> Option<&String> {
+        interner::lookup(p).and_then(|id| self.caps.get(&id))
+    }
+
+    /// Returns true if the file is seen for the first time, so its size should be counted.
+    /// Other names of a file with multiple hard links take no space.
+    pub fn first_link(m: &Metadata, seen: &mut HashSet<(u64, u64)>) -> bool {
+        m.nlink() < 2 || m.is_dir() || seen.insert((m.dev(), m.ino()))
     }
 
     /// Expands target taking to the account Linux /bin symlinks to /usr/bin etc.
@@ -89,7 +144,7 @@ impl RootFS {
                 let dlnk = PathBuf::from(dpth.to_str().unwrap().strip_prefix("/usr").unwrap());
 
                 for p in [dpth, dlnk] {
-                    if p.exists() {
+                    if prefix::exists(&p) {
                         out.push(p);
                     }
                 }
@@ -105,36 +160,283 @@ impl RootFS {
         vec![target]
     }
 
-    /// Diff the whole rootfs to see what's inside.
-    fn scan(&mut self) {
-        for rde in walkdir::WalkDir::new("/").follow_root_links(true).contents_first(true).follow_links(true) {
-            match rde {
-                Ok(entry) => {
-                    let p = entry.into_path();
+    /// Get the reason, why the root filesystem looks like the running system itself
+    pub fn is_host(root: &Path) -> Option<&'static str> {
+        if root == Path::new("/") {
+            return Some("is the root of the host");
+        }
 
-                    // Delete /tmp
-                    if p == Path::new("/tmp") && !self.tmp {
-                        continue;
-                    }
+        let same = |a: &Path, b: &Path| match (fs::symlink_metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        };
+        if same(root, Path::new("/")) {
+            return Some("is the root of the host, mounted elsewhere");
+        }
+        if same(&root.join("usr"), Path::new("/usr")) {
+            return Some("shares /usr with the host");
+        }
 
-                    if (p == Path::new("/proc") || p == Path::new("/sys") || p == Path::new("/dev")) && !self.pds {
-                        continue;
-                    }
+        // Images might have /proc mounted for the build, but not with the identity of the host
+        if root.join("proc/self").exists() {
+            if let (Ok(a), Ok(b)) = (fs::read_to_string(root.join("etc/machine-id")), fs::read_to_string("/etc/machine-id")) {
+                if !a.trim().is_empty() && a == b {
+                    return Some("has /proc mounted and the machine id of the host");
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Resolve the root filesystem path, which might be a symlink or contain "..",
+    /// and refuse setups where chroot would end up somewhere unexpected.
+    /// Tinted roots are told by the lock file inside the image, if there is any.
+    pub fn resolve_root(p: &Path, lockfile: Option<&Path>) -> Result<PathBuf, Error> {
+        let root = fs::canonicalize(p).map_err(|err| {
+            Error::new(err.kind(), format!("Unable to resolve root filesystem {}: {}", p.to_str().unwrap(), err))
+        })?;
+        if !root.is_dir() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Root filesystem {} is not a directory", root.to_str().unwrap()),
+            ));
+        }
+
+        if p.is_symlink() || p.components().any(|c| c == Component::ParentDir) {
+            log::info!("Root filesystem {} is resolved to {}", p.to_str().unwrap(), root.to_str().unwrap());
+        }
 
-                    if self.ptree.contains(&p) {
-                        continue;
+        for vfs in ["/proc", "/sys", "/dev"] {
+            if root.starts_with(vfs) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Root filesystem {} is inside the host's {}, which is not an image", root.to_str().unwrap(), vfs),
+                ));
+            }
+        }
+
+        // Host itself might be a tinted container
+        let lockfile = lockfile.map(|l| l.strip_prefix("/").unwrap_or(l));
+        if let Some(a) =
+            lockfile.and_then(|l| root.ancestors().skip(1).filter(|a| a.parent().is_some()).find(|a| a.join(l).exists()))
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Root filesystem {} is nested within another tinted root filesystem {}, point --root to the outer one",
+                    root.to_str().unwrap(),
+                    a.to_str().unwrap()
+                ),
+            ));
+        }
+
+        // Workspace might not exist yet
+        let wd = workspace::get_base();
+        let wd = fs::canonicalize(&wd).unwrap_or_else(|_| Self::normalise(&std::env::current_dir().unwrap_or_default().join(wd)));
+        if root != Path::new("/") && wd.starts_with(&root) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Temporary data directory {} is inside the root filesystem, set it elsewhere with --workdir",
+                    wd.to_str().unwrap()
+                ),
+            ));
+        }
+
+        Ok(root)
+    }
+
+    /// Lexically normalise the path, resolving "." and ".." components
+    pub fn normalise(p: &Path) -> PathBuf {
+        let mut out = PathBuf::from("/");
+        for c in p.components() {
+            match c {
+                Component::ParentDir => {
+                    out.pop();
+                }
+                Component::Normal(c) => out.push(c),
+                _ => {}
+            }
+        }
+
+        out
+    }
+
+    /// Resolve the directory symlinks of the path, collecting them
+    fn resolve_dir(d: &Path, links: &mut Vec<PathBuf>, depth: usize) -> Option<PathBuf> {
+        let mut out = PathBuf::from("/");
+        for c in Self::normalise(d).components().skip(1) {
+            out.push(c);
+            if prefix::is_symlink(&out) {
+                if depth > prefix::MAX_LINKS {
+                    return None;
+                }
+                links.push(out.to_owned());
+                out = Self::resolve_dir(&Self::link_target(&out)?, links, depth + 1)?;
+            }
+        }
+
+        Some(out)
+    }
+
+    /// Resolve directory symlinks on the way to the path, e.g. /bin/bash to /usr/bin/bash
+    /// on usrmerged systems. The path itself is not followed, if it is a symlink.
+    /// Returns the canonical spelling and the directory symlinks on the way.
+    pub fn resolve_dirs(p: &Path) -> (PathBuf, Vec<PathBuf>) {
+        let p = Self::normalise(p);
+        let mut links: Vec<PathBuf> = vec![];
+        match (p.parent(), p.file_name()) {
+            (Some(d), Some(name)) => match Self::resolve_dir(d, &mut links, 0) {
+                Some(d) => (d.join(name), links),
+                None => (p, vec![]),
+            },
+            _ => (p, links),
+        }
+    }
+
+    /// Get the direct target of a symlink as an absolute path.
+    /// Returns None if the path is not a symlink.
+    pub fn link_target(p: &Path) -> Option<PathBuf> {
+        let tgt = prefix::read_link(p).ok()?;
+        Some(Self::normalise(&p.parent().unwrap_or(Path::new("/")).join(tgt)))
+    }
+
+    /// Get interpreter of a script from its shebang line.
+    /// Interpreters called via "env" are looked up in the standard binary directories.
+    /// Returns None if the path is not a script.
+    pub fn interpreter(p: &Path) -> Option<PathBuf> {
+        let mut buf = [0u8; 256];
+        let n = prefix::open(p).and_then(|mut f| std::io::Read::read(&mut f, &mut buf)).ok()?;
+        let line = String::from_utf8_lossy(&buf[..n]).lines().next()?.strip_prefix("#!")?.trim().to_string();
+
+        let mut args = line.split_whitespace();
+        let intp = PathBuf::from(args.next()?);
+        if intp.file_name().and_then(|n| n.to_str()) != Some("env") {
+            return Some(intp);
+        }
+
+        let name = args.find(|a| !a.starts_with('-'))?;
+        ["/usr/local/bin", "/usr/bin", "/bin", "/usr/sbin", "/sbin"]
+            .iter()
+            .map(|d| Path::new(d).join(name))
+            .find(|p| prefix::exists(p))
+            .or_else(|| Some(PathBuf::from("/usr/bin").join(name)))
+    }
+
+    /// Add a file of the rootfs, which is seen for the first time
+    fn add_file(&mut self, p: &Path) {
+        let id = interner::intern(p);
+        if !self.rootfs.insert(id) {
+            return;
+        }
+
+        if let Some(caps) = xattrs::get_caps(&prefix::at(p)) {
+            self.caps.insert(id, xattrs::describe_caps(&caps));
+        }
+
+        if let Some(m) = prefix::metadata(p).ok().filter(|m| m.nlink() > 1) {
+            self.inodes.entry((m.dev(), m.ino())).or_default().push(id);
+        }
+    }
+
+    /// Get device and mount id of the path, following symlinks
+    fn get_mount(p: &Path) -> Option<MountId> {
+        let dev = prefix::metadata(p).ok()?.dev();
+        let cp = CString::new(prefix::host(p).as_os_str().as_bytes()).ok()?;
+        let mut stx: libc::statx = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statx(libc::AT_FDCWD, cp.as_ptr(), 0, libc::STATX_MNT_ID, &mut stx) } != 0
+            || stx.stx_mask & libc::STATX_MNT_ID == 0
+        {
+            return Some((dev, None));
+        }
+
+        Some((dev, Some(stx.stx_mnt_id)))
+    }
+
+    /// Get device and mount id of the root filesystem
+    pub fn get_root_mount() -> MountId {
+        Self::get_mount(Path::new("/")).unwrap_or_default()
+    }
+
+    /// Returns true if the directory is not a part of the root filesystem: a virtual filesystem,
+    /// like /proc, or another mount, e.g. a bind mount of the host.
+    /// Such directories are never walked. Symlinked directories are checked by their target.
+    pub fn is_foreign(p: &Path, root: MountId) -> bool {
+        let c = if prefix::is_symlink(p) { prefix::canonicalize(p).unwrap_or_else(|_| p.to_path_buf()) } else { p.to_path_buf() };
+        let foreign = match Self::get_mount(&c) {
+            Some((dev, mnt)) => dev != root.0 || (mnt.is_some() && root.1.is_some() && mnt != root.1),
+            None => false,
+        };
+        if foreign || defs::VIRTUAL_FS.iter().any(|v| c == Path::new(v)) {
+            log::debug!("Skipping {}, which is not a part of the root filesystem", p.to_str().unwrap());
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns true if the file is a fifo, a socket or a device node outside of the virtual filesystems.
+    /// Such files are removed as any other file.
+    fn is_special(p: &Path, ft: &FileType) -> bool {
+        (ft.is_fifo() || ft.is_socket() || ft.is_char_device() || ft.is_block_device())
+            && !defs::VIRTUAL_FS.iter().any(|v| p.starts_with(v))
+    }
+
+    /// Diff the rootfs of the image, accessed from the host.
+    /// Symlinks are not followed, as they point inside the image, but resolved within it.
+    fn scan_prefixed(&mut self) {
+        // Directories are filtered before their contents, so foreign ones are not descended into
+        let root = Self::get_root_mount();
+        for entry in walkdir::WalkDir::new(prefix::at("/"))
+            .into_iter()
+            .filter_entry(|e| !e.file_type().is_dir() || !Self::is_foreign(&prefix::strip(e.path()), root))
+            .filter_map(|e| e.ok())
+        {
+            let p = prefix::strip(entry.path());
+            if entry.file_type().is_symlink() {
+                match prefix::canonicalize(&p) {
+                    Ok(t) if prefix::is_file(&t) => self.add_file(&t),
+                    Ok(_) => {}
+                    Err(_) => {
+                        log::debug!("Unable to access \"{}\"", p.to_str().unwrap());
+                        self.broken_links.insert(interner::intern(&p));
                     }
+                }
+            } else if entry.file_type().is_file() || Self::is_special(&p, &entry.file_type()) {
+                self.add_file(&p);
+            }
+        }
+    }
+
+    /// Diff the whole rootfs to see what's inside.
+    fn scan(&mut self) {
+        if prefix::is_set() {
+            return self.scan_prefixed();
+        }
 
-                    if p.is_file() && p.exists() {
+        // Virtual filesystems and mounts of other devices are not walked, as they are not a part of the image
+        let root = Self::get_root_mount();
+        for rde in walkdir::WalkDir::new("/")
+            .follow_root_links(true)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|e| !e.file_type().is_dir() || !Self::is_foreign(e.path(), root))
+        {
+            match rde {
+                Ok(entry) => {
+                    let ft = entry.file_type();
+                    let p = entry.into_path();
+                    if (p.is_file() && p.exists()) || Self::is_special(&p, &ft) {
                         if let Ok(p) = fs::canonicalize(p) {
-                            self.rootfs.insert(p);
+                            self.add_file(&p);
                         }
                     }
                 }
                 Err(err) => {
                     log::debug!("Unable to access \"{}\"", err);
                     if let Some(p) = err.path() {
-                        self.broken_links.insert(p.to_path_buf());
+                        self.broken_links.insert(interner::intern(p));
                     }
                 }
             }
@@ -0,0 +1,413 @@
+/*
+SBOM of the tinted image in SPDX 2.3 or CycloneDX 1.5 JSON format.
+
+Lists kept packages with their versions and licenses from the package database
+and loose files, which do not belong to any package, so the tinted image
+can be fed into compliance pipelines without scanning it again.
+ */
+
+use crate::{
+    digest,
+    osinfo::OsInfo,
+    prefix,
+    scanner::{debftrace::DebPkgFileTrace, pkgbackend::PackageBackend, traceitf::PkgFileTrace},
+};
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+const NOASSERTION: &str = "NOASSERTION";
+
+/// Debian short license names (DEP-5) to SPDX identifiers
+const LICENSES: &[(&str, &str)] = &[
+    ("gpl-1", "GPL-1.0-only"),
+    ("gpl-1+", "GPL-1.0-or-later"),
+    ("gpl-2", "GPL-2.0-only"),
+    ("gpl-2+", "GPL-2.0-or-later"),
+    ("gpl-3", "GPL-3.0-only"),
+    ("gpl-3+", "GPL-3.0-or-later"),
+    ("lgpl-2", "LGPL-2.0-only"),
+    ("lgpl-2+", "LGPL-2.0-or-later"),
+    ("lgpl-2.1", "LGPL-2.1-only"),
+    ("lgpl-2.1+", "LGPL-2.1-or-later"),
+    ("lgpl-3", "LGPL-3.0-only"),
+    ("lgpl-3+", "LGPL-3.0-or-later"),
+    ("agpl-3", "AGPL-3.0-only"),
+    ("agpl-3+", "AGPL-3.0-or-later"),
+    ("gfdl-1.2", "GFDL-1.2-only"),
+    ("gfdl-1.2+", "GFDL-1.2-or-later"),
+    ("gfdl-1.3", "GFDL-1.3-only"),
+    ("gfdl-1.3+", "GFDL-1.3-or-later"),
+    ("artistic", "Artistic-1.0"),
+    ("artistic-2.0", "Artistic-2.0"),
+    ("apache-2.0", "Apache-2.0"),
+    ("bsd-2-clause", "BSD-2-Clause"),
+    ("bsd-3-clause", "BSD-3-Clause"),
+    ("bsd-4-clause", "BSD-4-Clause"),
+    ("expat", "MIT"),
+    ("mit", "MIT"),
+    ("isc", "ISC"),
+    ("zlib", "Zlib"),
+    ("mpl-1.1", "MPL-1.1"),
+    ("mpl-2.0", "MPL-2.0"),
+    ("cc0-1.0", "CC0-1.0"),
+    ("psf-2", "PSF-2.0"),
+    ("openssl", "OpenSSL"),
+    ("curl", "curl"),
+];
+
+#[derive(Serialize)]
+struct Checksum {
+    algorithm: &'static str,
+    #[serde(rename = "checksumValue")]
+    value: String,
+}
+
+#[derive(Serialize)]
+struct CreationInfo {
+    created: String,
+    creators: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxPackage {
+    #[serde(rename = "SPDXID")]
+    spdx_id: String,
+    name: String,
+    version_info: String,
+    download_location: &'static str,
+    license_concluded: &'static str,
+    license_declared: String,
+    copyright_text: &'static str,
+    files_analyzed: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxFile {
+    #[serde(rename = "SPDXID")]
+    spdx_id: String,
+    file_name: String,
+    checksums: Vec<Checksum>,
+    license_concluded: &'static str,
+    copyright_text: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Relationship {
+    spdx_element_id: &'static str,
+    relationship_type: &'static str,
+    related_spdx_element: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpdxDocument {
+    spdx_version: &'static str,
+    data_license: &'static str,
+    #[serde(rename = "SPDXID")]
+    spdx_id: &'static str,
+    name: String,
+    document_namespace: String,
+    creation_info: CreationInfo,
+    packages: Vec<SpdxPackage>,
+    files: Vec<SpdxFile>,
+    relationships: Vec<Relationship>,
+}
+
+#[derive(Serialize)]
+struct CdxHash {
+    alg: &'static str,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct CdxLicense {
+    expression: String,
+}
+
+#[derive(Serialize)]
+struct CdxComponent {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref", skip_serializing_if = "Option::is_none")]
+    bom_ref: Option<String>,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    licenses: Vec<CdxLicense>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    purl: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<CdxHash>,
+}
+
+#[derive(Serialize)]
+struct CdxTools {
+    components: Vec<CdxComponent>,
+}
+
+#[derive(Serialize)]
+struct CdxMetadata {
+    timestamp: String,
+    tools: CdxTools,
+    component: CdxComponent,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CdxDocument {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    serial_number: String,
+    version: u32,
+    metadata: CdxMetadata,
+    components: Vec<CdxComponent>,
+}
+
+/// Kept package: name, version and SPDX license expression
+type Package = (String, Option<String>, Option<String>);
+
+pub struct Sbom {
+    packages: Vec<Package>,
+    files: Vec<(PathBuf, String, String)>,
+    purl_type: Option<&'static str>,
+    os_id: String,
+}
+
+impl Sbom {
+    /// Collect packages and loose files of the kept paths
+    pub fn new(kept: &[PathBuf], pkb: &dyn PackageBackend) -> Self {
+        let mut pt = DebPkgFileTrace::new();
+        let mut pkgs: HashSet<String> = HashSet::default();
+        let mut files: Vec<(PathBuf, String, String)> = vec![];
+
+        for p in kept.iter().filter(|p| !prefix::is_symlink(p) && prefix::is_file(p)) {
+            if let Some(pkg) = pt.trace(p.to_owned()) {
+                pkgs.insert(pkg);
+                continue;
+            }
+
+            match digest::file_digests(&prefix::host(p)) {
+                Ok((sha1, sha256)) => files.push((p.to_owned(), sha1, sha256)),
+                Err(err) => log::warn!("Unable to checksum {}: {}", p.to_str().unwrap(), err),
+            }
+        }
+
+        let mut pkgs = pkgs.into_iter().collect::<Vec<String>>();
+        pkgs.sort();
+
+        Sbom {
+            packages: pkgs
+                .into_iter()
+                .map(|n| {
+                    let lic = Self::to_spdx_expr(&pkb.get_package_licenses(n.to_owned()));
+                    (n.to_owned(), pkb.get_package_version(n), lic)
+                })
+                .collect(),
+            files,
+            purl_type: if pkb.name() == "dpkg" { Some("deb") } else { None },
+            os_id: OsInfo::detect().get_id().to_string(),
+        }
+    }
+
+    /// Get creation time and a digest of the content, which makes documents unique
+    fn fingerprint(&self) -> (String, String) {
+        let created = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        let mut content = self.packages.iter().map(|(n, v, _)| format!("{} {:?}", n, v)).collect::<Vec<String>>();
+        content.extend(self.files.iter().map(|(_, _, sha256)| sha256.to_owned()));
+        content.push(created.to_owned());
+
+        (created, digest::sha256(content.join("\n").as_bytes()))
+    }
+
+    /// Get file name, relative to the root of the image
+    fn file_name(p: &Path) -> String {
+        Path::new(".").join(p.strip_prefix("/").unwrap_or(p)).to_str().unwrap().to_string()
+    }
+
+    /// Convert a single license name to SPDX identifier. Unknown ones are references.
+    fn to_spdx_id(name: &str) -> String {
+        let name = name.trim();
+        if let Some((_, id)) = LICENSES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+            return id.to_string();
+        }
+
+        format!("LicenseRef-{}", Self::sanitise(name))
+    }
+
+    /// Convert declared licenses of the package to SPDX license expression.
+    /// Licenses of DEP-5 might be already expressions with "or" and "and".
+    fn to_spdx_expr(licenses: &[String]) -> Option<String> {
+        let mut out: Vec<String> = vec![];
+        for l in licenses {
+            let alts = l
+                .split(" or ")
+                .map(|a| a.split(" and ").map(Self::to_spdx_id).collect::<Vec<String>>().join(" AND "))
+                .collect::<Vec<String>>();
+            let expr = if alts.len() > 1 { format!("({})", alts.join(" OR ")) } else { alts.join("") };
+            if !out.contains(&expr) {
+                out.push(expr);
+            }
+        }
+
+        if out.is_empty() {
+            None
+        } else {
+            Some(out.join(" AND "))
+        }
+    }
+
+    /// SPDX identifiers allow only letters, numbers, "." and "-"
+    fn sanitise(s: &str) -> String {
+        s.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '-' }).collect()
+    }
+
+    /// Get SBOM as SPDX 2.3 JSON document
+    pub fn to_spdx(&self, name: &str) -> Result<String, Error> {
+        let mut relationships: Vec<Relationship> = vec![];
+        let packages = self
+            .packages
+            .iter()
+            .map(|(n, v, l)| {
+                let spdx_id = format!("SPDXRef-Package-{}", Self::sanitise(n));
+                relationships.push(Relationship {
+                    spdx_element_id: "SPDXRef-DOCUMENT",
+                    relationship_type: "DESCRIBES",
+                    related_spdx_element: spdx_id.to_owned(),
+                });
+                SpdxPackage {
+                    spdx_id,
+                    name: n.to_owned(),
+                    version_info: v.to_owned().unwrap_or(NOASSERTION.to_string()),
+                    download_location: NOASSERTION,
+                    license_concluded: NOASSERTION,
+                    license_declared: l.to_owned().unwrap_or(NOASSERTION.to_string()),
+                    copyright_text: NOASSERTION,
+                    files_analyzed: false,
+                }
+            })
+            .collect::<Vec<SpdxPackage>>();
+
+        let files = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(i, (p, sha1, sha256))| {
+                let spdx_id = format!("SPDXRef-File-{}", i + 1);
+                relationships.push(Relationship {
+                    spdx_element_id: "SPDXRef-DOCUMENT",
+                    relationship_type: "DESCRIBES",
+                    related_spdx_element: spdx_id.to_owned(),
+                });
+                SpdxFile {
+                    spdx_id,
+                    file_name: Self::file_name(p),
+                    checksums: vec![
+                        Checksum { algorithm: "SHA1", value: sha1.to_owned() },
+                        Checksum { algorithm: "SHA256", value: sha256.to_owned() },
+                    ],
+                    license_concluded: NOASSERTION,
+                    copyright_text: NOASSERTION,
+                }
+            })
+            .collect::<Vec<SpdxFile>>();
+
+        // Namespace must be unique per document
+        let (created, fp) = self.fingerprint();
+        let doc = SpdxDocument {
+            spdx_version: "SPDX-2.3",
+            data_license: "CC0-1.0",
+            spdx_id: "SPDXRef-DOCUMENT",
+            name: name.to_string(),
+            document_namespace: format!("https://mezzotint.readthedocs.io/spdx/{}-{}", Self::sanitise(name), fp),
+            creation_info: CreationInfo { created, creators: vec![format!("Tool: mezzotint-{}", env!("CARGO_PKG_VERSION"))] },
+            packages,
+            files,
+            relationships,
+        };
+
+        Ok(format!("{}\n", serde_json::to_string_pretty(&doc)?))
+    }
+
+    /// Get package URL of the package, if the package type is known
+    fn purl(&self, name: &str, version: &Option<String>) -> Option<String> {
+        let purl = format!("pkg:{}/{}/{}", self.purl_type?, self.os_id, name.replace('+', "%2B"));
+        Some(match version {
+            Some(v) => format!("{}@{}", purl, v.replace('+', "%2B").replace(':', "%3A")),
+            None => purl,
+        })
+    }
+
+    /// Get SBOM as CycloneDX 1.5 JSON document
+    pub fn to_cyclonedx(&self, name: &str) -> Result<String, Error> {
+        let mut components = self
+            .packages
+            .iter()
+            .map(|(n, v, l)| CdxComponent {
+                kind: "library",
+                bom_ref: Some(format!("package-{}", Self::sanitise(n))),
+                name: n.to_owned(),
+                version: v.to_owned(),
+                licenses: l.iter().map(|l| CdxLicense { expression: l.to_owned() }).collect(),
+                purl: self.purl(n, v),
+                hashes: vec![],
+            })
+            .collect::<Vec<CdxComponent>>();
+
+        components.extend(self.files.iter().enumerate().map(|(i, (p, sha1, sha256))| CdxComponent {
+            kind: "file",
+            bom_ref: Some(format!("file-{}", i + 1)),
+            name: Self::file_name(p),
+            version: None,
+            licenses: vec![],
+            purl: None,
+            hashes: vec![
+                CdxHash { alg: "SHA-1", content: sha1.to_owned() },
+                CdxHash { alg: "SHA-256", content: sha256.to_owned() },
+            ],
+        }));
+
+        // Serial number is a UUID, made of the content digest
+        let (timestamp, fp) = self.fingerprint();
+        let doc = CdxDocument {
+            bom_format: "CycloneDX",
+            spec_version: "1.5",
+            serial_number: format!("urn:uuid:{}-{}-4{}-8{}-{}", &fp[..8], &fp[8..12], &fp[13..16], &fp[17..20], &fp[20..32]),
+            version: 1,
+            metadata: CdxMetadata {
+                timestamp,
+                tools: CdxTools {
+                    components: vec![CdxComponent {
+                        kind: "application",
+                        bom_ref: None,
+                        name: "mezzotint".to_string(),
+                        version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                        licenses: vec![],
+                        purl: None,
+                        hashes: vec![],
+                    }],
+                },
+                component: CdxComponent {
+                    kind: "container",
+                    bom_ref: None,
+                    name: name.to_string(),
+                    version: None,
+                    licenses: vec![],
+                    purl: None,
+                    hashes: vec![],
+                },
+            },
+            components,
+        };
+
+        Ok(format!("{}\n", serde_json::to_string_pretty(&doc)?))
+    }
+}
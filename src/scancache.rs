@@ -0,0 +1,111 @@
+/*
+Persistent cache of the scan results on the host.
+
+Contents and dependencies of the packages are the same for the same
+package name, version and architecture. Dependencies of an ELF file are
+the same for the same file and the same libraries of the image. Repeated
+tints of similar images, e.g. in CI, take these results from the cache
+directory instead of scanning again.
+
+The cache directory is on the host, so it is opened before chroot and
+its entries are accessed relative to it. Temporary entries of the killed
+runs are removed by the next run, the same way as their workspaces.
+ */
+
+use crate::{
+    digest,
+    workspace::{self, open_at, rename_at, unlink_at},
+};
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io::{Error, Read, Write},
+    os::unix::{ffi::OsStrExt, io::FromRawFd},
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+static DIR: Mutex<Option<Arc<File>>> = Mutex::new(None);
+
+/// Sequence of the temporary entries, so the workers of the same run never share one
+static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// Open the cache directory, creating it if needed
+pub fn open(dir: &Path) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    let cp = CString::new(dir.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(cp.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    *DIR.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(unsafe { File::from_raw_fd(fd) }));
+    log::debug!("Using scan cache at {}", dir.to_str().unwrap());
+    cleanup(dir);
+
+    Ok(())
+}
+
+/// Remove temporary entries, left by the runs those are no longer running
+fn cleanup(dir: &Path) {
+    for e in fs::read_dir(dir).into_iter().flatten().filter_map(|e| e.ok()) {
+        let name = e.file_name().to_str().unwrap_or_default().to_string();
+        let pid = match name.strip_prefix('.').and_then(|n| n.split('.').nth(1)).map(|pid| pid.parse::<u32>()) {
+            Some(Ok(pid)) => pid,
+            _ => continue,
+        };
+
+        if workspace::is_stale(pid) {
+            log::debug!("Removing stale scan cache entry {}", name);
+            let _ = fs::remove_file(e.path());
+        }
+    }
+}
+
+/// Is the cache used in this run
+pub fn is_enabled() -> bool {
+    DIR.lock().unwrap_or_else(PoisonError::into_inner).is_some()
+}
+
+/// Get name of the cache entry of the given kind, e.g. "contents"
+fn get_name(kind: &str, key: &[String]) -> String {
+    format!("{}-{}", kind, digest::sha256(key.join("\0").as_bytes()))
+}
+
+/// Get the cache directory. It is locked only to take it, so the workers access the entries in parallel.
+fn get_dir() -> Option<Arc<File>> {
+    DIR.lock().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// Get the cached result of the given kind by its key
+pub fn get(kind: &str, key: &[String]) -> Option<Vec<String>> {
+    let mut data = String::new();
+    open_at(&*get_dir()?, &get_name(kind, key), libc::O_RDONLY).ok()?.read_to_string(&mut data).ok()?;
+    log::debug!("Found {} of {} in the scan cache", kind, key[0]);
+
+    Some(data.lines().map(|l| l.to_string()).collect())
+}
+
+/// Store the result of the given kind by its key. The entry is replaced at once,
+/// so other runs, sharing the cache, never read it partially written.
+pub fn put(kind: &str, key: &[String], data: &[String]) {
+    let dir = match get_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+
+    let name = get_name(kind, key);
+    let tmp = format!(".{}.{}.{}", name, process::id(), SEQ.fetch_add(1, Ordering::Relaxed));
+    let r = open_at(&dir, &tmp, libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC)
+        .and_then(|mut f| f.write_all(data.iter().map(|l| format!("{}\n", l)).collect::<String>().as_bytes()))
+        .and_then(|_| rename_at(&dir, &tmp, &dir, &name));
+
+    if let Err(err) = r {
+        log::warn!("Unable to store {} of {} in the scan cache: {}", kind, key[0], err);
+        unlink_at(&dir, &tmp);
+    }
+}
@@ -1,5 +1,17 @@
-use crate::scanner::general::{Scanner, ScannerCommons};
-use std::{collections::HashSet, path::PathBuf};
+use crate::{
+    digest,
+    ldcache::LD_SO_CACHE,
+    prefix, scancache,
+    scanner::{
+        general::{Scanner, ScannerCommons},
+        ldso::LibResolver,
+    },
+};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 pub struct ElfScanner {
     commons: ScannerCommons,
@@ -33,13 +45,51 @@ impl ElfScanner {
 
         dynlibs
     }
+
+    /// Get key of the binary in the scan cache: its digest and the libraries of the image, known to the loader.
+    /// Returns None, if the binary cannot be read, e.g. it is a directory, or the image has no loader cache,
+    /// so its libraries are not known.
+    fn get_cache_key(target: &Path) -> Option<Vec<String>> {
+        let (_, sha256) = digest::file_digests(&prefix::host(target)).ok()?;
+        let ldcache = digest::sha256(&prefix::read(LD_SO_CACHE).ok()?);
+        Some(vec![target.to_str()?.to_string(), sha256, ldcache, if prefix::is_set() { "prefix" } else { "chroot" }.to_string()])
+    }
+
+    /// Find libraries of the binary, those cannot be resolved.
+    /// Returns None if this cannot be checked, e.g. ldd is not available.
+    pub fn get_unresolved(target: &str) -> Option<Vec<String>> {
+        if prefix::is_set() {
+            return Some(LibResolver::new().unresolved(Path::new(target)));
+        }
+
+        let out = Command::new("/usr/bin/ldd").arg(target).output().ok()?;
+        Some(
+            String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .filter_map(|l| l.split_once("=> not found").map(|(lib, _)| lib.trim().to_string()))
+                .collect(),
+        )
+    }
 }
 
 impl Scanner for ElfScanner {
     /// Scan for the required dynamic libraries in an executable
     fn scan(&mut self, pth: PathBuf) -> Vec<PathBuf> {
+        let key = if scancache::is_enabled() { Self::get_cache_key(&pth) } else { None };
+        // Libraries of the image might be removed since, e.g. by a previous tint
+        if let Some(libs) = key.as_ref().and_then(|k| scancache::get("elf", k)) {
+            if libs.iter().all(prefix::exists) {
+                return libs.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+            }
+        }
+
         log::debug!("Scanning for dependencies in {}", pth.to_str().unwrap());
-        self.get_dynlibs(pth.to_str().unwrap().to_string()).iter().map(PathBuf::from).collect::<Vec<PathBuf>>()
+        let libs = self.get_dynlibs(pth.to_str().unwrap().to_string());
+        if let Some(key) = key {
+            scancache::put("elf", &key, &libs);
+        }
+
+        libs.iter().map(PathBuf::from).collect::<Vec<PathBuf>>()
     }
 
     /// Bogus trait implementation, does nothing in this case
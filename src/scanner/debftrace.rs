@@ -1,10 +1,10 @@
-use crate::rootfs::RootFS;
+use crate::{prefix, rootfs::RootFS};
 
 use super::traceitf::PkgFileTrace;
 use std::{
     collections::HashMap,
     fs::{self, DirEntry},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 pub struct DebPkgFileTrace {
@@ -13,23 +13,28 @@ pub struct DebPkgFileTrace {
 
 impl DebPkgFileTrace {
     pub fn new() -> Self {
+        Self::at(&prefix::at("/"))
+    }
+
+    /// Constructor of the tracer of another root, e.g. on the host
+    pub fn at(root: &Path) -> Self {
         let mut d = DebPkgFileTrace { file_to_pkg: HashMap::default() };
-        d.load();
+        d.load(root);
         d
     }
 
     /// Read dpkg cache. All of it.
-    fn load(&mut self) {
-        if let Ok(rd) = fs::read_dir("/var/lib/dpkg/info") {
+    fn load(&mut self, root: &Path) {
+        if let Ok(rd) = fs::read_dir(root.join("var/lib/dpkg/info")) {
             for d in rd.filter_map(Result::ok).collect::<Vec<DirEntry>>() {
                 if d.path().to_str().unwrap().ends_with(".list") {
-                    self.load_pkg(d.path());
+                    self.load_pkg(root, d.path());
                 }
             }
         }
     }
 
-    fn load_pkg(&mut self, pinfo: PathBuf) {
+    fn load_pkg(&mut self, root: &Path, pinfo: PathBuf) {
         // Path to package name
         let pkgname = &pinfo
             .file_name()
@@ -43,7 +48,13 @@ impl DebPkgFileTrace {
 
         if let Ok(pkg_data) = fs::read_to_string(&pinfo) {
             for f_pth in pkg_data.split('\n').collect::<Vec<&str>>().iter().map(PathBuf::from) {
-                if f_pth.exists() && f_pth.is_file() {
+                // Symlinks of the current image are resolved within it
+                let is_file = if root == prefix::at("/") {
+                    prefix::is_file(&f_pth)
+                } else {
+                    root.join(f_pth.strip_prefix("/").unwrap_or(&f_pth)).is_file()
+                };
+                if is_file {
                     self.file_to_pkg.insert(f_pth, pkgname.to_string().to_owned());
                 }
             }
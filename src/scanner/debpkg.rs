@@ -1,30 +1,86 @@
 use crate::{
-    procdata::Autodeps,
+    digest, prefix,
+    rootfs::RootFS,
+    scancache,
     scanner::{
-        general::{Scanner, ScannerCommons},
+        general::ScannerCommons,
+        pkgbackend::{Capabilities, PackageBackend},
         tracedeb,
         traceitf::PkgDepTrace,
     },
 };
-use colored::Colorize;
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     io::{Error, ErrorKind},
-    path::PathBuf,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::Instant,
 };
 
-/// Scans content of the package, to which
-/// a target belongs to.
+/// Value, which belongs to the root filesystem. Several roots are processed in one run with the prefix.
+type Scoped<T> = Mutex<Option<(Option<PathBuf>, T)>>;
+
+/// Results of the queries by their keys. Each key has its own lock,
+/// so different keys are queried in parallel, but the same one only once.
+type Memo<T> = Scoped<HashMap<String, Arc<Mutex<Option<T>>>>>;
+
+/// Contents of the packages, those are already listed for the root filesystem.
+/// The dpkg database does not change while the image is scanned.
+static CONTENTS: Memo<Vec<String>> = Mutex::new(None);
+
+/// Owner packages of the files, those are already resolved for the root filesystem
+static OWNERS: Memo<Option<String>> = Mutex::new(None);
+
+/// Versions and architectures of the installed packages by their names
+static INSTALLED: Scoped<Option<HashMap<String, (String, String)>>> = Mutex::new(None);
+
+/// Digest of the package databases, those dependencies are traced by
+static DATABASE: Scoped<Option<String>> = Mutex::new(None);
+
+/// Status database of dpkg
+const DPKG_STATUS: &str = "/var/lib/dpkg/status";
+
+/// Package lists of apt
+const APT_LISTS: &str = "/var/lib/apt/lists";
+
+/// Amount of files, those owners are queried by one call
+const OWNERS_BATCH: usize = 1024;
+
+/// Get the value of the current root filesystem, dropping the value of another root
+fn scoped<T: Default>(slot: &mut Option<(Option<PathBuf>, T)>) -> &mut T {
+    let root = prefix::get();
+    if slot.as_ref().map(|(r, _)| *r != root).unwrap_or(true) {
+        *slot = Some((root, T::default()));
+    }
+
+    &mut slot.as_mut().unwrap().1
+}
+
+/// Get the memoized result of the key, or query it. Failed queries are not memoized.
+fn memoize<T: Clone>(memo: &Memo<T>, key: &str, query: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    let entry = scoped(&mut memo.lock().unwrap_or_else(PoisonError::into_inner)).entry(key.to_string()).or_default().clone();
+
+    let mut value = entry.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(v) = value.as_ref() {
+        return Ok(v.clone());
+    }
+
+    let v = query()?;
+    *value = Some(v.clone());
+    Ok(v)
+}
+
+/// Debian package backend, which finds packages
+/// and their content via dpkg database.
 pub struct DebPackageScanner {
     commons: ScannerCommons,
-    autodeps: Autodeps,
-    excluded_packages: HashSet<String>,
 }
 
 impl DebPackageScanner {
     /// Constructor
-    pub fn new(autodeps: Autodeps) -> Self {
-        DebPackageScanner { commons: ScannerCommons::new(), autodeps, excluded_packages: HashSet::default() }
+    pub fn new() -> Self {
+        DebPackageScanner { commons: ScannerCommons::new() }
     }
 
     /// Expands target taking to the account Linux /bin symlinks to /usr/bin etc.
@@ -50,82 +106,230 @@ impl DebPackageScanner {
         vec![target]
     }
 
-    /// Get package name of the target binary
-    /// May still not find a package for the target.
-    pub fn get_package_for(&self, target: String) -> Result<Option<String>, Error> {
+    /// Get version and architecture of the installed package. The package name might be qualified with the architecture.
+    fn get_installed(pkname: &str) -> Option<(String, String)> {
+        let mut installed = INSTALLED.lock().unwrap_or_else(PoisonError::into_inner);
+        let installed = scoped(&mut installed).get_or_insert_with(|| {
+            let mut out: HashMap<String, (String, String)> = HashMap::default();
+            for stanza in prefix::read_to_string(DPKG_STATUS).unwrap_or_default().split("\n\n") {
+                let (mut name, mut version, mut arch, mut ok) = ("", "", "", false);
+                for l in stanza.lines() {
+                    match l.split_once(": ") {
+                        Some(("Package", v)) => name = v.trim(),
+                        Some(("Version", v)) => version = v.trim(),
+                        Some(("Architecture", v)) => arch = v.trim(),
+                        Some(("Status", v)) => ok = v.trim().ends_with(" installed"),
+                        _ => {}
+                    }
+                }
+                if ok && !name.is_empty() {
+                    let v = (version.to_string(), arch.to_string());
+                    out.insert(format!("{}:{}", name, arch), v.to_owned());
+                    out.entry(name.to_string()).or_insert(v);
+                }
+            }
+            out
+        });
+
+        installed.get(pkname).cloned()
+    }
+
+    /// Get digest of the dpkg status database and of the apt package lists.
+    /// Lists are too large to be read, so their names, sizes and modification times are taken.
+    fn get_database_digest() -> String {
+        let mut database = DATABASE.lock().unwrap_or_else(PoisonError::into_inner);
+        scoped(&mut database)
+            .get_or_insert_with(|| {
+                let mut lists = prefix::read_dir(APT_LISTS)
+                    .map(|rd| {
+                        rd.filter_map(|e| e.ok())
+                            .filter_map(|e| {
+                                let m = e.metadata().ok()?;
+                                Some(format!("{} {} {}", e.file_name().to_str()?, m.len(), m.mtime()))
+                            })
+                            .collect::<Vec<String>>()
+                    })
+                    .unwrap_or_default();
+                lists.sort();
+
+                let mut data = prefix::read(DPKG_STATUS).unwrap_or_default();
+                data.extend(lists.join("\n").as_bytes());
+                digest::sha256(&data)
+            })
+            .to_owned()
+    }
+
+    /// Get the result of the query from the scan cache, or query it and store it there.
+    /// Only installed packages are cached, as their name, version and architecture are the key.
+    /// Results, those depend on other packages, are keyed by the package databases as well.
+    fn cached(
+        kind: &str, pkname: &str, with_database: bool, query: impl FnOnce() -> Result<Vec<String>, Error>,
+    ) -> Result<Vec<String>, Error> {
+        if !scancache::is_enabled() {
+            return query();
+        }
+        let mut key = match Self::get_installed(pkname) {
+            Some((version, arch)) => vec![pkname.to_string(), version, arch],
+            None => return query(),
+        };
+        if with_database {
+            key.push(Self::get_database_digest());
+        }
+
+        if let Some(data) = scancache::get(kind, &key) {
+            return Ok(data);
+        }
+        let data = query()?;
+        scancache::put(kind, &key, &data);
+
+        Ok(data)
+    }
+
+    /// Query dpkg database of the image. Without chroot the database is queried by dpkg of the host.
+    fn query(&self, args: &[String]) -> Result<Vec<String>, Error> {
+        let mut qargs: Vec<String> = vec![];
+        if let Some(root) = prefix::get() {
+            qargs.push(format!("--admindir={}", root.join("var/lib/dpkg").to_str().unwrap()));
+        }
+        qargs.extend(args.iter().cloned());
+
+        let start = Instant::now();
+        let out = self.commons.call_any("/usr/bin/dpkg-query".into(), &qargs);
+        log::debug!("dpkg-query {} with {} arguments took {} ms", args[0], args.len() - 1, start.elapsed().as_millis());
+
+        out
+    }
+
+    /// Check if dpkg is available in the image
+    fn check_dpkg() -> Result<(), Error> {
         let dpkg = PathBuf::from("/usr/bin/dpkg");
-        if !dpkg.exists() {
+        if !prefix::exists(&dpkg) {
             return Err(Error::new(ErrorKind::NotFound, format!("Unable to access \"{}\"", dpkg.to_str().unwrap())));
         }
 
-        let mut pkg: String = String::default();
+        Ok(())
+    }
+}
+
+impl PackageBackend for DebPackageScanner {
+    fn name(&self) -> &str {
+        "dpkg"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { autodeps: prefix::exists("/usr/bin/apt"), owners: true, virtuals: false }
+    }
+
+    /// Get package name of the target binary
+    /// May still not find a package for the target.
+    fn get_package_for(&self, target: String) -> Result<Option<String>, Error> {
+        Self::check_dpkg()?;
+
         for t in self.expand_target(target) {
-            if let Some(l) = (self.commons.call_any(dpkg.to_owned(), &["-S".to_string(), t.to_string()])?).into_iter().next() {
-                pkg = l.split_once(':').unwrap().0.trim().to_string();
-                break;
+            let pkg = memoize(&OWNERS, &t, || {
+                Ok(self
+                    .query(&["-S".to_string(), t.to_string()])?
+                    .first()
+                    .map(|l| l.split_once(':').unwrap().0.trim().to_string()))
+            })?;
+            if pkg.is_some() {
+                return Ok(pkg);
             }
         }
 
-        if pkg.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(pkg))
+        Ok(None)
+    }
+
+    /// Find packages of the targets by one query per batch. Files without packages are
+    /// remembered as well, so they are not queried again.
+    fn find_owners(&self, targets: &[String]) -> Result<(), Error> {
+        Self::check_dpkg()?;
+
+        let mut files = targets.iter().flat_map(|t| self.expand_target(t.to_owned())).collect::<Vec<String>>();
+        files.sort();
+        files.dedup();
+        for batch in files.chunks(OWNERS_BATCH) {
+            let mut found: HashMap<String, String> = HashMap::default();
+            for l in self.query(&[vec!["-S".to_string()], batch.to_vec()].concat())? {
+                // Diversions are reported as "diversion by <package> from|to: <path>"
+                if l.starts_with("diversion by") {
+                    continue;
+                }
+                if let (Some((pkg, _)), Some((_, p))) = (l.split_once(':'), l.split_once(": ")) {
+                    found.entry(p.trim().to_string()).or_insert_with(|| pkg.trim().to_string());
+                }
+            }
+
+            for f in batch {
+                memoize(&OWNERS, f, || Ok(found.get(f).cloned()))?;
+            }
         }
+
+        Ok(())
     }
 
     /// Get contents of the package.
     ///
     /// If package does not exists or dpkg database has no contents, an empty lines returned.
-    pub fn get_package_contents(&self, pkname: String) -> Result<Vec<PathBuf>, Error> {
+    fn get_package_contents(&self, pkname: String) -> Result<Vec<PathBuf>, Error> {
         let mut files: Vec<PathBuf> = Vec::default();
-        for fp in self.commons.call_any("/usr/bin/dpkg".into(), &["-L".to_string(), pkname])? {
+        for fp in memoize(&CONTENTS, &pkname, || {
+            Self::cached("contents", &pkname, false, || self.query(&["-L".to_string(), pkname.to_owned()]))
+        })? {
             if fp == "./" {
                 continue;
             }
 
-            // Preserve [sym]links
-            if !PathBuf::from(&fp).is_dir() {
+            // Preserve [sym]links and empty directories, shipped for the runtime
+            if !prefix::is_dir(&fp) {
+                // Owner of the file is known now, so it is not queried again
+                memoize(&OWNERS, &fp, || Ok(Some(pkname.to_owned())))?;
+                files.push(PathBuf::from(fp));
+            } else if RootFS::is_skeleton(Path::new(&fp)) {
                 files.push(PathBuf::from(fp));
             }
         }
         Ok(files)
     }
-}
 
-impl Scanner for DebPackageScanner {
-    fn scan(&mut self, pth: PathBuf) -> Vec<PathBuf> {
-        log::debug!("Scanning package contents for {:?}", pth.to_str());
+    /// Trace dependencies graph for the package
+    fn get_dependencies(&self, pkname: String, exclude: Vec<String>) -> Vec<String> {
+        let mut deps =
+            Self::cached("dependencies", &pkname, true, || Ok(tracedeb::DebPackageTrace::new().trace(pkname.to_owned())))
+                .unwrap_or_default();
+        deps.retain(|p| !exclude.contains(p));
+        deps
+    }
 
-        let mut out: Vec<PathBuf> = vec![];
-        let pkgname = self.get_package_for(pth.to_str().unwrap().to_string());
+    /// Get installed version of the package
+    fn get_package_version(&self, pkname: String) -> Option<String> {
+        // Status database has versions of all installed packages, so no query is needed
+        if let Some((version, _)) = Self::get_installed(&pkname) {
+            return Some(version);
+        }
 
-        if let Ok(Some(pkgname)) = pkgname {
-            log::debug!("{} corresponds to {}", pth.to_str().unwrap(), pkgname);
+        let out = self.query(&["-W".to_string(), "-f=${Version}".to_string(), pkname]).ok()?.join("");
 
-            match self.get_package_contents(pkgname.to_owned()) {
-                Ok(fp) => {
-                    out.extend(fp);
-                }
-                Err(err) => {
-                    log::error!("Failed getting contents of {}: {}", pkgname, err);
-                }
-            }
+        if out.is_empty() {
+            None
+        } else {
+            Some(out)
+        }
+    }
 
-            if self.autodeps == Autodeps::Clean || self.autodeps == Autodeps::Free {
-                // Trace dependencies graph for the package
-                for p in tracedeb::DebPackageTrace::new()
-                    .exclude(self.excluded_packages.clone().into_iter().collect::<Vec<String>>())
-                    .trace(pkgname.to_owned())
-                {
-                    log::info!("Keeping dependency package: {}", p.bright_yellow());
-                    match self.get_package_contents(p.to_owned()) {
-                        Ok(fp) => {
-                            out.extend(fp);
-                        }
-                        Err(err) => {
-                            log::error!("Failed getting contents of {}: {}", p, err);
-                        }
-                    }
+    /// Get licenses from the machine-readable copyright file (DEP-5) of the package.
+    /// Other copyright files have no licenses, which can be reliably extracted.
+    fn get_package_licenses(&self, pkname: String) -> Vec<String> {
+        let data = prefix::read_to_string(PathBuf::from("/usr/share/doc").join(pkname).join("copyright")).unwrap_or_default();
+        if !data.starts_with("Format:") {
+            return vec![];
+        }
+
+        let mut out: Vec<String> = vec![];
+        for l in data.lines() {
+            if let Some(lic) = l.strip_prefix("License:").map(|l| l.trim().to_string()) {
+                if !lic.is_empty() && !out.contains(&lic) {
+                    out.push(lic);
                 }
             }
         }
@@ -133,8 +337,62 @@ impl Scanner for DebPackageScanner {
         out
     }
 
-    fn exclude(&mut self, pkgs: Vec<String>) -> &mut Self {
-        self.excluded_packages.extend(pkgs);
-        self
+    fn get_database(&self) -> Vec<String> {
+        [
+            "/var/lib/dpkg",
+            "/var/lib/apt",
+            "/var/cache/apt",
+            "/var/cache/debconf",
+            "/var/log/apt",
+            "/var/log/dpkg.log",
+            "/etc/apt",
+            "/etc/dpkg",
+            "/usr/lib/apt",
+            "/usr/lib/dpkg",
+            "/usr/share/dpkg",
+            "/usr/bin/apt*",
+            "/usr/bin/dpkg*",
+            "/usr/sbin/dpkg*",
+        ]
+        .iter()
+        .map(|p| p.to_string())
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{memoize, DebPackageScanner, CONTENTS, DPKG_STATUS, OWNERS};
+    use crate::{prefix, testutil::scratch};
+    use std::{fs, path::PathBuf, sync::PoisonError};
+
+    /// Create a root filesystem with one installed package of the given version
+    fn root(name: &str, version: &str) -> PathBuf {
+        let d = scratch(name);
+        let status = d.join(DPKG_STATUS.trim_start_matches('/'));
+        fs::create_dir_all(status.parent().unwrap()).unwrap();
+        fs::write(status, format!("Package: bash\nStatus: install ok installed\nArchitecture: amd64\nVersion: {}\n\n", version))
+            .unwrap();
+        d
+    }
+
+    #[test]
+    fn memos_follow_root() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let (a, b) = (root("root-a", "5.1"), root("root-b", "5.2"));
+
+        prefix::set(Some(&a));
+        assert_eq!(DebPackageScanner::get_installed("bash"), Some(("5.1".to_string(), "amd64".to_string())));
+        assert_eq!(memoize(&CONTENTS, "bash", || Ok(vec!["/bin/a".to_string()])).unwrap(), vec!["/bin/a".to_string()]);
+        assert_eq!(memoize(&OWNERS, "/bin/a", || Ok(Some("bash".to_string()))).unwrap(), Some("bash".to_string()));
+
+        prefix::set(Some(&b));
+        assert_eq!(DebPackageScanner::get_installed("bash:amd64"), Some(("5.2".to_string(), "amd64".to_string())));
+        assert_eq!(memoize(&CONTENTS, "bash", || Ok(vec!["/bin/b".to_string()])).unwrap(), vec!["/bin/b".to_string()]);
+        assert_eq!(memoize(&OWNERS, "/bin/a", || Ok(None)).unwrap(), None);
+
+        prefix::set(None);
+        fs::remove_dir_all(&a).unwrap();
+        fs::remove_dir_all(&b).unwrap();
     }
 }
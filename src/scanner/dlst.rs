@@ -3,29 +3,119 @@ Data lister (fancy STDOUT printer)
 */
 
 use crate::{
-    filters::resources,
-    scanner::{debftrace::DebPkgFileTrace, traceitf::PkgFileTrace},
+    filters::{defs, junk::JunkExtensions, resources},
+    prefix,
+    provenance::Provenance,
+    rootfs::RootFS,
+    scanner::{debftrace::DebPkgFileTrace, runtimes::Runtime, traceitf::PkgFileTrace},
 };
 use bytesize::ByteSize;
 use colored::Colorize;
 use filesize::PathExt;
+use serde::Serialize;
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
+    io::{Error, Write},
     os::unix::prelude::PermissionsExt,
     path::{Path, PathBuf},
 };
 
+/// Entry of a directory in the tree: a kept file or a subtree below the depth limit
+/// with its amount of files and their size
+enum Entry<'a> {
+    File(&'a PathBuf),
+    Subtree(String, u64, u64),
+}
+
 /// ContentFormatter is a lister for finally gathered information,
 /// that needs to be displayed on the screen for the user for review
 pub struct ContentFormatter<'a> {
     fs_data: &'a Vec<PathBuf>,
-    last_dir: String,
     fs_removed: Option<&'a Vec<PathBuf>>,
+    runtimes: Option<&'a Vec<Runtime>>,
+    hardening: Option<&'a Vec<String>>,
+    security: Option<&'a Vec<String>>,
+    junk: JunkExtensions,
+    by_package: bool,
+    top: usize,
+    depth: usize,
+    collapse: usize,
+    dirs_only: bool,
+    plain: bool,
+    sizes: bool,
+    provenance: Option<&'a Provenance>,
 }
 
 impl<'a> ContentFormatter<'a> {
     pub(crate) fn new(fs_data: &'a Vec<PathBuf>) -> Self {
-        Self { fs_data, last_dir: "".to_string(), fs_removed: None }
+        Self {
+            fs_data,
+            fs_removed: None,
+            runtimes: None,
+            hardening: None,
+            security: None,
+            junk: JunkExtensions::new(),
+            by_package: false,
+            top: 0,
+            depth: 0,
+            collapse: 0,
+            dirs_only: false,
+            plain: false,
+            sizes: false,
+            provenance: None,
+        }
+    }
+
+    /// Set amount of the largest kept files to show
+    pub(crate) fn set_top(&mut self, top: usize) -> &mut Self {
+        self.top = top;
+        self
+    }
+
+    /// Set depth of the tree. Deeper directories are summarised in their ancestor.
+    pub(crate) fn set_depth(&mut self, depth: usize) -> &mut Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Set amount of entries, above which a directory is collapsed into a summary line
+    pub(crate) fn set_collapse(&mut self, collapse: usize) -> &mut Self {
+        self.collapse = collapse;
+        self
+    }
+
+    /// Show only directories with their amount of files and size
+    pub(crate) fn set_dirs_only(&mut self, dirs_only: bool) -> &mut Self {
+        self.dirs_only = dirs_only;
+        self
+    }
+
+    /// Print only kept paths one per line, optionally with their size in bytes
+    pub(crate) fn set_plain(&mut self, plain: bool, sizes: bool) -> &mut Self {
+        self.plain = plain;
+        self.sizes = sizes;
+        self
+    }
+
+    /// Set provenance of the kept paths to annotate them with the rule, which produced them
+    pub(crate) fn set_provenance(&mut self, provenance: Option<&'a Provenance>) -> &mut Self {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Get annotation of the path with its provenance, if requested
+    fn annotation(&self, p: &Path) -> String {
+        match self.provenance.and_then(|prov| prov.get(p)) {
+            Some((r, o)) if o.is_empty() => format!("  ({})", r.as_str()).dimmed().to_string(),
+            Some((r, o)) => format!("  ({}: {})", r.as_str(), o).dimmed().to_string(),
+            None => "".to_string(),
+        }
+    }
+
+    /// Set size breakdown of the kept files by the owning package
+    pub(crate) fn set_by_package(&mut self, by_package: bool) -> &mut Self {
+        self.by_package = by_package;
+        self
     }
 
     /// Set removed data
@@ -34,108 +124,336 @@ impl<'a> ContentFormatter<'a> {
         self
     }
 
-    /// Perform only a dry-run
-    fn format_removed(&self) -> (u64, u64) {
-        let mut total_size: u64 = 0;
-        let mut total_files: u64 = 0;
+    /// Set detected runtimes
+    pub(crate) fn set_runtimes(&mut self, r: &'a Vec<Runtime>) -> &mut Self {
+        self.runtimes = Some(r);
+        self
+    }
+
+    /// Set security warnings about the kept files: setuid/setgid, world-writable, histories and credentials
+    pub(crate) fn set_security(&mut self, s: &'a Vec<String>) -> &mut Self {
+        self.security = Some(s);
+        self
+    }
+
+    /// Set hardening issues of the kept files
+    pub(crate) fn set_hardening(&mut self, h: &'a Vec<String>) -> &mut Self {
+        self.hardening = Some(h);
+        self
+    }
+
+    /// Set junk extensions, used to highlight potential junk
+    pub(crate) fn set_junk_extensions(&mut self, junk: &JunkExtensions) -> &mut Self {
+        self.junk = junk.to_owned();
+        self
+    }
+
+    /// Print detected runtimes with the suggested presets
+    fn format_runtimes(&self) {
+        if let Some(rts) = self.runtimes {
+            if rts.is_empty() {
+                return;
+            }
 
+            println!("Detected {} runtimes:", rts.len().to_string().bright_yellow());
+            for r in rts {
+                println!(
+                    "  {} {} {}",
+                    r.get_name().bright_green(),
+                    r.get_version().bright_yellow(),
+                    format!("({})", r.get_location().to_str().unwrap()).blue()
+                );
+                for p in r.get_presets() {
+                    println!("    {} {}", "suggested:".blue(), p);
+                }
+            }
+            println!();
+        }
+    }
+
+    /// Print security warnings about the kept files
+    fn format_security(&self) {
+        if let Some(s) = self.security {
+            if s.is_empty() {
+                return;
+            }
+
+            println!("{}", format!("Found {} security warnings:", s.len()).bright_red().bold());
+            for i in s {
+                println!("  {} {}", "⛔".bright_red().bold(), i.bright_red());
+            }
+            println!();
+        }
+    }
+
+    /// Print hardening issues of the kept files
+    fn format_hardening(&self) {
+        if let Some(h) = self.hardening {
+            if h.is_empty() {
+                return;
+            }
+
+            println!("Found {} hardening issues:", h.len().to_string().bright_red());
+            for i in h {
+                println!("  {} {}", "⚠️".bright_red().bold(), i);
+            }
+            println!();
+        }
+    }
+
+    /// Print retained size by the owning package, largest first
+    fn format_by_package(&self, owned: &[(Option<String>, u64)]) {
+        let mut pkgs: Vec<(String, u64, u64)> = vec![];
+        for (pkg, size) in owned {
+            let pkg = pkg.to_owned().unwrap_or("(no package)".to_string());
+            match pkgs.iter_mut().find(|(p, _, _)| *p == pkg) {
+                Some(e) => (e.1, e.2) = (e.1 + 1, e.2 + size),
+                None => pkgs.push((pkg, 1, *size)),
+            }
+        }
+        pkgs.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+
+        let total = pkgs.iter().map(|(_, _, s)| s).sum::<u64>().max(1);
+        println!("{}", format!("  {:<32} {:>8} {:>12} {:>7}", "Package", "Files", "Size", "Share").blue());
+        for (pkg, files, size) in pkgs {
+            println!(
+                "  {:<32} {:>8} {} {:>7}",
+                pkg.bright_green(),
+                files,
+                format!("{:>12}", ByteSize::b(size).to_string()).bright_yellow(),
+                format!("{:.1}%", size as f64 * 100.0 / total as f64)
+            );
+        }
+        println!();
+    }
+
+    /// Get a reason, why the kept file looks prunable, if it does
+    fn prunable(&self, p: &Path) -> Option<&'static str> {
+        let fname = p.file_name().unwrap_or_default().to_str().unwrap_or_default();
+        for (prefix, why) in [
+            ("/usr/share/doc", "documentation"),
+            ("/usr/share/man", "manpage"),
+            ("/usr/share/info", "documentation"),
+            ("/usr/share/locale", "localisation"),
+            ("/usr/lib/debug", "debug symbols"),
+            ("/usr/include", "header"),
+        ] {
+            if p.starts_with(prefix) {
+                return Some(why);
+            }
+        }
+
+        if fname.ends_with(".a") {
+            Some("static library")
+        } else if resources::ResourcesDataFilter::is_potential_junk(fname, &self.junk) {
+            Some("potential junk")
+        } else {
+            None
+        }
+    }
+
+    /// Print the largest kept files, flagging ones, those look prunable
+    fn format_top(&self) {
+        let mut files = self
+            .fs_data
+            .iter()
+            .filter_map(|p| prefix::symlink_metadata(p).ok().filter(|m| m.is_file()).map(|m| (p, m.len())))
+            .collect::<Vec<(&PathBuf, u64)>>();
+        files.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        files.truncate(self.top);
+
+        println!("Largest {} kept files:", files.len().to_string().bright_yellow());
+        for (i, (p, size)) in files.into_iter().enumerate() {
+            let mut line = format!(
+                "  {:>3}. {} {}",
+                i + 1,
+                format!("{:>10}", ByteSize::b(size).to_string()).bright_yellow(),
+                p.to_str().unwrap()
+            );
+            if let Some(why) = self.prunable(p) {
+                line = format!("{}  {} {}", line, "⚠️".bright_red().bold(), format!("prunable: {}", why).bright_red());
+            }
+            println!("{}", line);
+        }
+        println!();
+    }
+
+    /// Perform only a dry-run
+    fn format_removed(&self) {
         if let Some(fsr) = self.fs_removed {
             for p in fsr {
-                if p.exists() {
-                    total_size += p.size_on_disk_fast(&p.metadata().unwrap()).unwrap();
-                }
-                total_files += 1;
                 log::debug!("  - {}", p.to_str().unwrap());
             }
         }
-        (total_files, total_size)
+    }
+
+    /// Group kept files by their directories. Directories, deeper than the depth limit,
+    /// are summarised as subtrees of their ancestor at the limit.
+    fn group(&self) -> BTreeMap<String, Vec<Entry<'a>>> {
+        let mut groups: BTreeMap<String, Vec<Entry<'a>>> = BTreeMap::default();
+        for p in self.fs_data.iter() {
+            let parent = p.parent().unwrap();
+            if self.depth > 0 && parent.components().count() > self.depth + 1 {
+                let dir = parent.components().take(self.depth + 1).collect::<PathBuf>();
+                let sub = parent.components().nth(self.depth + 1).unwrap().as_os_str().to_str().unwrap().to_string();
+                let size = Self::size(p);
+                let entries = groups.entry(dir.to_str().unwrap().to_string()).or_default();
+                match entries.iter_mut().find(|e| matches!(e, Entry::Subtree(n, _, _) if *n == sub)) {
+                    Some(Entry::Subtree(_, files, s)) => {
+                        *files += 1;
+                        *s += size;
+                    }
+                    _ => entries.push(Entry::Subtree(sub, 1, size)),
+                }
+                continue;
+            }
+            groups.entry(parent.to_str().unwrap().to_string()).or_default().push(Entry::File(p));
+        }
+
+        groups
+    }
+
+    /// Get size of the kept file or of the target of the symlink.
+    /// Dangling symlinks are taken as they are, missing files have no size.
+    fn size(p: &Path) -> u64 {
+        prefix::metadata(p).or_else(|_| prefix::symlink_metadata(p)).map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// Returns true if the kept file is executable. Symlinks are shown by their target instead.
+    fn is_exec(p: &Path) -> bool {
+        !prefix::is_symlink(p) && prefix::metadata(p).map(|m| m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+    }
+
+    /// Print a kept file of the tree
+    fn format_file(&self, leaf: &str, p: &Path) {
+        let mut fname = self.dn(p).1;
+        let note = self.annotation(p);
+        if prefix::is_symlink(p) {
+            println!(
+                "{} {} {} {}{}",
+                leaf.blue(),
+                fname.bright_cyan().bold(),
+                "⮕".yellow().dimmed(),
+                prefix::read_link(p).unwrap().as_path().to_str().unwrap().cyan(),
+                note
+            );
+        } else if Self::is_exec(p) {
+            println!("{} {}{}", leaf.blue(), fname.bright_green().bold(), note);
+        } else {
+            if fname.ends_with(".so") || fname.contains(".so.") {
+                fname = fname.green().to_string();
+            } else if resources::ResourcesDataFilter::is_potential_junk(&fname, &self.junk) {
+                fname = format!("{}  {}", "⚠️".bright_red().bold(), fname.bright_red());
+            }
+
+            println!("{} {}{}", leaf.blue(), fname, note);
+        }
     }
 
     #[allow(clippy::println_empty_string)]
     pub(crate) fn format(&mut self) {
-        let d_len = self.fs_data.len() - 1;
         let mut t_size: u64 = 0;
         let mut j_size: u64 = 0; // size of junk
         let mut j_total: u64 = 0; // total junk files
-        let mut d_total: u64 = 0;
-        let mut d_size: u64 = 0;
-        let (t_r_files, t_r_size) = self.format_removed();
-
-        for (pi, p) in self.fs_data.iter().enumerate() {
-            let mut t_leaf: String = "".to_string();
-            let mut leaf = "  ├─";
+        self.format_removed();
 
-            t_size += p.metadata().unwrap().len();
-            let (dname, mut fname) = self.dn(p);
+        if self.plain {
+            for p in self.fs_data.iter() {
+                if self.sizes {
+                    println!("{}\t{}", prefix::symlink_metadata(p).map(|m| m.len()).unwrap_or(0), p.to_str().unwrap());
+                } else {
+                    println!("{}", p.to_str().unwrap());
+                }
+            }
+            return;
+        }
 
-            if self.last_dir != dname {
-                self.last_dir = dname.to_owned();
-                t_leaf = "".to_string();
-                println!("\n{}", self.last_dir.bright_blue().bold());
-                println!("{}", "──┬──┄┄╌╌ ╌  ╌".blue());
+        for p in self.fs_data.iter() {
+            let size = Self::size(p);
+            t_size += size;
+            if !prefix::is_symlink(p)
+                && !Self::is_exec(p)
+                && resources::ResourcesDataFilter::is_potential_junk(&self.dn(p).1, &self.junk)
+            {
+                j_total += 1;
+                j_size += size;
             }
+        }
+
+        if self.dirs_only {
+            println!();
+        }
+        for (dname, entries) in self.group() {
+            let (d_total, d_size) = entries.iter().fold((0, 0), |(f, s), e| match e {
+                Entry::File(p) => (f + 1, s + Self::size(p)),
+                Entry::Subtree(_, files, size) => (f + files, s + size),
+            });
 
-            if pi == d_len || (pi < d_len && dname != self.fs_data[pi + 1].parent().unwrap().to_str().unwrap()) {
-                leaf = "  ╰─";
-                t_leaf = format!(
-                    "\n{}{}{}{}",
-                    "Files: ".blue(),
+            if self.dirs_only {
+                println!(
+                    "{} {}{}{}",
+                    format!("{:<48}", dname).bright_blue().bold(),
                     d_total.to_string().bright_blue(),
-                    ", Size: ".blue(),
+                    " files, ".blue(),
                     ByteSize::b(d_size).to_string().bright_blue()
                 );
-                (d_total, d_size) = (0, 0);
+                continue;
             }
 
-            if p.is_symlink() {
-                println!(
-                    "{} {} {} {}{}",
-                    leaf.blue(),
-                    fname.bright_cyan().bold(),
-                    "⮕".yellow().dimmed(),
-                    p.read_link().unwrap().as_path().to_str().unwrap().cyan(),
-                    t_leaf
-                );
-            } else if p.metadata().unwrap().permissions().mode() & 0o111 != 0 {
-                println!("{} {}{}", leaf.blue(), fname.bright_green().bold(), t_leaf);
+            println!("\n{}", dname.bright_blue().bold());
+            println!("{}", "──┬──┄┄╌╌ ╌  ╌".blue());
+            if self.collapse > 0 && entries.len() > self.collapse {
+                println!("{} {}", "  ╰─".blue(), format!("… {} files, {}", d_total, ByteSize::b(d_size)).dimmed());
             } else {
-                if fname.ends_with(".so") || fname.contains(".so.") {
-                    fname = fname.green().to_string();
-                } else if resources::ResourcesDataFilter::is_potential_junk(&fname) {
-                    j_total += 1;
-                    j_size += p.metadata().unwrap().len();
-                    fname = format!("{}  {}", "⚠️".bright_red().bold(), fname.bright_red());
+                for (i, e) in entries.iter().enumerate() {
+                    let leaf = if i == entries.len() - 1 { "  ╰─" } else { "  ├─" };
+                    match e {
+                        Entry::File(p) => self.format_file(leaf, p),
+                        Entry::Subtree(name, files, size) => println!(
+                            "{} {} {}",
+                            leaf.blue(),
+                            format!("{}/", name).bright_blue(),
+                            format!("… {} files, {}", files, ByteSize::b(*size)).dimmed()
+                        ),
+                    }
                 }
-
-                println!("{} {}{}", leaf.blue(), fname, t_leaf);
             }
 
-            d_total += 1;
-            d_size += p.metadata().unwrap().len();
+            println!(
+                "{}{}{}{}",
+                "Files: ".blue(),
+                d_total.to_string().bright_blue(),
+                ", Size: ".blue(),
+                ByteSize::b(d_size).to_string().bright_blue()
+            );
         }
 
         // Collect preserved packages
-        let mut pkgs: HashSet<String> = HashSet::default();
         let mut pt = DebPkgFileTrace::new();
-        for p in self.fs_data {
-            if let Some(pkg) = pt.trace(p.clone()) {
-                pkgs.insert(pkg);
-            }
-        }
+        let owned = self
+            .fs_data
+            .iter()
+            .map(|p| {
+                (
+                    pt.trace(p.clone()),
+                    prefix::symlink_metadata(p)
+                        .ok()
+                        .and_then(|m| prefix::host_nofollow(p).size_on_disk_fast(&m).ok())
+                        .unwrap_or(0),
+                )
+            })
+            .collect::<Vec<(Option<String>, u64)>>();
+        let pkgs = owned.iter().filter_map(|(p, _)| p.to_owned()).collect::<HashSet<String>>();
         let mut pkgs = pkgs.into_iter().collect::<Vec<String>>();
         pkgs.sort();
 
         // Print the summary
         println!(
-            "\nRemoved {} files, releasing {} of a disk space",
-            t_r_files.to_string().bright_green(),
-            ByteSize::b(t_r_size).to_string().bright_yellow()
-        );
-        println!(
-            "Preserved {} files, taking {} of a disk space",
-            (d_len + 1).to_string().bright_green(),
+            "\nPreserved {} files, taking {} of a disk space",
+            self.fs_data.len().to_string().bright_green(),
             ByteSize::b(t_size).to_string().bright_yellow()
         );
+        Summary::new(self.fs_data, self.fs_removed.map(|r| r.as_slice()).unwrap_or_default()).format();
         if j_total > 0 {
             println!(
                 "Potentially {} junk files, taking {} of a disk space",
@@ -144,17 +462,395 @@ impl<'a> ContentFormatter<'a> {
             );
         }
         println!("Kept {} packages as follows:\n  {}\n", pkgs.len().to_string().bright_yellow(), pkgs.join(", "));
+        if self.by_package {
+            self.format_by_package(&owned);
+        }
+        if self.top > 0 {
+            self.format_top();
+        }
+        self.format_runtimes();
+        self.format_security();
+        self.format_hardening();
     }
 
     /// Get dir/name split, painted accordingly
-    fn dn(&mut self, p: &Path) -> (String, String) {
+    fn dn(&self, p: &Path) -> (String, String) {
         let dname = p.parent().unwrap().to_str().unwrap().to_string();
         let fname = p.file_name().unwrap().to_str().unwrap().to_string();
 
-        if p.is_dir() {
+        if prefix::is_dir(p) {
             return (format!("{}", dname.bright_blue().bold()), "".to_string());
         }
 
         (dname, fname)
     }
 }
+
+/// Summary of the rootfs before and after the tint: files and their size on disk,
+/// in total, per top-level directory and per category of the removed files.
+/// It has to be made before the changes are applied.
+pub struct Summary {
+    // Top-level directory, kept files and size, removed files and size
+    dirs: BTreeMap<String, (u64, u64, u64, u64)>,
+
+    // Category of the removed files, their amount and size
+    categories: BTreeMap<&'static str, (u64, u64)>,
+}
+
+impl Summary {
+    pub(crate) fn new(kept: &[PathBuf], removed: &[PathBuf]) -> Self {
+        let mut pt = DebPkgFileTrace::new();
+        let mut categories: BTreeMap<&'static str, (u64, u64)> = BTreeMap::default();
+        let mut dirs: BTreeMap<String, (u64, u64, u64, u64)> = BTreeMap::default();
+        let mut seen: HashSet<(u64, u64)> = HashSet::default();
+        for (paths, is_kept) in [(kept, true), (removed, false)] {
+            for p in paths {
+                let size = prefix::symlink_metadata(p)
+                    .ok()
+                    .filter(|m| RootFS::first_link(m, &mut seen))
+                    .and_then(|m| prefix::host_nofollow(p).size_on_disk_fast(&m).ok())
+                    .unwrap_or(0);
+                let top = match p.components().nth(1) {
+                    Some(c) if p.components().count() > 2 => format!("/{}", c.as_os_str().to_str().unwrap()),
+                    _ => "/".to_string(),
+                };
+                let e = dirs.entry(top).or_default();
+                if is_kept {
+                    (e.0, e.1) = (e.0 + 1, e.1 + size);
+                } else {
+                    (e.2, e.3) = (e.2 + 1, e.3 + size);
+                    let c = categories.entry(Self::category(p, &mut pt)).or_default();
+                    (c.0, c.1) = (c.0 + 1, c.1 + size);
+                }
+            }
+        }
+
+        Summary { dirs, categories }
+    }
+
+    /// Get category of the removed file, matching the filters
+    fn category(p: &Path, pt: &mut DebPkgFileTrace) -> &'static str {
+        let fname = p.file_name().and_then(|f| f.to_str()).unwrap_or_default().to_lowercase();
+        let ext = |exts: &[&str]| exts.iter().any(|e| fname.ends_with(e));
+        let under = |dirs: &[&str]| dirs.iter().any(|d| p.starts_with(d));
+
+        if prefix::is_symlink(p) && !prefix::exists(p) {
+            "Broken symlinks"
+        } else if under(defs::DOC_LOCATIONS)
+            || under(&[defs::D_MANPAGES, "/usr/share/info"])
+            || ext(defs::DOC_F_EXT)
+            || ext(defs::DOC_FP_EXT)
+        {
+            "Documentation"
+        } else if under(defs::D_L10N_DIRS) || under(&[defs::D_I18N]) {
+            "Localisation"
+        } else if ext(defs::SRC_FH_EXT) || under(&["/usr/include"]) {
+            "Headers"
+        } else if ext(defs::ARC_F_EXT) {
+            "Archives"
+        } else if ext(defs::IMG_F_EXT) {
+            "Images"
+        } else if pt.trace(p.to_path_buf()).is_some() {
+            "Unneeded package content"
+        } else {
+            "Other"
+        }
+    }
+
+    /// Get reclaimed share in percents
+    fn pct(removed: u64, total: u64) -> String {
+        if total == 0 {
+            return "0%".to_string();
+        }
+
+        format!("{:.1}%", removed as f64 * 100.0 / total as f64)
+    }
+
+    /// Format a row of files or sizes: before, after and reclaimed
+    fn row(title: &str, kept: u64, removed: u64, size: bool) -> String {
+        let v = |n: u64| if size { ByteSize::b(n).to_string() } else { n.to_string() };
+        format!(
+            "  {:<16} {:>12} {:>12} {} {}",
+            title,
+            v(kept + removed),
+            v(kept),
+            format!("{:>12}", v(removed)).bright_green(),
+            format!("{:>7}", Self::pct(removed, kept + removed)).bright_yellow()
+        )
+    }
+
+    pub(crate) fn format(&self) {
+        let (mut kf, mut ks, mut rf, mut rs) = (0, 0, 0, 0);
+        for (f, s, r_f, r_s) in self.dirs.values() {
+            (kf, ks, rf, rs) = (kf + f, ks + s, rf + r_f, rs + r_s);
+        }
+
+        println!("\n{}", format!("  {:<16} {:>12} {:>12} {:>12}", "", "Before", "After", "Reclaimed").blue());
+        println!("{}", Self::row("Files", kf, rf, false));
+        println!("{}", Self::row("Disk space", ks, rs, true));
+
+        println!("\n{}", format!("  {:<16} {:>12} {:>12} {:>12}", "Directory", "Before", "After", "Reclaimed").blue());
+        for (d, (_, s, _, r_s)) in &self.dirs {
+            println!("{}", Self::row(d, *s, *r_s, true));
+        }
+
+        if rf > 0 {
+            let mut categories = self.categories.iter().collect::<Vec<(&&str, &(u64, u64))>>();
+            categories.sort_by(|a, b| b.1 .1.cmp(&a.1 .1).then(a.0.cmp(b.0)));
+            println!("\n{}", format!("  {:<26} {:>8} {:>12} {:>7}", "Removed", "Files", "Reclaimed", "Share").blue());
+            for (c, (f, s)) in categories {
+                println!(
+                    "  {:<26} {:>8} {} {}",
+                    c,
+                    f,
+                    format!("{:>12}", ByteSize::b(*s).to_string()).bright_green(),
+                    format!("{:>7}", Self::pct(*s, rs)).bright_yellow()
+                );
+            }
+        }
+        println!();
+    }
+}
+
+/// RulesFormatter is a lister for the profile keep/prune rules,
+/// evaluated against the rootfs
+pub struct RulesFormatter<'a> {
+    keep: &'a Vec<(String, Vec<PathBuf>)>,
+    prune: &'a Vec<(String, Vec<PathBuf>)>,
+}
+
+impl<'a> RulesFormatter<'a> {
+    pub(crate) fn new(keep: &'a Vec<(String, Vec<PathBuf>)>, prune: &'a Vec<(String, Vec<PathBuf>)>) -> Self {
+        Self { keep, prune }
+    }
+
+    /// Print rules of one kind with their matches
+    fn format_rules(&self, title: &str, rules: &[(String, Vec<PathBuf>)]) -> (u64, u64) {
+        let mut total_files: u64 = 0;
+        let mut total_size: u64 = 0;
+
+        for (rule, paths) in rules {
+            let size = paths.iter().filter_map(|p| prefix::symlink_metadata(p).ok()).map(|m| m.len()).sum::<u64>();
+            println!("\n{} {}", title.bright_blue().bold(), rule.bright_yellow());
+            println!("{}", "──┬──┄┄╌╌ ╌  ╌".blue());
+
+            if paths.is_empty() {
+                println!("  ╰─ {}", "matches nothing".bright_red());
+                continue;
+            }
+
+            for (i, p) in paths.iter().enumerate() {
+                println!("  {} {}", if i == paths.len() - 1 { "╰─" } else { "├─" }.blue(), p.to_str().unwrap());
+            }
+            println!(
+                "{}{}{}{}",
+                "Files: ".blue(),
+                paths.len().to_string().bright_blue(),
+                ", Size: ".blue(),
+                ByteSize::b(size).to_string().bright_blue()
+            );
+
+            total_files += paths.len() as u64;
+            total_size += size;
+        }
+
+        (total_files, total_size)
+    }
+
+    pub(crate) fn format(&self) {
+        let (k_files, k_size) = self.format_rules("Keep", self.keep);
+        let (p_files, p_size) = self.format_rules("Prune", self.prune);
+
+        // Paths, those are kept, but then pruned anyway
+        let pruned = self.prune.iter().flat_map(|(_, p)| p.iter()).collect::<HashSet<&PathBuf>>();
+        let mut conflicts =
+            self.keep.iter().flat_map(|(_, p)| p.iter()).filter(|p| pruned.contains(p)).collect::<Vec<&PathBuf>>();
+        conflicts.sort();
+        conflicts.dedup();
+
+        if !conflicts.is_empty() {
+            println!("\n{}", "Conflicts (kept, but pruned)".bright_red().bold());
+            println!("{}", "──┬──┄┄╌╌ ╌  ╌".red());
+            for (i, p) in conflicts.iter().enumerate() {
+                println!("  {} {}", if i == conflicts.len() - 1 { "╰─" } else { "├─" }.red(), p.to_str().unwrap().bright_red());
+            }
+        }
+
+        let unmatched = self.keep.iter().chain(self.prune.iter()).filter(|(_, p)| p.is_empty()).count();
+
+        println!(
+            "\nKeep rules match {} files, taking {} of a disk space",
+            k_files.to_string().bright_green(),
+            ByteSize::b(k_size).to_string().bright_yellow()
+        );
+        println!(
+            "Prune rules match {} files, taking {} of a disk space",
+            p_files.to_string().bright_green(),
+            ByteSize::b(p_size).to_string().bright_yellow()
+        );
+        if unmatched > 0 {
+            println!("{} rules match nothing", unmatched.to_string().bright_red());
+        }
+        if !conflicts.is_empty() {
+            println!("{} files are kept and pruned at the same time", conflicts.len().to_string().bright_red());
+        }
+        println!();
+    }
+}
+
+/// Decision about a path in the NDJSON stream
+#[derive(Serialize)]
+struct Decision<'a> {
+    path: &'a Path,
+    action: &'a str,
+    size: u64,
+    package: Option<String>,
+    reason: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    origin: &'a str,
+}
+
+/// NdjsonFormatter prints every final decision as a separate JSON object per line,
+/// so other tools can process them one by one, e.g. with jq
+pub struct NdjsonFormatter {
+    pt: DebPkgFileTrace,
+}
+
+impl NdjsonFormatter {
+    pub(crate) fn new() -> Self {
+        Self { pt: DebPkgFileTrace::new() }
+    }
+
+    /// Print the decision about the path
+    pub(crate) fn emit(&mut self, p: &Path, action: &str, reason: &str, origin: &str) {
+        let d = Decision {
+            path: p,
+            action,
+            size: prefix::symlink_metadata(p).map(|m| m.len()).unwrap_or(0),
+            package: self.pt.trace(p.to_owned()),
+            reason,
+            origin,
+        };
+
+        match serde_json::to_string(&d).map(|l| l + "\n") {
+            Ok(line) => {
+                let mut out = std::io::stdout().lock();
+                if let Err(err) = out.write_all(line.as_bytes()).and_then(|_| out.flush()) {
+                    log::error!("Unable to write the decision: {}", err);
+                }
+            }
+            Err(err) => log::error!("Unable to serialise the decision about {}: {}", p.to_str().unwrap(), err),
+        }
+    }
+}
+
+/// File in the JSON report
+#[derive(Serialize)]
+struct ReportFile {
+    path: PathBuf,
+    size: u64,
+    package: Option<String>,
+}
+
+/// Dry-run result in the JSON report
+#[derive(Serialize)]
+struct Report<'a> {
+    kept: Vec<ReportFile>,
+    removed: Vec<ReportFile>,
+    kept_size: u64,
+    removed_size: u64,
+    packages: Vec<String>,
+    runtimes: &'a [Runtime],
+    hardening: &'a [String],
+    security: &'a [String],
+}
+
+/// JsonFormatter is a machine-readable counterpart of the ContentFormatter,
+/// e.g. for diffing results in CI
+pub struct JsonFormatter<'a> {
+    fs_data: &'a Vec<PathBuf>,
+    fs_removed: &'a Vec<PathBuf>,
+    runtimes: &'a Vec<Runtime>,
+    hardening: &'a Vec<String>,
+    security: &'a Vec<String>,
+}
+
+impl<'a> JsonFormatter<'a> {
+    pub(crate) fn new(
+        fs_data: &'a Vec<PathBuf>, fs_removed: &'a Vec<PathBuf>, runtimes: &'a Vec<Runtime>, hardening: &'a Vec<String>,
+        security: &'a Vec<String>,
+    ) -> Self {
+        Self { fs_data, fs_removed, runtimes, hardening, security }
+    }
+
+    /// Get files with their sizes and owning packages
+    fn files(paths: &[PathBuf], pt: &mut DebPkgFileTrace) -> Vec<ReportFile> {
+        paths
+            .iter()
+            .map(|p| ReportFile {
+                path: p.to_owned(),
+                size: prefix::symlink_metadata(p).map(|m| m.len()).unwrap_or(0),
+                package: pt.trace(p.to_owned()),
+            })
+            .collect()
+    }
+
+    pub(crate) fn format(&self) -> Result<(), Error> {
+        let mut pt = DebPkgFileTrace::new();
+        let kept = Self::files(self.fs_data, &mut pt);
+        let removed = Self::files(self.fs_removed, &mut pt);
+
+        let mut packages = kept.iter().filter_map(|f| f.package.to_owned()).collect::<Vec<String>>();
+        packages.sort();
+        packages.dedup();
+
+        let r = Report {
+            kept_size: kept.iter().map(|f| f.size).sum(),
+            removed_size: removed.iter().map(|f| f.size).sum(),
+            kept,
+            removed,
+            packages,
+            runtimes: self.runtimes,
+            hardening: self.hardening,
+            security: self.security,
+        };
+        println!("{}", serde_json::to_string_pretty(&r)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentFormatter;
+    use crate::{prefix, testutil::scratch};
+    use std::{
+        fs,
+        os::unix::fs::symlink,
+        path::{Path, PathBuf},
+        sync::PoisonError,
+    };
+
+    #[test]
+    fn formats_dangling_links() {
+        let _lock = prefix::LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        let d = scratch("dangling");
+        fs::create_dir_all(d.join("usr/bin")).unwrap();
+        fs::write(d.join("usr/bin/tool"), "#!/bin/sh\n").unwrap();
+        symlink("missing", d.join("usr/bin/gone")).unwrap();
+        prefix::set(Some(&d));
+
+        assert_eq!(ContentFormatter::size(Path::new("/usr/bin/tool")), 10);
+        assert_eq!(ContentFormatter::size(Path::new("/usr/bin/gone")), 7);
+        assert_eq!(ContentFormatter::size(Path::new("/usr/bin/none")), 0);
+        assert!(!ContentFormatter::is_exec(Path::new("/usr/bin/gone")));
+        assert!(!ContentFormatter::is_exec(Path::new("/usr/bin/none")));
+
+        let kept = vec![PathBuf::from("/usr/bin/tool"), PathBuf::from("/usr/bin/gone"), PathBuf::from("/usr/bin/none")];
+        ContentFormatter::new(&kept).format();
+        ContentFormatter::new(&kept).set_depth(1).set_top(3).format();
+
+        prefix::set(None);
+        fs::remove_dir_all(&d).unwrap();
+    }
+}
@@ -0,0 +1,385 @@
+/*
+Symbol versions of ELF binaries.
+
+Reads version needs (.gnu.version_r) and version definitions (.gnu.version_d),
+so binaries requiring newer symbol versions than the kept libraries provide
+(e.g. glibc after copying a binary between images) can be detected.
+Needed libraries (DT_NEEDED of .dynamic) are read as well, as direct dependencies,
+with the search paths and the interpreter to resolve them without ldd.
+ */
+
+use crate::prefix;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+const SHT_DYNAMIC: u32 = 6;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+const PT_INTERP: u32 = 3;
+const SHT_GNU_VERDEF: u32 = 0x6fff_fffd;
+const SHT_GNU_VERNEED: u32 = 0x6fff_fffe;
+const VER_FLG_BASE: u16 = 0x1;
+
+/// Limit of entries in a version section, in case it is broken
+const MAX_ENTRIES: usize = 4096;
+
+/// Library file name and versions of it
+type Needed = (String, Vec<String>);
+
+/// Section header, only the needed fields
+struct Section {
+    kind: u32,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+pub struct ElfVersions {
+    le: bool,
+    is64: bool,
+    machine: u16,
+    sections: Vec<Section>,
+    file: File,
+}
+
+impl ElfVersions {
+    /// Open ELF file. Returns None if this is not an ELF file.
+    pub fn open(p: &Path) -> Option<Self> {
+        let mut file = prefix::open(p).ok()?;
+        let mut ident = [0u8; 16];
+        file.read_exact(&mut ident).ok()?;
+        if &ident[..4] != b"\x7fELF" {
+            return None;
+        }
+
+        let mut elf = ElfVersions { le: ident[5] == 1, is64: ident[4] == 2, machine: 0, sections: vec![], file };
+        elf.machine = elf.read_at(0x12, 2).map(|b| elf.u16(&b, 0)).ok()?;
+        elf.sections = elf.read_sections(elf.is64).ok()?;
+
+        Some(elf)
+    }
+
+    fn u16(&self, b: &[u8], at: usize) -> u16 {
+        let v = [b[at], b[at + 1]];
+        if self.le {
+            u16::from_le_bytes(v)
+        } else {
+            u16::from_be_bytes(v)
+        }
+    }
+
+    fn u32(&self, b: &[u8], at: usize) -> u32 {
+        let v = [b[at], b[at + 1], b[at + 2], b[at + 3]];
+        if self.le {
+            u32::from_le_bytes(v)
+        } else {
+            u32::from_be_bytes(v)
+        }
+    }
+
+    fn u64(&self, b: &[u8], at: usize) -> u64 {
+        let mut v = [0u8; 8];
+        v.copy_from_slice(&b[at..at + 8]);
+        if self.le {
+            u64::from_le_bytes(v)
+        } else {
+            u64::from_be_bytes(v)
+        }
+    }
+
+    /// Read bytes from the file at the offset
+    fn read_at(&mut self, offset: u64, size: u64) -> Result<Vec<u8>, Error> {
+        if size > self.file.metadata()?.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "Section is larger than the file"));
+        }
+
+        let mut buf = vec![0u8; size as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut buf)?;
+
+        Ok(buf)
+    }
+
+    /// Read section headers
+    fn read_sections(&mut self, is64: bool) -> Result<Vec<Section>, Error> {
+        let hdr = self.read_at(0, if is64 { 64 } else { 52 })?;
+        let (shoff, shentsize, shnum) = if is64 {
+            (self.u64(&hdr, 0x28), self.u16(&hdr, 0x3a), self.u16(&hdr, 0x3c))
+        } else {
+            (self.u32(&hdr, 0x20) as u64, self.u16(&hdr, 0x2e), self.u16(&hdr, 0x30))
+        };
+
+        if (shentsize as usize) < if is64 { 64 } else { 40 } {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid size of the section header"));
+        }
+
+        let data = self.read_at(shoff, shentsize as u64 * shnum as u64)?;
+        let mut out: Vec<Section> = vec![];
+        for i in 0..shnum as usize {
+            let s = &data[i * shentsize as usize..];
+            out.push(if is64 {
+                Section { kind: self.u32(s, 4), offset: self.u64(s, 24), size: self.u64(s, 32), link: self.u32(s, 40) }
+            } else {
+                Section {
+                    kind: self.u32(s, 4),
+                    offset: self.u32(s, 16) as u64,
+                    size: self.u32(s, 20) as u64,
+                    link: self.u32(s, 24),
+                }
+            });
+        }
+
+        Ok(out)
+    }
+
+    /// Returns true if both files are of the same class and machine, so one can load another
+    pub fn is_compatible(&self, other: &ElfVersions) -> bool {
+        self.is64 == other.is64 && self.le == other.le && self.machine == other.machine
+    }
+
+    /// Get the program interpreter (dynamic linker), e.g. "/lib64/ld-linux-x86-64.so.2"
+    pub fn get_interpreter(&mut self) -> Option<String> {
+        let hdr = self.read_at(0, if self.is64 { 64 } else { 52 }).ok()?;
+        let (phoff, phentsize, phnum) = if self.is64 {
+            (self.u64(&hdr, 0x20), self.u16(&hdr, 0x36), self.u16(&hdr, 0x38))
+        } else {
+            (self.u32(&hdr, 0x1c) as u64, self.u16(&hdr, 0x2a), self.u16(&hdr, 0x2c))
+        };
+        if (phentsize as usize) < if self.is64 { 56 } else { 32 } {
+            return None;
+        }
+
+        let data = self.read_at(phoff, phentsize as u64 * phnum as u64).ok()?;
+        for i in 0..phnum as usize {
+            let ph = &data[i * phentsize as usize..];
+            if self.u32(ph, 0) != PT_INTERP {
+                continue;
+            }
+
+            let (offset, size) =
+                if self.is64 { (self.u64(ph, 8), self.u64(ph, 32)) } else { (self.u32(ph, 4) as u64, self.u32(ph, 16) as u64) };
+            return Some(Self::string(&self.read_at(offset, size).ok()?, 0));
+        }
+
+        None
+    }
+
+    /// Read section of the type with its linked string table
+    fn read_section(&mut self, kind: u32) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (offset, size, link) = self.sections.iter().find(|s| s.kind == kind).map(|s| (s.offset, s.size, s.link))?;
+        let (str_offset, str_size) = self.sections.get(link as usize).map(|s| (s.offset, s.size))?;
+
+        Some((self.read_at(offset, size).ok()?, self.read_at(str_offset, str_size).ok()?))
+    }
+
+    /// Get a string from the string table
+    fn string(strtab: &[u8], at: u32) -> String {
+        let s = strtab.get(at as usize..).unwrap_or_default();
+        String::from_utf8_lossy(&s[..s.iter().position(|c| *c == 0).unwrap_or(s.len())]).to_string()
+    }
+
+    /// Get needed versions as library file name and its version names, e.g. "libc.so.6" and "GLIBC_2.34"
+    pub fn get_needed(&mut self) -> Vec<Needed> {
+        let (data, strtab) = match self.read_section(SHT_GNU_VERNEED) {
+            Some(s) => s,
+            None => return vec![],
+        };
+
+        let mut out: Vec<Needed> = vec![];
+        let mut at: usize = 0;
+        for _ in 0..MAX_ENTRIES {
+            if at + 16 > data.len() {
+                break;
+            }
+
+            let (cnt, file, aux, next) =
+                (self.u16(&data, at + 2), self.u32(&data, at + 4), self.u32(&data, at + 8), self.u32(&data, at + 12));
+            let mut versions: Vec<String> = vec![];
+            let mut vat = at + aux as usize;
+            for _ in 0..cnt {
+                if vat + 16 > data.len() {
+                    break;
+                }
+                versions.push(Self::string(&strtab, self.u32(&data, vat + 8)));
+                vat += self.u32(&data, vat + 12) as usize;
+            }
+            out.push((Self::string(&strtab, file), versions));
+
+            if next == 0 {
+                break;
+            }
+            at += next as usize;
+        }
+
+        out
+    }
+
+    /// Get needed libraries (direct dependencies) as file names, e.g. "libc.so.6"
+    pub fn get_needed_libs(&mut self) -> Vec<String> {
+        self.get_dynamic(DT_NEEDED)
+    }
+
+    /// Get library search paths of the binary, RUNPATH or the older RPATH, unexpanded
+    pub fn get_runpath(&mut self) -> Vec<String> {
+        let mut rp = self.get_dynamic(DT_RUNPATH);
+        if rp.is_empty() {
+            rp = self.get_dynamic(DT_RPATH);
+        }
+
+        rp.iter().flat_map(|p| p.split(':')).filter(|p| !p.is_empty()).map(|p| p.to_string()).collect()
+    }
+
+    /// Get string values of the dynamic section entries with the tag
+    fn get_dynamic(&mut self, kind: u64) -> Vec<String> {
+        let (data, strtab) = match self.read_section(SHT_DYNAMIC) {
+            Some(s) => s,
+            None => return vec![],
+        };
+
+        let esize = if self.is64 { 16 } else { 8 };
+        let mut out: Vec<String> = vec![];
+        for at in (0..data.len() / esize).take(MAX_ENTRIES).map(|i| i * esize) {
+            let (tag, val) = if self.is64 {
+                (self.u64(&data, at), self.u64(&data, at + 8))
+            } else {
+                (self.u32(&data, at) as u64, self.u32(&data, at + 4) as u64)
+            };
+
+            if tag == DT_NULL {
+                break;
+            } else if tag == kind {
+                out.push(Self::string(&strtab, val as u32));
+            }
+        }
+
+        out
+    }
+
+    /// Get defined versions, e.g. "GLIBC_2.34"
+    pub fn get_defined(&mut self) -> Vec<String> {
+        let (data, strtab) = match self.read_section(SHT_GNU_VERDEF) {
+            Some(s) => s,
+            None => return vec![],
+        };
+
+        let mut out: Vec<String> = vec![];
+        let mut at: usize = 0;
+        for _ in 0..MAX_ENTRIES {
+            if at + 20 > data.len() {
+                break;
+            }
+
+            let (flags, aux, next) = (self.u16(&data, at + 2), self.u32(&data, at + 12), self.u32(&data, at + 16));
+            let vat = at + aux as usize;
+
+            // Base version is the name of the library itself
+            if flags & VER_FLG_BASE == 0 && vat + 8 <= data.len() {
+                out.push(Self::string(&strtab, self.u32(&data, vat)));
+            }
+
+            if next == 0 {
+                break;
+            }
+            at += next as usize;
+        }
+
+        out
+    }
+}
+
+/// Find kept binaries, those need symbol versions, which are not provided by the kept libraries.
+/// Returns binary, library file name and missing versions.
+pub fn get_incompatible(kept: &[PathBuf]) -> Vec<(PathBuf, String, Vec<String>)> {
+    // Libraries are matched by the file name, as it is in DT_NEEDED
+    let fname = |p: &Path| p.file_name().unwrap_or_default().to_str().unwrap_or_default().to_string();
+    let mut defined: HashMap<String, HashSet<String>> = HashMap::default();
+    let mut needed: Vec<(PathBuf, Vec<Needed>)> = vec![];
+    for p in kept.iter().filter(|p| !prefix::is_symlink(p) && prefix::is_file(p)) {
+        if let Some(mut e) = ElfVersions::open(p) {
+            let vd = e.get_defined();
+            if !vd.is_empty() {
+                defined.entry(fname(p)).or_default().extend(vd);
+            }
+            needed.push((p.to_owned(), e.get_needed()));
+        }
+    }
+
+    // Sonames are usually symlinks to the actual library
+    for p in kept.iter().filter(|p| prefix::is_symlink(p)) {
+        if let Some(vd) = prefix::canonicalize(p).ok().and_then(|t| defined.get(&fname(&t)).cloned()) {
+            defined.entry(fname(p)).or_default().extend(vd);
+        }
+    }
+
+    let mut out: Vec<(PathBuf, String, Vec<String>)> = vec![];
+    for (p, libs) in needed {
+        for (lib, versions) in libs {
+            if let Some(vd) = defined.get(&lib) {
+                let missing = versions.into_iter().filter(|v| !vd.contains(v)).collect::<Vec<String>>();
+                if !missing.is_empty() {
+                    out.push((p.to_owned(), lib, missing));
+                }
+            }
+        }
+    }
+    out.sort();
+
+    out
+}
+
+/// Find kept binaries, those need libraries, which are not kept.
+/// Returns binary and the library file name.
+pub fn get_unresolved(kept: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    // Libraries are matched by the file name, as it is in DT_NEEDED
+    let names = kept.iter().filter_map(|p| p.file_name().and_then(|n| n.to_str())).collect::<HashSet<&str>>();
+    let mut out: Vec<(PathBuf, String)> = vec![];
+    for p in kept.iter().filter(|p| !prefix::is_symlink(p) && prefix::is_file(p)) {
+        if let Some(mut e) = ElfVersions::open(p) {
+            for lib in e.get_needed_libs() {
+                if !names.contains(lib.as_str()) {
+                    out.push((p.to_owned(), lib));
+                }
+            }
+        }
+    }
+    out.sort();
+
+    out
+}
+
+/// Find kept binaries, those the removal breaks: their interpreter or needed libraries
+/// are removed and no file of the same name is kept. Dependencies, which were missing
+/// before, are not reported. Returns binary and the names of its lost dependencies.
+pub fn get_broken(kept: &[PathBuf], removed: &[PathBuf]) -> Vec<(PathBuf, Vec<String>)> {
+    let name = |p: &Path| p.file_name().and_then(|n| n.to_str()).map(|n| n.to_string());
+    let names = kept.iter().filter_map(|p| name(p)).collect::<HashSet<String>>();
+    let lost = removed.iter().filter_map(|p| name(p)).filter(|n| !names.contains(n)).collect::<HashSet<String>>();
+    if lost.is_empty() {
+        return vec![];
+    }
+
+    let mut out: Vec<(PathBuf, Vec<String>)> = vec![];
+    for p in kept.iter().filter(|p| !prefix::is_symlink(p) && prefix::is_file(p)) {
+        if let Some(mut e) = ElfVersions::open(p) {
+            let mut deps = e.get_interpreter().into_iter().collect::<Vec<String>>();
+            deps.extend(e.get_needed_libs());
+
+            let broken = deps
+                .into_iter()
+                .filter(|d| name(Path::new(d)).map(|n| lost.contains(&n)).unwrap_or(false))
+                .collect::<Vec<String>>();
+            if !broken.is_empty() {
+                out.push((p.to_owned(), broken));
+            }
+        }
+    }
+    out.sort();
+
+    out
+}
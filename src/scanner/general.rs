@@ -1,21 +1,33 @@
-use std::{fs, io::Error, path::PathBuf, process::Command};
+use crate::{prefix, scanner::ldso::LibResolver};
+use std::{
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 pub(crate) trait Scanner {
     /// Scan path
     fn scan(&mut self, pth: PathBuf) -> Vec<PathBuf>;
 
     /// Add packages to be excluded from the scan
+    #[allow(dead_code)]
     fn exclude(&mut self, pkgs: Vec<String>) -> &mut Self;
 }
 
 pub struct ScannerCommons {
     elfrd_paths: Vec<String>,
     elfrd_p: String,
+    resolver: Option<LibResolver>,
 }
 
 impl ScannerCommons {
     pub fn new() -> Self {
-        ScannerCommons { elfrd_paths: vec!["/usr/bin/ldd".to_string(), "/usr/bin/readelf".to_string()], elfrd_p: "".to_string() }
+        ScannerCommons {
+            elfrd_paths: vec!["/usr/bin/ldd".to_string(), "/usr/bin/readelf".to_string()],
+            elfrd_p: "".to_string(),
+            resolver: None,
+        }
     }
 
     pub fn call_any(&self, cmd: PathBuf, args: &[String]) -> Result<Vec<String>, Error> {
@@ -37,6 +49,12 @@ impl ScannerCommons {
     /// NOTE: Future versions of mezzotint may have own readelf
     /// implemented for a better portability.
     pub fn call_libfind(&mut self, target: String) -> Result<Vec<String>, Error> {
+        // Tools of the image cannot run without chroot
+        if prefix::is_set() {
+            let (found, _) = self.resolver.get_or_insert_with(LibResolver::new).resolve(Path::new(&target));
+            return Ok(found.into_iter().map(|p| p.to_str().unwrap().to_string()).collect());
+        }
+
         // Set elfreader
         if self.elfrd_p.is_empty() {
             for p in &self.elfrd_paths {
@@ -56,7 +74,8 @@ impl ScannerCommons {
         if self.elfrd_p.ends_with("/ldd") {
             let out = Command::new(&self.elfrd_p).arg(target).output()?;
             for l in String::from_utf8(out.stdout).unwrap_or_default().lines() {
-                if !l.contains('/') {
+                // Libraries are indented, other lines are errors, like missing symbol versions
+                if !l.starts_with(char::is_whitespace) || !l.contains('/') {
                     continue;
                 }
 
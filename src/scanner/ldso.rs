@@ -0,0 +1,162 @@
+/*
+Resolver of the needed libraries, as the dynamic linker does it.
+
+ldd runs the dynamic linker of the image, so it works only inside the
+image. Without chroot the libraries are resolved from the ELF headers
+instead: RPATH or RUNPATH of the binary, directories of ld.so.conf and
+the default directories with their multiarch subdirectories, where only
+libraries of the same class and machine as of the binary are taken.
+ */
+
+use crate::{glob, prefix, scanner::elfver::ElfVersions};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Configuration of the library search directories
+const LD_SO_CONF: &str = "/etc/ld.so.conf";
+
+/// Directories, those the dynamic linker always searches
+const DEFAULT_DIRS: &[&str] = &["/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+
+/// Limit of nested includes, in case they are circular
+const MAX_INCLUDES: usize = 8;
+
+pub struct LibResolver {
+    dirs: Vec<PathBuf>,
+}
+
+impl LibResolver {
+    pub fn new() -> Self {
+        let mut dirs: Vec<PathBuf> = vec![];
+        Self::read_conf(Path::new(LD_SO_CONF), &mut dirs, 0);
+
+        // Multiarch directories, like /usr/lib/x86_64-linux-gnu, are built into the dynamic linker
+        for d in DEFAULT_DIRS {
+            if let Ok(rd) = prefix::read_dir(d) {
+                let mut ma = rd
+                    .filter_map(|e| e.ok())
+                    .map(|e| Path::new(d).join(e.file_name()))
+                    .filter(|p| p.file_name().unwrap_or_default().to_str().unwrap_or_default().contains("-linux-"))
+                    .filter(|p| prefix::is_dir(p))
+                    .collect::<Vec<PathBuf>>();
+                ma.sort();
+                dirs.extend(ma);
+            }
+        }
+        dirs.extend(DEFAULT_DIRS.iter().map(PathBuf::from));
+
+        let mut seen: HashSet<PathBuf> = HashSet::default();
+        dirs.retain(|d| seen.insert(d.to_owned()));
+
+        LibResolver { dirs }
+    }
+
+    /// Read directories of the ld.so.conf, following its includes
+    fn read_conf(p: &Path, dirs: &mut Vec<PathBuf>, depth: usize) {
+        if depth > MAX_INCLUDES {
+            return;
+        }
+
+        let data = match prefix::read_to_string(p) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+
+        for l in data.lines().map(|l| l.split('#').next().unwrap_or_default().trim()).filter(|l| !l.is_empty()) {
+            if let Some(inc) = l.strip_prefix("include").filter(|i| i.starts_with(char::is_whitespace)) {
+                for ptn in inc.split_whitespace() {
+                    // Relative includes are relative to the including file
+                    let ptn = p.parent().unwrap_or(Path::new("/")).join(ptn);
+                    let mut confs = match ptn.parent().map(prefix::read_dir) {
+                        Some(Ok(rd)) => rd
+                            .filter_map(|e| e.ok())
+                            .map(|e| ptn.parent().unwrap().join(e.file_name()))
+                            .filter(|c| glob::matches(ptn.to_str().unwrap_or_default(), c))
+                            .collect::<Vec<PathBuf>>(),
+                        _ => continue,
+                    };
+                    confs.sort();
+
+                    for c in confs {
+                        Self::read_conf(&c, dirs, depth + 1);
+                    }
+                }
+            } else if !l.starts_with("hwcap") {
+                dirs.push(PathBuf::from(l));
+            }
+        }
+    }
+
+    /// Expand dynamic string tokens of the search path
+    fn expand(dir: &str, origin: &Path) -> Option<PathBuf> {
+        let origin = origin.to_str().unwrap_or_default();
+        let dir = dir.replace("${ORIGIN}", origin).replace("$ORIGIN", origin);
+
+        // Platform dependent tokens cannot be known without the dynamic linker
+        if dir.contains('$') {
+            return None;
+        }
+
+        Some(PathBuf::from(dir))
+    }
+
+    /// Find the library in the search directories
+    fn find(&self, elf: &ElfVersions, lib: &str, runpath: &[PathBuf]) -> Option<PathBuf> {
+        if lib.contains('/') {
+            return Some(PathBuf::from(lib)).filter(|p| prefix::is_file(p));
+        }
+
+        runpath
+            .iter()
+            .chain(self.dirs.iter())
+            .map(|d| d.join(lib))
+            .find(|p| prefix::is_file(p) && ElfVersions::open(p).map(|l| l.is_compatible(elf)).unwrap_or(false))
+    }
+
+    /// Resolve direct dependencies of the binary, including its interpreter.
+    /// Returns paths of the found libraries and names of the missing ones.
+    pub fn resolve(&self, target: &Path) -> (Vec<PathBuf>, Vec<String>) {
+        let mut elf = match ElfVersions::open(target) {
+            Some(elf) => elf,
+            None => return (vec![], vec![]),
+        };
+
+        let origin = prefix::canonicalize(target)
+            .ok()
+            .and_then(|p| p.parent().map(|p| p.to_path_buf()))
+            .unwrap_or_else(|| PathBuf::from("/"));
+        let runpath = elf.get_runpath().iter().filter_map(|d| Self::expand(d, &origin)).collect::<Vec<PathBuf>>();
+
+        let mut found: Vec<PathBuf> =
+            elf.get_interpreter().map(PathBuf::from).filter(|p| prefix::exists(p)).into_iter().collect();
+        let mut missing: Vec<String> = vec![];
+        for lib in elf.get_needed_libs() {
+            match self.find(&elf, &lib, &runpath) {
+                Some(p) => found.push(p),
+                None => missing.push(lib),
+            }
+        }
+
+        (found, missing)
+    }
+
+    /// Find all libraries of the binary, those cannot be resolved, including the indirect ones
+    pub fn unresolved(&self, target: &Path) -> Vec<String> {
+        let mut seen: HashSet<PathBuf> = HashSet::default();
+        let mut queue: Vec<PathBuf> = vec![target.to_owned()];
+        let mut out: Vec<String> = vec![];
+        while let Some(p) = queue.pop() {
+            if !seen.insert(p.to_owned()) {
+                continue;
+            }
+
+            let (found, missing) = self.resolve(&p);
+            out.extend(missing.into_iter().filter(|m| !out.contains(m)).collect::<Vec<String>>());
+            queue.extend(found);
+        }
+
+        out
+    }
+}
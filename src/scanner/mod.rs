@@ -2,6 +2,10 @@ pub mod binlib;
 pub mod debftrace;
 pub mod debpkg;
 pub(crate) mod dlst;
+pub mod elfver;
 pub mod general;
+pub mod ldso;
+pub mod pkgbackend;
+pub mod runtimes;
 pub mod tracedeb;
 pub mod traceitf;
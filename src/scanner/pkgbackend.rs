@@ -0,0 +1,181 @@
+use crate::{
+    prefix,
+    procdata::Autodeps,
+    scanner::{debpkg::DebPackageScanner, general::Scanner},
+};
+use colored::Colorize;
+use std::{collections::HashSet, io::Error, path::PathBuf};
+
+/// Capabilities of a package backend
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Capabilities {
+    /// Can trace dependencies of a package
+    pub autodeps: bool,
+
+    /// Can find a package, owning a file
+    pub owners: bool,
+
+    /// Can resolve virtual packages to the real ones
+    pub virtuals: bool,
+}
+
+impl Capabilities {
+    /// Get list of the features, those are not available
+    pub fn get_unavailable(&self) -> Vec<&'static str> {
+        let mut out: Vec<&'static str> = vec![];
+        if !self.autodeps {
+            out.push("automatic dependencies");
+        }
+        if !self.owners {
+            out.push("file owners");
+        }
+        if !self.virtuals {
+            out.push("virtual packages");
+        }
+
+        out
+    }
+}
+
+/// Package manager backend
+pub trait PackageBackend {
+    /// Name of the backend
+    fn name(&self) -> &str;
+
+    /// Capabilities of the backend on the current system
+    fn capabilities(&self) -> Capabilities;
+
+    /// Get package name of the target file.
+    /// May still not find a package for the target.
+    fn get_package_for(&self, target: String) -> Result<Option<String>, Error>;
+
+    /// Find packages of many target files at once, so they are not queried one by one
+    fn find_owners(&self, targets: &[String]) -> Result<(), Error>;
+
+    /// Get contents of the package
+    fn get_package_contents(&self, pkname: String) -> Result<Vec<PathBuf>, Error>;
+
+    /// Get all dependencies of the package, except excluded ones
+    fn get_dependencies(&self, pkname: String, exclude: Vec<String>) -> Vec<String>;
+
+    /// Get installed version of the package
+    fn get_package_version(&self, pkname: String) -> Option<String>;
+
+    /// Get licenses of the package, as they are declared by the package
+    fn get_package_licenses(&self, pkname: String) -> Vec<String>;
+
+    /// Get patterns of the package database and the package manager itself
+    fn get_database(&self) -> Vec<String>;
+}
+
+/// Backend for systems without any supported package manager
+pub struct NullPackageBackend;
+
+impl PackageBackend for NullPackageBackend {
+    fn name(&self) -> &str {
+        "none"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { autodeps: false, owners: false, virtuals: false }
+    }
+
+    fn get_package_for(&self, _: String) -> Result<Option<String>, Error> {
+        Ok(None)
+    }
+
+    fn find_owners(&self, _: &[String]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_package_contents(&self, _: String) -> Result<Vec<PathBuf>, Error> {
+        Ok(vec![])
+    }
+
+    fn get_dependencies(&self, _: String, _: Vec<String>) -> Vec<String> {
+        vec![]
+    }
+
+    fn get_package_version(&self, _: String) -> Option<String> {
+        None
+    }
+
+    fn get_package_licenses(&self, _: String) -> Vec<String> {
+        vec![]
+    }
+
+    fn get_database(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+/// Detect package backend of the current root
+pub fn detect() -> Box<dyn PackageBackend> {
+    if prefix::exists("/usr/bin/dpkg") {
+        return Box::new(DebPackageScanner::new());
+    }
+
+    Box::new(NullPackageBackend)
+}
+
+/// Scans content of the package, to which a target belongs to,
+/// using whatever package backend is available.
+pub struct PackageScanner {
+    backend: Box<dyn PackageBackend>,
+    autodeps: Autodeps,
+    excluded_packages: HashSet<String>,
+}
+
+impl PackageScanner {
+    /// Constructor
+    pub fn new(autodeps: Autodeps) -> Self {
+        PackageScanner { backend: detect(), autodeps, excluded_packages: HashSet::default() }
+    }
+}
+
+impl Scanner for PackageScanner {
+    fn scan(&mut self, pth: PathBuf) -> Vec<PathBuf> {
+        log::debug!("Scanning package contents for {:?}", pth.to_str());
+
+        let mut out: Vec<PathBuf> = vec![];
+        let pkgname = self.backend.get_package_for(pth.to_str().unwrap().to_string());
+
+        if let Ok(Some(pkgname)) = pkgname {
+            log::debug!("{} corresponds to {}", pth.to_str().unwrap(), pkgname);
+
+            match self.backend.get_package_contents(pkgname.to_owned()) {
+                Ok(fp) => {
+                    out.extend(fp);
+                }
+                Err(err) => {
+                    log::error!("Failed getting contents of {}: {}", pkgname, err);
+                }
+            }
+
+            if self.backend.capabilities().autodeps && (self.autodeps == Autodeps::Clean || self.autodeps == Autodeps::Free) {
+                // Trace dependencies graph for the package
+                for p in self
+                    .backend
+                    .get_dependencies(pkgname.to_owned(), self.excluded_packages.clone().into_iter().collect::<Vec<String>>())
+                {
+                    log::info!("Keeping dependency package: {}", p.bright_yellow());
+                    match self.backend.get_package_contents(p.to_owned()) {
+                        Ok(fp) => {
+                            out.extend(fp);
+                        }
+                        Err(err) => {
+                            log::error!("Failed getting contents of {}: {}", p, err);
+                        }
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    fn exclude(&mut self, pkgs: Vec<String>) -> &mut Self {
+        self.excluded_packages.extend(pkgs);
+        self
+    }
+}
@@ -0,0 +1,238 @@
+/*
+Heuristic detection of language runtimes and C libraries in the rootfs
+ */
+
+use crate::prefix;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Directories with the binaries
+const BIN_DIRS: &[&str] = &["/usr/bin", "/usr/local/bin", "/bin"];
+
+/// Directories with the libraries
+const LIB_DIRS: &[&str] = &["/lib", "/lib64", "/usr/lib", "/usr/lib64", "/usr/local/lib"];
+
+/// Detected runtime
+#[derive(Debug, Clone, Serialize)]
+pub struct Runtime {
+    name: String,
+    version: String,
+    location: PathBuf,
+    presets: Vec<String>,
+}
+
+impl Runtime {
+    fn new(name: &str, version: &str, location: PathBuf, presets: &[String]) -> Self {
+        Runtime { name: name.to_string(), version: version.to_string(), location, presets: presets.to_vec() }
+    }
+
+    /// Get runtime name
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get runtime version. It is "unknown" if it cannot be detected.
+    pub fn get_version(&self) -> &str {
+        &self.version
+    }
+
+    /// Get runtime location
+    pub fn get_location(&self) -> &Path {
+        &self.location
+    }
+
+    /// Get suggested profile presets for this runtime
+    pub fn get_presets(&self) -> &Vec<String> {
+        &self.presets
+    }
+}
+
+/// Runtimes scanner, inventories what is installed
+pub struct RuntimeScanner {
+    runtimes: Vec<Runtime>,
+}
+
+impl RuntimeScanner {
+    pub fn new() -> Self {
+        RuntimeScanner { runtimes: vec![] }
+    }
+
+    /// List directory entries with their file names
+    fn ls(dir: &str) -> Vec<(String, PathBuf)> {
+        let mut out: Vec<(String, PathBuf)> = vec![];
+        if let Ok(rd) = prefix::read_dir(dir) {
+            for e in rd.filter_map(|e| e.ok()) {
+                out.push((e.file_name().to_str().unwrap_or_default().to_string(), Path::new(dir).join(e.file_name())));
+            }
+        }
+        out.sort();
+        out
+    }
+
+    /// Find a version string after a given marker in a binary file.
+    /// Version is anything consisting of digits and dots.
+    fn version_after(p: &Path, marker: &str) -> Option<String> {
+        let data = prefix::read(p).ok()?;
+        let marker = marker.as_bytes();
+        let pos = data.windows(marker.len()).position(|w| w == marker)? + marker.len();
+        let v = data[pos..].iter().take_while(|c| c.is_ascii_digit() || **c == b'.').map(|c| *c as char).collect::<String>();
+        let v = v.trim_end_matches('.').to_string();
+        if v.is_empty() {
+            None
+        } else {
+            Some(v)
+        }
+    }
+
+    /// Detect C library: glibc or musl
+    fn detect_libc(&mut self) {
+        for d in LIB_DIRS {
+            let mut dirs = vec![PathBuf::from(d)];
+            dirs.extend(Self::ls(d).into_iter().filter(|(n, p)| n.ends_with("-linux-gnu") && prefix::is_dir(p)).map(|(_, p)| p));
+
+            for d in dirs {
+                let libc = d.join("libc.so.6");
+                if prefix::exists(&libc) {
+                    let v = Self::version_after(&libc, "release version ").unwrap_or_else(|| "unknown".to_string());
+                    self.runtimes.push(Runtime::new("glibc", &v, libc, &[]));
+                    return;
+                }
+            }
+
+            for (n, p) in Self::ls(d) {
+                if n.starts_with("ld-musl-") {
+                    self.runtimes.push(Runtime::new("musl", "unknown", p, &[]));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Detect Python interpreters
+    fn detect_python(&mut self) {
+        for d in BIN_DIRS {
+            for (n, p) in Self::ls(d) {
+                if let Some(v) = n.strip_prefix("python") {
+                    if v.contains('.') && v.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                        self.runtimes.push(Runtime::new(
+                            "python",
+                            v,
+                            p.to_owned(),
+                            &[
+                                format!("targets: [{}]", p.to_str().unwrap()),
+                                format!("packages: [python{}]", v),
+                                "prune: [\"**/__pycache__/**\", \"**/test/**\", \"**/tests/**\"]".to_string(),
+                            ],
+                        ));
+                    }
+                }
+            }
+            if self.runtimes.iter().any(|r| r.name == "python") {
+                return;
+            }
+        }
+    }
+
+    /// Detect Node.js
+    fn detect_node(&mut self) {
+        for d in BIN_DIRS {
+            for n in ["node", "nodejs"] {
+                let p = PathBuf::from(d).join(n);
+                if !prefix::exists(&p) {
+                    continue;
+                }
+
+                let mut v = String::from("unknown");
+                if let Ok(hdr) = prefix::read_to_string("/usr/include/node/node_version.h") {
+                    let mut parts: Vec<String> = vec![];
+                    for k in ["NODE_MAJOR_VERSION", "NODE_MINOR_VERSION", "NODE_PATCH_VERSION"] {
+                        if let Some(l) = hdr.lines().find(|l| l.starts_with(&format!("#define {} ", k))) {
+                            parts.push(l.split_whitespace().last().unwrap_or_default().to_string());
+                        }
+                    }
+                    if parts.len() == 3 {
+                        v = parts.join(".");
+                    }
+                } else if let Some(pv) = Self::version_after(&p, "node/v") {
+                    v = pv;
+                }
+
+                self.runtimes.push(Runtime::new(
+                    "node",
+                    &v,
+                    p.to_owned(),
+                    &[
+                        format!("targets: [{}]", p.to_str().unwrap()),
+                        "prune: [\"**/node_modules/**/*.md\", \"**/node_modules/**/test/**\"]".to_string(),
+                    ],
+                ));
+                return;
+            }
+        }
+    }
+
+    /// Detect Java runtimes
+    fn detect_java(&mut self) {
+        for (n, p) in Self::ls("/usr/lib/jvm") {
+            if prefix::is_symlink(&p) || !prefix::is_dir(&p) {
+                continue;
+            }
+
+            let mut v = String::from("unknown");
+            if let Ok(rel) = prefix::read_to_string(p.join("release")) {
+                if let Some(l) = rel.lines().find(|l| l.starts_with("JAVA_VERSION=")) {
+                    v = l.split_once('=').unwrap().1.trim_matches('"').to_string();
+                }
+            } else if let Some(pv) = n.strip_prefix("java-") {
+                v = pv.split('-').next().unwrap_or_default().to_string();
+            }
+
+            let java = p.join("bin/java");
+            self.runtimes.push(Runtime::new(
+                "java",
+                &v,
+                p.to_owned(),
+                &[format!("targets: [{}]", java.to_str().unwrap()), format!("keep: [\"{}/lib/**\"]", p.to_str().unwrap())],
+            ));
+        }
+    }
+
+    /// Detect Ruby interpreters
+    fn detect_ruby(&mut self) {
+        for (n, p) in Self::ls("/usr/lib/ruby") {
+            if prefix::is_dir(&p) && n.chars().next().unwrap_or_default().is_ascii_digit() {
+                let mut tgt = PathBuf::from("/usr/bin/ruby");
+                for d in BIN_DIRS {
+                    for rn in [format!("ruby{}", n.rsplit_once('.').map(|v| v.0).unwrap_or(&n)), "ruby".to_string()] {
+                        if prefix::exists(PathBuf::from(d).join(&rn)) {
+                            tgt = PathBuf::from(d).join(rn);
+                        }
+                    }
+                }
+
+                self.runtimes.push(Runtime::new(
+                    "ruby",
+                    &n,
+                    p.to_owned(),
+                    &[format!("targets: [{}]", tgt.to_str().unwrap()), "prune: [\"**/gems/**/test/**\"]".to_string()],
+                ));
+            }
+        }
+    }
+
+    /// Inventory runtimes, present in the current root
+    pub fn scan(&mut self) -> &Vec<Runtime> {
+        self.runtimes.clear();
+        self.detect_libc();
+        self.detect_python();
+        self.detect_node();
+        self.detect_java();
+        self.detect_ruby();
+
+        for r in &self.runtimes {
+            log::debug!("Detected runtime {} {} at {}", r.name, r.version, r.location.to_str().unwrap());
+        }
+
+        &self.runtimes
+    }
+}
@@ -1,14 +1,14 @@
 use super::traceitf::PkgDepTrace;
+use crate::prefix;
 use std::{collections::HashSet, process::Command};
 
 pub struct DebPackageTrace {
     data: HashSet<String>,
-    exclude: HashSet<String>,
 }
 
 impl DebPackageTrace {
     pub fn new() -> Self {
-        DebPackageTrace { data: HashSet::default(), exclude: HashSet::default() }
+        DebPackageTrace { data: HashSet::default() }
     }
 
     /// Get list of package dependencies for the first nearby level
@@ -22,7 +22,11 @@ impl DebPackageTrace {
             self.data.clear();
         }
 
-        let mut c = Command::new("apt");
+        // Without chroot apt of the host reads the package lists of the image
+        let mut c = Command::new(if prefix::is_set() { "apt-cache" } else { "apt" });
+        if let Some(root) = prefix::get() {
+            c.args(["-o", &format!("Dir={}", root.to_str().unwrap())]);
+        }
         c.args(["depends", pkg.as_str()]);
 
         match c.output() {
@@ -58,14 +62,6 @@ impl PkgDepTrace for DebPackageTrace {
     fn trace(&mut self, pkgname: String) -> Vec<String> {
         log::info!("Getting dependencies for a package {}", pkgname);
 
-        let mut d = self.get_dependencies(pkgname, true);
-        d.retain(|p| !self.exclude.contains(p));
-
-        d
-    }
-
-    fn exclude(&mut self, pkgs: Vec<String>) -> &mut Self {
-        self.exclude.extend(pkgs);
-        self
+        self.get_dependencies(pkgname, true)
     }
 }
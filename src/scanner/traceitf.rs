@@ -3,7 +3,6 @@ use std::path::PathBuf;
 /// Package dependency trace
 pub trait PkgDepTrace {
     fn trace(&mut self, pkgname: String) -> Vec<String>;
-    fn exclude(&mut self, pkgs: Vec<String>) -> &mut Self;
 }
 
 pub trait PkgFileTrace {
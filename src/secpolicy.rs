@@ -0,0 +1,74 @@
+/*
+Mandatory access control policies of the kept binaries.
+
+AppArmor profiles are named after the path of the binary they confine,
+e.g. /etc/apparmor.d/usr.sbin.nginx for /usr/sbin/nginx. SELinux policy
+modules are named after the confined service, e.g. nginx.pp, and are
+installed into /usr/share/selinux and /var/lib/selinux. If the binary is
+kept, but its policy is removed, it runs unconfined or mislabeled.
+ */
+
+use crate::prefix;
+use std::{
+    collections::HashMap,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+const APPARMOR_DIR: &str = "/etc/apparmor.d";
+const SELINUX_SHARE: &str = "/usr/share/selinux";
+const SELINUX_STORE: &str = "/var/lib/selinux";
+
+/// Extensions of the SELinux policy modules
+const MODULE_EXT: &[&str] = &[".pp.bz2", ".pp", ".cil"];
+
+/// Get name of the SELinux module, the removed path belongs to
+fn get_module(p: &Path) -> Option<String> {
+    if p.starts_with(SELINUX_SHARE) {
+        let n = p.file_name()?.to_str()?;
+        return MODULE_EXT.iter().find_map(|x| n.strip_suffix(x)).map(|n| n.to_string());
+    }
+
+    // Module store: /var/lib/selinux/<policy>/active/modules/<priority>/<module>/...
+    let rel = p.strip_prefix(SELINUX_STORE).ok()?.components().collect::<Vec<Component>>();
+    match rel.get(1..5)? {
+        [a, m, _, module] if a.as_os_str() == "active" && m.as_os_str() == "modules" => {
+            module.as_os_str().to_str().map(|m| m.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Find policies of the kept executables, those are removed. Returns the binaries with the policies.
+pub fn get_removed_policies(kept: &[PathBuf], removed: &[PathBuf]) -> Vec<(PathBuf, PathBuf)> {
+    let mut apparmor: HashMap<String, PathBuf> = HashMap::default();
+    let mut selinux: HashMap<String, PathBuf> = HashMap::default();
+    for p in removed {
+        if p.parent() == Some(Path::new(APPARMOR_DIR)) {
+            apparmor.insert(p.file_name().unwrap_or_default().to_str().unwrap_or_default().to_string(), p.to_owned());
+        } else if let Some(m) = get_module(p) {
+            selinux.entry(m).or_insert_with(|| p.to_owned());
+        }
+    }
+    if apparmor.is_empty() && selinux.is_empty() {
+        return vec![];
+    }
+
+    let mut out: Vec<(PathBuf, PathBuf)> = vec![];
+    for p in kept {
+        match prefix::symlink_metadata(p) {
+            Ok(m) if m.is_file() && m.permissions().mode() & 0o111 != 0 => {}
+            _ => continue,
+        }
+
+        let profile = p.to_str().unwrap_or_default().trim_start_matches('/').replace('/', ".");
+        if let Some(pol) = apparmor.get(&profile) {
+            out.push((p.to_owned(), pol.to_owned()));
+        }
+        if let Some(pol) = p.file_name().and_then(|n| n.to_str()).and_then(|n| selinux.get(n)) {
+            out.push((p.to_owned(), pol.to_owned()));
+        }
+    }
+
+    out
+}
@@ -5,7 +5,7 @@ Shell call
 use std::{
     io::{Error, Write},
     path::PathBuf,
-    process::{Command, Stdio},
+    process::{Command, Output, Stdio},
 };
 
 const SHELL_DEFAULT: &str = "/usr/bin/sh";
@@ -47,17 +47,62 @@ impl ShellScript {
         Err(Error::new(std::io::ErrorKind::NotFound, "No supported shell has been found"))
     }
 
-    /// Run script
-    pub fn run(&self) -> Result<(String, String), Error> {
+    /// Execute script and get its output
+    fn exec(&self) -> Result<Output, Error> {
         let (shebang, script) = self.detach_shebang()?;
 
-        let mut p = Command::new(shebang).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let mut p = Command::new(shebang).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
         p.stdin.as_mut().unwrap().write_all(script.as_bytes())?;
 
-        let out = p.wait_with_output()?;
+        p.wait_with_output()
+    }
+
+    /// Run script
+    pub fn run(&self) -> Result<(String, String), Error> {
+        let out = self.exec()?;
         Ok((
             String::from_utf8(out.stdout).unwrap_or_else(|e| format!("Cannot get STDOUT: {}", e)),
             String::from_utf8(out.stderr).unwrap_or_else(|e| format!("Cannot get STDERR: {}", e)),
         ))
     }
+
+    /// Run script and return an error, if it exits with non-zero status
+    pub fn run_checked(&self) -> Result<(String, String), Error> {
+        let out = self.exec()?;
+        let stdout = String::from_utf8(out.stdout).unwrap_or_else(|e| format!("Cannot get STDOUT: {}", e));
+        let stderr = String::from_utf8(out.stderr).unwrap_or_else(|e| format!("Cannot get STDERR: {}", e));
+
+        if !out.status.success() {
+            return Err(Error::new(
+                std::io::ErrorKind::Other,
+                format!("Command \"{}\" failed ({}): {}", self.data.trim(), out.status, stderr.trim()),
+            ));
+        }
+
+        Ok((stdout, stderr))
+    }
+}
+
+/// Call an external command directly, without a shell.
+/// Returns its STDOUT or an error, if it exits with non-zero status.
+pub fn call(cmd: &str, args: &[&str]) -> Result<String, Error> {
+    log::debug!("Calling {} {}", cmd, args.join(" "));
+    let out = Command::new(cmd)
+        .args(args)
+        .output()
+        .map_err(|err| Error::new(err.kind(), format!("Unable to call {}: {}", cmd, err)))?;
+    if !out.status.success() {
+        return Err(Error::new(
+            std::io::ErrorKind::Other,
+            format!(
+                "Command \"{} {}\" failed ({}): {}",
+                cmd,
+                args.join(" "),
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
 }
@@ -0,0 +1,177 @@
+/*
+Filesystem snapshots of the rootfs before the changes are applied,
+so they can be instantly rolled back on capable filesystems:
+btrfs subvolumes, ZFS datasets and LVM thin volumes.
+ */
+
+use crate::shcall;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Location of the snapshot record inside the image
+pub const SNAPSHOT_PATH: &str = "/.tinted.snapshot";
+
+/// Snapshot of the rootfs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    kind: String,
+    origin: String,
+    name: String,
+    created: String,
+}
+
+/// Mount entry of the rootfs
+struct Mount {
+    mountpoint: PathBuf,
+    fstype: String,
+    source: String,
+}
+
+impl Snapshot {
+    /// Find a mount, containing the path
+    fn find_mount(root: &Path) -> Result<Mount, Error> {
+        let mut found: Option<Mount> = None;
+        for l in fs::read_to_string("/proc/self/mountinfo")?.lines() {
+            // ID PARENT MAJ:MIN ROOT MOUNTPOINT OPTS [OPTIONAL...] - FSTYPE SOURCE SUPEROPTS
+            let (pre, post) = match l.split_once(" - ") {
+                Some(p) => p,
+                None => continue,
+            };
+            let pre = pre.split(' ').collect::<Vec<&str>>();
+            let post = post.split(' ').collect::<Vec<&str>>();
+            if pre.len() < 5 || post.len() < 2 {
+                continue;
+            }
+
+            // Spaces and other special characters are octal-escaped
+            let mp = PathBuf::from(pre[4].replace("\\040", " ").replace("\\011", "\t").replace("\\134", "\\"));
+            if root.starts_with(&mp)
+                && found.as_ref().map(|m| mp.components().count() >= m.mountpoint.components().count()).unwrap_or(true)
+            {
+                found = Some(Mount { mountpoint: mp, fstype: post[0].to_string(), source: post[1].to_string() });
+            }
+        }
+
+        found.ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Unable to find mount of {}", root.to_str().unwrap())))
+    }
+
+    /// Get name for a new snapshot
+    fn new_name() -> String {
+        format!("mezzotint-{}", chrono::Local::now().format("%Y%m%d%H%M%S"))
+    }
+
+    /// Create a snapshot of the rootfs. Fails, if the filesystem does not support snapshots.
+    pub fn create(root: &Path) -> Result<Self, Error> {
+        let root = fs::canonicalize(root)?;
+        let rs = root.to_str().unwrap().to_string();
+        let mnt = Self::find_mount(&root)?;
+        let name = Self::new_name();
+
+        let snap = match mnt.fstype.as_str() {
+            "btrfs" => {
+                // Root must be a subvolume itself
+                shcall::call("btrfs", &["subvolume", "show", &rs])?;
+                let tgt = root.parent().unwrap_or(Path::new("/")).join(format!(
+                    ".{}.{}",
+                    root.file_name().and_then(|n| n.to_str()).unwrap_or("root"),
+                    name
+                ));
+                shcall::call("btrfs", &["subvolume", "snapshot", &rs, tgt.to_str().unwrap()])?;
+                Snapshot {
+                    kind: "btrfs".to_string(),
+                    origin: rs,
+                    name: tgt.to_str().unwrap().to_string(),
+                    created: String::new(),
+                }
+            }
+            "zfs" => {
+                if mnt.mountpoint != root {
+                    return Err(Error::new(ErrorKind::Unsupported, format!("{} is not a mountpoint of a ZFS dataset", rs)));
+                }
+                shcall::call("zfs", &["snapshot", &format!("{}@{}", mnt.source, name)])?;
+                Snapshot { kind: "zfs".to_string(), origin: mnt.source, name, created: String::new() }
+            }
+            _ if mnt.source.starts_with("/dev/") => {
+                if mnt.mountpoint != root {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!(
+                            "Snapshots on {} are supported only if {} is a mountpoint of a thin logical volume",
+                            mnt.fstype, rs
+                        ),
+                    ));
+                }
+                let lv = shcall::call("lvs", &["--noheadings", "-o", "vg_name,lv_name,segtype", &mnt.source])?;
+                let lv = lv.split_whitespace().collect::<Vec<&str>>();
+                if lv.len() != 3 || lv[2] != "thin" {
+                    return Err(Error::new(
+                        ErrorKind::Unsupported,
+                        format!("{} is not on a thin logical volume, snapshots are not supported", rs),
+                    ));
+                }
+                let origin = format!("{}/{}", lv[0], lv[1]);
+                let name = format!("{}-{}", lv[1], name);
+                shcall::call("lvcreate", &["-s", "-n", &name, &origin])?;
+                Snapshot { kind: "lvm".to_string(), origin, name: format!("{}/{}", lv[0], name), created: String::new() }
+            }
+            fstype => {
+                return Err(Error::new(ErrorKind::Unsupported, format!("Snapshots are not supported on {} filesystem", fstype)));
+            }
+        };
+
+        log::info!("Created {} snapshot {}", snap.kind, snap.name);
+        Ok(Snapshot { created: chrono::Local::now().to_rfc3339(), ..snap })
+    }
+
+    /// Load snapshot record from the file
+    pub fn load(p: &Path) -> Result<Self, Error> {
+        if !p.exists() {
+            return Err(Error::new(ErrorKind::NotFound, format!("No snapshot record found at {}", p.to_str().unwrap())));
+        }
+
+        serde_yaml::from_str::<Snapshot>(&fs::read_to_string(p)?).map_err(|err| {
+            Error::new(ErrorKind::InvalidData, format!("Unable to read snapshot record {}: {}", p.to_str().unwrap(), err))
+        })
+    }
+
+    /// Save snapshot record to the file
+    pub fn save(&self, p: &Path) -> Result<(), Error> {
+        fs::write(p, serde_yaml::to_string(self).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))?)
+    }
+
+    /// Roll the rootfs back to the snapshot. The snapshot is consumed.
+    pub fn rollback(&self) -> Result<(), Error> {
+        log::info!("Rolling back to {} snapshot {}, taken at {}", self.kind, self.name, self.created);
+        match self.kind.as_str() {
+            "btrfs" => {
+                // Swap the tinted subvolume with a writable copy of the snapshot
+                let tinted = format!("{}.tinted", self.origin);
+                fs::rename(&self.origin, &tinted).map_err(|err| {
+                    Error::new(err.kind(), format!("Unable to move away {}, is it a mountpoint? {}", self.origin, err))
+                })?;
+                if let Err(err) = shcall::call("btrfs", &["subvolume", "snapshot", &self.name, &self.origin]) {
+                    fs::rename(&tinted, &self.origin)?;
+                    return Err(err);
+                }
+                shcall::call("btrfs", &["subvolume", "delete", &tinted])?;
+                shcall::call("btrfs", &["subvolume", "delete", &self.name])?;
+            }
+            "zfs" => {
+                let snap = format!("{}@{}", self.origin, self.name);
+                shcall::call("zfs", &["rollback", "-r", &snap])?;
+                shcall::call("zfs", &["destroy", &snap])?;
+            }
+            "lvm" => {
+                shcall::call("lvconvert", &["--merge", &self.name])?;
+                log::warn!("If {} is in use, the merge is deferred until its next activation", self.origin);
+            }
+            kind => return Err(Error::new(ErrorKind::Unsupported, format!("Unknown snapshot kind: {}", kind))),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,481 @@
+/*
+Stripping of the kept ELF files.
+
+Debug sections (and with "all" also the symbol table and comments) are
+removed from executables and shared libraries in-process, without strip of
+the image. Only sections, those are not loaded and follow all loaded ones
+in the section table, are removed, so the loaded part of the file is left
+byte to byte and the indices of the dynamic symbols stay valid. The kept
+sections, which are not loaded, and the section table are moved right
+after the loaded part.
+
+Files with data, not covered by any section (e.g. appended archives of
+self-extracting binaries), are left as is.
+ */
+
+use crate::{prefix, xattrs};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::CString,
+    fs::{self, Metadata},
+    io::Error,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+const SHT_SYMTAB: u32 = 2;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_INFO_LINK: u64 = 0x40;
+const SHN_LORESERVE: u16 = 0xff00;
+const SHN_ABS: u16 = 0xfff1;
+
+/// Prefixes of the debug sections
+const DEBUG_SECTIONS: &[&str] = &[".debug", ".zdebug", ".gnu.debuglto_", ".gnu_debugdata", ".stab"];
+
+/// Sections, not needed at runtime, besides the debug ones
+const UNNEEDED_SECTIONS: &[&str] = &[".symtab", ".strtab", ".comment"];
+
+/// Section header, with its raw bytes to be written back
+struct Section {
+    name: String,
+    kind: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    raw: Vec<u8>,
+}
+
+/// ELF file in memory
+struct Elf<'a> {
+    data: &'a [u8],
+    le: bool,
+    is64: bool,
+}
+
+impl<'a> Elf<'a> {
+    fn uint(&self, at: usize, size: usize) -> Option<u64> {
+        let b = self.data.get(at..at + size)?;
+        let mut v: u64 = 0;
+        for i in 0..size {
+            let byte = if self.le { b[size - 1 - i] } else { b[i] };
+            v = (v << 8) | byte as u64;
+        }
+
+        Some(v)
+    }
+
+    /// Read address-sized field, which is 4 bytes on 32-bit ELF and 8 bytes on 64-bit ELF
+    fn addr(&self, at: usize) -> Option<u64> {
+        self.uint(at, if self.is64 { 8 } else { 4 })
+    }
+
+    fn name_at(&self, offset: usize) -> String {
+        let s = self.data.get(offset..).unwrap_or_default();
+        String::from_utf8_lossy(&s[..s.iter().position(|b| *b == 0).unwrap_or(0)]).to_string()
+    }
+
+    /// Read section headers with their names
+    fn read_sections(&self, shoff: usize, shentsize: usize, shnum: usize, shstrndx: usize) -> Option<Vec<Section>> {
+        let mut out: Vec<Section> = vec![];
+        for i in 0..shnum {
+            let at = shoff + i * shentsize;
+            let raw = self.data.get(at..at + shentsize)?.to_vec();
+            out.push(if self.is64 {
+                Section {
+                    name: String::new(),
+                    kind: self.uint(at + 4, 4)? as u32,
+                    flags: self.uint(at + 8, 8)?,
+                    offset: self.uint(at + 24, 8)?,
+                    size: self.uint(at + 32, 8)?,
+                    link: self.uint(at + 40, 4)? as u32,
+                    info: self.uint(at + 44, 4)? as u32,
+                    align: self.uint(at + 48, 8)?,
+                    raw,
+                }
+            } else {
+                Section {
+                    name: String::new(),
+                    kind: self.uint(at + 4, 4)? as u32,
+                    flags: self.uint(at + 8, 4)?,
+                    offset: self.uint(at + 16, 4)?,
+                    size: self.uint(at + 20, 4)?,
+                    link: self.uint(at + 24, 4)? as u32,
+                    info: self.uint(at + 28, 4)? as u32,
+                    align: self.uint(at + 32, 4)?,
+                    raw,
+                }
+            });
+        }
+
+        let strtab = out.get(shstrndx)?.offset as usize;
+        for (i, s) in out.iter_mut().enumerate() {
+            s.name = self.name_at(strtab + self.uint(shoff + i * shentsize, 4)? as usize);
+        }
+
+        Some(out)
+    }
+
+    /// Write the value into the buffer with the byte order of the file
+    fn put(&self, buf: &mut [u8], at: usize, size: usize, v: u64) {
+        for i in 0..size {
+            let byte = ((v >> (8 * i)) & 0xff) as u8;
+            buf[if self.le { at + i } else { at + size - 1 - i }] = byte;
+        }
+    }
+}
+
+/// Returns true if the section can be dropped in the given mode
+fn is_strippable(name: &str, all: bool) -> bool {
+    DEBUG_SECTIONS.iter().any(|p| name.starts_with(p)) || (all && UNNEEDED_SECTIONS.contains(&name))
+}
+
+/// Strip the ELF file in memory. Returns None, if it is not an executable or a shared library,
+/// has nothing to strip or cannot be stripped safely.
+pub fn strip(data: &[u8], all: bool) -> Option<Vec<u8>> {
+    if data.len() < 52 || &data[..4] != b"\x7fELF" {
+        return None;
+    }
+    let elf = Elf { data, le: data[5] == 1, is64: data[4] == 2 };
+    let etype = elf.uint(0x10, 2)? as u16;
+    if etype != ET_EXEC && etype != ET_DYN {
+        return None;
+    }
+
+    let (phoff, shoff) = if elf.is64 { (elf.addr(0x20)?, elf.addr(0x28)?) } else { (elf.addr(0x1c)?, elf.addr(0x20)?) };
+    let h = if elf.is64 { 0x36 } else { 0x2a };
+    let (phentsize, phnum) = (elf.uint(h, 2)? as usize, elf.uint(h + 2, 2)? as usize);
+    let (shentsize, shnum, shstrndx) = (elf.uint(h + 4, 2)? as usize, elf.uint(h + 6, 2)? as usize, elf.uint(h + 8, 2)? as usize);
+    if shnum == 0 || shstrndx >= shnum || shentsize < if elf.is64 { 64 } else { 40 } {
+        return None;
+    }
+
+    // Loaded part of the file: headers and all segments
+    let mut load_end = phoff as usize + phentsize * phnum;
+    for i in 0..phnum {
+        let at = phoff as usize + i * phentsize;
+        let (offset, filesz) =
+            if elf.is64 { (elf.uint(at + 8, 8)?, elf.uint(at + 32, 8)?) } else { (elf.uint(at + 4, 4)?, elf.uint(at + 16, 4)?) };
+        load_end = load_end.max(offset.saturating_add(filesz) as usize);
+    }
+    if load_end > data.len() {
+        return None;
+    }
+
+    let mut sections = elf.read_sections(shoff as usize, shentsize, shnum, shstrndx)?;
+    let last_alloc = sections.iter().rposition(|s| s.flags & SHF_ALLOC != 0).unwrap_or(0);
+    let mut removed = (last_alloc + 1..shnum)
+        .filter(|i| {
+            let s = &sections[*i];
+            *i != shstrndx && s.flags & SHF_ALLOC == 0 && s.offset as usize >= load_end && is_strippable(&s.name, all)
+        })
+        .collect::<HashSet<usize>>();
+
+    // Sections, linked from the kept ones, stay as well, e.g. .strtab of the kept .symtab
+    loop {
+        let linked = (0..shnum)
+            .filter(|i| !removed.contains(i))
+            .map(|i| sections[i].link as usize)
+            .filter(|l| removed.contains(l))
+            .collect::<Vec<usize>>();
+        if linked.is_empty() {
+            break;
+        }
+        for l in linked {
+            removed.remove(&l);
+        }
+    }
+    if removed.is_empty() {
+        return None;
+    }
+
+    // Everything after the loaded part must be known, otherwise it is some appended data
+    let shtab = (shoff as usize, shoff as usize + shentsize * shnum, 8);
+    let mut extents = sections
+        .iter()
+        .filter(|s| s.kind != SHT_NOBITS && s.offset as usize >= load_end)
+        .map(|s| (s.offset as usize, s.offset.saturating_add(s.size) as usize, s.align.max(1) as usize))
+        .chain([shtab])
+        .collect::<Vec<(usize, usize, usize)>>();
+    extents.sort();
+    let mut cur = load_end;
+    for (start, end, align) in extents {
+        if start > cur && start - cur >= align.max(8) {
+            return None;
+        }
+        cur = cur.max(end);
+    }
+    if cur != data.len() {
+        return None;
+    }
+
+    // Kept sections after the loaded part are moved right after it
+    let mut out = data[..load_end].to_vec();
+    let mut order = (0..shnum).filter(|i| !removed.contains(i)).collect::<Vec<usize>>();
+    order.sort_by_key(|i| sections[*i].offset);
+    for i in order {
+        let s = &mut sections[i];
+        if s.kind == SHT_NOBITS || (s.offset as usize) < load_end {
+            continue;
+        }
+        let align = s.align.max(1) as usize;
+        out.resize((out.len() + align - 1) / align * align, 0);
+        let src = data.get(s.offset as usize..s.offset.saturating_add(s.size) as usize)?;
+        s.offset = out.len() as u64;
+        out.extend_from_slice(src);
+    }
+
+    // Section indices are remapped, as the removed ones are gone
+    let remap = (0..shnum).filter(|i| !removed.contains(i)).enumerate().map(|(n, o)| (o, n)).collect::<HashMap<usize, usize>>();
+    let ix = |i: u32| remap.get(&(i as usize)).copied().unwrap_or(0) as u64;
+
+    // Symbols of the kept symbol table might point to the moved or removed sections
+    let (symsize, shndx_at) = if elf.is64 { (24, 6) } else { (16, 14) };
+    for s in sections.iter().enumerate().filter(|(i, s)| !removed.contains(i) && s.kind == SHT_SYMTAB).map(|(_, s)| s) {
+        for n in 0..s.size as usize / symsize {
+            let at = s.offset as usize + n * symsize + shndx_at;
+            let old =
+                if elf.le { u16::from_le_bytes([out[at], out[at + 1]]) } else { u16::from_be_bytes([out[at], out[at + 1]]) };
+            if old != 0 && old < SHN_LORESERVE {
+                let new = remap.get(&(old as usize)).map(|n| *n as u64).unwrap_or(SHN_ABS as u64);
+                elf.put(&mut out, at, 2, new);
+            }
+        }
+    }
+
+    let align = if elf.is64 { 8 } else { 4 };
+    out.resize((out.len() + align - 1) / align * align, 0);
+    let new_shoff = out.len() as u64;
+    for (i, s) in sections.iter().enumerate().filter(|(i, _)| !removed.contains(i)) {
+        let mut raw = s.raw.clone();
+        let (off_at, link_at, info_at) = if elf.is64 { (24, 40, 44) } else { (16, 24, 28) };
+        if i != 0 {
+            elf.put(&mut raw, off_at, if elf.is64 { 8 } else { 4 }, s.offset);
+            elf.put(&mut raw, link_at, 4, ix(s.link));
+            if (s.kind == SHT_REL || s.kind == SHT_RELA) && s.flags & SHF_INFO_LINK != 0 {
+                elf.put(&mut raw, info_at, 4, ix(s.info));
+            }
+        }
+        out.extend_from_slice(&raw);
+    }
+
+    let mut hdr = out[..h + 10].to_vec();
+    elf.put(&mut hdr, if elf.is64 { 0x28 } else { 0x20 }, if elf.is64 { 8 } else { 4 }, new_shoff);
+    elf.put(&mut hdr, h + 6, 2, remap.len() as u64);
+    elf.put(&mut hdr, h + 8, 2, ix(shstrndx as u32));
+    out[..h + 10].copy_from_slice(&hdr);
+
+    if out.len() >= data.len() {
+        return None;
+    }
+
+    Some(out)
+}
+
+/// Strips the kept ELF files
+pub struct Stripper {
+    all: bool,
+    dry_run: bool,
+}
+
+impl Stripper {
+    /// Constructor. Only debug sections are removed, unless all unneeded sections are requested.
+    pub fn new(all: bool, dry_run: bool) -> Self {
+        Stripper { all, dry_run }
+    }
+
+    /// Write the stripped content into a temporary file next to the original one, and move it over
+    /// the original and its hardlinks, so a failed write never leaves a truncated file in the image.
+    fn replace(links: &[PathBuf], m: &Metadata, data: &[u8]) -> Result<(), Error> {
+        let tmp = |hp: &Path| {
+            hp.with_file_name(format!(".{}.mezzotint", hp.file_name().unwrap_or_default().to_str().unwrap_or_default()))
+        };
+        let hp = prefix::host_nofollow(&links[0]);
+        let t = tmp(&hp);
+        let r = fs::write(&t, data)
+            .and_then(|_| {
+                // Owner is set first, as it drops setuid and setgid bits
+                let ct = CString::new(t.as_os_str().as_bytes())?;
+                if unsafe { libc::lchown(ct.as_ptr(), m.uid(), m.gid()) } != 0 {
+                    return Err(Error::last_os_error());
+                }
+                fs::set_permissions(&t, m.permissions())?;
+                xattrs::copy_security(&hp, &t)
+            })
+            .and_then(|_| fs::rename(&t, &hp));
+        if let Err(err) = r {
+            let _ = fs::remove_file(&t);
+            return Err(err);
+        }
+
+        for l in &links[1..] {
+            let hl = prefix::host_nofollow(l);
+            let t = tmp(&hl);
+            fs::hard_link(&hp, &t)?;
+            fs::rename(&t, &hl).map_err(|err| {
+                let _ = fs::remove_file(&t);
+                err
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Strip the kept files. Returns number of the stripped files and the saved bytes.
+    pub fn run(&self, kept: &[PathBuf]) -> Result<(usize, u64), Error> {
+        // Hardlinks share the content, so it is stripped once for all of them
+        let mut inodes: HashMap<(u64, u64), usize> = HashMap::default();
+        let mut groups: Vec<(Vec<PathBuf>, Metadata)> = vec![];
+        for p in kept {
+            match fs::symlink_metadata(prefix::host_nofollow(p)) {
+                Ok(m) if m.is_file() && m.len() > 52 => match inodes.get(&(m.dev(), m.ino())) {
+                    Some(i) => groups[*i].0.push(p.to_owned()),
+                    None => {
+                        inodes.insert((m.dev(), m.ino()), groups.len());
+                        groups.push((vec![p.to_owned()], m));
+                    }
+                },
+                _ => continue,
+            }
+        }
+
+        let (mut files, mut saved) = (0, 0);
+        for (links, m) in groups {
+            let (p, hp) = (&links[0], prefix::host_nofollow(&links[0]));
+            let data = match fs::read(&hp) {
+                Ok(data) => data,
+                Err(err) => {
+                    log::warn!("Not stripping {}, as it cannot be read: {}", p.to_str().unwrap(), err);
+                    continue;
+                }
+            };
+            let out = match strip(&data, self.all) {
+                Some(out) => out,
+                None => continue,
+            };
+
+            // Writing drops file capabilities, and they are verified after the changes
+            if xattrs::get_caps(&hp).is_some() {
+                log::debug!("Not stripping {}, as it has file capabilities", p.to_str().unwrap());
+                continue;
+            }
+
+            // Stripped file is a new one, so links outside of the kept files would keep the old content
+            if m.nlink() as usize > links.len() {
+                log::debug!("Not stripping {}, as it has hardlinks, those are not kept", p.to_str().unwrap());
+                continue;
+            }
+
+            log::debug!("Stripping {}: {} bytes less", p.to_str().unwrap(), data.len() - out.len());
+            if !self.dry_run {
+                Self::replace(&links, &m, &out)?;
+            }
+            files += 1;
+            saved += (data.len() - out.len()) as u64;
+        }
+
+        Ok((files, saved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip, Elf};
+
+    /// Section of the test ELF: name offset, type, flags, offset, size, alignment
+    type Shdr = (u32, u32, u64, u64, u64, u64);
+
+    /// Build a 64-bit shared library with loaded .text, then .debug_info, .comment and the section names
+    fn elf() -> Vec<u8> {
+        let names = b"\0.text\0.debug_info\0.comment\0.shstrtab\0";
+        let sections: [Shdr; 5] = [
+            (0, 0, 0, 0, 0, 0),
+            (1, 1, 0x6, 120, 16, 16),
+            (7, 1, 0, 136, 32, 1),
+            (19, 1, 0x30, 168, 8, 1),
+            (28, 3, 0, 176, 38, 1),
+        ];
+
+        let mut out = b"\x7fELF\x02\x01\x01".to_vec();
+        out.resize(16, 0);
+        out.extend(3u16.to_le_bytes());
+        out.extend(62u16.to_le_bytes());
+        out.extend(1u32.to_le_bytes());
+        for v in [0u64, 64, 216] {
+            out.extend(v.to_le_bytes());
+        }
+        out.extend(0u32.to_le_bytes());
+        for v in [64u16, 56, 1, 64, 5, 4] {
+            out.extend(v.to_le_bytes());
+        }
+
+        // Single loaded segment of the headers and .text
+        out.extend(1u32.to_le_bytes());
+        out.extend(5u32.to_le_bytes());
+        for v in [0u64, 0, 0, 136, 136, 0x1000] {
+            out.extend(v.to_le_bytes());
+        }
+        out.extend([0x90; 16]);
+        out.extend([0xdb; 32]);
+        out.extend(b"GCC: 13\0");
+        out.extend(names);
+        out.resize(216, 0);
+
+        for (name, kind, flags, offset, size, align) in sections {
+            out.extend(name.to_le_bytes());
+            out.extend(kind.to_le_bytes());
+            for v in [flags, 0, offset, size] {
+                out.extend(v.to_le_bytes());
+            }
+            out.extend([0u8; 8]);
+            out.extend(align.to_le_bytes());
+            out.extend(0u64.to_le_bytes());
+        }
+
+        out
+    }
+
+    /// Get names of the sections and the content of the named one
+    fn parse(data: &[u8], name: &str) -> (Vec<String>, Vec<u8>) {
+        let e = Elf { data, le: true, is64: true };
+        let (shoff, shnum, shstrndx) = (e.uint(0x28, 8).unwrap(), e.uint(0x3c, 2).unwrap(), e.uint(0x3e, 2).unwrap());
+        let sections = e.read_sections(shoff as usize, 64, shnum as usize, shstrndx as usize).unwrap();
+        let content = sections
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| data[s.offset as usize..(s.offset + s.size) as usize].to_vec())
+            .unwrap_or_default();
+
+        (sections.into_iter().map(|s| s.name).collect(), content)
+    }
+
+    #[test]
+    fn strips_debug() {
+        let data = elf();
+        assert_eq!(parse(&data, ".debug_info").0, ["", ".text", ".debug_info", ".comment", ".shstrtab"]);
+
+        let out = strip(&data, false).unwrap();
+        assert!(out.len() < data.len());
+        assert_eq!(out[64..136], data[64..136], "loaded part is left as is");
+        let (names, comment) = parse(&out, ".comment");
+        assert_eq!(names, ["", ".text", ".comment", ".shstrtab"]);
+        assert_eq!(comment, b"GCC: 13\0");
+        assert_eq!(parse(&out, ".text").1, [0x90; 16]);
+        assert!(strip(&out, false).is_none(), "nothing is left to strip");
+
+        let all = strip(&data, true).unwrap();
+        assert_eq!(parse(&all, ".text").0, ["", ".text", ".shstrtab"]);
+
+        // Appended data, not covered by any section, is left as is
+        let mut appended = data.clone();
+        appended.extend([0u8; 64]);
+        assert!(strip(&appended, false).is_none());
+    }
+}
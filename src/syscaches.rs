@@ -0,0 +1,173 @@
+/*
+System caches of the kept components.
+
+Caches of fonts, image loaders and MIME types still list the removed files
+after the tint. A cache is refreshed by the tool of the image, if anything
+is removed from its sources and the tool is kept. Tools run only inside the
+image, so without chroot the caches are only reported.
+
+Preset files of systemd are pruned of the lines, enabling or disabling the
+removed units. Their wants and requires symlinks are broken by the removal,
+so they are swept with the other broken symlinks.
+ */
+
+use crate::{glob, prefix, shcall::ShellScript};
+use std::{
+    collections::HashSet,
+    fs,
+    io::Error,
+    path::{Path, PathBuf},
+};
+
+/// Cache, built by a tool of the image from the files of its sources
+struct SysCache {
+    name: &'static str,
+    sources: &'static [&'static str],
+    tools: &'static [&'static str],
+    args: &'static str,
+}
+
+const CACHES: &[SysCache] = &[
+    SysCache {
+        name: "fontconfig",
+        sources: &["/usr/share/fonts", "/usr/local/share/fonts"],
+        tools: &["/usr/bin/fc-cache"],
+        args: "-s -f",
+    },
+    SysCache {
+        name: "gdk-pixbuf loaders",
+        sources: &[
+            "/usr/lib/*/gdk-pixbuf-2.0/*/loaders",
+            "/usr/lib/gdk-pixbuf-2.0/*/loaders",
+            "/usr/lib64/gdk-pixbuf-2.0/*/loaders",
+        ],
+        tools: &["/usr/lib/*/gdk-pixbuf-2.0/gdk-pixbuf-query-loaders", "/usr/bin/gdk-pixbuf-query-loaders*"],
+        args: "--update-cache",
+    },
+    SysCache {
+        name: "MIME database",
+        sources: &["/usr/share/mime/packages"],
+        tools: &["/usr/bin/update-mime-database"],
+        args: "/usr/share/mime",
+    },
+];
+
+/// Directories of the systemd presets
+const PRESET_DIRS: &[&str] = &["/etc/systemd/system-preset", "/usr/lib/systemd/system-preset", "/lib/systemd/system-preset"];
+
+/// Directories of the systemd units
+const UNIT_DIRS: &[&str] = &["/etc/systemd/system", "/usr/lib/systemd/system", "/lib/systemd/system"];
+
+pub struct SysCaches {
+    removed: Vec<PathBuf>,
+    dry_run: bool,
+}
+
+impl SysCaches {
+    /// Caches, affected by the removed paths
+    pub fn new(removed: &[PathBuf], dry_run: bool) -> Self {
+        SysCaches { removed: removed.to_vec(), dry_run }
+    }
+
+    /// Returns true if anything is removed from the sources
+    fn is_changed(&self, sources: &[&str]) -> bool {
+        self.removed.iter().any(|p| sources.iter().any(|s| glob::matches_tree(s, p)))
+    }
+
+    /// Find the kept tool of the cache
+    fn find_tool(tools: &[&str]) -> Option<PathBuf> {
+        tools
+            .iter()
+            .flat_map(|t| if glob::is_glob(t) { glob::expand_tree(t) } else { vec![PathBuf::from(t)] })
+            .find(|t| prefix::exists(t))
+    }
+
+    /// Refresh the caches, those sources are changed. Failures are only warned about,
+    /// as a stale cache does not break the image.
+    pub fn refresh(&self) -> Result<(), Error> {
+        for c in CACHES.iter().filter(|c| self.is_changed(c.sources)) {
+            let tool = match Self::find_tool(c.tools) {
+                Some(tool) => tool,
+                None => {
+                    log::debug!("Not refreshing {}, as its tool is not kept", c.name);
+                    continue;
+                }
+            };
+
+            let cmd = format!("{} {}", tool.to_str().unwrap(), c.args);
+            if self.dry_run {
+                log::info!("Would refresh {}: {}", c.name, cmd);
+            } else if prefix::is_set() {
+                log::warn!("Unable to refresh {} without chroot. Run \"{}\" in the image.", c.name, cmd);
+            } else {
+                log::info!("Refreshing {}: {}", c.name, cmd);
+                if let Err(err) = ShellScript::new(cmd, None).run_checked() {
+                    log::warn!("Unable to refresh {}: {}", c.name, err);
+                }
+            }
+        }
+
+        self.prune_presets()
+    }
+
+    /// Get names of the removed units, those are not available anymore
+    fn get_removed_units(&self) -> HashSet<String> {
+        self.removed
+            .iter()
+            .filter(|p| UNIT_DIRS.iter().any(|d| p.parent() == Some(Path::new(d))))
+            .filter_map(|p| p.file_name().and_then(|n| n.to_str()).map(|n| n.to_string()))
+            .filter(|n| {
+                // Unit might still be available from another directory, e.g. overridden in /etc
+                !UNIT_DIRS
+                    .iter()
+                    .filter_map(|d| prefix::canonicalize(Path::new(d).join(n)).ok())
+                    .any(|u| !self.removed.contains(&u))
+            })
+            .collect()
+    }
+
+    /// Remove lines of the presets, referring to the removed units
+    fn prune_presets(&self) -> Result<(), Error> {
+        let units = self.get_removed_units();
+        if units.is_empty() {
+            return Ok(());
+        }
+
+        // On usrmerged systems /lib is the same directory as /usr/lib
+        let mut dirs = PRESET_DIRS.iter().filter_map(|d| prefix::canonicalize(d).ok()).collect::<Vec<PathBuf>>();
+        dirs.dedup();
+        for d in dirs {
+            let mut presets = match prefix::read_dir(&d) {
+                Ok(rd) => rd.filter_map(|e| e.ok()).map(|e| d.join(e.file_name())).collect::<Vec<PathBuf>>(),
+                Err(_) => continue,
+            };
+            presets.retain(|p| p.extension().map(|x| x == "preset").unwrap_or(false) && prefix::is_file(p));
+            presets.sort();
+
+            for p in presets {
+                let data = prefix::read_to_string(&p)?;
+                let mut out: Vec<&str> = vec![];
+                let mut pruned = 0;
+                for l in data.lines() {
+                    let mut fields = l.split_whitespace();
+                    match (fields.next(), fields.next()) {
+                        (Some("enable") | Some("disable"), Some(unit)) if units.contains(unit) => pruned += 1,
+                        _ => out.push(l),
+                    }
+                }
+
+                if pruned == 0 {
+                    continue;
+                }
+                if self.dry_run {
+                    log::info!("Would prune {} lines of the removed units from {}", pruned, p.to_str().unwrap());
+                } else {
+                    log::info!("Pruned {} lines of the removed units from {}", pruned, p.to_str().unwrap());
+                    fs::write(prefix::host(&p), if out.is_empty() { String::new() } else { format!("{}\n", out.join("\n")) })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
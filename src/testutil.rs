@@ -0,0 +1,29 @@
+/*
+Fixtures of the unit tests.
+
+Tests run in parallel within one process, and several test runs may
+share the same temporary directory, so every fixture is named after
+the process and the test.
+*/
+
+use std::{fs, path::PathBuf};
+
+/// Get a path of the fixture in the temporary directory
+fn fixture(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mezzotint-test-{}-{}", std::process::id(), name))
+}
+
+/// Create an empty scratch directory for the test
+pub fn scratch(name: &str) -> PathBuf {
+    let d = fixture(name);
+    let _ = fs::remove_dir_all(&d);
+    fs::create_dir_all(&d).unwrap();
+    d
+}
+
+/// Write a scratch file for the test
+pub fn scratch_file(name: &str, data: &str) -> PathBuf {
+    let p = fixture(name);
+    fs::write(&p, data).unwrap();
+    p
+}
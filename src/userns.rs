@@ -0,0 +1,160 @@
+/*
+Unprivileged operation in a user namespace.
+
+Root filesystems, extracted by rootless podman or umoci, are owned by the
+user, but chroot needs CAP_SYS_CHROOT. In a new user namespace the user is
+mapped to root, so the whole pipeline runs as usual without sudo.
+
+If the user has subordinate ids in /etc/subuid and /etc/subgid, they are
+mapped as well by newuidmap(1) and newgidmap(1), like rootless podman does,
+so files of other owners in the image keep their ids. Otherwise only the
+user and its group are mapped, and files of other owners belong to nobody.
+ */
+
+use std::{
+    env,
+    ffi::CStr,
+    fs,
+    io::{Error, ErrorKind},
+    process::Command,
+};
+
+/// Subordinate user ids
+const SUBUID: &str = "/etc/subuid";
+
+/// Subordinate group ids
+const SUBGID: &str = "/etc/subgid";
+
+/// Get name of the user, if it is known
+fn get_user_name(uid: libc::uid_t) -> Option<String> {
+    let pw = unsafe { libc::getpwuid(uid) };
+    if pw.is_null() {
+        return None;
+    }
+
+    Some(unsafe { CStr::from_ptr((*pw).pw_name) }.to_string_lossy().to_string())
+}
+
+/// Get the first range of subordinate ids of the user as its start and length
+fn get_subids(db: &str, name: &str, id: u32) -> Option<(u32, u32)> {
+    fs::read_to_string(db).ok()?.lines().find_map(|l| {
+        let f = l.trim().split(':').collect::<Vec<&str>>();
+        if f.len() != 3 || (f[0] != name && f[0] != id.to_string()) {
+            return None;
+        }
+
+        Some((f[1].parse().ok()?, f[2].parse().ok()?)).filter(|(_, len)| *len > 0)
+    })
+}
+
+/// Returns true if the command is found in PATH
+fn has_command(cmd: &str) -> bool {
+    env::var_os("PATH").map(|p| env::split_paths(&p).any(|d| d.join(cmd).is_file())).unwrap_or(false)
+}
+
+/// Write the id map of the process
+fn write_map(p: &str, data: &str) -> Result<(), Error> {
+    fs::write(p, data).map_err(|err| Error::new(err.kind(), format!("Unable to write {}: {}", p, err)))
+}
+
+fn unshare() -> Result<(), Error> {
+    if unsafe { libc::unshare(libc::CLONE_NEWUSER) } != 0 {
+        let err = Error::last_os_error();
+        return Err(Error::new(
+            err.kind(),
+            format!("Unable to create a user namespace: {}. Are unprivileged user namespaces enabled?", err),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Enter the namespace, mapping only the user and its group to root
+fn enter_single(uid: libc::uid_t, gid: libc::gid_t) -> Result<(), Error> {
+    unshare()?;
+
+    // Unprivileged process may map its group only, if it cannot drop its supplementary groups
+    write_map("/proc/self/setgroups", "deny")?;
+    write_map("/proc/self/uid_map", &format!("0 {} 1", uid))?;
+    write_map("/proc/self/gid_map", &format!("0 {} 1", gid))
+}
+
+/// Enter the namespace, mapping the subordinate ids as well. Setuid helpers cannot
+/// map ids from inside of the namespace, so they are called by a child, forked before.
+fn enter_mapped(uid: libc::uid_t, gid: libc::gid_t, subuid: (u32, u32), subgid: (u32, u32)) -> Result<(), Error> {
+    let mut fds = [0 as libc::c_int; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    if pid == 0 {
+        // Parent closes the pipe without writing, if it could not unshare
+        unsafe { libc::close(fds[1]) };
+        let mut b = [0u8; 1];
+        if unsafe { libc::read(fds[0], b.as_mut_ptr() as *mut libc::c_void, 1) } != 1 {
+            unsafe { libc::_exit(1) };
+        }
+
+        let ppid = unsafe { libc::getppid() }.to_string();
+        let map = |cmd: &str, id: u32, sub: (u32, u32)| {
+            Command::new(cmd)
+                .args([ppid.as_str(), "0", &id.to_string(), "1", "1", &sub.0.to_string(), &sub.1.to_string()])
+                .status()
+                .map(|s| s.success())
+                .unwrap_or(false)
+        };
+        let ok = map("newuidmap", uid, subuid) && map("newgidmap", gid, subgid);
+        unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+    }
+
+    unsafe { libc::close(fds[0]) };
+    let ret = unshare();
+    if ret.is_ok() {
+        unsafe { libc::write(fds[1], b"1".as_ptr() as *const libc::c_void, 1) };
+    }
+    unsafe { libc::close(fds[1]) };
+
+    let mut status: libc::c_int = 0;
+    unsafe { libc::waitpid(pid, &mut status, 0) };
+    ret?;
+
+    if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+        return Err(Error::new(ErrorKind::PermissionDenied, "Unable to map subordinate ids by newuidmap and newgidmap"));
+    }
+
+    Ok(())
+}
+
+/// Run the rest of the process as root in a new user namespace.
+/// It must be called before anything else, while the process has no threads.
+pub fn enter() -> Result<(), Error> {
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    if uid == 0 {
+        log::debug!("Already running as root, user namespace is not needed");
+        return Ok(());
+    }
+
+    let name = get_user_name(uid).unwrap_or_default();
+    match (get_subids(SUBUID, &name, uid), get_subids(SUBGID, &name, gid)) {
+        (Some(subuid), Some(subgid)) if has_command("newuidmap") && has_command("newgidmap") => {
+            enter_mapped(uid, gid, subuid, subgid)?;
+            log::info!(
+                "Running in a user namespace as root, mapped to uid {} and {} subordinate ids from {}",
+                uid,
+                subuid.1,
+                subuid.0
+            );
+        }
+        _ => {
+            enter_single(uid, gid)?;
+            log::info!("Running in a user namespace as root, mapped to uid {}. Files of other owners belong to nobody.", uid);
+        }
+    }
+
+    Ok(())
+}
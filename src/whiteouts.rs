@@ -0,0 +1,248 @@
+/*
+Overlayfs upper directory with whiteouts of the removed files.
+
+Instead of removing files from the rootfs, removed files are hidden by
+whiteouts (0/0 character devices) and emptied mount points are made opaque,
+so the result can be applied as an additional layer on top of the untouched
+base. State files of the tint are written into the layer as well.
+
+The directory is on the host, so it is opened before chroot and all
+entries are made relative to its descriptor.
+ */
+
+use crate::prefix;
+use std::{
+    collections::HashSet,
+    ffi::CString,
+    fs::{self, File},
+    io::{Error, ErrorKind, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::MetadataExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+};
+
+/// Attribute of the overlayfs opaque directories
+const OPAQUE: &str = "trusted.overlay.opaque";
+
+/// Directories, which are mount points of the container, so they stay even if emptied
+const MOUNT_POINTS: &[&str] = &["/", "/dev", "/proc", "/run", "/sys", "/tmp"];
+
+pub enum Whiteout {
+    Remove(PathBuf),
+    Opaque(PathBuf),
+}
+
+pub struct Whiteouts {
+    path: PathBuf,
+    dir: File,
+    count: usize,
+}
+
+impl Whiteouts {
+    /// Open an empty upper directory. It must be called on the host.
+    pub fn create(p: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(p)?;
+        if fs::read_dir(p)?.next().is_some() {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("Whiteout directory {} is not empty", p.to_str().unwrap())));
+        }
+
+        Ok(Whiteouts { path: p.to_owned(), dir: File::open(p)?, count: 0 })
+    }
+
+    /// Get path in the image relative to the upper directory
+    fn rel(p: &Path) -> Result<CString, Error> {
+        let r = p.strip_prefix("/").unwrap_or(p);
+        let r = if r.as_os_str().is_empty() { Path::new(".") } else { r };
+        CString::new(r.as_os_str().as_bytes()).map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))
+    }
+
+    fn check(ret: libc::c_int, what: &str, p: &Path) -> Result<(), Error> {
+        if ret != 0 {
+            let err = Error::last_os_error();
+            return Err(Error::new(
+                err.kind(),
+                format!("Unable to {} {} in the whiteout directory: {}", what, p.to_str().unwrap(), err),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Create the directory and its parents, as they are in the image, so the layer does not change them
+    fn mkdirs(&self, p: &Path) -> Result<(), Error> {
+        let fd = self.dir.as_raw_fd();
+        for d in p.ancestors().collect::<Vec<&Path>>().into_iter().rev().filter(|d| d.parent().is_some()) {
+            // Directories, those are new in the layer, belong to root
+            let (uid, gid, mode) =
+                prefix::symlink_metadata(d).map(|m| (m.uid(), m.gid(), m.mode() & 0o7777)).unwrap_or((0, 0, 0o755));
+            let cd = Self::rel(d)?;
+            if unsafe { libc::mkdirat(fd, cd.as_ptr(), 0o700) } != 0 {
+                if Error::last_os_error().kind() != ErrorKind::AlreadyExists {
+                    return Self::check(-1, "create directory", d);
+                }
+                if !self.is_whiteout(d) {
+                    continue;
+                }
+
+                // Hidden directory gets new content, so it is re-created as opaque
+                Self::check(unsafe { libc::unlinkat(fd, cd.as_ptr(), 0) }, "remove whiteout", d)?;
+                Self::check(unsafe { libc::mkdirat(fd, cd.as_ptr(), 0o700) }, "create directory", d)?;
+                self.set_opaque(d)?;
+            }
+
+            Self::check(unsafe { libc::fchownat(fd, cd.as_ptr(), uid, gid, libc::AT_SYMLINK_NOFOLLOW) }, "change owner of", d)?;
+            Self::check(unsafe { libc::fchmodat(fd, cd.as_ptr(), mode, 0) }, "change mode of", d)?;
+        }
+
+        Ok(())
+    }
+
+    /// Hide the path with a whiteout
+    fn whiteout(&self, p: &Path) -> Result<(), Error> {
+        if let Some(d) = p.parent() {
+            self.mkdirs(d)?;
+        }
+
+        let cp = Self::rel(p)?;
+        Self::check(
+            unsafe { libc::mknodat(self.dir.as_raw_fd(), cp.as_ptr(), libc::S_IFCHR, libc::makedev(0, 0)) },
+            "create whiteout",
+            p,
+        )
+    }
+
+    /// Returns true if the path in the upper directory is a whiteout
+    fn is_whiteout(&self, p: &Path) -> bool {
+        let cp = match Self::rel(p) {
+            Ok(cp) => cp,
+            Err(_) => return false,
+        };
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        let ret = unsafe { libc::fstatat(self.dir.as_raw_fd(), cp.as_ptr(), &mut st, libc::AT_SYMLINK_NOFOLLOW) };
+        ret == 0 && st.st_mode & libc::S_IFMT == libc::S_IFCHR && st.st_rdev == 0
+    }
+
+    /// Hide all content of the directory, making it opaque
+    fn opaque(&self, p: &Path) -> Result<(), Error> {
+        self.mkdirs(p)?;
+        self.set_opaque(p)
+    }
+
+    /// Mark the directory in the upper directory as opaque
+    fn set_opaque(&self, p: &Path) -> Result<(), Error> {
+        let cp = Self::rel(p)?;
+        let fd = unsafe { libc::openat(self.dir.as_raw_fd(), cp.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY) };
+        if fd < 0 {
+            return Self::check(-1, "open", p);
+        }
+
+        let d = unsafe { File::from_raw_fd(fd) };
+        let cn = CString::new(OPAQUE).unwrap();
+        Self::check(
+            unsafe { libc::fsetxattr(d.as_raw_fd(), cn.as_ptr(), b"y".as_ptr() as *const libc::c_void, 1, 0) },
+            "make opaque",
+            p,
+        )
+    }
+
+    /// Write a file into the layer, e.g. the state of the tint
+    pub fn write(&self, p: &Path, data: &[u8]) -> Result<(), Error> {
+        if let Some(d) = p.parent() {
+            self.mkdirs(d)?;
+        }
+
+        let cp = Self::rel(p)?;
+        let fd = unsafe {
+            libc::openat(self.dir.as_raw_fd(), cp.as_ptr(), libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC, 0o644 as libc::c_uint)
+        };
+        if fd < 0 {
+            return Self::check(-1, "create", p);
+        }
+
+        unsafe { File::from_raw_fd(fd) }.write_all(data)
+    }
+
+    /// Walk the directory, as if the files were removed and the leftovers swept:
+    /// symlinks to the removed files are broken and emptied directories are removed.
+    /// Returns true if nothing is left in the directory and its whiteouts otherwise.
+    fn sweep(d: &Path, removed: &HashSet<PathBuf>, stays: &dyn Fn(&Path) -> bool) -> (bool, Vec<Whiteout>) {
+        let mut entries = match prefix::read_dir(d) {
+            Ok(rd) => rd.filter_map(|e| e.ok()).map(|e| prefix::strip(e.path())).collect::<Vec<PathBuf>>(),
+            Err(_) => return (false, vec![]),
+        };
+        entries.sort();
+
+        let mut out: Vec<Whiteout> = vec![];
+        let mut left = false;
+        for p in entries {
+            let gone = if stays(&p) {
+                false
+            } else if prefix::is_symlink(&p) {
+                removed.contains(&p)
+                    || prefix::canonicalize(&p).map(|t| t.ancestors().any(|a| removed.contains(a))).unwrap_or(true)
+            } else if prefix::is_dir(&p) {
+                let (gone, sub) = Self::sweep(&p, removed, stays);
+                if !gone {
+                    out.extend(sub);
+                }
+                gone
+            } else {
+                removed.contains(&p)
+            };
+
+            if gone {
+                out.push(Whiteout::Remove(p));
+            } else {
+                left = true;
+            }
+        }
+
+        // Hiding the whole content takes one entry
+        if !left && !out.is_empty() && MOUNT_POINTS.contains(&d.to_str().unwrap_or_default()) {
+            return (false, vec![Whiteout::Opaque(d.to_owned())]);
+        }
+
+        (!left && !MOUNT_POINTS.contains(&d.to_str().unwrap_or_default()), out)
+    }
+
+    /// Get whiteouts for the removed files of the rootfs, mounted at the root.
+    /// Paths, those stay, e.g. protected ones, are not swept.
+    pub fn plan(removed: &[PathBuf], stays: &dyn Fn(&Path) -> bool) -> Vec<Whiteout> {
+        let mut removed = removed.iter().cloned().collect::<HashSet<PathBuf>>();
+        loop {
+            // Swept directories might break symlinks, those are already walked
+            let out = Self::sweep(Path::new("/"), &removed, stays).1;
+            let n = removed.len();
+            removed.extend(out.iter().filter_map(|w| match w {
+                Whiteout::Remove(p) => Some(p.to_owned()),
+                Whiteout::Opaque(_) => None,
+            }));
+            if removed.len() == n {
+                return out;
+            }
+        }
+    }
+
+    /// Write whiteouts of the removed files. Returns number of the entries.
+    pub fn apply(&mut self, removed: &[PathBuf], stays: &dyn Fn(&Path) -> bool) -> Result<usize, Error> {
+        for w in Self::plan(removed, stays) {
+            match &w {
+                Whiteout::Remove(p) => {
+                    log::debug!("Hiding {}", p.to_str().unwrap());
+                    self.whiteout(p)?
+                }
+                Whiteout::Opaque(p) => {
+                    log::debug!("Hiding content of {}", p.to_str().unwrap());
+                    self.opaque(p)?
+                }
+            }
+            self.count += 1;
+        }
+
+        log::info!("{} whiteouts are written to {}", self.count, self.path.to_str().unwrap());
+        Ok(self.count)
+    }
+}
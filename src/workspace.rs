@@ -0,0 +1,216 @@
+/*
+Temporary workspaces on the host.
+
+Workspace names are derived from the process id, so leftovers of failed
+or killed runs can be recognised and removed by the next run.
+
+Files of a workspace are accessed relative to its directory, which is opened
+on the host, so they stay reachable after chroot to the image as well.
+Finished files are moved out of the workspace to their destination at once,
+so failed runs never leave partially written results behind.
+ */
+
+use bytesize::ByteSize;
+use std::{
+    env,
+    ffi::CString,
+    fs::{self, File},
+    io::{self, Error},
+    os::unix::{
+        fs::MetadataExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, PoisonError},
+};
+
+/// Prefix of all workspace directories
+const WS_PREFIX: &str = "mezzotint-";
+
+static BASE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Set base directory for all workspaces. Defaults to the system temporary directory.
+pub fn set_base(p: &Path) {
+    *BASE.lock().unwrap_or_else(PoisonError::into_inner) = Some(p.to_owned());
+}
+
+/// Get base directory for the workspaces
+pub fn get_base() -> PathBuf {
+    BASE.lock().unwrap_or_else(PoisonError::into_inner).clone().unwrap_or_else(env::temp_dir)
+}
+
+/// Returns true if the process, which has left a temporary entry, is no longer running
+pub fn is_stale(pid: u32) -> bool {
+    pid != process::id() && !Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+/// Open an entry of the directory
+pub fn open_at(dir: &File, name: &str, flags: libc::c_int) -> Result<File, Error> {
+    let cn = CString::new(name)?;
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), cn.as_ptr(), flags | libc::O_CLOEXEC, 0o644 as libc::c_uint) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+/// Rename an entry between the directories
+pub fn rename_at(src: &File, sname: &str, dst: &File, dname: &str) -> Result<(), Error> {
+    let (cs, cd) = (CString::new(sname)?, CString::new(dname)?);
+    if unsafe { libc::renameat(src.as_raw_fd(), cs.as_ptr(), dst.as_raw_fd(), cd.as_ptr()) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Remove an entry of the directory
+pub fn unlink_at(dir: &File, name: &str) {
+    if let Ok(cn) = CString::new(name) {
+        unsafe { libc::unlinkat(dir.as_raw_fd(), cn.as_ptr(), 0) };
+    }
+}
+
+/// Temporary directory, removed with all its content when dropped
+pub struct Workspace {
+    path: PathBuf,
+    dir: File,
+}
+
+impl Workspace {
+    /// Create a workspace of a given purpose, e.g. "preview"
+    pub fn new(name: &str) -> Result<Self, Error> {
+        let base = get_base();
+        fs::create_dir_all(&base)?;
+        Self::cleanup(&base);
+
+        let path = base.join(format!("{}{}-{}", WS_PREFIX, name, process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        let dir = open_at(&File::open(&base)?, path.file_name().unwrap().to_str().unwrap(), libc::O_RDONLY | libc::O_DIRECTORY)?;
+        log::debug!("Created workspace {}", path.to_str().unwrap());
+
+        Ok(Workspace { path, dir })
+    }
+
+    /// Remove workspaces, left by the processes those are no longer running
+    fn cleanup(base: &Path) {
+        let entries = match fs::read_dir(base) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for e in entries.filter_map(|e| e.ok()) {
+            let name = e.file_name().to_str().unwrap_or_default().to_string();
+            let pid = match name.strip_prefix(WS_PREFIX).and_then(|n| n.rsplit_once('-')).map(|(_, pid)| pid.parse::<u32>()) {
+                Some(Ok(pid)) => pid,
+                _ => continue,
+            };
+
+            if is_stale(pid) && e.path().is_dir() {
+                log::debug!("Removing stale workspace {}", e.path().to_str().unwrap());
+                if let Err(err) = fs::remove_dir_all(e.path()) {
+                    log::warn!("Unable to remove stale workspace {}: {}", e.path().to_str().unwrap(), err);
+                }
+            }
+        }
+    }
+
+    /// Get path of the workspace
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create a file in the workspace
+    pub fn create(&self, name: &str) -> Result<File, Error> {
+        open_at(&self.dir, name, libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC)
+    }
+
+    /// Move a finished file of the workspace to the directory, replacing the destination at once.
+    /// Across filesystems it is copied into a temporary entry of the directory first.
+    pub fn persist(&self, name: &str, dir: &File, dest: &str) -> Result<(), Error> {
+        match rename_at(&self.dir, name, dir, dest) {
+            Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {}
+            r => return r,
+        }
+
+        let tmp = format!(".{}.{}", dest, process::id());
+        let r = open_at(&self.dir, name, libc::O_RDONLY)
+            .and_then(|mut src| io::copy(&mut src, &mut open_at(dir, &tmp, libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC)?))
+            .and_then(|_| rename_at(dir, &tmp, dir, dest));
+        if r.is_err() {
+            unlink_at(dir, &tmp);
+        }
+        unlink_at(&self.dir, name);
+
+        r
+    }
+
+    /// Returns true if the workspace is reachable by its path, which is not the case after chroot
+    fn is_reachable(&self) -> bool {
+        match (fs::metadata(&self.path), self.dir.metadata()) {
+            (Ok(pm), Ok(dm)) => (pm.dev(), pm.ino()) == (dm.dev(), dm.ino()),
+            _ => false,
+        }
+    }
+
+    /// Get total size of the files in the workspace
+    pub fn size(&self) -> u64 {
+        walkdir::WalkDir::new(&self.path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.metadata().ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum()
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        // Path might lead to a different directory inside the image, so it is left for the next run
+        if !self.is_reachable() {
+            log::debug!("Workspace {} is not reachable, leaving it to the next run", self.path.to_str().unwrap());
+            return;
+        }
+
+        log::debug!("Removing workspace {} ({})", self.path.to_str().unwrap(), ByteSize::b(self.size()));
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            log::warn!("Unable to remove workspace {}: {}", self.path.to_str().unwrap(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_base, Workspace};
+    use crate::testutil::scratch;
+    use std::{fs::File, io::Write};
+
+    #[test]
+    fn persist_and_drop() {
+        let base = scratch("workspace");
+        let dest = scratch("workspace-dest");
+        set_base(&base);
+
+        // Workspace of a process, which is not running
+        std::fs::create_dir(base.join("mezzotint-backup-4194305")).unwrap();
+
+        let ws = Workspace::new("test").unwrap();
+        assert!(!base.join("mezzotint-backup-4194305").exists());
+        ws.create("data").unwrap().write_all(b"0123456789").unwrap();
+        assert_eq!(ws.size(), 10);
+
+        ws.persist("data", &File::open(&dest).unwrap(), "result").unwrap();
+        assert_eq!(std::fs::read(dest.join("result")).unwrap(), b"0123456789");
+        assert!(!ws.get_path().join("data").exists());
+
+        let p = ws.get_path().to_owned();
+        drop(ws);
+        assert!(!p.exists());
+    }
+}
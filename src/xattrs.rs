@@ -0,0 +1,178 @@
+/*
+Extended attributes of the files.
+
+Security attributes, such as file capabilities (e.g. of ping), are
+lost if a file is copied or its owner is changed, so they are carried
+over explicitly and verified after the changes are applied.
+ */
+
+use std::{
+    ffi::CString,
+    io::{Error, ErrorKind},
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+/// Attribute of the file capabilities
+pub const CAPABILITY: &str = "security.capability";
+
+/// Attribute of the SELinux security context
+pub const SELINUX: &str = "security.selinux";
+
+/// Names of the capabilities by their numbers
+const CAP_NAMES: &[&str] = &[
+    "cap_chown",
+    "cap_dac_override",
+    "cap_dac_read_search",
+    "cap_fowner",
+    "cap_fsetid",
+    "cap_kill",
+    "cap_setgid",
+    "cap_setuid",
+    "cap_setpcap",
+    "cap_linux_immutable",
+    "cap_net_bind_service",
+    "cap_net_broadcast",
+    "cap_net_admin",
+    "cap_net_raw",
+    "cap_ipc_lock",
+    "cap_ipc_owner",
+    "cap_sys_module",
+    "cap_sys_rawio",
+    "cap_sys_chroot",
+    "cap_sys_ptrace",
+    "cap_sys_pacct",
+    "cap_sys_admin",
+    "cap_sys_boot",
+    "cap_sys_nice",
+    "cap_sys_resource",
+    "cap_sys_time",
+    "cap_sys_tty_config",
+    "cap_mknod",
+    "cap_lease",
+    "cap_audit_write",
+    "cap_audit_control",
+    "cap_setfcap",
+    "cap_mac_override",
+    "cap_mac_admin",
+    "cap_syslog",
+    "cap_wake_alarm",
+    "cap_block_suspend",
+    "cap_audit_read",
+    "cap_perfmon",
+    "cap_bpf",
+    "cap_checkpoint_restore",
+];
+
+/// Effective flag of the capabilities
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x000001;
+
+fn c_path(p: &Path) -> Result<CString, Error> {
+    CString::new(p.as_os_str().as_bytes()).map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))
+}
+
+/// Get names of the extended attributes, without following symlinks
+pub fn list(p: &Path) -> Vec<String> {
+    let cp = match c_path(p) {
+        Ok(cp) => cp,
+        Err(_) => return vec![],
+    };
+
+    let mut names = vec![0u8; 0x10000];
+    let len = unsafe { libc::llistxattr(cp.as_ptr(), names.as_mut_ptr() as *mut libc::c_char, names.len()) };
+    if len <= 0 {
+        return vec![];
+    }
+
+    names[..len as usize].split(|b| *b == 0).filter(|n| !n.is_empty()).map(|n| String::from_utf8_lossy(n).to_string()).collect()
+}
+
+/// Get value of the extended attribute, without following symlinks
+pub fn get(p: &Path, name: &str) -> Option<Vec<u8>> {
+    let cp = c_path(p).ok()?;
+    let cn = CString::new(name).ok()?;
+    let mut value = vec![0u8; 0x10000];
+    let len = unsafe { libc::lgetxattr(cp.as_ptr(), cn.as_ptr(), value.as_mut_ptr() as *mut libc::c_void, value.len()) };
+    if len < 0 {
+        return None;
+    }
+
+    value.truncate(len as usize);
+    Some(value)
+}
+
+/// Get all extended attributes with their values
+pub fn get_all(p: &Path) -> Vec<(String, Vec<u8>)> {
+    list(p).into_iter().filter_map(|n| get(p, &n).map(|v| (n, v))).collect()
+}
+
+/// Set value of the extended attribute, without following symlinks
+pub fn set(p: &Path, name: &str, value: &[u8]) -> Result<(), Error> {
+    let cp = c_path(p)?;
+    let cn = CString::new(name).map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+    if unsafe { libc::lsetxattr(cp.as_ptr(), cn.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Copy security attributes between files, e.g. after the file is copied
+pub fn copy_security(src: &Path, dst: &Path) -> Result<(), Error> {
+    for (n, v) in get_all(src).into_iter().filter(|(n, _)| n.starts_with("security.")) {
+        set(dst, &n, &v)?;
+    }
+
+    Ok(())
+}
+
+/// Get raw file capabilities of the file, if any
+pub fn get_caps(p: &Path) -> Option<Vec<u8>> {
+    get(p, CAPABILITY)
+}
+
+/// Get raw SELinux context of the file, if it is labeled
+pub fn get_context(p: &Path) -> Option<Vec<u8>> {
+    get(p, SELINUX)
+}
+
+/// Describe raw SELinux context, which is a zero-terminated string, e.g. "system_u:object_r:bin_t:s0"
+pub fn describe_context(ctx: &[u8]) -> String {
+    String::from_utf8_lossy(ctx.strip_suffix(&[0]).unwrap_or(ctx)).to_string()
+}
+
+/// Describe raw file capabilities like getcap(8) does, e.g. "cap_net_raw=ep"
+pub fn describe_caps(caps: &[u8]) -> String {
+    let word = |i: usize| caps.get(i..i + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).unwrap_or(0);
+
+    // Magic and flags, then permitted and inheritable sets of 32 bits each. Revision 1 has only one set.
+    let magic = word(0);
+    let (permitted, inheritable) = if caps.len() >= 20 {
+        ((word(4) as u64) | (word(12) as u64) << 32, (word(8) as u64) | (word(16) as u64) << 32)
+    } else {
+        (word(4) as u64, word(8) as u64)
+    };
+
+    let names = |set: u64| {
+        (0..64)
+            .filter(|i| set & (1 << i) != 0)
+            .map(|i| CAP_NAMES.get(i as usize).map(|n| n.to_string()).unwrap_or_else(|| format!("cap_{}", i)))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+
+    let e = if magic & VFS_CAP_FLAGS_EFFECTIVE != 0 { "e" } else { "" };
+    if permitted != 0 && permitted == inheritable {
+        return format!("{}={}ip", names(permitted), e);
+    }
+
+    let mut out: Vec<String> = vec![];
+    if permitted != 0 {
+        out.push(format!("{}={}p", names(permitted), e));
+    }
+    if inheritable != 0 {
+        out.push(format!("{}=i", names(inheritable)));
+    }
+
+    out.join(" ")
+}